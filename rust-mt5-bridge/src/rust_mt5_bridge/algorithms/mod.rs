@@ -0,0 +1,135 @@
+//! Trading algorithms run by this DLL on behalf of the MQL Programs.
+//!
+//! MQL Programs choose their algorithm when registering, through the `algorithm` JSON parameter -- in the form
+//! `{"algorithm": "NaiveTrader", "stop_win": 0.02}` -- which is parsed by [instantiate()] into one of the algorithms
+//! known here, with its typed parameters.
+//!
+//! Algorithms are fed with market data & trading events through the [TradingAlgorithm] callbacks and respond with
+//! the [OrderRequest]s they want to issue -- which are, then, sent by MQL (see `Mql5Command::OrderSend`), having their
+//! outcomes fed back through [TradingAlgorithm::on_order_result()].
+//!
+//! To add a new algorithm: implement [TradingAlgorithm] in its own module and teach [instantiate()] how to build it.
+
+mod naive_trader;
+pub use naive_trader::*;
+
+use super::{
+    types::*,
+    mql5_commands::{OrderRequest, Mql5CommandResult, Mql5CallError},
+    ogre_exchange_models::RiskManagementConditions,
+    order_manager::OrderManager,
+};
+use std::fmt::Debug;
+
+
+/// Callbacks for trading algorithms -- all of them return the orders the algorithm decided to issue in response
+/// (most often, none). Default implementations ignore the event.
+pub trait TradingAlgorithm: Debug + Send {
+
+    /// The name, as used in the `algorithm` JSON parameter
+    fn name(&self) -> &'static str;
+
+    /// Called when MQL informs the details of the symbol being traded -- typically, once per session
+    fn on_symbol_info(&mut self, _symbol_info: &SymbolInfoRust) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called when MQL informs the state of the trading account
+    fn on_account_info(&mut self, _account_info: &AccountInfoRust) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called on every new quote or trade
+    fn on_tick(&mut self, _tick: &MqlTick) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called on every trade, with its aggressor already classified -- including the ones Metatrader didn't report,
+    /// inferred from the books (see [Trade::inferred])
+    fn on_trade(&mut self, _trade: &Trade) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called on book updates, with the `delta_events` that turned the previous `books` into the given ones
+    fn on_book_deltas(&mut self, _books: &OrderBooks, _delta_events: &[BookEvents]) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called when something happens to our orders, deals or positions -- `orders` is already updated with the transaction
+    fn on_trade_transaction(&mut self, _orders: &OrderManager, _transaction: &MqlTradeTransaction, _request: &MqlTradeRequest, _result: &MqlTradeResult) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called when MQL reports the outcome of sending `order` -- one issued by this algorithm: either the trade server's
+    /// answer or the reason why none will come. Fills, if any, are still informed through [Self::on_trade_transaction()]
+    fn on_order_result(&mut self, _order: &OrderRequest, _result: &Result<Mql5CommandResult, Mql5CallError>) -> Vec<OrderRequest> {
+        vec![]
+    }
+
+    /// Called when the pre-trade checks refuse to send `order` -- one issued by this algorithm -- telling which limit was hit.\
+    /// Orders returned here go through the same checks: blindly retrying would just be refused again
+    fn on_order_refused(&mut self, _order: &OrderRequest, _condition: &RiskManagementConditions) -> Vec<OrderRequest> {
+        vec![]
+    }
+}
+
+
+/// Parses the `algorithm` JSON parameter given by MQL Programs when registering, returning the algorithm it describes
+/// -- or `Ok(None)` if `algorithm_json` is not a JSON object (like MQL Programs that only provide market data, which
+/// inform a plain description).\
+/// `Err` is returned, with a descriptive message, if the algorithm is unknown or its parameters are invalid
+pub fn instantiate(algorithm_json: &str, symbol: &str) -> Result<Option<Box<dyn TradingAlgorithm>>, String> {
+    if !algorithm_json.trim_start().starts_with('{') {
+        return Ok(None);
+    }
+    let mut parameters: serde_json::Map<String, serde_json::Value> = serde_json::from_str(algorithm_json)
+        .map_err(|err| format!("Couldn't parse the algorithm JSON '{algorithm_json}': {err}"))?;
+    let Some(serde_json::Value::String(algorithm_name)) = parameters.remove("algorithm") else {
+        return Err(format!("The algorithm JSON '{algorithm_json}' lacks the string field \"algorithm\", with the name of the algorithm to run"));
+    };
+    // not for the algorithm: parsed by `RiskLimits::from_algorithm_json()`
+    parameters.remove("risk_limits");
+    let parameters = serde_json::Value::Object(parameters);
+    let parameters_error = |err: serde_json::Error| format!("Invalid parameters for algorithm '{algorithm_name}' in '{algorithm_json}': {err}");
+    match algorithm_name.as_str() {
+        "NaiveTrader" => Ok(Some(Box::new(NaiveTrader::new(symbol, serde_json::from_value(parameters).map_err(parameters_error)?)))),
+        unknown => Err(format!("Unknown algorithm '{unknown}' in '{algorithm_json}' -- known ones are: [\"NaiveTrader\"]")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks the algorithm JSON parameter is parsed into the right algorithm -- or that meaningful errors are returned
+    #[test]
+    fn algorithm_instantiation() {
+        let algorithm = instantiate(r#"{"algorithm": "NaiveTrader", "stop_win": 0.02}"#, "PETR4")
+            .expect("instantiating a known algorithm with valid parameters")
+            .expect("a JSON object should yield an algorithm");
+        assert_eq!(algorithm.name(), "NaiveTrader", "Wrong algorithm instantiated");
+
+        let with_risk_limits = instantiate(r#"{"algorithm": "NaiveTrader", "stop_win": 0.02, "risk_limits": {"max_quantity": 100}}"#, "PETR4")
+            .expect("`risk_limits` should not be taken as an algorithm parameter");
+        assert!(with_risk_limits.is_some(), "An algorithm should be instantiated along with `risk_limits`");
+
+        let no_algorithm = instantiate("Market Data Provider", "PETR4").expect("plain descriptions are not errors");
+        assert!(no_algorithm.is_none(), "Plain descriptions should not yield an algorithm");
+
+        let errors = [
+            (r#"{"algorithm": "NaiveTrader", "stop_win": 0.02"#,                   "Couldn't parse"),
+            (r#"{"stop_win": 0.02}"#,                                              "lacks the string field"),
+            (r#"{"algorithm": "WiseTrader"}"#,                                     "Unknown algorithm 'WiseTrader'"),
+            (r#"{"algorithm": "NaiveTrader", "stop_win": "2%"}"#,                  "Invalid parameters for algorithm 'NaiveTrader'"),
+            (r#"{"algorithm": "NaiveTrader", "stop_win": 0.02, "stop_wim": 0.1}"#, "unknown field `stop_wim`"),
+        ];
+        for (algorithm_json, expected_error) in errors {
+            match instantiate(algorithm_json, "PETR4") {
+                Ok(algorithm) => panic!("Instantiating '{algorithm_json}' should have failed, but {algorithm:?} was returned"),
+                Err(error) => assert!(error.contains(expected_error), "Wrong error message for '{algorithm_json}': '{error}' -- it should contain '{expected_error}'"),
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+//! Reference [TradingAlgorithm]: buys at market whenever flat, leaving the exit to the Take Profit & Stop Loss levels
+//! sent along with the order -- so no exit logic is needed here.
+//!
+//! Usage: `{"algorithm": "NaiveTrader", "stop_win": 0.02, "stop_loss": 0.01, "volume": 100}`
+
+use super::{
+    TradingAlgorithm,
+    OrderRequest,
+    OrderManager,
+    Mql5CommandResult,
+    Mql5CallError,
+    RiskManagementConditions,
+    super::types::*,
+};
+use log::{info, warn};
+use serde::Deserialize;
+
+
+/// Typed parameters for [NaiveTrader], parsed from the `algorithm` JSON
+#[derive(Debug,Clone,PartialEq,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NaiveTraderParams {
+    /// Take Profit, as a fraction of the entry price -- `0.02` means 2% above the buying price
+    pub stop_win:  f64,
+    /// Stop Loss, as a fraction of the entry price -- if absent, positions are only closed by the Take Profit
+    pub stop_loss: Option<f64>,
+    /// Lots to buy on each entry -- if absent, the minimum volume allowed for the symbol is used
+    pub volume:    Option<f64>,
+}
+
+
+/// See the [module](self) docs
+#[derive(Debug)]
+pub struct NaiveTrader {
+    symbol:    String,
+    params:    NaiveTraderParams,
+    /// Known after [TradingAlgorithm::on_symbol_info()] -- used to round the Take Profit & Stop Loss levels
+    tick_size: Option<f64>,
+    /// Known after [TradingAlgorithm::on_symbol_info()], if not given in [NaiveTraderParams::volume]
+    volume:    Option<f64>,
+    /// Lots held for [Self::symbol], according to the deals seen in [TradingAlgorithm::on_trade_transaction()]
+    position:  f64,
+    /// Tells if we are waiting for the outcome of an issued order -- so no other one should be issued.\
+    /// Reset when the order is filled, rejected or couldn't be sent
+    awaiting_execution: bool,
+}
+
+impl NaiveTrader {
+
+    pub fn new(symbol: &str, params: NaiveTraderParams) -> Self {
+        Self {
+            symbol:             symbol.to_string(),
+            tick_size:          None,
+            volume:             params.volume,
+            position:           0.0,
+            awaiting_execution: false,
+            params,
+        }
+    }
+
+    /// Rounds `price` to the nearest valid one for the symbol
+    fn round_price(&self, price: f64) -> f64 {
+        match self.tick_size {
+            Some(tick_size) if tick_size > 0.0 => (price / tick_size).round() * tick_size,
+            _ => price,
+        }
+    }
+}
+
+impl TradingAlgorithm for NaiveTrader {
+
+    fn name(&self) -> &'static str {
+        "NaiveTrader"
+    }
+
+    fn on_symbol_info(&mut self, symbol_info: &SymbolInfoRust) -> Vec<OrderRequest> {
+        self.tick_size = Some(symbol_info.symbol_trade_tick_size);
+        if self.params.volume.is_none() {
+            self.volume = Some(symbol_info.symbol_volume_min);
+        }
+        vec![]
+    }
+
+    fn on_tick(&mut self, tick: &MqlTick) -> Vec<OrderRequest> {
+        let Some(volume) = self.volume else {
+            return vec![];
+        };
+        if self.position != 0.0 || self.awaiting_execution || tick.ask <= 0.0 {
+            return vec![];
+        }
+        self.awaiting_execution = true;
+        let order = OrderRequest {
+            action:             EnumTradeRequestActions::TradeActionDeal,
+            symbol:             self.symbol.clone(),
+            volume,
+            price:              tick.ask,
+            sl:                 self.params.stop_loss.map_or(0.0, |stop_loss| self.round_price(tick.ask * (1.0 - stop_loss))),
+            tp:                 self.round_price(tick.ask * (1.0 + self.params.stop_win)),
+            order_type:         EnumOrderType::OrderTypeBuy,
+            order_type_filling: EnumOrderTypeFilling::OrderFillingFok,
+            order_type_time:    EnumOrderTypeTime::OrderTimeDay,
+            comment:            String::from("NaiveTrader entry"),
+        };
+        info!("NaiveTrader: {}: flat -- entering with {order:?}", self.symbol);
+        vec![order]
+    }
+
+    fn on_trade_transaction(&mut self, _orders: &OrderManager, transaction: &MqlTradeTransaction, _request: &MqlTradeRequest, result: &MqlTradeResult) -> Vec<OrderRequest> {
+        match transaction.transaction_type {
+            EnumTradeTransactionType::TradeTransactionDealAdd if transaction.symbol == self.symbol => {
+                match transaction.deal_type {
+                    mql_trade_transaction::EnumDealType::DealTypeBuy  => self.position += transaction.volume,
+                    mql_trade_transaction::EnumDealType::DealTypeSell => self.position -= transaction.volume,
+                    _ => return vec![],
+                }
+                self.awaiting_execution = false;
+                info!("NaiveTrader: {}: position is now {} lots", self.symbol, self.position);
+            },
+            EnumTradeTransactionType::TradeTransactionRequest
+                if !matches!(result.retcode, Mt5TradeServerReturnCodes::TradeRetcodeDone | Mt5TradeServerReturnCodes::TradeRetcodeDonePartial | Mt5TradeServerReturnCodes::TradeRetcodePlaced) => {
+                warn!("NaiveTrader: {}: order rejected with {:?} ('{}') -- will retry on the next tick", self.symbol, result.retcode, result.comment);
+                self.awaiting_execution = false;
+            },
+            _ => (),
+        }
+        vec![]
+    }
+
+    fn on_order_result(&mut self, order: &OrderRequest, result: &Result<Mql5CommandResult, Mql5CallError>) -> Vec<OrderRequest> {
+        let sent = matches!(result, Ok(Mql5CommandResult::Trade { mt5_error_code: 0, result })
+                                    if matches!(result.retcode, Mt5TradeServerReturnCodes::TradeRetcodeDone | Mt5TradeServerReturnCodes::TradeRetcodeDonePartial | Mt5TradeServerReturnCodes::TradeRetcodePlaced));
+        if !sent {
+            warn!("NaiveTrader: {}: {order:?} was not sent: {result:?} -- will retry on the next tick", self.symbol);
+            self.awaiting_execution = false;
+        }
+        vec![]
+    }
+
+    fn on_order_refused(&mut self, order: &OrderRequest, condition: &RiskManagementConditions) -> Vec<OrderRequest> {
+        warn!("NaiveTrader: {}: {order:?} was refused by the Risk Manager: {condition:?} -- will retry on the next tick", self.symbol);
+        self.awaiting_execution = false;
+        vec![]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::ogre_exchange_models::RiskManagementOrderRefusalConditions;
+    use chrono::NaiveDateTime;
+
+
+    /// checks [NaiveTrader] enters only when flat -- and that rejections & closed positions allow new entries
+    #[test]
+    fn enters_when_flat() {
+        let mut trader = NaiveTrader::new("PETR4", NaiveTraderParams { stop_win: 0.02, stop_loss: Some(0.01), volume: Some(100.0) });
+        let symbol = String::from("PETR4");
+        let tick = MqlTick { symbol: &symbol, time_msc: 0, bid: 24.99, ask: 25.00, last: 25.00, volume: 100.0, flags: 0 };
+
+        let orders = trader.on_tick(&tick);
+        assert_eq!(orders.len(), 1, "A flat NaiveTrader should enter on the first tick");
+        let order = &orders[0];
+        assert_eq!((order.order_type, order.volume, order.price), (EnumOrderType::OrderTypeBuy, 100.0, 25.00), "Wrong entry order: {order:?}");
+        assert!((order.tp - 25.50).abs() < 1e-9 && (order.sl - 24.75).abs() < 1e-9, "Wrong exit levels: {order:?}");
+        assert!(trader.on_tick(&tick).is_empty(), "No orders should be issued while awaiting the execution of the previous one");
+
+        trader.on_trade_transaction(&OrderManager::new(), &transaction(EnumTradeTransactionType::TradeTransactionRequest, mql_trade_transaction::EnumDealType::DealTypeBuy, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeNoMoney));
+        assert_eq!(trader.on_tick(&tick).len(), 1, "Rejected orders should be retried on the next tick");
+
+        trader.on_trade_transaction(&OrderManager::new(), &transaction(EnumTradeTransactionType::TradeTransactionDealAdd, mql_trade_transaction::EnumDealType::DealTypeBuy, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone));
+        assert!(trader.on_tick(&tick).is_empty(), "No orders should be issued while positioned");
+
+        trader.on_trade_transaction(&OrderManager::new(), &transaction(EnumTradeTransactionType::TradeTransactionDealAdd, mql_trade_transaction::EnumDealType::DealTypeSell, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone));
+        assert_eq!(trader.on_tick(&tick).len(), 1, "A new entry should be issued once the position is closed by the Take Profit or Stop Loss");
+    }
+
+    /// checks [NaiveTrader] isn't stuck awaiting the execution of orders that were never sent -- or refused by the trade server
+    #[test]
+    fn unsent_orders() {
+        let mut trader = NaiveTrader::new("PETR4", NaiveTraderParams { stop_win: 0.02, stop_loss: None, volume: Some(100.0) });
+        let symbol = String::from("PETR4");
+        let tick = MqlTick { symbol: &symbol, time_msc: 0, bid: 24.99, ask: 25.00, last: 25.00, volume: 100.0, flags: 0 };
+        let trade = |mt5_error_code, retcode| Ok(Mql5CommandResult::Trade { mt5_error_code, result: result(retcode) });
+
+        let order = trader.on_tick(&tick).pop().expect("A flat NaiveTrader should enter on the first tick");
+        trader.on_order_result(&order, &trade(0, Mt5TradeServerReturnCodes::TradeRetcodeDone));
+        assert!(trader.on_tick(&tick).is_empty(), "Sent orders should be awaited until they are filled");
+
+        for unsent in [Err(Mql5CallError::Abandoned), trade(4756, Mt5TradeServerReturnCodes::TradeRetcodeInvalid), trade(0, Mt5TradeServerReturnCodes::TradeRetcodeMarketClosed)] {
+            trader.on_order_result(&order, &unsent);
+            assert_eq!(trader.on_tick(&tick).len(), 1, "Orders not sent ({unsent:?}) should be retried on the next tick");
+        }
+
+        let refusal = RiskManagementConditions::OrderRefused(RiskManagementOrderRefusalConditions::QuantityTooHigh { quantity_limit: 10 });
+        trader.on_order_refused(&order, &refusal);
+        assert_eq!(trader.on_tick(&tick).len(), 1, "Orders refused by the Risk Manager should be retried on the next tick");
+    }
+
+    /// checks no orders are issued before the volume is known -- when it comes from the symbol info
+    #[test]
+    fn volume_from_symbol_info() {
+        let mut trader = NaiveTrader::new("PETR4", NaiveTraderParams { stop_win: 0.02, stop_loss: None, volume: None });
+        let symbol = String::from("PETR4");
+        let tick = MqlTick { symbol: &symbol, time_msc: 0, bid: 24.99, ask: 25.00, last: 25.00, volume: 100.0, flags: 0 };
+        assert!(trader.on_tick(&tick).is_empty(), "No orders should be issued before the volume is known");
+        trader.on_symbol_info(&SymbolInfoRust::for_testing(0.01, 100.0));
+        let orders = trader.on_tick(&tick);
+        assert_eq!(orders.first().map(|order| (order.volume, order.sl)), Some((100.0, 0.0)), "The minimum volume should be used -- and, without `stop_loss`, no Stop Loss level should be set");
+        assert!((orders[0].tp - 25.50).abs() < 1e-9, "The Take Profit should be rounded to the tick size: {:?}", orders[0]);
+    }
+
+
+    fn transaction(transaction_type: EnumTradeTransactionType, deal_type: mql_trade_transaction::EnumDealType, volume: f64) -> MqlTradeTransaction {
+        MqlTradeTransaction {
+            deal: 1, order: 1, symbol: String::from("PETR4"), transaction_type,
+            order_type: EnumOrderType::OrderTypeBuy, order_state: EnumOrderState::OrderStateFilled, deal_type,
+            time_type: EnumOrderTypeTime::OrderTimeDay, time_expiration: NaiveDateTime::from_timestamp(0, 0),
+            price: 25.00, price_trigger: 0.0, price_sl: 0.0, price_tp: 0.0, volume, position: 1, position_by: 0,
+        }
+    }
+
+    fn request() -> MqlTradeRequest {
+        MqlTradeRequest {
+            action: EnumTradeRequestActions::TradeActionDeal, magic: 0, order: 0, symbol: String::from("PETR4"),
+            volume: 100.0, price: 25.00, stoplimit: 0.0, sl: 0.0, tp: 0.0, deviation: 0,
+            order_type: EnumOrderType::OrderTypeBuy, order_type_filling: EnumOrderTypeFilling::OrderFillingFok,
+            order_type_time: EnumOrderTypeTime::OrderTimeDay, expiration: NaiveDateTime::from_timestamp(0, 0),
+            comment: String::new(), position: 0, position_by: 0,
+        }
+    }
+
+    fn result(retcode: Mt5TradeServerReturnCodes) -> MqlTradeResult {
+        MqlTradeResult { retcode, deal: 0, order: 0, volume: 0.0, price: 0.0, bid: 0.0, ask: 0.0, comment: String::new(), request_id: 0, retcode_external: 0 }
+    }
+}
@@ -0,0 +1,222 @@
+//! Authentication for the "External Connector" protocol:
+//!   1) the `OgreExchange` greets with [OgreExchangeMessagesForExternalConnectors::Welcome];
+//!   2) the "External Connector" answers with its [ConnectorIdentification], stating the `account_token` of its MQL Program;
+//!   3) the `OgreExchange` checks the protocol version & capabilities (see `negotiation.rs`), then the account against its [CredentialStore] and sends
+//!      [OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue];
+//!   4) the "External Connector" answers with [ExternalConnectorMessages::UserAuthorization], containing the secret
+//!      configured for the account in its own [CredentialStore].
+//!
+//! Any failure ends the handshake with [OgreExchangeMessagesForExternalConnectors::Disconnected] and the proper
+//! [DisconnectionReason]. Accounts failing too many authorizations in a row are frozen for a while.
+//!
+//! The "External Connector" side runs in this DLL -- see `external_connector_processor.rs`. The `OgreExchange` side runs
+//! in the `OgreExchange`: a double of it, the loopback peer of our tests, is in `loopback_exchange.rs`.
+//!
+//! When listening for connections (`CommsMode::Server`), anyone may connect -- so the roles are also reversed: before
+//! being trusted with our secret, the peer must prove it is the `OgreExchange` with
+//! [OgreExchangeMessagesForExternalConnectors::ServerAuthorization], containing the secret of the identified account,
+//! which our [Authenticator] verifies -- with the same [AuthenticationLimits] & freezes.
+//!
+//! [OgreExchangeMessagesForExternalConnectors::Welcome]: super::messages_model::OgreExchangeMessagesForExternalConnectors::Welcome
+//! [OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue]: super::messages_model::OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue
+//! [OgreExchangeMessagesForExternalConnectors::ServerAuthorization]: super::messages_model::OgreExchangeMessagesForExternalConnectors::ServerAuthorization
+//! [OgreExchangeMessagesForExternalConnectors::Disconnected]: super::messages_model::OgreExchangeMessagesForExternalConnectors::Disconnected
+//! [ExternalConnectorMessages::UserAuthorization]: super::messages_model::ExternalConnectorMessages::UserAuthorization
+//! [ConnectorIdentification]: super::super::ogre_exchange_models::ConnectorIdentification
+//! [DisconnectionReason]: super::super::ogre_exchange_models::DisconnectionReason
+
+use super::super::ogre_exchange_models::{AccountToken, DisconnectionReason};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use log::warn;
+
+
+/// The secrets of the known accounts -- in JSON, `{"<account_token>": {"secret": "<secret>"}, ...}`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct CredentialStore {
+    accounts: HashMap<AccountToken, AccountCredentials>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountCredentials {
+    pub secret:   String,
+    /// If present, the account is disabled -- for the given reason
+    #[serde(default)]
+    pub disabled: Option<String>,
+}
+
+impl CredentialStore {
+
+    /// Adds (or replaces) the credentials of `account_token`
+    pub fn with_account(mut self, account_token: &str, secret: &str) -> Self {
+        self.accounts.insert(account_token.to_string(), AccountCredentials { secret: secret.to_string(), disabled: None });
+        self
+    }
+
+    /// Disables the (already added) `account_token`, for the given reason
+    pub fn with_disabled_account(mut self, account_token: &str, reason: &str) -> Self {
+        if let Some(credentials) = self.accounts.get_mut(account_token) {
+            credentials.disabled = Some(reason.to_string());
+        }
+        self
+    }
+
+    /// The secret to authenticate `account_token` with -- `None` if the account is unknown
+    pub fn secret(&self, account_token: &str) -> Option<&str> {
+        self.accounts.get(account_token).map(|credentials| credentials.secret.as_str())
+    }
+
+    /// The credentials of `account_token` -- `None` if the account is unknown
+    pub fn account(&self, account_token: &str) -> Option<&AccountCredentials> {
+        self.accounts.get(account_token)
+    }
+}
+
+
+/// Limits for failed authorizations -- after `max_failed_attempts` in a row, the account is frozen for `freeze_duration`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AuthenticationLimits {
+    pub max_failed_attempts:  u32,
+    pub freeze_duration_secs: u64,
+}
+
+impl Default for AuthenticationLimits {
+    fn default() -> Self {
+        Self { max_failed_attempts: 3, freeze_duration_secs: 300 }
+    }
+}
+
+
+/// Verifies the accounts against a [CredentialStore], keeping track of failed attempts -- shared by all sessions
+pub struct Authenticator {
+    credentials:     CredentialStore,
+    limits:          AuthenticationLimits,
+    failed_attempts: Mutex<HashMap<AccountToken, FailedAttempts>>,
+}
+
+#[derive(Debug, Default)]
+struct FailedAttempts {
+    count:        u32,
+    frozen_until: Option<Instant>,
+}
+
+impl Authenticator {
+
+    pub fn new(credentials: CredentialStore, limits: AuthenticationLimits) -> Self {
+        Self { credentials, limits, failed_attempts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Checks `account_token` may attempt to authenticate -- that it is known, enabled and not frozen
+    pub fn identify(&self, account_token: &str, now: Instant) -> Result<(), DisconnectionReason> {
+        let Some(credentials) = self.credentials.account(account_token) else {
+            return Err(DisconnectionReason::UnknownAccount);
+        };
+        if let Some(reason) = &credentials.disabled {
+            return Err(DisconnectionReason::AccountDisabled { message: reason.clone() });
+        }
+        let mut failed_attempts = self.failed_attempts.lock();
+        let Some(attempts) = failed_attempts.get_mut(account_token) else {
+            return Ok(());
+        };
+        match attempts.frozen_until {
+            Some(frozen_until) if frozen_until > now => Err(DisconnectionReason::AccountFrozen {
+                message:                   format!("too many failed authorizations -- the account is frozen for {}s", self.limits.freeze_duration_secs),
+                // saturated at ~49 days
+                remaining_duration_millis: (frozen_until - now).as_millis().min(u32::MAX as u128) as u32,
+            }),
+            Some(_expired) => {
+                attempts.frozen_until = None;
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Checks `secret` authenticates `account_token` -- counting failures and freezing the account when there are too many in a row
+    pub fn authorize(&self, account_token: &str, secret: &str, now: Instant) -> Result<(), DisconnectionReason> {
+        self.identify(account_token, now)?;
+        let expected_secret = self.credentials.secret(account_token).expect("`identify()` accepted an unknown account");
+        let mut failed_attempts = self.failed_attempts.lock();
+        if constant_time_eq(expected_secret.as_bytes(), secret.as_bytes()) {
+            failed_attempts.remove(account_token);
+            return Ok(());
+        }
+        let attempts = failed_attempts.entry(account_token.to_string()).or_default();
+        attempts.count += 1;
+        warn!("Authenticator: failed authorization #{} (out of {}) for account '{account_token}'", attempts.count, self.limits.max_failed_attempts);
+        if attempts.count >= self.limits.max_failed_attempts {
+            attempts.count = 0;
+            attempts.frozen_until = Some(now + Duration::from_secs(self.limits.freeze_duration_secs));
+            drop(failed_attempts);
+            return self.identify(account_token, now);
+        }
+        Err(DisconnectionReason::AuthenticationFailure)
+    }
+}
+
+
+/// Compares secrets in a time independent of where they differ -- so it can't be used to guess them
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks the JSON representation of the credentials
+    #[test]
+    fn credentials_from_json() {
+        let credentials: CredentialStore = serde_json::from_str(r#"{"acnt_tkn": {"secret": "s3cr3t"}, "disabled_tkn": {"secret": "s3cr3t", "disabled": "abuses"}}"#)
+            .expect("parsing the credentials");
+        assert_eq!(credentials, CredentialStore::default()
+                                    .with_account("acnt_tkn", "s3cr3t")
+                                    .with_account("disabled_tkn", "s3cr3t")
+                                    .with_disabled_account("disabled_tkn", "abuses"),
+                   "Wrong credentials parsed");
+    }
+
+    /// checks unknown, disabled and frozen accounts are refused with the right reasons -- and that freezes expire
+    #[test]
+    fn retry_limits_and_freezes() {
+        let credentials = CredentialStore::default()
+            .with_account("acnt_tkn", "s3cr3t")
+            .with_account("disabled_tkn", "s3cr3t")
+            .with_disabled_account("disabled_tkn", "abuses");
+        let authenticator = Authenticator::new(credentials, AuthenticationLimits { max_failed_attempts: 3, freeze_duration_secs: 2 });
+        let now = Instant::now();
+
+        assert_eq!(authenticator.identify("unknown_tkn", now), Err(DisconnectionReason::UnknownAccount), "Unknown accounts should be refused");
+        assert_eq!(authenticator.identify("disabled_tkn", now), Err(DisconnectionReason::AccountDisabled { message: String::from("abuses") }), "Disabled accounts should be refused");
+        assert_eq!(authenticator.authorize("acnt_tkn", "s3cr3t", now), Ok(()), "The right secret should authenticate");
+
+        assert_eq!(authenticator.authorize("acnt_tkn", "guess1", now), Err(DisconnectionReason::AuthenticationFailure), "Wrong secrets should be refused");
+        assert_eq!(authenticator.authorize("acnt_tkn", "s3cr3t", now), Ok(()), "Failures below the limit should not prevent authenticating");
+        assert_eq!(authenticator.authorize("acnt_tkn", "guess2", now), Err(DisconnectionReason::AuthenticationFailure), "Successes should reset the failures count");
+        assert_eq!(authenticator.authorize("acnt_tkn", "guess3", now), Err(DisconnectionReason::AuthenticationFailure), "Wrong secrets should be refused");
+        assert!(matches!(authenticator.authorize("acnt_tkn", "guess4", now), Err(DisconnectionReason::AccountFrozen { remaining_duration_millis: 2_000, .. })),
+                "Hitting the failures limit should freeze the account");
+        assert!(matches!(authenticator.authorize("acnt_tkn", "s3cr3t", now + Duration::from_secs(1)), Err(DisconnectionReason::AccountFrozen { remaining_duration_millis: 1_000, .. })),
+                "Frozen accounts should be refused, even with the right secret");
+        assert_eq!(authenticator.authorize("acnt_tkn", "s3cr3t", now + Duration::from_secs(2)), Ok(()), "Freezes should expire");
+
+        // the default freeze lasts for minutes -- way beyond what fits in `u32` nanoseconds
+        let authenticator = Authenticator::new(CredentialStore::default().with_account("acnt_tkn", "s3cr3t"), AuthenticationLimits::default());
+        for _ in 1..AuthenticationLimits::default().max_failed_attempts {
+            _ = authenticator.authorize("acnt_tkn", "guess", now);
+        }
+        assert!(matches!(authenticator.authorize("acnt_tkn", "guess", now), Err(DisconnectionReason::AccountFrozen { remaining_duration_millis: 300_000, .. })),
+                "Long freezes should be reported in full");
+        assert!(matches!(authenticator.authorize("acnt_tkn", "s3cr3t", now + Duration::from_secs(299)), Err(DisconnectionReason::AccountFrozen { remaining_duration_millis: 1_000, .. })),
+                "Long freezes should be reported in full, until they expire");
+    }
+}
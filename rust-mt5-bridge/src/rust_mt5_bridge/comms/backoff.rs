@@ -0,0 +1,86 @@
+//! Exponential backoff with jitter, for the reconnections to the `OgreExchange` -- see [RetryPolicy].
+
+use super::super::{
+    config::RetryPolicy,
+    xorshift::XorShift,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+/// Tells how long to wait before each attempt to (re)connect -- see [RetryPolicy]
+#[derive(Debug)]
+pub struct Backoff {
+    policy:       RetryPolicy,
+    /// failed attempts since the last [Self::reset()]
+    attempts:     u32,
+    /// for the jitter
+    random:       XorShift,
+}
+
+impl Backoff {
+
+    pub fn new(policy: RetryPolicy) -> Self {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since_epoch| since_epoch.as_nanos() as u64);
+        Self::with_seed(policy, seed)
+    }
+
+    /// Like [Self::new()], but with a deterministic jitter
+    pub fn with_seed(policy: RetryPolicy, seed: u64) -> Self {
+        Self { policy, attempts: 0, random: XorShift::new(seed) }
+    }
+
+    /// Accounts for a failed attempt, returning how long to wait before the next one -- or `None` if
+    /// [RetryPolicy::max_attempts] were already made
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.policy.max_attempts.is_some_and(|max_attempts| self.attempts >= max_attempts) {
+            return None;
+        }
+        let max_backoff_millis = self.policy.max_backoff_millis as f64;
+        let backoff_millis = (self.policy.initial_backoff_millis as f64 * self.policy.backoff_multiplier.powi(self.attempts.min(i32::MAX as u32) as i32))
+            .min(max_backoff_millis);
+        // uniform in [-1, 1)
+        let random = (self.random.next_u64() >> 11) as f64 / (1u64 << 52) as f64 - 1.0;
+        let jittered_millis = (backoff_millis * (1.0 + self.policy.jitter * random)).clamp(0.0, max_backoff_millis);
+        self.attempts += 1;
+        Some(Duration::from_millis(jittered_millis.round() as u64))
+    }
+
+    /// Failed attempts since the last [Self::reset()]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// To be called after a successful attempt -- so the next failure waits only the initial backoff
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks waits grow exponentially up to the maximum -- giving up after the configured attempts & starting over when reset
+    #[test]
+    fn exponential_growth() {
+        let mut backoff = Backoff::with_seed(RetryPolicy { initial_backoff_millis: 100, max_backoff_millis: 1_000, backoff_multiplier: 3.0, jitter: 0.0, max_attempts: Some(5) }, 42);
+        let delays = (0..6).map(|_| backoff.next_delay().map(|delay| delay.as_millis())).collect::<Vec<_>>();
+        assert_eq!(delays, vec![Some(100), Some(300), Some(900), Some(1_000), Some(1_000), None], "Wrong backoff progression");
+        assert_eq!(backoff.attempts(), 5, "Attempts beyond the maximum shouldn't be counted");
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)), "Resetting should start over from the initial backoff");
+    }
+
+    /// checks the jitter stays within the configured fraction -- and actually varies the waits
+    #[test]
+    fn jitter() {
+        let policy = RetryPolicy { initial_backoff_millis: 1_000, max_backoff_millis: 60_000, backoff_multiplier: 1.0, jitter: 0.25, max_attempts: None };
+        let mut backoff = Backoff::with_seed(policy, 7);
+        let delays = (0..1_000).map(|_| backoff.next_delay().expect("retrying forever").as_millis()).collect::<Vec<_>>();
+        assert!(delays.iter().all(|delay| (750..=1_250).contains(delay)), "Jittered waits should be within 25% of the backoff");
+        let (min, max) = (delays.iter().min().unwrap(), delays.iter().max().unwrap());
+        assert!(*min < 800 && *max > 1_200, "The jitter should span its whole range -- waits ranged from {min}ms to {max}ms");
+    }
+}
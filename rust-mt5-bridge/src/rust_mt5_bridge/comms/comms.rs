@@ -1,98 +1,323 @@
-//! Some methods to control our internal server...
+//! Some methods to control our internal server... started & stopped by `DllMain()`
 
+use super::super::{
+    rust_mt5_bridge,
+    types::FatalErrorKind,
+    config::{self, CommsConfig, CommsMode},
+    mql5_commands::Mql5Command,
+};
 use super::{
+    authenticator::Authenticator,
+    backoff::Backoff,
     external_connector_processor::ServerProtocolProcessor,
-    messages_model::OgreExchangeMessagesForExternalConnectors,
+    heartbeat::LatencyStats,
+    messages_model::{ExternalConnectorMessages, OgreExchangeMessagesForExternalConnectors},
     runtime::Runtime,
+    wire_format::WireMessage,
 };
 use std::{
     thread::{self, JoinHandle},
     sync::Arc,
-    future::{self, Future},
-    time::Duration,
+    pin::pin,
+    time::{Duration, Instant},
 };
 use std::pin::Pin;
-use reactive_messaging::prelude::ProcessorRemoteStreamType;
-use futures::TryFutureExt;
+use reactive_messaging::prelude::{ConnectionEvent, Peer, ProcessorRemoteStreamType};
+use futures::{TryFutureExt, future::{self, Either}};
 use once_cell::sync::Lazy;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, watch};
 use log::{debug,warn,error};
-use reactive_messaging::SocketClient;
+use reactive_messaging::{SocketClient, SocketServer};
 
 
-const SERVER_IP: &str = "127.0.0.1";
-const PORT:      u16  = 9758;
+/// Starts the communications with the `OgreExchange`, as set in `config` -- either connecting to it or waiting for its
+/// connection, depending on [CommsMode] -- on a dedicated Tokio runtime, started after `config.startup_delay_millis`.\
+/// They run until [shutdown_external_connector_server()] is called
+pub fn start_external_connector_server(config: &CommsConfig) {
+    if !config.enabled {
+        warn!("ExternalConnector: communications are disabled by the configuration -- not starting them");
+        return;
+    }
+    let mut runtime = RUNTIME.blocking_write();
+    if runtime.runner.is_some() {
+        warn!("ExternalConnector: communications were already started -- ignoring the request to start them again");
+        return;
+    }
+    let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+    runtime.shutdown_sender = Some(shutdown_sender);
+    runtime.runner = Some(start_tokio(config.clone(), shutdown_receiver));
+}
 
+/// Stops the communications started by [start_external_connector_server()]: peers are told `GoodBye` -- after their queued
+/// messages are sent -- the socket is closed (releasing the port) and the Tokio runtime is stopped.\
+/// Takes, at most, `shutdown_timeout_millis` (see [CommsConfig]) -- after which whatever is left is abandoned: this is called
+/// by `DllMain()`, under the Windows loader lock, where waiting on other threads is prone to deadlocks
+pub fn shutdown_external_connector_server() {
+    let comms = &config::get().comms;
+    let (shutdown_sender, runner) = {
+        let mut runtime = RUNTIME.blocking_write();
+        (runtime.shutdown_sender.take(), runtime.runner.take())
+    };
+    let Some(runner) = runner else {
+        debug!("ExternalConnector: communications were not started -- nothing to shut down");
+        return;
+    };
+    warn!("ExternalConnector: shutting down communications with {}:{} ({:?} mode) -- within {}ms", comms.host, comms.port, comms.mode, comms.shutdown_timeout_millis);
+    if let Some(shutdown_sender) = shutdown_sender {
+        _ = shutdown_sender.send(true);
+    }
+    let deadline = Instant::now() + Duration::from_millis(comms.shutdown_timeout_millis);
+    while !runner.is_finished() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+    }
+    if runner.is_finished() {
+        if runner.join().is_err() {
+            error!("ExternalConnector: the communications' thread panicked while shutting down");
+        }
+        warn!("ExternalConnector: communications are shut down");
+    } else {
+        error!("ExternalConnector: communications didn't shut down within {}ms -- abandoning them", comms.shutdown_timeout_millis);
+    }
+}
 
-pub fn start_external_connector_server() {
-    start_tokio(async_main);
+/// The latency statistics of the sessions bound to `symbol`, along with their peer ids -- none if the communications are not up
+pub fn latency_stats(symbol: &str) -> Vec<(u32, LatencyStats)> {
+    RUNTIME.blocking_read().processor.as_ref()
+        .map_or_else(Vec::new, |processor| processor.latency_stats(symbol))
+}
 
-    async fn fallible_async_main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-        let server_processor_ref1 = Arc::new(ServerProtocolProcessor::new());
-        let server_processor_ref2 = Arc::clone(&server_processor_ref1);
+/// Tells if the communications are up -- started by [start_external_connector_server()] & not yet shut down
+#[cfg(test)]
+pub(crate) fn is_running() -> bool {
+    RUNTIME.blocking_read().processor.is_some()
+}
 
-        warn!("ExternalConnector: starting as a server listening at {SERVER_IP}:{PORT}");
-warn!("################ 4) is RUNTIME's Mutex unlocked? {}!", RUNTIME.try_write().is_ok());
 
-        let socket_server = SocketClient::spawn_responsive_processor(SERVER_IP.to_string(), PORT,
-            move |connection_events| {
-                server_processor_ref1.server_events_callback(connection_events);
-                future::ready(())
+// the Runtime with state information for our services
+static RUNTIME: Lazy<RwLock<Runtime>> = Lazy::new(|| RwLock::new(Runtime {
+    runner:          None,
+    shutdown_sender: None,
+    processor:       None,
+    socket_client:   None,
+    socket_server:   None,
+}));
+
+/// code taken from the kickass-app-template: runs `async_main()` in a new thread, dedicated to the Tokio runtime
+fn start_tokio(config: CommsConfig, shutdown_receiver: watch::Receiver<bool>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        debug!("  about to start the Tokio runtime with all available CPUs as worker threads...");
+        let tokio_runtime = match tokio::runtime::Builder::new_multi_thread()
+            .thread_stack_size(2 * 1024 * 1024)
+            .enable_all()
+            .build() {
+            Ok(tokio_runtime) => tokio_runtime,
+            Err(err) => {
+                rust_mt5_bridge::set_fatal_error(-1, FatalErrorKind::ConnectivityLost, FatalErrorKind::ConnectivityLost.default_severity(),
+                                                 format!("ExternalConnector: couldn't start the Tokio runtime: {err}"));
+                return;
             },
-            move |client_addr, port, peer, client_messages_stream: ProcessorRemoteStreamType<OgreExchangeMessagesForExternalConnectors>| {
-                warn!("another ExternalConnector processor started!");
-                server_processor_ref2.dialog_processor(client_addr, port, peer, client_messages_stream)
+        };
+        // the socket gets half the timeout to flush its messages (see `stop()`) -- the runtime, the other half
+        let half_shutdown_timeout = Duration::from_millis(config.shutdown_timeout_millis / 2);
+        tokio_runtime.block_on(async_main(config, shutdown_receiver));
+        tokio_runtime.shutdown_timeout(half_shutdown_timeout);
+    })
+}
+
+/// Runs the communications until `shutdown_receiver` says so -- supervising them: if the connection can't be established
+/// or is lost, reconnections are attempted as set by the [RetryPolicy](config::RetryPolicy), with the MQL Programs being
+/// kept informed through `Comment()`s. Only when giving up they are told to quit, with a [FatalErrorKind::ConnectivityLost]
+async fn async_main(config: CommsConfig, mut shutdown_receiver: watch::Receiver<bool>) {
+    async fn shutdown_requested(shutdown_receiver: &mut watch::Receiver<bool>) {
+        // `Err` means the sender is gone -- also a reason to stop
+        while !*shutdown_receiver.borrow() {
+            if shutdown_receiver.changed().await.is_err() {
+                break;
             }
-        ).await?;
-        RUNTIME.write().await.socket_server.replace(socket_server);
-        warn!("################ socket_server is in RUNTIME -- is the mutex unlocked for writing? {}", RUNTIME.try_write().is_ok());
-warn!("################ socket_server RETURNED from starting");
-
-        // TODO tokio never ends in this DLL
-        loop {
-            tokio::time::sleep(Duration::from_secs(60)).await;
         }
     }
-
-    async fn async_main() {
-        if let Err(err) = fallible_async_main().await {
-            error!("ExternalConnector Server exited with error: {}", err);
+    if tokio::time::timeout(Duration::from_millis(config.startup_delay_millis), shutdown_requested(&mut shutdown_receiver)).await.is_ok() {
+        warn!("ExternalConnector: shut down before the startup delay elapsed -- the communications were never started");
+        return;
+    }
+    let processor = match config.mode {
+        // only a single peer -- the `OgreExchange` -- so new connections carry on the lost ones
+        CommsMode::Client => ServerProtocolProcessor::new(config.credentials.clone(), config.heartbeat.clone()).with_session_resumption(),
+        // anyone may connect: peers must prove they are the `OgreExchange`
+        CommsMode::Server => ServerProtocolProcessor::new(config.credentials.clone(), config.heartbeat.clone())
+            .with_peer_authentication(Authenticator::new(config.credentials.clone(), config.authentication.clone())),
+    }.with_wire_format(config.wire_format);
+    let processor = Arc::new(processor);
+    let mut backoff = Backoff::new(config.retry.clone());
+    loop {
+        match start(&config, &processor).await {
+            Ok(()) => {
+                if backoff.attempts() > 0 {
+                    notify_mql_programs(format!("OgreExchange: communications with {}:{} are back", config.host, config.port));
+                }
+                backoff.reset();
+                rust_mt5_bridge::clear_global_fatal_error(FatalErrorKind::ConnectivityLost);
+                // servers keep listening, with peers coming & going by themselves: only clients need to reconnect
+                let connection_lost = async {
+                    match config.mode {
+                        CommsMode::Client => processor.peer_disconnected().await,
+                        CommsMode::Server => future::pending().await,
+                    }
+                };
+                let shutdown = shutdown_requested(&mut shutdown_receiver);
+                if let Either::Right(_) = future::select(pin!(connection_lost), pin!(shutdown)).await {
+                    break;
+                }
+                error!("ExternalConnector: the connection to {}:{} was lost", config.host, config.port);
+                release_socket().await;
+            },
+            Err(err) => error!("ExternalConnector: couldn't start the communications with {}:{}: {err}", config.host, config.port),
+        }
+        let Some(delay) = backoff.next_delay() else {
+            let message = format!("ExternalConnector: giving up on the communications with {}:{} after {} failed attempts", config.host, config.port, backoff.attempts());
+            notify_mql_programs(format!("OgreExchange: {message}"));
+            rust_mt5_bridge::set_fatal_error(-1, FatalErrorKind::ConnectivityLost, FatalErrorKind::ConnectivityLost.default_severity(), message);
+            shutdown_requested(&mut shutdown_receiver).await;
+            break;
+        };
+        warn!("ExternalConnector: retrying in {delay:?} -- attempt #{}", backoff.attempts());
+        notify_mql_programs(format!("OgreExchange: no communications with {}:{} -- retrying in {}s (attempt #{})", config.host, config.port, delay.as_secs(), backoff.attempts()));
+        if tokio::time::timeout(delay, shutdown_requested(&mut shutdown_receiver)).await.is_ok() {
+            break;
         }
     }
+    stop(config.shutdown_timeout_millis / 2).await;
 }
 
-pub fn shutdown_external_connector_server() {
-    warn!("ExternalConnector: shutting down server at {SERVER_IP}:{PORT}");
-    // RUNTIME.blocking_write().socket_server.take().expect("SocketServer seems to not have been started")
-    //     .unpin().shutdown().expect("FAILED TO SHUTDOWN");
-    std::thread::sleep(Duration::from_secs(1));
-    warn!("ExternalConnector: shutdown signal sent");
+/// Connects to the `OgreExchange` -- or starts listening for its connections -- registering everything in [RUNTIME]
+async fn start(config: &CommsConfig, processor: &Arc<ServerProtocolProcessor>) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let server_processor_ref1 = Arc::clone(processor);
+    let server_processor_ref2 = Arc::clone(processor);
+    let connection_events_callback = move |connection_events: ConnectionEvent<WireMessage<ExternalConnectorMessages>>| {
+        server_processor_ref1.server_events_callback(connection_events);
+        future::ready(())
+    };
+    let dialog_processor_builder = move |client_addr: String, port: u16, peer: Arc<Peer<WireMessage<ExternalConnectorMessages>>>, client_messages_stream: ProcessorRemoteStreamType<OgreExchangeMessagesForExternalConnectors>| {
+        warn!("another ExternalConnector processor started!");
+        server_processor_ref2.dialog_processor(client_addr, port, peer, client_messages_stream)
+    };
+
+    match config.mode {
+        CommsMode::Client => {
+            warn!("ExternalConnector: starting as a client, connecting to {}:{}", config.host, config.port);
+            let socket_client = SocketClient::spawn_responsive_processor(config.host.clone(), config.port, connection_events_callback, dialog_processor_builder).await?;
+            RUNTIME.write().await.socket_client.replace(socket_client);
+        },
+        CommsMode::Server => {
+            warn!("ExternalConnector: starting as a server listening at {}:{}", config.host, config.port);
+            let mut socket_server = SocketServer::new(config.host.clone(), config.port);
+            socket_server.spawn_responsive_processor(connection_events_callback, dialog_processor_builder).await?;
+            RUNTIME.write().await.socket_server.replace(socket_server);
+        },
+    }
+    RUNTIME.write().await.processor.replace(Arc::clone(processor));
+    Ok(())
 }
 
-// the Runtime with state information for our services
-const RUNTIME: Lazy<RwLock<Runtime>> = Lazy::new(|| RwLock::new(Runtime {
-    tokio_runtime: None,
-    socket_server: None,
+/// Closes the socket whose connection was lost -- so a new one may be started
+async fn release_socket() {
+    let (socket_client, socket_server) = {
+        let mut runtime = RUNTIME.write().await;
+        (runtime.socket_client.take(), runtime.socket_server.take())
+    };
+    let result = match (socket_client, socket_server) {
+        (Some(socket_client), _) => socket_client.shutdown(),
+        (_, Some(socket_server)) => socket_server.shutdown(),
+        (None, None) => Ok(()),
+    };
+    if let Err(err) = result {
+        debug!("ExternalConnector: error releasing the socket whose connection was lost: {err}");
+    }
+}
 
-}));
+/// Shows `message` on the charts of all MQL Programs -- see [Mql5Command::Comment]
+fn notify_mql_programs(message: String) {
+    for handle in rust_mt5_bridge::HANDLES.live_handle_ids().into_iter().filter_map(|handle_id| rust_mt5_bridge::HANDLES.get(handle_id)) {
+        handle.mql5_calls.schedule(Mql5Command::Comment(message.clone()));
+    }
+}
 
-/// code taken from the kickass-app-template
-fn start_tokio<AsyncMainFutureType: Future<Output=()>>
-              (async_main: impl Fn() -> AsyncMainFutureType + Send + 'static) -> JoinHandle<()> {
-    thread::spawn(move || {
-        std::thread::sleep(Duration::from_secs(5));
-        debug!("  about to start the Tokio runtime with all available CPUs as worker threads...");
-        let mut tokio_runner = tokio::runtime::Builder::new_multi_thread();
-        let tokio_runtime = Arc::new(tokio_runner
-            .thread_stack_size(2 * 1024 * 1024)
-            .enable_all()
-            .build()
-            .unwrap());
-warn!("################ 1) is RUNTIME's Mutex unlocked? {}!", RUNTIME.try_write().is_ok());
-warn!("################ 2) is RUNTIME's Mutex unlocked? {}!", RUNTIME.try_write().is_ok());
-        RUNTIME.blocking_write().tokio_runtime = Some(Arc::clone(&tokio_runtime));
-warn!("################ 3) is RUNTIME's Mutex unlocked? {}!", RUNTIME.try_write().is_ok());
-        tokio_runtime
-            .block_on(async_main())
-    })
+/// Says `GoodBye` to the peers and closes the socket -- waiting, at most, `timeout_millis` for the peers to be disconnected
+/// after their queued messages are sent.\
+/// NOTE: `reactive-messaging`'s `shutdown()` takes no timeout (it uses its own, of 5s), so ours is enforced here
+async fn stop(timeout_millis: u64) {
+    let (processor, socket_client, socket_server) = {
+        let mut runtime = RUNTIME.write().await;
+        (runtime.processor.take(), runtime.socket_client.take(), runtime.socket_server.take())
+    };
+    if let Some(processor) = &processor {
+        processor.shutdown();
+    }
+    let result = match (socket_client, socket_server) {
+        (Some(socket_client), _) => socket_client.shutdown(),
+        (_, Some(socket_server)) => socket_server.shutdown(),
+        (None, None) => Ok(()),
+    };
+    if let Err(err) = result {
+        error!("ExternalConnector: error shutting down the socket: {err}");
+    }
+    if let Some(processor) = processor {
+        if tokio::time::timeout(Duration::from_millis(timeout_millis), processor.all_peers_disconnected()).await.is_err() {
+            warn!("ExternalConnector: peers were still connected {timeout_millis}ms after the shutdown -- abandoning them");
+        }
+    }
+}
+
+
+/// Serializes the tests that start the communications -- they share [RUNTIME]
+#[cfg(test)]
+pub(crate) static COMMS_TESTS: parking_lot::Mutex<()> = parking_lot::const_mutex(());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::config::RetryPolicy;
+    use std::net::{TcpListener, TcpStream};
+
+
+    /// checks the connection is retried until the `OgreExchange` is up -- and reestablished, after the lost one is released
+    #[test]
+    fn reconnection() {
+        let _serial = COMMS_TESTS.lock();
+        let port = TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).expect("finding a free port").port();
+        let config = CommsConfig {
+            mode:                    CommsMode::Client,
+            port,
+            startup_delay_millis:    0,
+            shutdown_timeout_millis: 200,
+            retry:                   RetryPolicy { initial_backoff_millis: 10, max_backoff_millis: 50, backoff_multiplier: 2.0, jitter: 0.0, max_attempts: Some(100) },
+            ..CommsConfig::default()
+        };
+        let (shutdown_sender, shutdown_receiver) = watch::channel(false);
+        let comms = thread::spawn(move || tokio::runtime::Builder::new_multi_thread().enable_all().build().expect("building the Tokio runtime")
+            .block_on(async_main(config, shutdown_receiver)));
+
+        // nobody listening, for a while: the attempts are refused
+        thread::sleep(Duration::from_millis(100));
+        let listener = TcpListener::bind(("127.0.0.1", port)).expect("listening at the configured port");
+        listener.set_nonblocking(true).expect("setting the listener as non blocking");
+        let accept = || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                match listener.accept() {
+                    Ok((connection, _)) => break Some(connection),
+                    Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                    Err(_) => break None,
+                }
+            }
+        };
+        let connection: TcpStream = accept().expect("the connection should have been retried until the server was up");
+        thread::sleep(Duration::from_millis(100));
+        drop(connection);
+        let _connection = accept().expect("the lost connection should have been reestablished");
+
+        shutdown_sender.send(true).expect("signaling the shutdown");
+        comms.join().expect("the communications shouldn't panic");
+        assert!(!is_running(), "The communications should have been stopped");
+    }
 }
@@ -1,111 +1,1323 @@
 //! Handles the communications between the "External Connector" and the `OgreExchange` Server, for the External Connector's side
+//!
+//! Orders scheduled by the `OgreExchange` are routed to the terminal through the MQL call queue (see `mql5_commands.rs`)
+//! -- their outcomes arrive later, when MQL reports the results of `OrderSend()`, and are then sent to the peer
+//! (outside of the request / answer flow) as [ExternalConnectorMessages::ExecutedOrder], [ExternalConnectorMessages::PendingOrder]
+//! or [ExternalConnectorMessages::CancelledOrder].
+//!
+//! Sessions start unbound: the server binds each of them to the MQL Program trading a given symbol (and, optionally,
+//! account) with [OgreExchangeMessagesForExternalConnectors::BindSession] -- any number of sessions may be bound to the
+//! same symbol. Bindings are by symbol, rather than by `handle_id`, so MQL Programs may come and go (being re-registered
+//! with new `handle_id`s) without affecting the sessions bound to them.
+//!
+//! Sessions are kept alive by a heartbeat, measuring round trip times & clock skews -- they are dropped if any of the
+//! configured [HeartbeatLimits] is exceeded. See `heartbeat.rs`.
+//!
+//! Sessions go through the authentication handshake of `authenticator.rs` -- see [AuthenticationState]: until authorized,
+//! they may only be bound while not yet identified, and asking for the open positions or scheduling & cancelling orders is
+//! refused. When asked for authorization, the secret configured for the account of the bound MQL Program is sent -- when
+//! listening for connections, only after the peer proves itself with that same secret (see
+//! [ServerProtocolProcessor::with_peer_authentication()]).
+//!
+//! Peers also receive the market data for the symbol of their session -- see `market_data_publisher.rs`.
+//!
+//! When connecting to the `OgreExchange` (rather than being connected to), sessions may be resumed after reconnections
+//! -- see [ServerProtocolProcessor::with_session_resumption()].
+//!
+//! Upon [ServerProtocolProcessor::shutdown()], the market data queued for each peer is flushed before it is told `GoodBye`.
+//!
+//! The protocol version & capabilities are negotiated upon `Welcome` -- see `negotiation.rs`.
+//!
+//! Messages are sent in the [WireFormat] of each session -- switched by the server with
+//! [OgreExchangeMessagesForExternalConnectors::UseWireFormat]. See `wire_format.rs`.
 
 use super::super::{
-    types::Handle,
-    comms::messages_model::{ExternalConnectorMessages, OgreExchangeMessagesForExternalConnectors, PROTOCOL_VERSION},
-    ogre_exchange_models::ConnectorIdentification,
+    types::*,
+    comms::{
+        messages_model::{ExternalConnectorMessages, OgreExchangeMessagesForExternalConnectors, ConnectorIdentificationOrderCancellationReasons, PROTOCOL_VERSION},
+        market_data_publisher::{self, MARKET_DATA_QUEUE_CAPACITY, date_and_time, to_mills},
+        authenticator::{Authenticator, CredentialStore},
+        heartbeat::{Heartbeat, HeartbeatLimits, LatencyStats},
+        wire_format::{WireFormat, WireMessage},
+        negotiation,
+    },
+    ogre_exchange_models::{AccountToken, Capabilities, ConnectorIdentification, DisconnectionReason, OrderCommand, Order, OrderTypes, Parties},
+    mql5_commands::{Mql5Command, Mql5CommandResult, Mql5CallError, OrderRequest},
+    portfolio::PositionDirection,
     rust_mt5_bridge,
 };
 use std::{
-    cell::UnsafeCell,
-    sync::{
-        Arc,
-        atomic::Ordering::Relaxed,
-    },
+    future,
+    sync::{Arc, atomic::{AtomicBool, Ordering::Relaxed}},
+    time::{Duration, Instant, SystemTime},
 };
 use reactive_messaging::prelude::{ConnectionEvent,Peer,ProcessorRemoteStreamType};
+use reactive_mutiny::types::ChannelProducer;
 use dashmap::DashMap;
-use futures::{Stream,StreamExt};
-use log::{debug,info,warn};
+use parking_lot::Mutex;
+use futures::{stream,Stream,StreamExt};
+use chrono::{Local, Timelike, Utc};
+use log::{debug,info,warn,error};
+
+
+/// How often the heartbeat of each session is checked -- see [heartbeat_stream()]
+const HEARTBEAT_RESOLUTION: Duration = Duration::from_millis(100);
+/// How long orders placed by the `OgreExchange` are tracked without being seen by the Order Manager -- after which they are
+/// taken as finished & evicted by the day roll over (see `order_manager.rs`). See [Session::forget_finished_orders()]
+const UNSEEN_ORDERS_TIMEOUT: Duration = Duration::from_secs(60);
 
 
 /// Session for each connected peer
 struct Session {
-    symbol_handle: &'static Handle,
+    peer_id:            u32,
+    /// the secrets to answer [OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue] with
+    credentials:        Arc<CredentialStore>,
+    /// verifies the peer's [OgreExchangeMessagesForExternalConnectors::ServerAuthorization] -- `None` if the peer is
+    /// trusted with our secrets. See [ServerProtocolProcessor::with_peer_authentication()]
+    peer_authenticator: Option<Arc<Authenticator>>,
+    /// where this session is in the authentication handshake
+    authentication:     Mutex<AuthenticationState>,
+    /// the MQL Program this session is bound to -- `None` until bound (see the [module](self) docs)
+    binding:            Mutex<Option<SessionBinding>>,
+    /// Exchange tickets (and when they were placed) of the pending orders scheduled by the `OgreExchange`, by their `ogre_id`
+    /// -- kept while they may be cancelled. See [Self::forget_finished_orders()]
+    ogre_orders:        DashMap<u32, (u64, Instant)>,
+    heartbeat:          Mutex<Heartbeat>,
+    /// set when the peer disconnects -- ending the session's streams
+    disconnected:       AtomicBool,
+    /// set for sessions resuming the binding of a lost one -- whose state is then replayed once authorized
+    resumed:            AtomicBool,
+    /// the format messages are sent to the peer in
+    wire_format:        Mutex<WireFormat>,
+    /// what was agreed upon with the server -- all we support, until it tells what it supports in its `Welcome`
+    capabilities:       Mutex<Capabilities>,
+    /// Sends messages to the peer outside of the request / answer flow -- like the outcomes of scheduled orders. See [Self::send()]
+    send_to_peer:       Box<dyn Fn(WireMessage<ExternalConnectorMessages>) + Send + Sync>,
+}
+
+/// The authentication states of a [Session], in the order they are reached -- see `authenticator.rs`
+#[derive(Debug, Clone, PartialEq)]
+enum AuthenticationState {
+    /// waiting for the server's `Welcome`
+    Connected,
+    /// the server was welcomed, but we couldn't identify ourselves -- the session is not bound to a registered MQL Program
+    Welcomed,
+    /// we identified ourselves as an MQL Program trading for `account_token`
+    Identified { account_token: AccountToken },
+    /// the authentication completed for `account_token`: as the "External Connector", once our secret is sent (the server
+    /// drops us if it is wrong) -- or, when the peer must prove itself, once it does. The session may only be bound to
+    /// MQL Programs of that account
+    Authorized { account_token: AccountToken },
+}
+
+struct SessionBinding {
+    symbol:        String,
+    account_token: Option<String>,
+    /// the last resolved handle -- revalidated on every use, as its MQL Program might have been unregistered since
+    handle:        Option<Arc<Handle>>,
+}
+
+impl Session {
+
+    fn new(peer_id: u32, credentials: Arc<CredentialStore>, heartbeat_limits: HeartbeatLimits, send_to_peer: Box<dyn Fn(WireMessage<ExternalConnectorMessages>) + Send + Sync>) -> Self {
+        Self {
+            peer_id,
+            credentials,
+            peer_authenticator: None,
+            authentication:     Mutex::new(AuthenticationState::Connected),
+            binding:            Mutex::new(None),
+            ogre_orders:        DashMap::new(),
+            heartbeat:          Mutex::new(Heartbeat::new(heartbeat_limits)),
+            disconnected:       AtomicBool::new(false),
+            resumed:            AtomicBool::new(false),
+            wire_format:        Mutex::new(WireFormat::default()),
+            capabilities:       Mutex::new(negotiation::supported_capabilities()),
+            send_to_peer,
+        }
+    }
+
+    /// Has the peer prove itself, with `authenticator`, before being trusted -- see [ServerProtocolProcessor::with_peer_authentication()]
+    fn with_peer_authenticator(mut self, authenticator: Arc<Authenticator>) -> Self {
+        self.peer_authenticator = Some(authenticator);
+        self
+    }
+
+    /// Sets the [WireFormat] messages are sent in -- until the server asks for another one
+    fn with_wire_format(self, wire_format: WireFormat) -> Self {
+        *self.wire_format.lock() = wire_format;
+        self
+    }
+
+    /// Wraps `message` so it is sent in the current [WireFormat] of this session
+    fn wire_message(&self, message: ExternalConnectorMessages) -> WireMessage<ExternalConnectorMessages> {
+        WireMessage { format: *self.wire_format.lock(), message }
+    }
+
+    /// Sends `message` to the peer outside of the request / answer flow
+    fn send(&self, message: ExternalConnectorMessages) {
+        (self.send_to_peer)(self.wire_message(message))
+    }
+
+    /// (Re)binds this session -- and its market data -- to the MQL Program trading `symbol` (for `account_token`, if given),
+    /// returning its handle -- or `None` if no such MQL Program is registered yet
+    fn bind(&self, symbol: &str, account_token: Option<&str>) -> Option<Arc<Handle>> {
+        let handle = rust_mt5_bridge::find_handle(symbol, account_token);
+        self.binding.lock().replace(SessionBinding {
+            symbol:        symbol.to_string(),
+            account_token: account_token.map(str::to_string),
+            handle:        handle.clone(),
+        });
+        market_data_publisher::bind(self.peer_id, symbol);
+        handle
+    }
+
+    /// Advances the authentication upon the server's `Welcome`
+    fn on_welcome(&self) {
+        let mut authentication = self.authentication.lock();
+        if *authentication == AuthenticationState::Connected {
+            *authentication = AuthenticationState::Welcomed;
+        }
+    }
+
+    /// Advances the authentication upon identifying ourselves as the MQL Program of `handle` -- pinning the binding to
+    /// its account, so the session won't follow MQL Programs of other accounts
+    fn on_identification(&self, handle: &Handle) {
+        if let Some(binding) = self.binding.lock().as_mut() {
+            binding.account_token = Some(handle.account_token.clone());
+        }
+        let mut authentication = self.authentication.lock();
+        if matches!(*authentication, AuthenticationState::Connected | AuthenticationState::Welcomed) {
+            *authentication = AuthenticationState::Identified { account_token: handle.account_token.clone() };
+        }
+    }
+
+    /// The handle of the MQL Program this session is bound to -- provided the session is authorized for its account.
+    /// Otherwise, the reason why not
+    fn authorized_handle(&self) -> Result<Arc<Handle>, String> {
+        let authentication = self.authentication.lock().clone();
+        let AuthenticationState::Authorized { account_token } = authentication else {
+            return Err(format!("this session is not authorized -- it is {authentication:?}"));
+        };
+        match self.handle() {
+            Some(handle) if handle.account_token == account_token => Ok(handle),
+            Some(handle) => Err(format!("the bound MQL Program trades for account '{}', not for the authorized one, '{account_token}'", handle.account_token)),
+            None => Err(self.unbound_reason()),
+        }
+    }
+
+    /// The symbol this session is bound to -- if any
+    fn bound_symbol(&self) -> Option<String> {
+        self.binding.lock().as_ref().map(|binding| binding.symbol.clone())
+    }
+
+    /// The symbol this session is bound to, for logging purposes
+    fn symbol(&self) -> String {
+        self.bound_symbol().unwrap_or_else(|| String::from("<<unbound>>"))
+    }
+
+    /// The handle of the MQL Program this session is bound to -- `None` if unbound or if no such MQL Program is registered (anymore)
+    fn handle(&self) -> Option<Arc<Handle>> {
+        let mut binding = self.binding.lock();
+        let binding = binding.as_mut()?;
+        let is_live = |handle: &Arc<Handle>| rust_mt5_bridge::HANDLES.get(handle.handle_id).is_some_and(|live_handle| Arc::ptr_eq(&live_handle, handle));
+        if !binding.handle.as_ref().is_some_and(is_live) {
+            binding.handle = rust_mt5_bridge::find_handle(&binding.symbol, binding.account_token.as_deref());
+        }
+        binding.handle.clone()
+    }
+
+    /// Stops tracking the `OgreExchange` orders which reached a final state, according to the Order Manager of `handle`
+    fn forget_finished_orders(&self, handle: &Handle) {
+        let orders = handle.orders.lock();
+        self.ogre_orders.retain(|_ogre_id, (ticket, placed_at)| match orders.order(*ticket) {
+            Some(order) => !order.status.is_final(),
+            None => placed_at.elapsed() < UNSEEN_ORDERS_TIMEOUT,
+        });
+    }
+
+    /// The message dropping this session, as the peer's connection broke one of the [HeartbeatLimits] -- telling it the `reason`
+    fn drop_for(&self, reason: DisconnectionReason) -> ExternalConnectorMessages {
+        error!("ExternalConnector({}): dropping the session of peer #{}: {reason:?} -- latency stats: {:?}", self.symbol(), self.peer_id, self.heartbeat.lock().stats());
+        ExternalConnectorMessages::Disconnected(reason)
+    }
+
+    /// The answer for messages requiring a bound MQL Program, when [Self::handle()] is `None`
+    fn unbound_error(&self) -> ExternalConnectorMessages {
+        ExternalConnectorMessages::ProcessorError(self.unbound_reason())
+    }
+
+    /// Why [Self::handle()] is `None`
+    fn unbound_reason(&self) -> String {
+        match self.bound_symbol() {
+            Some(symbol) => format!("no MQL Program is registered for symbol '{symbol}' (anymore) -- try again later"),
+            None         => String::from("this session is not bound to a symbol -- `BindSession` must be sent first"),
+        }
+    }
 }
-unsafe impl Send for Session {}
-unsafe impl Sync for Session {}
 
 pub struct ServerProtocolProcessor {
-    sessions: Arc<DashMap<u32, Arc<Session>>>,
+    sessions:           Arc<DashMap<u32, Arc<Session>>>,
+    credentials:        Arc<CredentialStore>,
+    heartbeat_limits:   HeartbeatLimits,
+    /// set by [Self::shutdown()] -- making each session say `GoodBye` once its market data is flushed
+    shutting_down:      Arc<AtomicBool>,
+    /// see [Self::with_session_resumption()]
+    resume_sessions:    bool,
+    /// the binding (symbol & account) of the last session that disconnected while bound
+    last_binding:       Mutex<Option<(String, Option<String>)>>,
+    /// signaled whenever a peer disconnects -- see [Self::peer_disconnected()]
+    disconnections:     tokio::sync::Notify,
+    /// see [Self::with_wire_format()]
+    wire_format:        WireFormat,
+    /// see [Self::with_peer_authentication()]
+    peer_authenticator: Option<Arc<Authenticator>>,
 }
 
 impl ServerProtocolProcessor {
 
-    pub fn new() -> Self {
+    pub fn new(credentials: CredentialStore, heartbeat_limits: HeartbeatLimits) -> Self {
         Self {
-            sessions: Arc::new(DashMap::new()),
+            sessions:           Arc::new(DashMap::new()),
+            credentials:        Arc::new(credentials),
+            heartbeat_limits,
+            shutting_down:      Arc::new(AtomicBool::new(false)),
+            resume_sessions:    false,
+            last_binding:       Mutex::new(None),
+            disconnections:     tokio::sync::Notify::new(),
+            wire_format:        WireFormat::default(),
+            peer_authenticator: None,
         }
     }
 
-    pub fn server_events_callback(&self, connection_event: ConnectionEvent<ExternalConnectorMessages>) {
+    /// Has peers prove they are the `OgreExchange` -- with [OgreExchangeMessagesForExternalConnectors::ServerAuthorization],
+    /// verified by `authenticator` -- before trusting them with our secrets or our accounts: for when we are the ones
+    /// listening for connections, which anyone may open
+    pub fn with_peer_authentication(mut self, authenticator: Authenticator) -> Self {
+        self.peer_authenticator = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the [WireFormat] sessions start sending their messages in -- the server may switch it with
+    /// [OgreExchangeMessagesForExternalConnectors::UseWireFormat]
+    pub fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+
+    /// Has new sessions resume the binding of the last one that was lost -- for when we are the ones connecting to the
+    /// `OgreExchange`, so reconnections carry on where the broken connection stopped: the `Welcome` is then answered with
+    /// our `ConnectorIdentification` and, once authorized, the whole book of the symbol, the open positions & the pending
+    /// orders are sent along with our `UserAuthorization`
+    pub fn with_session_resumption(mut self) -> Self {
+        self.resume_sessions = true;
+        self
+    }
+
+    /// Resolves when a peer disconnects -- if one disconnected since the last call, resolves right away
+    pub async fn peer_disconnected(&self) {
+        self.disconnections.notified().await
+    }
+
+    /// Waits until no peers are connected
+    pub async fn all_peers_disconnected(&self) {
+        loop {
+            let disconnection = self.disconnections.notified();
+            if self.sessions.is_empty() {
+                return;
+            }
+            disconnection.await;
+        }
+    }
+
+    /// Ends all sessions: their heartbeats stop & their market data streams end -- after the already queued events are
+    /// sent -- followed by a `GoodBye`. Idempotent.\
+    /// Peers connecting afterwards are told `GoodBye` right away
+    pub fn shutdown(&self) {
+        if self.shutting_down.swap(true, Relaxed) {
+            return;
+        }
+        info!("ExternalConnector: shutting down {} session(s)", self.sessions.len());
+        for session in self.sessions.iter() {
+            session.disconnected.store(true, Relaxed);
+            market_data_publisher::unsubscribe(session.peer_id);
+        }
+    }
+
+    /// The latency statistics of the sessions bound to `symbol`, along with their peer ids -- see `heartbeat.rs`
+    pub fn latency_stats(&self, symbol: &str) -> Vec<(u32, LatencyStats)> {
+        self.sessions.iter()
+            .filter(|session| session.bound_symbol().is_some_and(|bound_symbol| bound_symbol == symbol))
+            .map(|session| (session.peer_id, session.heartbeat.lock().stats().clone()))
+            .collect()
+    }
+
+    pub fn server_events_callback(&self, connection_event: ConnectionEvent<WireMessage<ExternalConnectorMessages>>) {
         match connection_event {
             ConnectionEvent::PeerConnected { peer } => {
-                debug!("Connected: {:?}", peer);
-                let symbol_handle = unsafe {
-                    rust_mt5_bridge::HANDLES
-                        .get(peer.peer_id as usize % rust_mt5_bridge::HANDLE_COUNT.load(Relaxed) as usize)
-                        .expect("BUG: HANDLER with the same PEER_ID is not registered")
-                };
-                debug!("Connected: {:?} -- giving it symbol '{}'", peer, symbol_handle.symbol);
-                self.sessions.insert(peer.peer_id, Arc::new(Session { symbol_handle }));
+                let peer_id = peer.peer_id;
+                let send_to_peer = Box::new(move |message: WireMessage<ExternalConnectorMessages>| {
+                    if !peer.sender.try_send_movable(message) {
+                        warn!("Couldn't send a message to peer #{}: its sending queue is full", peer.peer_id);
+                    }
+                });
+                let session = self.new_session(peer_id, send_to_peer);
+                debug!("Connected: peer #{peer_id} -- its session is bound to {}", session.symbol());
+                self.sessions.insert(peer_id, session);
             },
             ConnectionEvent::PeerDisconnected { peer, stream_stats } => {
-                debug!("Disconnected: {:?} -- stats: {:?}", peer, stream_stats);
-                //let _ = processor_uni.try_send(|slot| *slot = ClientMessages::Quit);
-                self.sessions.remove(&peer.peer_id);
+                market_data_publisher::unsubscribe(peer.peer_id);
+                if let Some((_peer_id, session)) = self.sessions.remove(&peer.peer_id) {
+                    self.on_session_lost(&session);
+                    debug!("Disconnected: {:?} -- stats: {:?} -- latency stats: {:?}", peer, stream_stats, session.heartbeat.lock().stats());
+                }
+                self.disconnections.notify_one();
             }
             ConnectionEvent::ApplicationShutdown { timeout_ms } => {
-                info!("Ping-Pong server shutdown requested. Notifying all peers within {timeout_ms}ms...");
+                info!("ExternalConnector shutdown requested. Notifying all peers within {timeout_ms}ms...");
+                self.shutdown();
             }
         }
     }
 
-    pub fn dialog_processor(&self, _client_addr: String, _port: u16, peer: Arc<Peer<ExternalConnectorMessages>>, client_messages_stream: ProcessorRemoteStreamType<OgreExchangeMessagesForExternalConnectors>) -> impl Stream<Item=ExternalConnectorMessages> {
+    /// The session for a newly connected peer -- unbound, unless resuming the last lost one (see [Self::with_session_resumption()])
+    fn new_session(&self, peer_id: u32, send_to_peer: Box<dyn Fn(WireMessage<ExternalConnectorMessages>) + Send + Sync>) -> Arc<Session> {
+        let mut session = Session::new(peer_id, Arc::clone(&self.credentials), self.heartbeat_limits.clone(), send_to_peer)
+            .with_wire_format(self.wire_format);
+        if let Some(authenticator) = &self.peer_authenticator {
+            session = session.with_peer_authenticator(Arc::clone(authenticator));
+        }
+        let session = Arc::new(session);
+        if self.resume_sessions {
+            if let Some((symbol, account_token)) = self.last_binding.lock().clone() {
+                info!("ExternalConnector({symbol}): peer #{peer_id} resumes the last lost session -- its state will be replayed upon `Welcome`");
+                session.bind(&symbol, account_token.as_deref());
+                session.resumed.store(true, Relaxed);
+            }
+        }
+        session
+    }
+
+    /// Ends `session`, remembering its binding for the next one to resume it
+    fn on_session_lost(&self, session: &Session) {
+        session.disconnected.store(true, Relaxed);
+        if let Some(binding) = session.binding.lock().as_ref() {
+            self.last_binding.lock().replace((binding.symbol.clone(), binding.account_token.clone()));
+        }
+    }
+
+    pub fn dialog_processor(&self, _client_addr: String, _port: u16, peer: Arc<Peer<WireMessage<ExternalConnectorMessages>>>, client_messages_stream: ProcessorRemoteStreamType<OgreExchangeMessagesForExternalConnectors>) -> impl Stream<Item=WireMessage<ExternalConnectorMessages>> {
 
         let session = self.sessions.get(&peer.peer_id)
                                                .unwrap_or_else(|| panic!("Server BUG! Peer {:?} showed up, but we don't have a session for it! It should have been created by the `connection_events()` callback", peer))
                                                .value()
                                                .clone();     // .clone() the Arc, so we are free to move it to the the next closure (and drop it after the Stream closes)
 
-        client_messages_stream.map(move |client_message| {
+        let market_data_stream = market_data_publisher::subscribe(peer.peer_id, MARKET_DATA_QUEUE_CAPACITY);
+        if let Some(symbol) = session.bound_symbol() {
+            market_data_publisher::bind(peer.peer_id, &symbol);
+        }
+        if self.shutting_down.load(Relaxed) {
+            // too late: `shutdown()` already ran
+            market_data_publisher::unsubscribe(peer.peer_id);
+            session.disconnected.store(true, Relaxed);
+        }
+        let market_data_stream = flushed_then_goodbye(market_data_stream, Arc::clone(&self.shutting_down));
+        let heartbeat_stream = heartbeat_stream(Arc::clone(&session));
+        let answering_session = Arc::clone(&session);
+        // `None` marks the end of the peer's messages: the connection is gone, so the dialog ends -- the other streams
+        // wouldn't, preventing the disconnection from being reported
+        let answers_stream = client_messages_stream.flat_map(move |client_message| stream::iter(process_server_message(&answering_session, &client_message)))
+            .map(Some)
+            .chain(stream::once(future::ready(None)));
+        // the format is only resolved when sending -- so a `UseWireFormat` also applies to the already queued messages
+        stream::select(stream::select(answers_stream, market_data_stream.map(Some)), heartbeat_stream.map(Some))
+            .take_while(|message| future::ready(message.is_some()))
+            .filter_map(future::ready)
+            .map(move |message| session.wire_message(message))
+    }
+}
+
+
+/// Relays the `market_data_stream` until it ends -- then, if `shutting_down`, says `GoodBye`, so the peer only
+/// disconnects after receiving all the queued events
+fn flushed_then_goodbye(market_data_stream: impl Stream<Item=ExternalConnectorMessages>, shutting_down: Arc<AtomicBool>) -> impl Stream<Item=ExternalConnectorMessages> {
+    market_data_stream.chain(stream::once(async move {
+        shutting_down.load(Relaxed).then(|| ExternalConnectorMessages::GoodBye(String::from("External Connector is shutting down")))
+    }).filter_map(future::ready))
+}
+
+/// Sends the `KeepAliveRequest`s of `session` -- or the message dropping it, if the peer stops answering in time
+fn heartbeat_stream(session: Arc<Session>) -> impl Stream<Item=ExternalConnectorMessages> {
+    stream::unfold(Some(session), |session| async move {
+        let session = session?;
+        loop {
+            tokio::time::sleep(HEARTBEAT_RESOLUTION).await;
+            if session.disconnected.load(Relaxed) {
+                return None;
+            }
+            let tick = session.heartbeat.lock().tick(Instant::now(), utc_millis_of_day());
+            match tick {
+                Ok(Some(n)) => return Some((ExternalConnectorMessages::KeepAliveRequest(n), Some(session))),
+                Ok(None) => continue,
+                Err(reason) => return Some((session.drop_for(reason), None)),
+            }
+        }
+    })
+}
+
+/// Our clock, as sent in `KeepAliveRequest`s -- see `heartbeat.rs`
+fn utc_millis_of_day() -> u32 {
+    let now = Utc::now();
+    now.num_seconds_from_midnight() * 1000 + now.timestamp_subsec_millis().min(999)
+}
 
-            match &*client_message {
 
-                OgreExchangeMessagesForExternalConnectors::Welcome => {
-                    ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor {
-                        version: PROTOCOL_VERSION.to_string(),
-                        symbol: session.symbol_handle.symbol.clone(),
-                        account_token: session.symbol_handle.account_token.to_string(),
-                    })
+/// Returns the answers to `server_message` -- possibly none: some answers (like the outcomes of scheduled orders)
+/// are sent later, through [Session::send()]
+fn process_server_message(session: &Arc<Session>, server_message: &OgreExchangeMessagesForExternalConnectors) -> Vec<ExternalConnectorMessages> {
+    let symbol = session.symbol();
+    match server_message {
+
+        OgreExchangeMessagesForExternalConnectors::Welcome { version, capabilities } => {
+            if let Err(reason) = negotiation::check_peer_version(version) {
+                error!("ExternalConnector({symbol}): the server's protocol version '{version}' is not compatible with ours, '{PROTOCOL_VERSION}' -- leaving");
+                return vec![ExternalConnectorMessages::GoodBye(format!("{reason:?}: the server's protocol version '{version}' is not compatible with ours, '{PROTOCOL_VERSION}'"))];
+            }
+            let capabilities = negotiation::supported_capabilities().intersection(capabilities);
+            debug!("ExternalConnector({symbol}): capabilities agreed with the server (version '{version}'): {capabilities:?}");
+            *session.capabilities.lock() = capabilities.clone();
+            session.on_welcome();
+            let Some(handle) = session.handle() else {
+                return vec![session.unbound_error()];
+            };
+            session.on_identification(&handle);
+            vec![identification(&handle, capabilities)]
+        },
+
+        OgreExchangeMessagesForExternalConnectors::BindSession { symbol: new_symbol, account_token } => {
+            // not yet identified sessions must be bound to identify themselves -- the others, only if authorized & within their account
+            let authentication = session.authentication.lock().clone();
+            let account_token = match (authentication, account_token) {
+                (AuthenticationState::Welcomed, account_token) => account_token.clone(),
+                (AuthenticationState::Authorized { account_token: authorized }, None) => Some(authorized),
+                (AuthenticationState::Authorized { account_token: authorized }, Some(account_token)) if *account_token == authorized => Some(authorized),
+                (authentication, _) => {
+                    warn!("ExternalConnector({symbol}): refusing to bind the session of peer #{} to '{new_symbol}' (account {account_token:?}) while it is {authentication:?}", session.peer_id);
+                    return vec![ExternalConnectorMessages::ProcessorError(format!("BindSession: not allowed while {authentication:?} -- sessions may only be bound before identifying themselves or, once authorized, to MQL Programs of their account"))];
+                },
+            };
+            match session.bind(new_symbol, account_token.as_deref()) {
+                Some(handle) => {
+                    info!("ExternalConnector({symbol}): session of peer #{} bound to '{new_symbol}' (handle_id {})", session.peer_id, handle.handle_id);
+                    session.on_identification(&handle);
+                    vec![identification(&handle, session.capabilities.lock().clone())]
+                },
+                None => {
+                    warn!("ExternalConnector({symbol}): session of peer #{} bound to '{new_symbol}' (account {account_token:?}), for which no MQL Program is registered yet", session.peer_id);
+                    vec![session.unbound_error()]
                 },
+            }
+        },
+
+        OgreExchangeMessagesForExternalConnectors::UseWireFormat(wire_format) => {
+            if *wire_format == WireFormat::Binary && !session.capabilities.lock().binary_encoding {
+                return vec![ExternalConnectorMessages::ProcessorError(String::from("UseWireFormat: the binary encoding was not agreed upon -- see the capabilities in `Welcome`"))];
+            }
+            info!("ExternalConnector({symbol}): the server asked peer #{} to use the {wire_format:?} wire format", session.peer_id);
+            *session.wire_format.lock() = *wire_format;
+            vec![ExternalConnectorMessages::WireFormatInUse(*wire_format)]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue => {
-                    panic!("I didn't expect to have to ProvideAuthorizationToContinue")
+        OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue => {
+            let authentication = session.authentication.lock().clone();
+            let account_token = match authentication {
+                AuthenticationState::Identified { account_token } if session.peer_authenticator.is_none() => account_token,
+                AuthenticationState::Authorized { account_token } => account_token,
+                AuthenticationState::Identified { account_token } => {
+                    warn!("ExternalConnector({symbol}): peer #{} asked for the secret of account '{account_token}' without proving itself first -- dropping it", session.peer_id);
+                    let message = String::from("`ServerAuthorization` must precede `ProvideAuthorizationToContinue`");
+                    return vec![ExternalConnectorMessages::Disconnected(DisconnectionReason::ProtocolOffense { message })];
+                },
+                AuthenticationState::Connected | AuthenticationState::Welcomed => {
+                    warn!("ExternalConnector({symbol}): the server asked for authorization before we could identify ourselves -- leaving");
+                    return vec![ExternalConnectorMessages::GoodBye(String::from("authorization asked before identification"))];
                 },
+            };
+            let Some(secret) = session.credentials.secret(&account_token) else {
+                error!("ExternalConnector({symbol}): the server asked for authorization, but no credentials are configured for account '{account_token}' -- leaving");
+                return vec![ExternalConnectorMessages::GoodBye(format!("no credentials for account '{account_token}'"))];
+            };
+            *session.authentication.lock() = AuthenticationState::Authorized { account_token };
+            let mut answers = vec![ExternalConnectorMessages::UserAuthorization(secret.to_string())];
+            if session.resumed.swap(false, Relaxed) {
+                if let Some(handle) = session.handle() {
+                    // the `OgreExchange` lost track of us while we were disconnected
+                    let book_depth = session.capabilities.lock().book_depth as usize;
+                    answers.extend(market_data_publisher::book_snapshot(&handle.symbol, &Local::now().naive_local(), &handle.books.lock(), book_depth)
+                        .into_iter()
+                        .map(ExternalConnectorMessages::MarketData));
+                    answers.extend(open_positions(session, &handle));
+                }
+            }
+            answers
+        },
 
-                OgreExchangeMessagesForExternalConnectors::Disconnected(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::ServerAuthorization(secret) => {
+            let Some(authenticator) = &session.peer_authenticator else {
+                return vec![ExternalConnectorMessages::ProcessorError(String::from("ServerAuthorization: not expected -- we are the ones connecting to the server"))];
+            };
+            let authentication = session.authentication.lock().clone();
+            let AuthenticationState::Identified { account_token } = authentication else {
+                warn!("ExternalConnector({symbol}): dropping peer #{}, which tried to authorize itself while {authentication:?}", session.peer_id);
+                let message = format!("`ServerAuthorization` is not expected while {authentication:?}");
+                return vec![ExternalConnectorMessages::Disconnected(DisconnectionReason::ProtocolOffense { message })];
+            };
+            match authenticator.authorize(&account_token, secret, Instant::now()) {
+                Ok(()) => {
+                    info!("ExternalConnector({symbol}): peer #{} authorized for account '{account_token}'", session.peer_id);
+                    *session.authentication.lock() = AuthenticationState::Authorized { account_token };
+                    vec![]
+                },
+                Err(reason) => {
+                    warn!("ExternalConnector({symbol}): dropping peer #{}, which failed to authorize itself for account '{account_token}': {reason:?}", session.peer_id);
+                    vec![ExternalConnectorMessages::Disconnected(reason)]
+                },
+            }
+        },
+
+        OgreExchangeMessagesForExternalConnectors::Disconnected(reason) => {
+            match reason {
+                DisconnectionReason::UnknownAccount |
+                DisconnectionReason::AuthenticationFailure |
+                DisconnectionReason::AccountDisabled { .. } => error!("ExternalConnector({symbol}): the server refused our credentials: {reason:?} -- please, review them"),
+                DisconnectionReason::AccountFrozen { remaining_duration_millis, .. } => error!("ExternalConnector({symbol}): the server froze our account for the next {remaining_duration_millis}ms: {reason:?}"),
+                _ => warn!("ExternalConnector({symbol}): disconnected by the server: {reason:?}"),
+            }
+            vec![]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::KeepAliveRequest(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::KeepAliveRequest(n) => {
+            let answer = ExternalConnectorMessages::KeepAliveAnswer(n.wrapping_add(1));
+            let skew_check = session.heartbeat.lock().on_peer_request(*n, utc_millis_of_day());
+            match skew_check {
+                Ok(()) => vec![answer],
+                Err(reason) => vec![answer, session.drop_for(reason)],
+            }
+        },
 
-                OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer(n) => {
+            debug!("ExternalConnector({symbol}): keep alive answered with {n}");
+            let round_trip_check = session.heartbeat.lock().on_answer(*n, Instant::now());
+            match round_trip_check {
+                Ok(()) => vec![],
+                Err(reason) => vec![session.drop_for(reason)],
+            }
+        },
 
-                OgreExchangeMessagesForExternalConnectors::ScheduleOrder(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::ScheduleOrder(order_command) => schedule_order(session, order_command),
 
-                OgreExchangeMessagesForExternalConnectors::CancelOrder { .. } => todo!(),
+        OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id, reason } => {
+            let handle = match session.authorized_handle() {
+                Ok(handle) => handle,
+                Err(message) => return vec![ExternalConnectorMessages::ProcessorError(format!("CancelOrder: {message}"))],
+            };
+            session.forget_finished_orders(&handle);
+            let Some(ticket) = session.ogre_orders.get(ogre_id).map(|entry| entry.0) else {
+                return vec![ExternalConnectorMessages::ProcessorError(format!("CancelOrder: order #{ogre_id} is unknown -- it was either never placed or is already done"))];
+            };
+            let (ogre_id, reason, callback_session) = (*ogre_id, reason.clone(), Arc::clone(session));
+            handle.mql5_calls.schedule_with_callback(Mql5Command::OrderDelete { order: ticket }, move |result| {
+                let message = match result {
+                    Ok(Mql5CommandResult::Trade { result, .. }) if result.retcode == Mt5TradeServerReturnCodes::TradeRetcodeDone => {
+                        callback_session.ogre_orders.remove(&ogre_id);
+                        ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated { order_id: ogre_id, reason })
+                    },
+                    unsuccessful => ExternalConnectorMessages::ProcessorError(format!("CancelOrder: couldn't cancel order #{ogre_id} (ticket {ticket}): {unsuccessful:?}")),
+                };
+                callback_session.send(message);
+            });
+            vec![]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::StateOpenPositions => todo!(),
+        OgreExchangeMessagesForExternalConnectors::StateOpenPositions => match session.authorized_handle() {
+            Ok(handle) => open_positions(session, &handle),
+            Err(message) => vec![ExternalConnectorMessages::ProcessorError(format!("StateOpenPositions: {message}"))],
+        },
 
-                OgreExchangeMessagesForExternalConnectors::ChartPoints { .. } => todo!(),
+        OgreExchangeMessagesForExternalConnectors::ChartPoints { .. } => {
+            debug!("ExternalConnector({symbol}): ignoring chart points -- they are not drawn by this External Connector");
+            vec![]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::NoAnswer => todo!(),
+        OgreExchangeMessagesForExternalConnectors::NoAnswer => vec![],
 
-                OgreExchangeMessagesForExternalConnectors::UnknownMessage(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::UnknownMessage(message) => {
+            warn!("ExternalConnector({symbol}): the server didn't understand one of our messages: '{message}'");
+            vec![]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::ProcessorError(_) => todo!(),
+        OgreExchangeMessagesForExternalConnectors::ProcessorError(error_message) => {
+            error!("ExternalConnector({symbol}): the server failed processing one of our messages: '{error_message}'");
+            vec![]
+        },
 
-                OgreExchangeMessagesForExternalConnectors::ShuttingDown => todo!(),
+        OgreExchangeMessagesForExternalConnectors::ShuttingDown => {
+            info!("ExternalConnector({symbol}): the server is shutting down");
+            vec![ExternalConnectorMessages::GoodBye(String::from("acknowledging the server shutdown"))]
+        },
+    }
+}
+
+/// Our identification, as the MQL Program of `handle` -- with the `capabilities` agreed upon with the server
+fn identification(handle: &Handle, capabilities: Capabilities) -> ExternalConnectorMessages {
+    ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor {
+        version:       PROTOCOL_VERSION.to_string(),
+        symbol:        handle.symbol.clone(),
+        account_token: handle.account_token.to_string(),
+        capabilities,
+    })
+}
+
+/// The open positions & pending orders of the MQL Program of `session` -- for its symbol
+fn open_positions(session: &Session, handle: &Handle) -> Vec<ExternalConnectorMessages> {
+    let positions = handle.portfolio.lock().open_positions()
+        .filter(|position| position.symbol == handle.symbol)
+        .map(|position| ExternalConnectorMessages::OpenPosition {
+            symbol:                     position.symbol.clone(),
+            side:                       if position.direction == PositionDirection::Long { Parties::Buyer } else { Parties::Seller },
+            quantity:                   position.volume.round() as u32,
+            average_unitary_mill_value: to_mills(position.average_price),
+        })
+        .collect::<Vec<_>>();
+    let pending_orders = handle.orders.lock().open_orders()
+        .filter(|order| order.symbol == handle.symbol)
+        .map(|order| {
+            // orders not scheduled by the `OgreExchange` have no `ogre_id`
+            let ogre_id = session.ogre_orders.iter().find(|entry| entry.value().0 == order.ticket).map_or(0, |entry| *entry.key());
+            match u32::try_from(order.ticket) {
+                Ok(exchange_id) => ExternalConnectorMessages::PendingOrder { ogre_id, exchange_id },
+                Err(_) => unrepresentable_ticket(&handle.symbol, "PendingOrder", ogre_id, order.ticket),
             }
         })
+        .collect::<Vec<_>>();
+    positions.into_iter().chain(pending_orders).collect()
+}
+
+/// Routes `order_command` to the terminal -- answering right away only if the order can't be sent.\
+/// In [ConnectorIdentificationOrderCancellationReasons], `order_id` is the `ogre_id`, as the order may have never reached the Exchange
+fn schedule_order(session: &Arc<Session>, order_command: &OrderCommand) -> Vec<ExternalConnectorMessages> {
+    let (OrderCommand::Buy(order) | OrderCommand::Sell(order)) = order_command;
+    let ogre_id = order.ogre_id;
+    let cancelled = |message: String| {
+        warn!("ExternalConnector({}): cancelling the order scheduled by the server: {message} -- {order_command:?}", session.symbol());
+        vec![ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { order_id: ogre_id, message })]
+    };
+    let handle = match session.authorized_handle() {
+        Ok(handle) => handle,
+        Err(message) => return cancelled(message),
+    };
+    if order.symbol != handle.symbol {
+        return cancelled(format!("symbol '{}' is not the one of this External Connector: '{}'", order.symbol, handle.symbol));
+    }
+    if !session.capabilities.lock().order_types.contains(&order.order_type.kind()) {
+        return cancelled(format!("order type {:?} was not agreed upon -- see the capabilities in `Welcome`", order.order_type.kind()));
+    }
+    let order_request = order_request(order_command);
+    if let Err(risk_management_condition) = handle.risk_manager.lock().check(&order_request, SystemTime::now()) {
+        return cancelled(format!("refused by the External Connector's Risk Manager: {risk_management_condition:?}"));
+    }
+    let (symbol, scheduled_at, callback_session) = (order.symbol.clone(), Instant::now(), Arc::clone(session));
+    let weak_handle = Arc::downgrade(&handle);
+    handle.mql5_calls.schedule_with_callback(Mql5Command::OrderSend(order_request), move |result| {
+        // only pending orders may be cancelled -- executed ones are done
+        if let Ok(Mql5CommandResult::Trade { result, .. }) = &result {
+            if result.retcode == Mt5TradeServerReturnCodes::TradeRetcodePlaced {
+                if let Some(handle) = weak_handle.upgrade() {
+                    callback_session.forget_finished_orders(&handle);
+                }
+                callback_session.ogre_orders.insert(ogre_id, (result.order, Instant::now()));
+            }
+        }
+        callback_session.send(order_outcome(ogre_id, symbol, result, scheduled_at));
+    });
+    vec![]
+}
+
+/// The MQL `OrderSend()` request for `order_command`
+fn order_request(order_command: &OrderCommand) -> OrderRequest {
+    let (is_buy, order) = match order_command {
+        OrderCommand::Buy(order)  => (true, order),
+        OrderCommand::Sell(order) => (false, order),
+    };
+    let Order { ogre_id, symbol, quantity, unitary_mill_value, order_type, .. } = order;
+    let (action, order_type, price, order_type_filling) = match order_type {
+        OrderTypes::MarketOrder => (EnumTradeRequestActions::TradeActionDeal,
+                                    if is_buy { EnumOrderType::OrderTypeBuy } else { EnumOrderType::OrderTypeSell },
+                                    *unitary_mill_value,
+                                    EnumOrderTypeFilling::OrderFillingFok),
+        OrderTypes::LimitedOrder { price_limit_mill } => (EnumTradeRequestActions::TradeActionPending,
+                                                          if is_buy { EnumOrderType::OrderTypeBuyLimit } else { EnumOrderType::OrderTypeSellLimit },
+                                                          *price_limit_mill,
+                                                          EnumOrderTypeFilling::OrderFillingReturn),
+    };
+    OrderRequest {
+        action,
+        symbol:          symbol.clone(),
+        volume:          *quantity as f64,
+        price:           price as f64 / 1000.0,
+        sl:              0.0,
+        tp:              0.0,
+        order_type,
+        order_type_filling,
+        order_type_time: EnumOrderTypeTime::OrderTimeDay,
+        comment:         format!("OgreExchange #{ogre_id}"),
+    }
+}
+
+/// The message informing the `OgreExchange` of what happened to the order it scheduled with `ogre_id`, given the `OrderSend()` `result`
+fn order_outcome(ogre_id: u32, symbol: String, result: Result<Mql5CommandResult, Mql5CallError>, scheduled_at: Instant) -> ExternalConnectorMessages {
+    let result = match result {
+        Ok(Mql5CommandResult::Trade { result, .. }) => result,
+        unexpected => {
+            // the order might have been sent, after all: it is up to the server to inquire about its pending orders
+            warn!("ExternalConnector({symbol}): the outcome of the order #{ogre_id} is unknown: {unexpected:?}");
+            return ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::TimeoutWhileScheduling {
+                order_id:      ogre_id,
+                elapsed_nanos: scheduled_at.elapsed().as_nanos().min(u32::MAX as u128) as u32,
+            });
+        },
+    };
+    match result.retcode {
+        Mt5TradeServerReturnCodes::TradeRetcodeDone | Mt5TradeServerReturnCodes::TradeRetcodeDonePartial => {
+            let Ok(order_id) = u32::try_from(result.order) else {
+                return unrepresentable_ticket(&symbol, "ExecutedOrder", ogre_id, result.order);
+            };
+            let (date, time) = date_and_time(&Local::now().naive_local());
+            ExternalConnectorMessages::ExecutedOrder {
+                date,
+                time,
+                symbol,
+                unitary_mill_value: to_mills(result.price),
+                quantity:           result.volume.round() as u32,
+                partial:            result.retcode == Mt5TradeServerReturnCodes::TradeRetcodeDonePartial,
+                order_id,
+                ogre_id,
+            }
+        },
+        Mt5TradeServerReturnCodes::TradeRetcodePlaced => match u32::try_from(result.order) {
+            Ok(exchange_id) => ExternalConnectorMessages::PendingOrder { ogre_id, exchange_id },
+            Err(_) => unrepresentable_ticket(&symbol, "PendingOrder", ogre_id, result.order),
+        },
+        retcode => ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated {
+            order_id: ogre_id,
+            message:  format!("{retcode:?}: '{}'", result.comment),
+        }),
+    }
+}
+
+/// The error sent instead of the `message` about the order `ogre_id` -- as its Metatrader `ticket` (a `u64`) doesn't fit the
+/// protocol's `u32` ids. The order is still tracked by its full ticket, so it may be cancelled
+fn unrepresentable_ticket(symbol: &str, message: &str, ogre_id: u32, ticket: u64) -> ExternalConnectorMessages {
+    error!("ExternalConnector({symbol}): couldn't inform `{message}` for order #{ogre_id}: its ticket {ticket} exceeds the protocol's 32 bits ids");
+    ExternalConnectorMessages::ProcessorError(format!("{message}: the ticket {ticket} of order #{ogre_id} exceeds the protocol's 32 bits ids"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::{
+        risk_manager::{RiskManager, RiskLimits},
+        ogre_exchange_models::{OrderCancellationReasons, OrderKinds, RiskManagementConnectionDroppingConditions},
+        comms::authenticator::AuthenticationLimits,
+        comms::loopback_exchange::Handshake,
+        comms::messages_model::ExternalConnectorMarketData,
+    };
+
+
+    /// checks the answers to the server messages that don't involve orders
+    #[test]
+    fn housekeeping_messages() {
+        let (session, _sent) = session(9101, "housekeeping_tkn");
+        assert_eq!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::KeepAliveRequest(7)),
+                   vec![ExternalConnectorMessages::KeepAliveAnswer(8)],
+                   "Keep alive requests should be answered with the next number");
+        assert!(matches!(process_server_message(&session, &welcome()).as_slice(),
+                         [ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor { symbol, .. })] if symbol == "PETR4"),
+                "The server should be welcomed with our identification");
+        for unanswered in [OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer(8),
+                           OgreExchangeMessagesForExternalConnectors::ChartPoints {},
+                           OgreExchangeMessagesForExternalConnectors::NoAnswer,
+                           OgreExchangeMessagesForExternalConnectors::UnknownMessage(String::from("?")),
+                           OgreExchangeMessagesForExternalConnectors::ProcessorError(String::from("!"))] {
+            assert_eq!(process_server_message(&session, &unanswered), vec![], "{unanswered:?} should not be answered");
+        }
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ShuttingDown).as_slice(), [ExternalConnectorMessages::GoodBye(_)]),
+                "Server shutdowns should be answered with a GoodBye");
+        market_data_publisher::unsubscribe(9101);
+        rust_mt5_bridge::HANDLES.unregister(session.handle().expect("the session should be bound").handle_id);
+    }
+
+    /// checks sessions are dropped when the peer's keep alive messages break the configured limits
+    #[test]
+    fn heartbeat_limits() {
+        let limits = HeartbeatLimits { max_clock_skew_millis: Some(1_000), max_round_trip_millis: Some(60_000), ..HeartbeatLimits::default() };
+        let session = Arc::new(Session::new(9105, Arc::new(CredentialStore::default()), limits, Box::new(|_message| ())));
+        let peer_clock = (utc_millis_of_day() + 3_600_000) % 86_400_000;
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::KeepAliveRequest(peer_clock)).as_slice(),
+                         [ExternalConnectorMessages::KeepAliveAnswer(answer), ExternalConnectorMessages::Disconnected(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::ClockSkewTooHigh { .. }))]
+                         if *answer == peer_clock + 1),
+                "Peers whose clocks are too far off should be answered, then dropped for that reason");
+        assert!(session.heartbeat.lock().stats().estimated_clock_skew_millis.is_some_and(|skew| (skew - 3_600_000).abs() < 1_000), "Wrong clock skew estimated");
+
+        let n = session.heartbeat.lock().tick(Instant::now() - Duration::from_secs(61), 0).expect("sending a request").expect("the first request should be sent right away");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer(n + 1)).as_slice(),
+                         [ExternalConnectorMessages::Disconnected(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::RoundTripTimeTooHigh { .. }))]),
+                "Peers taking too long to answer should be dropped for that reason");
+        market_data_publisher::unsubscribe(9105);
+    }
+
+    /// checks the latency statistics are reported for the sessions bound to the asked symbol
+    #[test]
+    fn latency_stats_by_symbol() {
+        let (session, _sent) = session(9120, "latency_tkn");
+        let n = session.heartbeat.lock().tick(Instant::now(), 0).expect("sending a request").expect("the first request should be sent right away");
+        process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer(n + 1));
+        let processor = ServerProtocolProcessor::new(CredentialStore::default(), HeartbeatLimits::default());
+        processor.sessions.insert(9120, Arc::clone(&session));
+        processor.sessions.insert(9121, Arc::new(Session::new(9121, Arc::new(CredentialStore::default()), HeartbeatLimits::default(), Box::new(|_message| ()))));
+        assert!(matches!(processor.latency_stats("PETR4").as_slice(), [(9120, LatencyStats { round_trips: 1, .. })]),
+                "Only the stats of the sessions bound to the symbol should be reported");
+        assert_eq!(processor.latency_stats("VALE3"), vec![], "No stats should be reported for symbols without sessions");
+        for peer_id in [9120, 9121] {
+            market_data_publisher::unsubscribe(peer_id);
+        }
+        rust_mt5_bridge::HANDLES.unregister(session.handle().expect("the session should be bound").handle_id);
+    }
+
+    /// checks the queued market data is flushed before the peer is told `GoodBye` -- which only happens when shutting down
+    #[test]
+    fn shutdown_flushes_market_data() {
+        let market_data = |shutting_down: bool| {
+            let (mut sender, receiver) = futures::channel::mpsc::channel(4);
+            for n in 0..2 {
+                sender.try_send(ExternalConnectorMessages::KeepAliveRequest(n)).expect("queueing an event");
+            }
+            drop(sender);
+            futures::executor::block_on(flushed_then_goodbye(receiver, Arc::new(AtomicBool::new(shutting_down))).collect::<Vec<_>>())
+        };
+        assert!(matches!(market_data(true).as_slice(), [ExternalConnectorMessages::KeepAliveRequest(0), ExternalConnectorMessages::KeepAliveRequest(1), ExternalConnectorMessages::GoodBye(_)]),
+                "When shutting down, `GoodBye` should only be said after all queued events");
+        assert!(matches!(market_data(false).as_slice(), [ExternalConnectorMessages::KeepAliveRequest(0), ExternalConnectorMessages::KeepAliveRequest(1)]),
+                "Peers disconnecting by themselves shouldn't be told `GoodBye`");
+    }
+
+    /// checks the server may switch the wire format of a session -- applying to its answer & to the messages sent afterwards
+    #[test]
+    fn wire_format_switch() {
+        let formats = Arc::new(Mutex::new(vec![]));
+        let formats_ref = Arc::clone(&formats);
+        let session = Arc::new(Session::new(9109, Arc::new(CredentialStore::default()), HeartbeatLimits::default(), Box::new(move |message: WireMessage<_>| formats_ref.lock().push(message.format)))
+            .with_wire_format(WireFormat::Ron));
+        session.send(ExternalConnectorMessages::KeepAliveRequest(1));
+        let answers = process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::UseWireFormat(WireFormat::Binary));
+        assert_eq!(answers, vec![ExternalConnectorMessages::WireFormatInUse(WireFormat::Binary)], "Switching the wire format should be confirmed");
+        assert_eq!(answers.into_iter().map(|answer| session.wire_message(answer).format).collect::<Vec<_>>(), vec![WireFormat::Binary],
+                   "The confirmation should already be sent in the new format");
+        session.send(ExternalConnectorMessages::KeepAliveRequest(2));
+        assert_eq!(*formats.lock(), vec![WireFormat::Ron, WireFormat::Binary], "Messages should be sent in the format in use when sending them");
+        market_data_publisher::unsubscribe(9109);
+    }
+
+    /// checks sessions are bound by symbol & account -- following the MQL Program as it is unregistered and registered again
+    #[test]
+    fn session_binding() {
+        let credentials = CredentialStore::default().with_account("binding_tkn", "binding_tkn_s3cr3t");
+        let session = Arc::new(Session::new(9103, Arc::new(credentials), HeartbeatLimits::default(), Box::new(|_message| ())));
+        let market_data = market_data_publisher::subscribe(9103, 16);
+        assert!(matches!(process_server_message(&session, &welcome()).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Unbound sessions can't identify themselves");
+        assert!(matches!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)).as_slice(), [ExternalConnectorMessages::CancelledOrder(_)]),
+                "Unbound sessions should cancel scheduled orders");
+
+        let bind = |account_token: &str| OgreExchangeMessagesForExternalConnectors::BindSession { symbol: String::from("PETR4"), account_token: Some(account_token.to_string()) };
+        assert!(matches!(process_server_message(&session, &bind("binding_tkn")).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Binding to a symbol with no registered MQL Program should be reported");
+        let handle_id = register_handle("binding_tkn");
+        assert_eq!(session.handle().map(|handle| handle.handle_id), Some(handle_id), "The binding should take effect once the MQL Program registers");
+        assert!(matches!(process_server_message(&session, &bind("binding_tkn")).as_slice(),
+                         [ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor { symbol, account_token, .. })] if symbol == "PETR4" && account_token == "binding_tkn"),
+                "Binding should be answered with the identification of the bound MQL Program");
+        authorize(&session);
+        assert!(matches!(process_server_message(&session, &bind("another_tkn")).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "MQL Programs of other accounts should not be bound to");
+        process_server_message(&session, &bind("binding_tkn"));
+
+        rust_mt5_bridge::HANDLES.unregister(handle_id);
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::StateOpenPositions).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Sessions should notice their MQL Program is gone");
+        let new_handle_id = register_handle("binding_tkn");
+        assert_eq!(session.handle().map(|handle| handle.handle_id), Some(new_handle_id), "Sessions should follow their MQL Program when it registers again");
+        assert!(matches!(process_server_message(&session, &welcome()).as_slice(), [ExternalConnectorMessages::ConnectorIdentification(_)]),
+                "Sessions should be functional again once their MQL Program registers again");
+
+        drop(market_data);
+        market_data_publisher::unsubscribe(9103);
+        rust_mt5_bridge::HANDLES.unregister(new_handle_id);
+    }
+
+    /// checks binding & orders are refused until the session is authorized -- and that it may then only be bound to MQL
+    /// Programs of the authorized account
+    #[test]
+    fn authorization_required() {
+        let credentials = CredentialStore::default().with_account("authorization_tkn", "authorization_tkn_s3cr3t");
+        let session = Arc::new(Session::new(9112, Arc::new(credentials), HeartbeatLimits::default(), Box::new(|_message| ())));
+        let handle_id = register_handle("authorization_tkn");
+        let other_handle_id = register_handle("other_authorization_tkn");
+        let bind = |account_token: Option<&str>| OgreExchangeMessagesForExternalConnectors::BindSession { symbol: String::from("PETR4"), account_token: account_token.map(str::to_string) };
+        let cancel = OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 1, reason: OrderCancellationReasons::Unspecified { order_id: 1, message: String::new() } };
+
+        assert!(matches!(process_server_message(&session, &bind(Some("authorization_tkn"))).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Binding before `Welcome` should be refused");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue).as_slice(), [ExternalConnectorMessages::GoodBye(_)]),
+                "Sessions should leave if asked for authorization before identifying themselves");
+        process_server_message(&session, &welcome());
+        assert!(matches!(process_server_message(&session, &bind(Some("authorization_tkn"))).as_slice(), [ExternalConnectorMessages::ConnectorIdentification(_)]),
+                "Welcomed sessions should be bound, to identify themselves");
+        assert!(matches!(process_server_message(&session, &bind(Some("other_authorization_tkn"))).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Identified sessions should not be rebound before being authorized");
+        assert!(matches!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)).as_slice(), [ExternalConnectorMessages::CancelledOrder(_)]),
+                "Orders should be cancelled before the session is authorized");
+        assert!(matches!(process_server_message(&session, &cancel).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Cancellations should be refused before the session is authorized");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::StateOpenPositions).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Open positions should not be stated before the session is authorized");
+        assert_eq!(rust_mt5_bridge::HANDLES.get(handle_id).expect("the MQL Program should be registered").mql5_calls.len(), 0, "Nothing should have been routed to MQL");
+
+        authorize(&session);
+        assert_eq!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)), vec![], "Authorized sessions should have their orders routed to MQL");
+        assert!(matches!(process_server_message(&session, &bind(Some("other_authorization_tkn"))).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "Authorized sessions should not be bound to MQL Programs of other accounts");
+        assert!(matches!(process_server_message(&session, &bind(None)).as_slice(),
+                         [ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor { account_token, .. })] if account_token == "authorization_tkn"),
+                "Authorized sessions should be bound to MQL Programs of their account, even if none is given");
+        market_data_publisher::unsubscribe(9112);
+        rust_mt5_bridge::HANDLES.unregister(handle_id);
+        rust_mt5_bridge::HANDLES.unregister(other_handle_id);
+    }
+
+    /// checks that, when listening for connections, peers must prove they are the `OgreExchange` before being trusted with
+    /// our secret & orders -- being dropped with the right reasons, and frozen if they fail too many times
+    #[test]
+    fn peer_authentication() {
+        let handle_id = register_handle("peer_auth_tkn");
+        let credentials = CredentialStore::default().with_account("peer_auth_tkn", "peer_auth_tkn_s3cr3t");
+        let processor = ServerProtocolProcessor::new(credentials.clone(), HeartbeatLimits::default())
+            .with_peer_authentication(Authenticator::new(credentials, AuthenticationLimits { max_failed_attempts: 2, freeze_duration_secs: 60 }));
+        let identified_session = |peer_id: u32| {
+            let session = processor.new_session(peer_id, Box::new(|_message| ()));
+            process_server_message(&session, &welcome());
+            process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::BindSession { symbol: String::from("PETR4"), account_token: Some(String::from("peer_auth_tkn")) });
+            session
+        };
+        let server_authorization = |secret: &str| OgreExchangeMessagesForExternalConnectors::ServerAuthorization(secret.to_string());
+
+        let session = identified_session(9113);
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue).as_slice(),
+                         [ExternalConnectorMessages::Disconnected(DisconnectionReason::ProtocolOffense { .. })]),
+                "Peers asking for our secret before proving themselves should be dropped");
+        let session = identified_session(9114);
+        assert_eq!(process_server_message(&session, &server_authorization("guess")), vec![ExternalConnectorMessages::Disconnected(DisconnectionReason::AuthenticationFailure)],
+                   "Peers with wrong secrets should be dropped");
+        assert!(matches!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)).as_slice(), [ExternalConnectorMessages::CancelledOrder(_)]),
+                "Orders of peers that failed to prove themselves should be cancelled");
+
+        let session = identified_session(9115);
+        assert_eq!(process_server_message(&session, &server_authorization("peer_auth_tkn_s3cr3t")), vec![], "Peers with the right secret should be authorized");
+        assert_eq!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue),
+                   vec![ExternalConnectorMessages::UserAuthorization(String::from("peer_auth_tkn_s3cr3t"))],
+                   "Our secret should be sent to authorized peers");
+        assert_eq!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)), vec![], "Orders of authorized peers should be routed to MQL");
+
+        for peer_id in [9116, 9117] {
+            process_server_message(&identified_session(peer_id), &server_authorization("guess"));
+        }
+        assert!(matches!(process_server_message(&identified_session(9118), &server_authorization("peer_auth_tkn_s3cr3t")).as_slice(),
+                         [ExternalConnectorMessages::Disconnected(DisconnectionReason::AccountFrozen { .. })]),
+                "Failing too many times should freeze the account -- even for the right secret");
+        assert!(matches!(process_server_message(&Arc::new(Session::new(9119, Arc::new(CredentialStore::default()), HeartbeatLimits::default(), Box::new(|_message| ()))), &server_authorization("peer_auth_tkn_s3cr3t")).as_slice(),
+                         [ExternalConnectorMessages::ProcessorError(_)]),
+                "Peers should only be asked to prove themselves when we are listening for connections");
+        for peer_id in 9113..=9119 {
+            market_data_publisher::unsubscribe(peer_id);
+        }
+        rust_mt5_bridge::HANDLES.unregister(handle_id);
+    }
+
+    /// checks reconnected sessions resume the binding of the lost one -- replaying the identification & the state upon `Welcome`
+    #[test]
+    fn session_resumption() {
+        let (lost_session, _sent) = session(9106, "resume_tkn");
+        let handle = lost_session.handle().expect("the MQL Program should be registered");
+        handle.books.lock().sell_orders.push_back(MqlBookInfo { book_type: EnumBookType::BookTypeSell, price: 32.02, volume: 300.0 });
+        handle.books.lock().buy_orders.push_back(MqlBookInfo { book_type: EnumBookType::BookTypeBuy, price: 32.01, volume: 100.0 });
+
+        let processor = ServerProtocolProcessor::new(CredentialStore::default(), HeartbeatLimits::default());
+        processor.on_session_lost(&lost_session);
+        assert_eq!(processor.new_session(9107, Box::new(|_message| ())).bound_symbol(), None, "Servers shouldn't resume sessions");
+
+        let processor = ServerProtocolProcessor::new(CredentialStore::default().with_account("resume_tkn", "resume_tkn_s3cr3t"), HeartbeatLimits::default()).with_session_resumption();
+        processor.on_session_lost(&lost_session);
+        let resumed_session = processor.new_session(9108, Box::new(|_message| ()));
+        assert_eq!(resumed_session.handle().map(|resumed_handle| resumed_handle.handle_id), Some(handle.handle_id), "The lost session's binding should be resumed");
+        assert!(matches!(process_server_message(&resumed_session, &welcome()).as_slice(), [ExternalConnectorMessages::ConnectorIdentification(_)]),
+                "Resumed sessions should identify themselves -- replaying nothing before being authorized");
+        let replay = process_server_message(&resumed_session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue);
+        assert!(matches!(replay.as_slice(), [
+                    ExternalConnectorMessages::UserAuthorization(_),
+                    ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book { price_level_mills: 32020, available_quantity: 300, side: Parties::Seller, .. }),
+                    ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book { price_level_mills: 32010, available_quantity: 100, side: Parties::Buyer, .. }),
+                ]), "Resumed sessions should replay the book once authorized -- not {replay:?}");
+        assert!(matches!(process_server_message(&resumed_session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue).as_slice(), [ExternalConnectorMessages::UserAuthorization(_)]),
+                "The state should only be replayed once");
+
+        for peer_id in [9106, 9107, 9108] {
+            market_data_publisher::unsubscribe(peer_id);
+        }
+        rust_mt5_bridge::HANDLES.unregister(handle.handle_id);
+    }
+
+    /// plays the authentication handshake against a loopback `OgreExchange` peer, checking the account of the bound MQL Program
+    /// is authenticated with the configured secret -- or refused with the right reasons
+    #[test]
+    fn authentication_handshake() {
+        let (session, _sent) = session(9104, "auth_tkn");
+        let now = Instant::now();
+
+        let authenticator = Authenticator::new(CredentialStore::default().with_account("auth_tkn", "auth_tkn_s3cr3t"), AuthenticationLimits::default());
+        let mut handshake = Handshake::new(&authenticator);
+        assert_eq!(loopback(&session, &mut handshake, now), vec![handshake.welcome(), OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue],
+                   "The handshake should complete without disconnections");
+        assert_eq!(handshake.authenticated_account(), Some("auth_tkn"), "The account of the bound MQL Program should be authenticated");
+
+        let authenticator = Authenticator::new(CredentialStore::default().with_account("another_tkn", "s3cr3t"), AuthenticationLimits::default());
+        assert_eq!(loopback(&session, &mut Handshake::new(&authenticator), now).last(), Some(&OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::UnknownAccount)),
+                   "Accounts unknown to the server should be refused");
+
+        let authenticator = Authenticator::new(CredentialStore::default().with_account("auth_tkn", "another_s3cr3t"), AuthenticationLimits { max_failed_attempts: 2, freeze_duration_secs: 1 });
+        assert_eq!(loopback(&session, &mut Handshake::new(&authenticator), now).last(), Some(&OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AuthenticationFailure)),
+                   "Wrong secrets should be refused");
+        assert!(matches!(loopback(&session, &mut Handshake::new(&authenticator), now).last(), Some(OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AccountFrozen { .. }))),
+                "Retrying too many times should freeze the account");
+        assert!(matches!(loopback(&session, &mut Handshake::new(&authenticator), now).last(), Some(OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AccountFrozen { .. }))),
+                "Frozen accounts should be refused right after identifying themselves");
+        market_data_publisher::unsubscribe(9104);
+        rust_mt5_bridge::HANDLES.unregister(session.handle().expect("the session should be bound").handle_id);
+    }
+
+    /// checks servers in incompatible protocol versions are left -- and that only the capabilities they offer are used
+    #[test]
+    fn protocol_negotiation() {
+        let (session, _sent) = session(9110, "negotiation_tkn");
+        let welcome = |version: &str, capabilities: Capabilities| OgreExchangeMessagesForExternalConnectors::Welcome { version: version.to_string(), capabilities };
+        for incompatible_version in ["2023-07-04", "0.1.0", "2.0.0"] {
+            assert!(matches!(process_server_message(&session, &welcome(incompatible_version, negotiation::supported_capabilities())).as_slice(), [ExternalConnectorMessages::GoodBye(_)]),
+                    "Servers in version '{incompatible_version}' should be left");
+        }
+
+        let offered = Capabilities { binary_encoding: false, book_depth: 10, order_types: vec![OrderKinds::MarketOrder] };
+        assert!(matches!(process_server_message(&session, &welcome(PROTOCOL_VERSION, offered.clone())).as_slice(),
+                         [ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor { capabilities, .. })] if *capabilities == offered),
+                "Only the offered capabilities should be claimed");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::UseWireFormat(WireFormat::Binary)).as_slice(), [ExternalConnectorMessages::ProcessorError(_)]),
+                "The binary wire format should be refused if it was not agreed upon");
+        assert_eq!(*session.wire_format.lock(), WireFormat::Ron, "The wire format should not change if refused");
+        assert!(matches!(process_server_message(&session, &schedule(1, OrderTypes::LimitedOrder { price_limit_mill: 25_000 })).as_slice(),
+                         [ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { order_id: 1, .. })]),
+                "Order types not agreed upon should be cancelled right away");
+        market_data_publisher::unsubscribe(9110);
+        rust_mt5_bridge::HANDLES.unregister(session.handle().expect("the session should be bound").handle_id);
+    }
+
+    /// follows orders scheduled by the server through MQL, checking their outcomes are sent back -- including cancellations
+    #[test]
+    fn order_scheduling() {
+        let (session, sent) = session(9102, "scheduling_tkn");
+        let handle = session.handle().expect("the session should be bound");
+
+        // market order
+        assert_eq!(process_server_message(&session, &schedule(1, OrderTypes::MarketOrder)), vec![], "Outcomes should only be known after MQL sends the order");
+        let (call_id, call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        assert!(call.contains(r#""fn_to_call":"OrderSend""#) && call.contains("OgreExchange #1"), "Wrong MQL call: {call}");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10009, 55)).expect("completing the call");
+        assert!(matches!(sent.lock().pop(), Some(ExternalConnectorMessages::ExecutedOrder { ogre_id: 1, order_id: 55, quantity: 100, unitary_mill_value: 25010, partial: false, .. })),
+                "Executions should be informed with `ExecutedOrder`");
+
+        // limit order, which is then cancelled
+        process_server_message(&session, &schedule(2, OrderTypes::LimitedOrder { price_limit_mill: 24_500 }));
+        let (call_id, call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        assert!(call.contains(r#""price":24.5"#), "Limit orders should be priced at the limit: {call}");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10008, 56)).expect("completing the call");
+        assert_eq!(sent.lock().pop(), Some(ExternalConnectorMessages::PendingOrder { ogre_id: 2, exchange_id: 56 }), "Placed orders should be informed with `PendingOrder`");
+        let reason = OrderCancellationReasons::UserInitiated { order_id: 2, message: String::from("changed my mind") };
+        assert_eq!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 2, reason: reason.clone() }), vec![], "Cancellations should be answered after MQL acts");
+        let (call_id, call) = handle.mql5_calls.next_call().expect("the cancellation should be routed to MQL");
+        assert_eq!(call, r#"{"fn_to_call":"OrderDelete","params":{"order":56}}"#, "Wrong MQL call for the cancellation");
+        handle.mql5_calls.complete(&format!(r#"{{"fn_called": "OrderDelete", "call_id": {call_id}, "returns": {}}}"#, trade_returns(10009, 56))).expect("completing the call");
+        assert_eq!(sent.lock().pop(), Some(ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated { order_id: 2, reason })),
+                   "Cancellations should be confirmed, echoing the reason");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 2, reason: OrderCancellationReasons::Unspecified { order_id: 2, message: String::new() } }).as_slice(),
+                         [ExternalConnectorMessages::ProcessorError(_)]),
+                "Cancelling unknown orders should be answered with an error");
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 1, reason: OrderCancellationReasons::Unspecified { order_id: 1, message: String::new() } }).as_slice(),
+                         [ExternalConnectorMessages::ProcessorError(_)]),
+                "Executed orders should not be tracked for cancellation");
+
+        // limit order, which is then filled
+        process_server_message(&session, &schedule(5, OrderTypes::LimitedOrder { price_limit_mill: 24_500 }));
+        let (call_id, _call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10008, 57)).expect("completing the call");
+        assert_eq!(sent.lock().pop(), Some(ExternalConnectorMessages::PendingOrder { ogre_id: 5, exchange_id: 57 }), "Placed orders should be informed with `PendingOrder`");
+        assert_eq!(session.ogre_orders.len(), 1, "Placed orders should be tracked");
+        handle.orders.lock().on_trade_transaction(&order_transaction(57, EnumOrderState::OrderStateFilled), &trade_request(), &trade_result());
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 5, reason: OrderCancellationReasons::Unspecified { order_id: 5, message: String::new() } }).as_slice(),
+                         [ExternalConnectorMessages::ProcessorError(_)]),
+                "Orders in their final states should no longer be tracked");
+        assert_eq!(session.ogre_orders.len(), 0, "Finished orders should be forgotten");
+
+        // rejections
+        process_server_message(&session, &schedule(3, OrderTypes::MarketOrder));
+        let (call_id, _call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10019, 0)).expect("completing the call");
+        assert!(matches!(sent.lock().pop(), Some(ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { order_id: 3, .. }))),
+                "Rejections should be informed with `CancelledOrder`");
+        let mut wrong_symbol = Order { ogre_id: 4, aggressor: Parties::Buyer, order_type: OrderTypes::MarketOrder, date: 0, time: 0, symbol: String::from("VALE3"), unitary_mill_value: 25_010, quantity: 100 };
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ScheduleOrder(OrderCommand::Buy(wrong_symbol.clone()))).as_slice(),
+                         [ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { order_id: 4, .. })]),
+                "Orders for other symbols should be cancelled right away");
+        wrong_symbol.symbol = String::from("PETR4");
+        wrong_symbol.quantity = 1_000;
+        assert!(matches!(process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::ScheduleOrder(OrderCommand::Buy(wrong_symbol))).as_slice(),
+                         [ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { order_id: 4, .. })]),
+                "Orders refused by the Risk Manager should be cancelled right away");
+        assert_eq!(handle.mql5_calls.len(), 0, "Cancelled orders should not be routed to MQL");
+        market_data_publisher::unsubscribe(9102);
+        rust_mt5_bridge::HANDLES.unregister(handle.handle_id);
+    }
+
+    /// checks Metatrader tickets beyond the protocol's `u32` ids are reported as errors -- rather than truncated into
+    /// the ids of other orders -- while the orders may still be cancelled
+    #[test]
+    fn tickets_beyond_u32() {
+        let (session, sent) = session(9111, "big_tickets_tkn");
+        let handle = session.handle().expect("the session should be bound");
+        let big_ticket = u32::MAX as u64 + 57;
+
+        process_server_message(&session, &schedule(1, OrderTypes::MarketOrder));
+        let (call_id, _call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10009, big_ticket)).expect("completing the call");
+        assert!(matches!(sent.lock().pop(), Some(ExternalConnectorMessages::ProcessorError(error)) if error.contains("ExecutedOrder") && error.contains(&big_ticket.to_string())),
+                "Executions with tickets beyond `u32` should be informed as errors");
+
+        process_server_message(&session, &schedule(2, OrderTypes::LimitedOrder { price_limit_mill: 24_500 }));
+        let (call_id, _call) = handle.mql5_calls.next_call().expect("the order should be routed to MQL");
+        handle.mql5_calls.complete(&order_send_returns(call_id, 10008, big_ticket + 1)).expect("completing the call");
+        assert!(matches!(sent.lock().pop(), Some(ExternalConnectorMessages::ProcessorError(error)) if error.contains("PendingOrder") && error.contains(&(big_ticket + 1).to_string())),
+                "Placed orders with tickets beyond `u32` should be informed as errors");
+        let reason = OrderCancellationReasons::UserInitiated { order_id: 2, message: String::from("changed my mind") };
+        process_server_message(&session, &OgreExchangeMessagesForExternalConnectors::CancelOrder { ogre_id: 2, reason });
+        let (_call_id, call) = handle.mql5_calls.next_call().expect("the cancellation should be routed to MQL");
+        assert_eq!(call, format!(r#"{{"fn_to_call":"OrderDelete","params":{{"order":{}}}}}"#, big_ticket + 1), "Cancellations should use the full ticket");
+        market_data_publisher::unsubscribe(9111);
+        rust_mt5_bridge::HANDLES.unregister(handle.handle_id);
+    }
+
+
+    /// An authorized session bound to the "PETR4" MQL Program of `account_token` -- registered here, whose secret is
+    /// `<account_token>_s3cr3t` -- along with the messages sent to the peer.\
+    /// As subscriptions & handles are global, each test must use its own `peer_id` & `account_token` -- unsubscribing &
+    /// unregistering them when done
+    fn session(peer_id: u32, account_token: &str) -> (Arc<Session>, Arc<Mutex<Vec<ExternalConnectorMessages>>>) {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let sent_ref = Arc::clone(&sent);
+        let credentials = CredentialStore::default().with_account(account_token, &format!("{account_token}_s3cr3t"));
+        let session = Arc::new(Session::new(peer_id, Arc::new(credentials), HeartbeatLimits::default(), Box::new(move |message: WireMessage<_>| sent_ref.lock().push(message.message))));
+        register_handle(account_token);
+        session.bind("PETR4", Some(account_token)).expect("binding to a registered MQL Program");
+        process_server_message(&session, &welcome());
+        authorize(&session);
+        (session, sent)
+    }
+
+    /// Authorizes the identified `session`, answering the server's request for it
+    fn authorize(session: &Arc<Session>) {
+        assert!(matches!(process_server_message(session, &OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue).as_slice(), [ExternalConnectorMessages::UserAuthorization(_)]),
+                "The session should have been authorized");
+    }
+
+    /// Registers a "PETR4" MQL Program for `account_token`, limited to 500 papers per order by the Risk Manager -- returning its `handle_id`
+    fn register_handle(account_token: &str) -> i32 {
+        rust_mt5_bridge::HANDLES.register(|handle_id| Handle {
+            account_token: account_token.to_string(),
+            risk_manager:  Mutex::new(RiskManager::new("PETR4", RiskLimits { max_quantity: Some(500), ..RiskLimits::default() })),
+            ..Handle::for_testing(handle_id, "PETR4")
+        }).expect("registering a handle")
+    }
+
+    /// Delivers the messages of `session` to `handshake` and vice-versa, starting with the server's `Welcome` and until no more
+    /// messages are exchanged -- returning all messages sent by the server
+    fn loopback(session: &Arc<Session>, handshake: &mut Handshake, now: Instant) -> Vec<OgreExchangeMessagesForExternalConnectors> {
+        let mut server_messages = vec![];
+        let mut next_server_message = Some(handshake.welcome());
+        while let Some(server_message) = next_server_message.take() {
+            for connector_message in process_server_message(session, &server_message) {
+                next_server_message = handshake.process(&connector_message, now);
+            }
+            server_messages.push(server_message);
+        }
+        server_messages
+    }
+
+    /// The greeting of a server in our protocol version, offering all we support
+    fn welcome() -> OgreExchangeMessagesForExternalConnectors {
+        OgreExchangeMessagesForExternalConnectors::Welcome { version: PROTOCOL_VERSION.to_string(), capabilities: negotiation::supported_capabilities() }
+    }
+
+    fn schedule(ogre_id: u32, order_type: OrderTypes) -> OgreExchangeMessagesForExternalConnectors {
+        OgreExchangeMessagesForExternalConnectors::ScheduleOrder(OrderCommand::Buy(Order {
+            ogre_id, aggressor: Parties::Buyer, order_type, date: 0, time: 0, symbol: String::from("PETR4"), unitary_mill_value: 25_010, quantity: 100,
+        }))
     }
-}
\ No newline at end of file
+
+    /// The `TRADE_TRANSACTION_HISTORY_ADD` informing `order` reached `order_state`
+    fn order_transaction(order: u64, order_state: EnumOrderState) -> MqlTradeTransaction {
+        MqlTradeTransaction {
+            deal: 0, order, symbol: String::from("PETR4"), transaction_type: EnumTradeTransactionType::TradeTransactionHistoryAdd,
+            order_type: EnumOrderType::OrderTypeBuyLimit, order_state, deal_type: mql_trade_transaction::EnumDealType::DealTypeBuy,
+            time_type: EnumOrderTypeTime::OrderTimeDay, time_expiration: chrono::NaiveDateTime::from_timestamp(0, 0),
+            price: 24.5, price_trigger: 0.0, price_sl: 0.0, price_tp: 0.0, volume: 100.0, position: 0, position_by: 0,
+        }
+    }
+
+    fn trade_request() -> MqlTradeRequest {
+        MqlTradeRequest {
+            action: EnumTradeRequestActions::TradeActionPending, magic: 0, order: 0, symbol: String::from("PETR4"),
+            volume: 100.0, price: 24.5, stoplimit: 0.0, sl: 0.0, tp: 0.0, deviation: 0,
+            order_type: EnumOrderType::OrderTypeBuyLimit, order_type_filling: EnumOrderTypeFilling::OrderFillingReturn,
+            order_type_time: EnumOrderTypeTime::OrderTimeDay, expiration: chrono::NaiveDateTime::from_timestamp(0, 0),
+            comment: String::new(), position: 0, position_by: 0,
+        }
+    }
+
+    fn trade_result() -> MqlTradeResult {
+        MqlTradeResult { retcode: Mt5TradeServerReturnCodes::TradeRetcodeDone, deal: 0, order: 0, volume: 0.0, price: 0.0, bid: 0.0, ask: 0.0, comment: String::new(), request_id: 1, retcode_external: 0 }
+    }
+
+    fn order_send_returns(call_id: u32, retcode: u32, order: u64) -> String {
+        format!(r#"{{"fn_called": "OrderSend", "call_id": {call_id}, "returns": {}}}"#, trade_returns(retcode, order))
+    }
+
+    fn trade_returns(retcode: u32, order: u64) -> String {
+        format!(r#"{{"mt5_error_code": 0, "result": {{"retcode": {retcode}, "deal": 0, "order": {order}, "volume": 100.0, "price": 25.01, "bid": 25.0, "ask": 25.01, "comment": "", "request_id": 1, "retcode_external": 0}}}}"#)
+    }
+}
@@ -0,0 +1,197 @@
+//! Keep-alive for the "External Connector" sessions: `KeepAliveRequest`s are sent periodically, measuring the round trip
+//! times of their answers, while the `KeepAliveRequest`s sent by the peer are used to estimate the skew between our clocks.
+//!
+//! By convention, the number in a `KeepAliveRequest` is the sender's UTC time, in milliseconds since midnight -- which
+//! is answered with that number + 1 (see [OgreExchangeMessagesForExternalConnectors::KeepAliveRequest](super::messages_model::OgreExchangeMessagesForExternalConnectors::KeepAliveRequest)).
+//!
+//! Sessions must be dropped -- with the [DisconnectionReason] returned here -- when any of the [HeartbeatLimits] is exceeded.
+
+use super::super::ogre_exchange_models::{DisconnectionReason, RiskManagementConnectionDroppingConditions};
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use log::debug;
+
+
+/// Milliseconds in a day -- the range of the numbers in `KeepAliveRequest`s
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+
+/// The configurable keep-alive parameters -- absent limits are not enforced
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct HeartbeatLimits {
+    /// How often `KeepAliveRequest`s are sent
+    pub interval_millis:       u64,
+    /// How long to wait for a `KeepAliveAnswer` before considering the peer gone
+    pub timeout_millis:        u64,
+    /// Maximum acceptable round trip time
+    pub max_round_trip_millis: Option<u64>,
+    /// Maximum acceptable difference between our clock and the peer's
+    pub max_clock_skew_millis: Option<u64>,
+}
+
+impl Default for HeartbeatLimits {
+    fn default() -> Self {
+        Self { interval_millis: 5_000, timeout_millis: 15_000, max_round_trip_millis: None, max_clock_skew_millis: None }
+    }
+}
+
+
+/// Latency statistics of a session -- see [Heartbeat::stats()]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    /// How many `KeepAliveAnswer`s were received
+    pub round_trips:                 u64,
+    pub last_round_trip:             Option<Duration>,
+    pub min_round_trip:              Option<Duration>,
+    pub max_round_trip:              Option<Duration>,
+    pub mean_round_trip:             Option<Duration>,
+    /// The peer's clock minus ours -- `None` until the peer sends a `KeepAliveRequest`
+    pub estimated_clock_skew_millis: Option<i64>,
+}
+
+
+/// Keep-alive state of a single session -- see the [module](self) docs
+#[derive(Debug)]
+pub struct Heartbeat {
+    limits:           HeartbeatLimits,
+    /// the number in our last `KeepAliveRequest` & when it was sent -- while unanswered
+    outstanding:      Option<(u32, Instant)>,
+    last_request_at:  Option<Instant>,
+    total_round_trip: Duration,
+    stats:            LatencyStats,
+}
+
+impl Heartbeat {
+
+    pub fn new(limits: HeartbeatLimits) -> Self {
+        Self { limits, outstanding: None, last_request_at: None, total_round_trip: Duration::ZERO, stats: LatencyStats::default() }
+    }
+
+    /// To be called often (more than once per [HeartbeatLimits::interval_millis]): returns the number to send in a
+    /// `KeepAliveRequest`, if it is time for one -- `millis_of_day` being our current UTC time.\
+    /// `Err` is returned if our previous request timed out
+    pub fn tick(&mut self, now: Instant, millis_of_day: u32) -> Result<Option<u32>, DisconnectionReason> {
+        if let Some((_n, sent_at)) = self.outstanding {
+            if now.saturating_duration_since(sent_at) >= Duration::from_millis(self.limits.timeout_millis) {
+                return Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::PingTimeout));
+            }
+            return Ok(None);
+        }
+        if self.last_request_at.is_some_and(|last_request_at| now.saturating_duration_since(last_request_at) < Duration::from_millis(self.limits.interval_millis)) {
+            return Ok(None);
+        }
+        self.outstanding = Some((millis_of_day, now));
+        self.last_request_at = Some(now);
+        Ok(Some(millis_of_day))
+    }
+
+    /// Measures the round trip time of our `KeepAliveRequest`, given the peer's `answer` to it.\
+    /// `Err` is returned if it is too high
+    pub fn on_answer(&mut self, answer: u32, now: Instant) -> Result<(), DisconnectionReason> {
+        let sent_at = match self.outstanding {
+            Some((n, sent_at)) if answer == n.wrapping_add(1) => sent_at,
+            _ => {
+                debug!("Heartbeat: ignoring the unexpected keep alive answer {answer} -- the outstanding request is {:?}", self.outstanding);
+                return Ok(());
+            },
+        };
+        self.outstanding = None;
+        let round_trip = now.saturating_duration_since(sent_at);
+        self.total_round_trip += round_trip;
+        let stats = &mut self.stats;
+        stats.round_trips += 1;
+        stats.last_round_trip = Some(round_trip);
+        stats.min_round_trip = Some(stats.min_round_trip.map_or(round_trip, |min| min.min(round_trip)));
+        stats.max_round_trip = Some(stats.max_round_trip.map_or(round_trip, |max| max.max(round_trip)));
+        stats.mean_round_trip = Some(self.total_round_trip / stats.round_trips as u32);
+        match self.limits.max_round_trip_millis {
+            Some(max_round_trip_millis) if round_trip > Duration::from_millis(max_round_trip_millis) =>
+                Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::RoundTripTimeTooHigh {
+                    nanos: round_trip.as_nanos().min(u32::MAX as u128) as u32,
+                })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Estimates the clock skew from the peer's `KeepAliveRequest`, which carries its `peer_millis_of_day` -- compensating
+    /// for half the last round trip time. `millis_of_day` is our current UTC time.\
+    /// `Err` is returned if the skew is too high
+    pub fn on_peer_request(&mut self, peer_millis_of_day: u32, millis_of_day: u32) -> Result<(), DisconnectionReason> {
+        if peer_millis_of_day as i64 >= MILLIS_PER_DAY {
+            debug!("Heartbeat: the peer's keep alive request {peer_millis_of_day} doesn't carry its clock -- the clock skew can't be estimated");
+            return Ok(());
+        }
+        let one_way_millis = self.stats.last_round_trip.map_or(0, |round_trip| round_trip.as_millis() as i64 / 2);
+        // wrapped into +/- 12h, as the clocks may be on different sides of midnight
+        let skew_millis = (peer_millis_of_day as i64 + one_way_millis - millis_of_day as i64 + MILLIS_PER_DAY / 2).rem_euclid(MILLIS_PER_DAY) - MILLIS_PER_DAY / 2;
+        self.stats.estimated_clock_skew_millis = Some(skew_millis);
+        match self.limits.max_clock_skew_millis {
+            Some(max_clock_skew_millis) if skew_millis.unsigned_abs() > max_clock_skew_millis =>
+                Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::ClockSkewTooHigh {
+                    estimated_delta_nanos: (skew_millis.unsigned_abs() as u128 * 1_000_000).min(u32::MAX as u128) as u32,
+                })),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn stats(&self) -> &LatencyStats {
+        &self.stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks requests are issued at the configured interval, with their round trip times measured -- timing out unanswered ones
+    #[test]
+    fn round_trips_and_timeouts() {
+        let mut heartbeat = Heartbeat::new(HeartbeatLimits { interval_millis: 1_000, timeout_millis: 3_000, max_round_trip_millis: Some(500), max_clock_skew_millis: None });
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert_eq!(heartbeat.tick(at(0), 100), Ok(Some(100)), "The first request should be sent right away");
+        assert_eq!(heartbeat.tick(at(10), 110), Ok(None), "No requests should be sent while one is outstanding");
+        assert_eq!(heartbeat.on_answer(100, at(20)), Ok(()), "Answers with the wrong number should be ignored");
+        assert_eq!(heartbeat.on_answer(101, at(40)), Ok(()), "Answers within the limits should be accepted");
+        assert_eq!(heartbeat.tick(at(500), 600), Ok(None), "Requests should respect the interval");
+        assert_eq!(heartbeat.tick(at(1_000), 1_100), Ok(Some(1_100)), "Requests should be sent at every interval");
+        assert_eq!(heartbeat.on_answer(1_101, at(1_060)), Ok(()), "Answers within the limits should be accepted");
+        assert_eq!(heartbeat.stats(), &LatencyStats {
+            round_trips:                 2,
+            last_round_trip:             Some(Duration::from_millis(60)),
+            min_round_trip:              Some(Duration::from_millis(40)),
+            max_round_trip:              Some(Duration::from_millis(60)),
+            mean_round_trip:             Some(Duration::from_millis(50)),
+            estimated_clock_skew_millis: None,
+        }, "Wrong latency statistics");
+
+        heartbeat.tick(at(2_000), 2_100).expect("sending a request");
+        assert_eq!(heartbeat.on_answer(2_101, at(2_600)), Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::RoundTripTimeTooHigh { nanos: 600_000_000 })),
+                   "Slow answers should drop the session");
+        heartbeat.tick(at(3_000), 3_100).expect("sending a request");
+        assert_eq!(heartbeat.tick(at(5_999), 6_099), Ok(None), "Requests shouldn't time out before the configured time");
+        assert_eq!(heartbeat.tick(at(6_000), 6_100), Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::PingTimeout)),
+                   "Unanswered requests should drop the session");
+    }
+
+    /// checks clock skews are estimated from the peer's requests -- even across midnight
+    #[test]
+    fn clock_skew() {
+        let mut heartbeat = Heartbeat::new(HeartbeatLimits { max_clock_skew_millis: Some(1_000), ..HeartbeatLimits::default() });
+        let start = Instant::now();
+        heartbeat.tick(start, 0).expect("sending a request");
+        heartbeat.on_answer(1, start + Duration::from_millis(200)).expect("answers without limits should be accepted");
+
+        assert_eq!(heartbeat.on_peer_request(10_000, 10_400), Ok(()), "Small skews should be accepted");
+        assert_eq!(heartbeat.stats().estimated_clock_skew_millis, Some(-300), "Skews should compensate for the one way trip time");
+        assert_eq!(heartbeat.on_peer_request(86_399_900, 500), Ok(()), "Skews should be estimated across midnight");
+        assert_eq!(heartbeat.stats().estimated_clock_skew_millis, Some(-500), "Wrong skew estimated across midnight");
+        assert_eq!(heartbeat.on_peer_request(12_000, 10_000), Err(DisconnectionReason::RiskManager(RiskManagementConnectionDroppingConditions::ClockSkewTooHigh { estimated_delta_nanos: 2_100_000_000 })),
+                   "Large skews should drop the session");
+        assert_eq!(heartbeat.on_peer_request(u32::MAX, 10_000), Ok(()), "Requests not carrying the peer's clock should be ignored");
+    }
+}
@@ -0,0 +1,159 @@
+//! The `OgreExchange` side of the authentication handshake described in `authenticator.rs` -- for testing the "External
+//! Connector" against a local loopback peer: in production, this side runs in the `OgreExchange`, never in this DLL.
+//!
+//! The [Handshake] (backed by a shared [Authenticator]) verifies the accounts against a [CredentialStore], freezing the
+//! ones failing too many authorizations in a row -- see [AuthenticationLimits].
+//!
+//! [CredentialStore]: super::authenticator::CredentialStore
+//! [AuthenticationLimits]: super::authenticator::AuthenticationLimits
+
+use super::{
+    super::ogre_exchange_models::{AccountToken, Capabilities, ConnectorIdentification, DisconnectionReason},
+    authenticator::Authenticator,
+    messages_model::{ExternalConnectorMessages, OgreExchangeMessagesForExternalConnectors, PROTOCOL_VERSION},
+    negotiation,
+};
+use std::time::Instant;
+use log::{info, warn};
+
+
+/// The `OgreExchange` side of the authentication handshake with a single "External Connector" -- see the [module](self) docs
+pub struct Handshake<'a> {
+    authenticator:        &'a Authenticator,
+    state:                HandshakeState,
+    /// what we offer in our `Welcome`
+    offered_capabilities: Capabilities,
+    /// what the "External Connector" claimed, out of the offered ones, in its identification
+    agreed_capabilities:  Option<Capabilities>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum HandshakeState {
+    AwaitingIdentification,
+    AwaitingAuthorization { account_token: AccountToken },
+    Authenticated         { account_token: AccountToken },
+    Refused,
+}
+
+impl<'a> Handshake<'a> {
+
+    pub fn new(authenticator: &'a Authenticator) -> Self {
+        Self {
+            authenticator,
+            state:                HandshakeState::AwaitingIdentification,
+            offered_capabilities: negotiation::supported_capabilities(),
+            agreed_capabilities:  None,
+        }
+    }
+
+    /// Offers `capabilities` in our `Welcome` -- instead of all the supported ones
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.offered_capabilities = capabilities;
+        self
+    }
+
+    /// The greeting starting this handshake
+    pub fn welcome(&self) -> OgreExchangeMessagesForExternalConnectors {
+        OgreExchangeMessagesForExternalConnectors::Welcome { version: PROTOCOL_VERSION.to_string(), capabilities: self.offered_capabilities.clone() }
+    }
+
+    /// Advances the handshake with `message`, returning the answer to it -- `None` once authenticated, when messages
+    /// are no longer part of the handshake
+    pub fn process(&mut self, message: &ExternalConnectorMessages, now: Instant) -> Option<OgreExchangeMessagesForExternalConnectors> {
+        let refuse = |state: &mut HandshakeState, reason: DisconnectionReason| {
+            *state = HandshakeState::Refused;
+            Some(OgreExchangeMessagesForExternalConnectors::Disconnected(reason))
+        };
+        match (&self.state, message) {
+            (HandshakeState::AwaitingIdentification, ExternalConnectorMessages::ConnectorIdentification(identification)) => {
+                let (ConnectorIdentification::MarketDataBridge { version, account_token, capabilities, .. } |
+                     ConnectorIdentification::FullAdvisor      { version, account_token, capabilities, .. } |
+                     ConnectorIdentification::WatcherAdvisor   { version, account_token, capabilities, .. }) = identification;
+                if let Err(reason) = negotiation::check_peer_version(version) {
+                    warn!("Handshake: refusing account '{account_token}', whose protocol version '{version}' is not compatible with ours, '{PROTOCOL_VERSION}'");
+                    return refuse(&mut self.state, reason);
+                }
+                if !capabilities.is_subset_of(&self.offered_capabilities) {
+                    let message = format!("claimed capabilities {capabilities:?} were not offered: {:?}", self.offered_capabilities);
+                    return refuse(&mut self.state, DisconnectionReason::ProtocolOffense { message });
+                }
+                self.agreed_capabilities = Some(capabilities.clone());
+                match self.authenticator.identify(account_token, now) {
+                    Ok(()) => {
+                        self.state = HandshakeState::AwaitingAuthorization { account_token: account_token.clone() };
+                        Some(OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue)
+                    },
+                    Err(reason) => refuse(&mut self.state, reason),
+                }
+            },
+            (HandshakeState::AwaitingAuthorization { account_token }, ExternalConnectorMessages::UserAuthorization(secret)) => {
+                match self.authenticator.authorize(account_token, secret, now) {
+                    Ok(()) => {
+                        info!("Handshake: account '{account_token}' authenticated");
+                        self.state = HandshakeState::Authenticated { account_token: account_token.clone() };
+                        None
+                    },
+                    Err(reason) => refuse(&mut self.state, reason),
+                }
+            },
+            (HandshakeState::Authenticated { .. }, _) => None,
+            (state, unexpected) => {
+                let message = format!("{unexpected:?} is not expected while in the authentication state {state:?}");
+                refuse(&mut self.state, DisconnectionReason::ProtocolOffense { message })
+            },
+        }
+    }
+
+    /// The capabilities agreed upon with the "External Connector" -- `None` if it didn't identify itself (yet)
+    pub fn agreed_capabilities(&self) -> Option<&Capabilities> {
+        self.agreed_capabilities.as_ref()
+    }
+
+    /// The account authenticated by this handshake -- `None` if not (yet) authenticated
+    pub fn authenticated_account(&self) -> Option<&str> {
+        match &self.state {
+            HandshakeState::Authenticated { account_token } => Some(account_token),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::authenticator::{AuthenticationLimits, CredentialStore};
+
+
+    /// checks the handshake refuses messages out of order -- and stays out of the way once authenticated
+    #[test]
+    fn handshake_states() {
+        let authenticator = Authenticator::new(CredentialStore::default().with_account("acnt_tkn", "s3cr3t"), AuthenticationLimits::default());
+        let identification = |version: &str| ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::MarketDataBridge {
+            version: version.to_string(), symbol: String::from("PETR4"), account_token: String::from("acnt_tkn"), capabilities: negotiation::supported_capabilities(),
+        });
+        let now = Instant::now();
+
+        let mut handshake = Handshake::new(&authenticator);
+        assert!(matches!(handshake.process(&ExternalConnectorMessages::UserAuthorization(String::from("s3cr3t")), now),
+                         Some(OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::ProtocolOffense { .. }))),
+                "Authorizations before the identification should be refused");
+
+        let mut handshake = Handshake::new(&authenticator);
+        assert!(matches!(handshake.process(&identification("2023-07-04"), now), Some(OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::DeprecatedConnectorVersion { .. }))),
+                "Connectors in unsupported protocol versions should be refused");
+
+        let offered = Capabilities { binary_encoding: false, ..negotiation::supported_capabilities() };
+        let mut handshake = Handshake::new(&authenticator).with_capabilities(offered);
+        assert!(matches!(handshake.process(&identification(PROTOCOL_VERSION), now), Some(OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::ProtocolOffense { .. }))),
+                "Connectors claiming capabilities that were not offered should be refused");
+
+        let mut handshake = Handshake::new(&authenticator);
+        assert_eq!(handshake.process(&identification(PROTOCOL_VERSION), now), Some(OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue), "Known accounts should be asked for authorization");
+        assert_eq!(handshake.agreed_capabilities(), Some(&negotiation::supported_capabilities()), "The claimed capabilities should be agreed upon");
+        assert_eq!(handshake.authenticated_account(), None, "Identifying is not authenticating");
+        assert_eq!(handshake.process(&ExternalConnectorMessages::UserAuthorization(String::from("s3cr3t")), now), None, "Successful authorizations need no answer");
+        assert_eq!(handshake.authenticated_account(), Some("acnt_tkn"), "The account should be authenticated");
+        assert_eq!(handshake.process(&ExternalConnectorMessages::KeepAliveRequest(1), now), None, "Once authenticated, messages are not part of the handshake");
+    }
+}
@@ -0,0 +1,266 @@
+//! Streams the market data seen by the MQL Programs to the `OgreExchange` peers whose sessions are bound to the same symbol,
+//! as [ExternalConnectorMessages::MarketData].
+//!
+//! Peers are subscribed as soon as their dialog starts, but only receive market data once their session is bound to a
+//! symbol (see [bind()]) -- any number of peers may be bound to the same symbol.
+//!
+//! Publishing happens in the Metatrader callback threads (`on_tick()` & `on_book()`), which must never block: each peer
+//! has a bounded queue, merged into its dialog's output stream (see `external_connector_processor.rs`) -- if a slow peer
+//! lets its queue fill up, new events are dropped (and counted) for that peer only, until it catches up.
+
+use super::{
+    super::{
+        types::*,
+        ogre_exchange_models::Parties,
+    },
+    messages_model::{ExternalConnectorMessages, ExternalConnectorMarketData},
+};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use dashmap::DashMap;
+use futures::channel::mpsc;
+use once_cell::sync::Lazy;
+use log::{debug, warn};
+
+
+/// How many market data events may be waiting to be sent to each peer -- the excess is dropped
+pub const MARKET_DATA_QUEUE_CAPACITY: usize = 1024;
+
+
+/// Subscriptions, by `peer_id`
+static SUBSCRIPTIONS: Lazy<DashMap<u32, Subscription>> = Lazy::new(DashMap::new);
+
+struct Subscription {
+    /// `None` until the peer's session is bound to a symbol
+    symbol:   Option<String>,
+    sender:   mpsc::Sender<ExternalConnectorMessages>,
+    /// how many events couldn't be queued, as the peer wasn't keeping up
+    dropped:  u64,
+    /// tells if the last event was dropped -- so only the start of each dropping streak is logged
+    dropping: bool,
+}
+
+
+/// Subscribes `peer_id` to market data, returning the stream of messages to be sent to it -- which stays quiet until
+/// the peer is [bind()]ed to a symbol
+pub fn subscribe(peer_id: u32, capacity: usize) -> mpsc::Receiver<ExternalConnectorMessages> {
+    let (sender, receiver) = mpsc::channel(capacity);
+    SUBSCRIPTIONS.insert(peer_id, Subscription { symbol: None, sender, dropped: 0, dropping: false });
+    receiver
+}
+
+/// Streams the market data of `symbol` to the subscribed `peer_id` -- replacing the symbol it was previously bound to, if any.\
+/// Returns `false` if `peer_id` is not subscribed
+pub fn bind(peer_id: u32, symbol: &str) -> bool {
+    SUBSCRIPTIONS.get_mut(&peer_id)
+        .map(|mut subscription| subscription.symbol = Some(symbol.to_string()))
+        .is_some()
+}
+
+/// Stops publishing to `peer_id` -- ending its stream
+pub fn unsubscribe(peer_id: u32) {
+    if let Some((_peer_id, subscription)) = SUBSCRIPTIONS.remove(&peer_id) {
+        debug!("MarketDataPublisher: peer #{peer_id} unsubscribed from {:?} -- {} events were dropped", subscription.symbol, subscription.dropped);
+    }
+}
+
+/// How many events were dropped for `peer_id`, as it wasn't keeping up -- `None` if it is not subscribed
+pub fn dropped_events(peer_id: u32) -> Option<u64> {
+    SUBSCRIPTIONS.get(&peer_id).map(|subscription| subscription.dropped)
+}
+
+/// Publishes a trade reported by `on_tick()`
+pub fn publish_trade(trade: &Trade) {
+    publish(trade.symbol, || {
+        let (date, time) = date_and_time(&trade.time);
+        vec![ExternalConnectorMarketData::Trade {
+            date,
+            time,
+            symbol:             trade.symbol.clone(),
+            unitary_mill_value: to_mills(trade.price),
+            quantity:           trade.quantity,
+            aggressor:          aggressor(trade),
+        }]
+    })
+}
+
+/// Publishes the state of each price level affected by `delta_events` -- taken from `books`, which must already have the
+/// events applied. Removed levels are published with `available_quantity` 0.\
+/// `n_orders` is always unknown (`None`), as Metatrader doesn't inform it -- and market orders (see [BookParties::MarketSellers]) are left out
+pub fn publish_book_deltas(symbol: &str, time: &NaiveDateTime, books: &OrderBooks, delta_events: &[BookEvents]) {
+    publish(symbol, || {
+        let (date, time) = date_and_time(time);
+        delta_events.iter()
+            .filter_map(|delta_event| {
+                let (book, price) = match delta_event {
+                    BookEvents::Add    { book, price, .. } |
+                    BookEvents::Del    { book, price, .. } |
+                    BookEvents::Update { book, price, .. } => (book, *price),
+                };
+                let (levels, side) = match book {
+                    BookParties::Sellers => (&books.sell_orders, Parties::Seller),
+                    BookParties::Buyers  => (&books.buy_orders,  Parties::Buyer),
+                    // the protocol has no messages for them, yet
+                    BookParties::MarketSellers | BookParties::MarketBuyers => return None,
+                };
+                let available_quantity = levels.iter()
+                    .find(|level| level.price == price)
+                    .map_or(0, |level| level.volume.round() as u32);
+                Some(book_level(symbol, date, time, price, available_quantity, side))
+            })
+            .collect()
+    })
+}
+
+/// The state of the price levels in `books` -- up to `depth` of them, for each side -- as of `time`: for peers that need the
+/// whole book, like the ones that just reconnected. Levels are listed in the same order as [OrderBooks::iter()]
+pub fn book_snapshot(symbol: &str, time: &NaiveDateTime, books: &OrderBooks, depth: usize) -> Vec<ExternalConnectorMarketData> {
+    let (date, time) = date_and_time(time);
+    // in MetaTrader's order, the best ask is the last of the sell levels & the best bid, the first of the buy levels
+    books.sell_orders.iter().skip(books.sell_orders.len().saturating_sub(depth)).map(|level| (level, Parties::Seller))
+        .chain(books.buy_orders.iter().take(depth).map(|level| (level, Parties::Buyer)))
+        .map(|(level, side)| book_level(symbol, date, time, level.price, level.volume.round() as u32, side))
+        .collect()
+}
+
+/// Encodes `date_time` as (YYYYMMDD, HHMMSSMMM) -- as used in the messages
+pub fn date_and_time(date_time: &NaiveDateTime) -> (u32, u32) {
+    let date = date_time.year() as u32 * 10000 + date_time.month() * 100 + date_time.day();
+    let time = date_time.hour() * 10000000 + date_time.minute() * 100000 + date_time.second() * 1000 + (date_time.nanosecond() / 1_000_000).min(999);
+    (date, time)
+}
+
+/// Converts `price` to the "mill value" representation used in the messages
+pub fn to_mills(price: f64) -> u32 {
+    (price * 1000.0).round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+
+/// A `Book` event for a price level -- `n_orders` is always unknown, as Metatrader doesn't inform it
+fn book_level(symbol: &str, date: u32, time: u32, price: f64, available_quantity: u32, side: Parties) -> ExternalConnectorMarketData {
+    ExternalConnectorMarketData::Book {
+        date,
+        time,
+        symbol:            symbol.to_string(),
+        price_level_mills: to_mills(price),
+        n_orders:          None,
+        available_quantity,
+        side,
+    }
+}
+
+/// Queues the events built by `events` for all peers subscribed to `symbol` -- building them only if there are any
+fn publish(symbol: &str, events: impl FnOnce() -> Vec<ExternalConnectorMarketData>) {
+    if SUBSCRIPTIONS.is_empty() {
+        return;
+    }
+    let mut events = Some(events);
+    let mut messages = vec![];
+    for mut subscription in SUBSCRIPTIONS.iter_mut().filter(|subscription| subscription.symbol.as_deref() == Some(symbol)) {
+        if let Some(events) = events.take() {
+            messages = events();
+        }
+        let peer_id = *subscription.key();
+        let subscription = subscription.value_mut();
+        for event in &messages {
+            match subscription.sender.try_send(ExternalConnectorMessages::MarketData(event.clone())) {
+                Ok(()) => subscription.dropping = false,
+                Err(err) if err.is_full() => {
+                    if !subscription.dropping {
+                        warn!("MarketDataPublisher: peer #{peer_id} is not keeping up with '{symbol}' -- dropping events until it does ({} dropped so far)", subscription.dropped);
+                        subscription.dropping = true;
+                    }
+                    subscription.dropped += 1;
+                },
+                // disconnected: will be unsubscribed by `PeerDisconnected`
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// Maps the [TradeParty] into the `OgreExchange`'s [Parties] -- resolving unknown aggressors by the trade price:
+/// the one closer to the ask is considered a buy
+fn aggressor(trade: &Trade) -> Parties {
+    match trade.aggressor {
+        TradeParty::Buyer  { .. } => Parties::Buyer,
+        TradeParty::Seller { .. } => Parties::Seller,
+        TradeParty::Ambiguous   { bid, ask } |
+        TradeParty::Unspecified { bid, ask } => if (ask - trade.price).abs() <= (trade.price - bid).abs() { Parties::Buyer } else { Parties::Seller },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use chrono::NaiveDate;
+
+
+    /// checks trades & book deltas are converted to the messages -- and only sent to peers bound to their symbol
+    #[test]
+    fn market_data_conversion() {
+        let (petr4, vale3) = (String::from("PETR4.pub"), String::from("VALE3.pub"));
+        let mut petr4_receiver = subscribe(9001, 16);
+        let mut vale3_receiver = subscribe(9002, 16);
+        let mut unbound_receiver = subscribe(9004, 16);
+        assert!(bind(9001, &petr4) && bind(9002, &vale3), "Subscribed peers should be bindable");
+        assert!(!bind(9005, &petr4), "Unsubscribed peers should not be bindable");
+        let time = NaiveDate::from_ymd_opt(2023, 7, 4).unwrap().and_hms_milli_opt(10, 5, 9, 21).unwrap();
+
+        publish_trade(&Trade { symbol: &petr4, time, aggressor: TradeParty::Unspecified { bid: 32.01, ask: 32.02 }, confidence: AggressorConfidence::High, inferred: false, quantity: 100, price: 32.02 });
+        assert_eq!(petr4_receiver.try_next().ok().flatten(),
+                   Some(ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Trade { date: 20230704, time: 100509021, symbol: petr4.clone(), unitary_mill_value: 32020, quantity: 100, aggressor: Parties::Buyer })),
+                   "Wrong trade message");
+
+        let books = OrderBooks {
+            sell_orders: VecDeque::from([MqlBookInfo { book_type: EnumBookType::BookTypeSell, price: 32.02, volume: 300.0 }]),
+            ..OrderBooks::default()
+        };
+        publish_book_deltas(&petr4, &time, &books, &[
+            BookEvents::Update { book: BookParties::Sellers,      price: 32.02, delta_quantity: 200.0 },
+            BookEvents::Add    { book: BookParties::MarketBuyers, price: 32.02, quantity: 500.0 },
+            BookEvents::Del    { book: BookParties::Buyers,       price: 32.01, quantity: 100.0 },
+        ]);
+        let book_event = |price_level_mills, available_quantity, side| Some(ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book {
+            date: 20230704, time: 100509021, symbol: petr4.clone(), price_level_mills, n_orders: None, available_quantity, side,
+        }));
+        assert_eq!(petr4_receiver.try_next().ok().flatten(), book_event(32020, 300, Parties::Seller), "Updated levels should be published with their current quantity");
+        assert_eq!(petr4_receiver.try_next().ok().flatten(), book_event(32010, 0, Parties::Buyer), "Removed levels should be published with no quantity -- and market orders, not at all");
+        assert!(vale3_receiver.try_next().is_err(), "Peers bound to other symbols should receive nothing");
+        assert!(unbound_receiver.try_next().is_err(), "Unbound peers should receive nothing");
+
+        bind(9002, &petr4);
+        bind(9004, &petr4);
+        publish_trade(&Trade { symbol: &petr4, time, aggressor: TradeParty::Seller { bid: 32.01, ask: 32.02 }, confidence: AggressorConfidence::High, inferred: false, quantity: 200, price: 32.01 });
+        for (peer, receiver) in [("9001", &mut petr4_receiver), ("9002 (rebound)", &mut vale3_receiver), ("9004", &mut unbound_receiver)] {
+            assert!(matches!(receiver.try_next().ok().flatten(), Some(ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Trade { quantity: 200, .. }))),
+                    "All peers bound to the symbol should receive its trades -- peer {peer} didn't");
+        }
+        unsubscribe(9001);
+        unsubscribe(9002);
+        unsubscribe(9004);
+    }
+
+    /// checks slow peers have their events dropped -- instead of blocking the publisher
+    #[test]
+    fn backpressure() {
+        let symbol = String::from("PETR4.backpressure");
+        let mut receiver = subscribe(9003, 4);
+        bind(9003, &symbol);
+        let time = NaiveDate::from_ymd_opt(2023, 7, 4).unwrap().and_hms_opt(10, 5, 9).unwrap();
+        for _ in 0..100 {
+            publish_trade(&Trade { symbol: &symbol, time, aggressor: TradeParty::Seller { bid: 32.01, ask: 32.02 }, confidence: AggressorConfidence::High, inferred: false, quantity: 100, price: 32.01 });
+        }
+        let mut received = 0;
+        while let Ok(Some(_)) = receiver.try_next() {
+            received += 1;
+        }
+        assert!(received > 0 && received < 100, "Only the events fitting the queue should have been received -- not {received}");
+        assert_eq!(dropped_events(9003), Some(100 - received), "The remaining events should have been dropped");
+        publish_trade(&Trade { symbol: &symbol, time, aggressor: TradeParty::Seller { bid: 32.01, ask: 32.02 }, confidence: AggressorConfidence::High, inferred: false, quantity: 100, price: 32.01 });
+        assert!(receiver.try_next().ok().flatten().is_some(), "Events should be received again once the peer catches up");
+        unsubscribe(9003);
+        assert_eq!(dropped_events(9003), None, "Unsubscribed peers should be forgotten");
+    }
+}
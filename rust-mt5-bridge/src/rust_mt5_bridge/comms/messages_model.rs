@@ -1,13 +1,15 @@
 //! Defines the `reactive-messaging` models for inter-process communications
 
 use std::error::Error;
-use reactive_messaging::{ron_deserializer, ron_serializer, SocketServerDeserializer, SocketServerSerializer};
+use std::fmt::{Display, Formatter};
+use reactive_messaging::{ron_serializer, SocketServerDeserializer, SocketServerSerializer};
 use super::super::ogre_exchange_models::*;
+use super::wire_format::{self, WireFormat, WireMessage};
 use serde::{Serialize, Deserialize};
 
 
-/// Here so history, possibly... dates while in alpha, semantic versioning from there on
-pub const PROTOCOL_VERSION: &str = "2023-07-04";
+/// Here so history, possibly... dates while in alpha, semantic versioning from there on -- see `negotiation.rs` for the compatibility rules
+pub const PROTOCOL_VERSION: &str = "1.0.0";
 
 
 /// Messages sent by agents integrated on external Exchanges or Brokers, sharing information
@@ -23,12 +25,17 @@ pub enum ExternalConnectorMessages {
     UserAuthorization(String),
 
     /// Asks the `OgreExchange` server to return, as soon as possible, the given number + 1 with a [OgreExchangeMessagesForExternalConnectors::KeepAliveAnswer]
-    /// -- may be used to measure round trip times, as well as to check the peer's liveliness
+    /// -- may be used to measure round trip times, as well as to check the peer's liveliness.\
+    /// The number is the sender's UTC time, in milliseconds since midnight -- so clock skews may be estimated
     KeepAliveRequest(u32),
 
     /// Similar to [ExternalConnectorMessages::KeepAliveRequest], but sent in response to [OgreExchangeMessagesForExternalConnectors::KeepAliveRequest]
     KeepAliveAnswer(u32),
 
+    /// Answers [OgreExchangeMessagesForExternalConnectors::UseWireFormat], confirming the format this "External Connector"
+    /// sends its messages in, from this one on
+    WireFormatInUse(WireFormat),
+
     /// The "External Connector" sends this message when there is Market Data updates to share regarding negotiable symbols
     MarketData(ExternalConnectorMarketData),
 
@@ -68,6 +75,18 @@ pub enum ExternalConnectorMessages {
         // order_data...
     },
 
+    /// A [ConnectorIdentification::FullAdvisor] client informs one of its open positions, as asked by
+    /// [OgreExchangeMessagesForExternalConnectors::StateOpenPositions]
+    OpenPosition {
+        symbol: String,
+        /// [Parties::Buyer] for long positions, [Parties::Seller] for short ones
+        side: Parties,
+        /// how many papers of that symbol are held
+        quantity: u32,
+        /// the average entry price, multiplied by 1000 -- or cent value multiplied by 10
+        average_unitary_mill_value: u32,
+    },
+
     /// A [ConnectorIdentification::FullAdvisor] client asks for any new drawable events (after `sequential`) to be sent back
     ChartPoints { sequential: u32 },
 
@@ -88,20 +107,49 @@ pub enum ExternalConnectorMessages {
     /// The `String` param contains a textual explanation for the disconnection reason.
     GoodBye(String),
 
+    /// The "External Connector" drops the `OgreExchange` server, for the given reason -- the counterpart of
+    /// [OgreExchangeMessagesForExternalConnectors::Disconnected]
+    Disconnected(DisconnectionReason),
+
 }
 
 /// Response/reactions/inquiries the `OgreExchange` may re-act or pro-act when interacting with "External Connectors"
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum OgreExchangeMessagesForExternalConnectors {
 
-    /// `OgreExchange` server greeting, sent as soon as the connection with the client is accepted.\
-    /// From here, client must send [ExternalConnectorMessages::ConnectorIdentification]
-    Welcome,
+    /// `OgreExchange` server greeting, sent as soon as the connection with the client is accepted, stating its protocol `version`
+    /// and the `capabilities` it offers.\
+    /// From here, client must send [ExternalConnectorMessages::ConnectorIdentification] -- or say `GoodBye`, if the `version`
+    /// is not compatible with its own (see `negotiation.rs`)
+    Welcome {
+        version:      Version,
+        capabilities: Capabilities,
+    },
+
+    /// Binds the session to the MQL Program trading `symbol` -- for `account_token`, if given -- whose market data & orders
+    /// will, from here on, be the subject of the session. May be sent again to rebind it.\
+    /// Answered with [ExternalConnectorMessages::ConnectorIdentification] -- or with [ExternalConnectorMessages::ProcessorError]
+    /// if no such MQL Program is registered (in which case the binding remains, taking effect once it registers)
+    BindSession {
+        symbol:        String,
+        account_token: Option<String>,
+    },
+
+    /// Asks the "External Connector" to send its messages in the given [WireFormat] -- taking effect for its answer,
+    /// [ExternalConnectorMessages::WireFormatInUse]. Both formats are always accepted on receipt -- see `comms/wire_format.rs`
+    UseWireFormat(WireFormat),
 
     /// Depending on the [ConnectorIdentification], the `OgreExchange` server may require [UserAuthorization] to continue.\
-    /// Upon receiving this, the "External Connector" must answer with [ConnectorIdentification::UserAuthorization]
+    /// Upon receiving this, the "External Connector" must answer with [ConnectorIdentification::UserAuthorization].\
+    /// Sessions not authorized this way may not rebind, ask for the open positions or schedule & cancel orders
     ProvideAuthorizationToContinue,
 
+    /// When the "External Connector" is the one listening for connections -- which anyone may open -- the `OgreExchange`
+    /// must prove itself with the secret of the account stated in [ExternalConnectorMessages::ConnectorIdentification],
+    /// before anything requiring authorization -- [OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue]
+    /// included. Not answered if the secret is right: refusals are answered with [ExternalConnectorMessages::Disconnected]
+    ServerAuthorization(String),
+
     /// If something goes unexpected, the `OgreExchange` server may decide to drop the "External Connector"
     /// -- wrong/missing login, wrong protocol, server being shutdown, ...
     Disconnected(DisconnectionReason),
@@ -110,7 +158,8 @@ pub enum OgreExchangeMessagesForExternalConnectors {
     /// -- may be used to measure round trip times, as well as to check the peer's liveliness.\
     /// If a [ConnectorIdentification::MarketDataBridge] times out responding, its asset's symbol is
     /// marked as `stale` and precautions are taken to minimize risks -- no new buying orders (cancelling un-executed ones),
-    /// sound an alarm if there are open positions, sell all open positions at market price, etc.
+    /// sound an alarm if there are open positions, sell all open positions at market price, etc.\
+    /// As in [ExternalConnectorMessages::KeepAliveRequest], the number is the sender's UTC time, in milliseconds since midnight
     KeepAliveRequest(u32),
 
     /// Similar to [OgreExchangeMessagesForExternalConnectors::KeepAliveRequest], but sent in response to
@@ -154,8 +203,9 @@ pub enum OgreExchangeMessagesForExternalConnectors {
 }
 
 
-// Implementations of the RON SerDe
-///////////////////////////////////
+// Implementations of the SerDe
+////////////////////////////////
+// messages are sent in RON -- unless wrapped in a `WireMessage`, telling the format -- and received in any format
 
 // TODO 2023-07-04: rename "SocketServer" Serialize/Deserializer to "ReactiveMessaging*"
 impl SocketServerSerializer<ExternalConnectorMessages> for ExternalConnectorMessages {
@@ -173,7 +223,7 @@ impl SocketServerSerializer<ExternalConnectorMessages> for ExternalConnectorMess
 
     #[inline(always)]
     fn is_disconnect_message(processor_answer: &ExternalConnectorMessages) -> bool {
-        matches!(processor_answer, ExternalConnectorMessages::GoodBye(_))
+        matches!(processor_answer, ExternalConnectorMessages::GoodBye(_) | ExternalConnectorMessages::Disconnected(_))
     }
 
     #[inline(always)]
@@ -185,7 +235,31 @@ impl SocketServerSerializer<ExternalConnectorMessages> for ExternalConnectorMess
 impl SocketServerDeserializer<ExternalConnectorMessages> for ExternalConnectorMessages {
     #[inline(always)]
     fn deserialize(local_message: &[u8]) -> Result<ExternalConnectorMessages, Box<dyn Error + Sync + Send>> {
-        ron_deserializer(local_message)
+        wire_format::deserialize(local_message)
+    }
+}
+
+impl SocketServerSerializer<WireMessage<ExternalConnectorMessages>> for WireMessage<ExternalConnectorMessages> {
+
+    #[inline(always)]
+    fn serialize(remote_message: &WireMessage<ExternalConnectorMessages>, buffer: &mut Vec<u8>) {
+        wire_format::serialize(remote_message.format, &remote_message.message, buffer)
+            .expect("`wire_format::serialize()` for `ExternalConnectorMessages`");
+    }
+
+    #[inline(always)]
+    fn processor_error_message(err: String) -> WireMessage<ExternalConnectorMessages> {
+        WireMessage { format: WireFormat::Ron, message: ExternalConnectorMessages::ProcessorError(err) }
+    }
+
+    #[inline(always)]
+    fn is_disconnect_message(processor_answer: &WireMessage<ExternalConnectorMessages>) -> bool {
+        <ExternalConnectorMessages as SocketServerSerializer<ExternalConnectorMessages>>::is_disconnect_message(&processor_answer.message)
+    }
+
+    #[inline(always)]
+    fn is_no_answer_message(processor_answer: &WireMessage<ExternalConnectorMessages>) -> bool {
+        <ExternalConnectorMessages as SocketServerSerializer<ExternalConnectorMessages>>::is_no_answer_message(&processor_answer.message)
     }
 }
 
@@ -216,14 +290,14 @@ impl SocketServerSerializer<OgreExchangeMessagesForExternalConnectors> for OgreE
 impl SocketServerDeserializer<OgreExchangeMessagesForExternalConnectors> for OgreExchangeMessagesForExternalConnectors {
     #[inline(always)]
     fn deserialize(local_message: &[u8]) -> Result<OgreExchangeMessagesForExternalConnectors, Box<dyn Error + Sync + Send>> {
-        ron_deserializer(local_message)
+        wire_format::deserialize(local_message)
     }
 }
 
 
 /// Market data, as informed by the client -- with easy to generate info (when compared to the
 /// heavily optimized internal versions of the same data that we use).\
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ExternalConnectorMarketData {
 
     /// Sent by a [ClientIdentification::MarketDataBridge] client to update the server with the symbol information
@@ -245,8 +319,8 @@ pub enum ExternalConnectorMarketData {
         symbol: String,
         /// the price
         price_level_mills: u32,
-        /// the number of orders waiting
-        n_orders: u32,
+        /// the number of orders waiting -- `None` if unknown, as Metatrader doesn't inform it
+        n_orders: Option<u32>,
         /// the total quantity of booked orders
         available_quantity: u32,
         /// the operation those orders want to make
@@ -270,18 +344,105 @@ pub enum ExternalConnectorMarketData {
         aggressor: Parties,
     },
 }
-impl Into<MarketData> for ExternalConnectorMarketData {
-    fn into(self) -> MarketData {
-        match self {
+/// Validates & converts the market data informed by "External Connectors" into the internal representation
+/// -- encoding `date` & `time` as [NeatDate] & [NeatTime]. See also [ExternalConnectorMarketData::try_into_market_data_for()]
+impl TryFrom<ExternalConnectorMarketData> for MarketData {
+    type Error = MarketDataConversionError;
+
+    fn try_from(market_data: ExternalConnectorMarketData) -> Result<Self, Self::Error> {
+        Ok(match market_data {
             ExternalConnectorMarketData::SymbolState { symbol, in_auction } => MarketData::SymbolState(SymbolState { symbol, in_auction }),
-            ExternalConnectorMarketData::Book { .. } => todo!("Please, go ahead and develop the conversion from ExternalConnector Book Into SingleBook"),
-            ExternalConnectorMarketData::Trade { .. } => todo!("Please, go ahead and develop the conversion from ExternalConnector Trade Into SingleTrade"),
+            ExternalConnectorMarketData::Book { date, time, symbol, price_level_mills, n_orders, available_quantity, side } =>
+                MarketData::BookState(SingleBook { date: to_neat_date(date)?, time: to_neat_time(time)?, symbol, price_level_mills, n_orders, available_quantity, side }),
+            ExternalConnectorMarketData::Trade { date, time, symbol, unitary_mill_value, quantity, aggressor } =>
+                MarketData::Trade(SingleTrade { date: to_neat_date(date)?, time: to_neat_time(time)?, symbol, unitary_mill_value, quantity, aggressor }),
+        })
+    }
+}
+impl ExternalConnectorMarketData {
+
+    /// Same as the [TryFrom] conversion into [MarketData], additionally checking the data is for `expected_symbol`
+    /// -- the one stated by the "External Connector" when identifying itself
+    pub fn try_into_market_data_for(self, expected_symbol: &str) -> Result<MarketData, MarketDataConversionError> {
+        let symbol = match &self {
+            ExternalConnectorMarketData::SymbolState { symbol, .. } |
+            ExternalConnectorMarketData::Book        { symbol, .. } |
+            ExternalConnectorMarketData::Trade       { symbol, .. } => symbol,
+        };
+        if symbol != expected_symbol {
+            return Err(MarketDataConversionError::SymbolMismatch { expected: expected_symbol.to_string(), found: symbol.clone() });
+        }
+        MarketData::try_from(self)
+    }
+}
+/// The reverse of the [TryFrom] conversion -- which is lossless: `date` & `time` are restored as informed
+impl From<MarketData> for ExternalConnectorMarketData {
+    fn from(market_data: MarketData) -> Self {
+        match market_data {
+            MarketData::SymbolState(SymbolState { symbol, in_auction }) => ExternalConnectorMarketData::SymbolState { symbol, in_auction },
+            MarketData::BookState(SingleBook { date, time, symbol, price_level_mills, n_orders, available_quantity, side }) =>
+                ExternalConnectorMarketData::Book { date: from_neat_date(date), time: from_neat_time(time), symbol, price_level_mills, n_orders, available_quantity, side },
+            MarketData::Trade(SingleTrade { date, time, symbol, unitary_mill_value, quantity, aggressor }) =>
+                ExternalConnectorMarketData::Trade { date: from_neat_date(date), time: from_neat_time(time), symbol, unitary_mill_value, quantity, aggressor },
         }
     }
 }
 
+/// Reasons for [ExternalConnectorMarketData] to be refused when converting it into [MarketData]
+#[derive(Debug, PartialEq)]
+pub enum MarketDataConversionError {
+    /// `date` is not a valid YYYYMMDD date
+    InvalidDate { date: u32 },
+    /// `date` is valid, but doesn't fit in a [NeatDate]
+    DateOverflow { date: u32 },
+    /// `time` is not a valid HHMMSSMMM time
+    InvalidTime { time: u32 },
+    /// The data is for a symbol other than the one the "External Connector" identified itself with
+    SymbolMismatch { expected: String, found: String },
+}
+impl Display for MarketDataConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidDate { date }              => write!(f, "invalid date {date} -- expected YYYYMMDD"),
+            Self::DateOverflow { date }             => write!(f, "date {date} is out of the NeatDate range"),
+            Self::InvalidTime { time }              => write!(f, "invalid time {time} -- expected HHMMSSMMM"),
+            Self::SymbolMismatch { expected, found } => write!(f, "market data for symbol '{found}' was informed, but '{expected}' was expected"),
+        }
+    }
+}
+impl Error for MarketDataConversionError {}
+
+/// YYYYMMDD -> [NeatDate]
+fn to_neat_date(date: u32) -> Result<NeatDate, MarketDataConversionError> {
+    let (year, month, day) = ((date / 10000) as i32, (date / 100) % 100, date % 100);
+    chrono::NaiveDate::from_ymd_opt(year, month, day).ok_or(MarketDataConversionError::InvalidDate { date })?;
+    neat_date(year, month, day).ok_or(MarketDataConversionError::DateOverflow { date })
+}
+
+/// [NeatDate] -> YYYYMMDD
+fn from_neat_date(neat_date: NeatDate) -> u32 {
+    let (year, month, day) = ymd_from_neat_date(neat_date);
+    year as u32 * 10000 + month * 100 + day
+}
+
+/// HHMMSSMMM -> [NeatTime]
+fn to_neat_time(time: u32) -> Result<NeatTime, MarketDataConversionError> {
+    let (hours, minutes, seconds, millis) = (time / 10000000, (time / 100000) % 100, (time / 1000) % 100, time % 1000);
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(MarketDataConversionError::InvalidTime { time });
+    }
+    neat_time(((hours * 60 + minutes) * 60 + seconds) * 1000 + millis).ok_or(MarketDataConversionError::InvalidTime { time })
+}
+
+/// [NeatTime] -> HHMMSSMMM
+fn from_neat_time(neat_time: NeatTime) -> u32 {
+    let millis_of_day = millis_of_day_from_neat_time(neat_time);
+    let (seconds_of_day, millis) = (millis_of_day / 1000, millis_of_day % 1000);
+    (seconds_of_day / 3600) * 10000000 + ((seconds_of_day / 60) % 60) * 100000 + (seconds_of_day % 60) * 1000 + millis
+}
+
 /// Reasons for the "External Connector" to have aborted executing one of orders the `OgreExchange` had scheduled for execution
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ConnectorIdentificationOrderCancellationReasons {
     /// The user -- a human operator -- had, for some reason, manually cancelled the order
     UserInitiated { order_id: u32, message: String },
@@ -293,14 +454,31 @@ pub enum ConnectorIdentificationOrderCancellationReasons {
     BrokerInitiated { order_id: u32, message: String },
     /// The Exchange didn't accept the order, after all
     ExchangeInitiated { order_id: u32, message: String },
+    /// Confirms the cancellation asked by [OgreExchangeMessagesForExternalConnectors::CancelOrder], echoing its `reason`
+    OgreExchangeInitiated { order_id: u32, reason: OrderCancellationReasons },
 }
-impl Into<OrderCancellationReasons> for ConnectorIdentificationOrderCancellationReasons {
-    fn into(self) -> OrderCancellationReasons {
-        match self {
-            ConnectorIdentificationOrderCancellationReasons::UserInitiated { .. } => {todo!("todo...")}
-            ConnectorIdentificationOrderCancellationReasons::TimeoutWhileScheduling { .. } => {todo!("todo...")}
-            ConnectorIdentificationOrderCancellationReasons::BrokerInitiated { .. } => {todo!("todo...")}
-            ConnectorIdentificationOrderCancellationReasons::ExchangeInitiated { .. } => {todo!("todo...")}
+impl From<ConnectorIdentificationOrderCancellationReasons> for OrderCancellationReasons {
+    fn from(reason: ConnectorIdentificationOrderCancellationReasons) -> Self {
+        match reason {
+            ConnectorIdentificationOrderCancellationReasons::UserInitiated          { order_id, message }       => OrderCancellationReasons::UserInitiated          { order_id, message },
+            ConnectorIdentificationOrderCancellationReasons::TimeoutWhileScheduling { order_id, elapsed_nanos } => OrderCancellationReasons::TimeoutWhileScheduling { order_id, elapsed_nanos },
+            ConnectorIdentificationOrderCancellationReasons::BrokerInitiated        { order_id, message }       => OrderCancellationReasons::BrokerInitiated        { order_id, message },
+            ConnectorIdentificationOrderCancellationReasons::ExchangeInitiated      { order_id, message }       => OrderCancellationReasons::ExchangeInitiated      { order_id, message },
+            ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated  { reason, .. }              => reason,
+        }
+    }
+}
+/// The reverse of the conversion above -- reasons without a counterpart are wrapped in [ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated],
+/// so `OrderCancellationReasons` -> `ConnectorIdentificationOrderCancellationReasons` -> `OrderCancellationReasons` is lossless
+impl From<OrderCancellationReasons> for ConnectorIdentificationOrderCancellationReasons {
+    fn from(reason: OrderCancellationReasons) -> Self {
+        match reason {
+            OrderCancellationReasons::UserInitiated          { order_id, message }       => ConnectorIdentificationOrderCancellationReasons::UserInitiated          { order_id, message },
+            OrderCancellationReasons::TimeoutWhileScheduling { order_id, elapsed_nanos } => ConnectorIdentificationOrderCancellationReasons::TimeoutWhileScheduling { order_id, elapsed_nanos },
+            OrderCancellationReasons::BrokerInitiated        { order_id, message }       => ConnectorIdentificationOrderCancellationReasons::BrokerInitiated        { order_id, message },
+            OrderCancellationReasons::ExchangeInitiated      { order_id, message }       => ConnectorIdentificationOrderCancellationReasons::ExchangeInitiated      { order_id, message },
+            OrderCancellationReasons::RiskManagerInitiated   { order_id, .. }            |
+            OrderCancellationReasons::Unspecified            { order_id, .. }            => ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated  { order_id, reason },
         }
     }
 }
@@ -310,6 +488,7 @@ impl Into<OrderCancellationReasons> for ConnectorIdentificationOrderCancellation
 #[cfg(any(test,doc))]
 mod tests {
     use super::*;
+    use super::super::super::xorshift::XorShift;
 
 
     /// assures serialization / deserialization works for all external connector messages
@@ -317,22 +496,28 @@ mod tests {
     fn serde_for_external_connector_messages() {
         // keep this in sync with all available `ExternalConnectorMessages` variants, in the order they are declared there
         let external_connector_messages = vec![
-            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor      { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7") }),
-            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::MarketDataBridge { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7") }),
-            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::WatcherAdvisor   { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7") }),
+            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::FullAdvisor      { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7"), capabilities: capabilities() }),
+            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::MarketDataBridge { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7"), capabilities: capabilities() }),
+            ExternalConnectorMessages::ConnectorIdentification(ConnectorIdentification::WatcherAdvisor   { version: format!("v.1.2.3"), symbol: format!("PETR3"), account_token: format!("AkD9jH7BcgH68Js7"), capabilities: capabilities() }),
             ExternalConnectorMessages::UserAuthorization(format!("PaSsD321")),
             ExternalConnectorMessages::KeepAliveRequest(1),
             ExternalConnectorMessages::KeepAliveAnswer(2),
+            ExternalConnectorMessages::WireFormatInUse(WireFormat::Binary),
             ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::SymbolState { symbol: format!("PETR3"), in_auction: false }),
-            ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book        { date: 22011979, time: 213214001, symbol: format!("PETR3"), price_level_mills: 32120, n_orders: 100, available_quantity: 1000, side: Parties::Buyer }),
+            ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book        { date: 22011979, time: 213214001, symbol: format!("PETR3"), price_level_mills: 32120, n_orders: Some(100), available_quantity: 1000, side: Parties::Buyer }),
             ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Trade       { date: 22011979, time: 213214001, symbol: format!("PETR3"), unitary_mill_value: 32120, quantity: 100, aggressor: Parties::Buyer }),
             ExternalConnectorMessages::ExecutedOrder { date: 22011979, time: 213214001, symbol: format!("PETR3"), unitary_mill_value: 32120, quantity: 100, partial: false, order_id: 1, ogre_id: 1 },
             ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::UserInitiated          { order_id: 1, message: format!("MT5 was closed") } ),
             ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::TimeoutWhileScheduling { order_id: 2, elapsed_nanos: 1234567890 } ),
             ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::BrokerInitiated        { order_id: 3, message: format!("You didn't provide enough warranties for that operation") } ),
+            ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::ExchangeInitiated      { order_id: 4, message: format!("Auction started") } ),
+            ExternalConnectorMessages::CancelledOrder(ConnectorIdentificationOrderCancellationReasons::OgreExchangeInitiated  { order_id: 5, reason: OrderCancellationReasons::UserInitiated { order_id: 5, message: format!("Slipt into that button...") } } ),
             ExternalConnectorMessages::PendingOrder { ogre_id: 1, exchange_id: 1 },
+            ExternalConnectorMessages::OpenPosition { symbol: format!("PETR3"), side: Parties::Buyer, quantity: 100, average_unitary_mill_value: 32120 },
             ExternalConnectorMessages::ChartPoints { sequential: 1 },
             ExternalConnectorMessages::GoodBye(format!("done for today! the sea has awesome waves! time for body surfing!")),
+            ExternalConnectorMessages::Disconnected(DisconnectionReason::AuthenticationFailure),
+            ExternalConnectorMessages::Disconnected(DisconnectionReason::AccountFrozen { message: format!("too many wrong authorization attempts"), remaining_duration_millis: 1234567890 }),
             ExternalConnectorMessages::UnknownMessage(format!("Not sure where this is used...")),
         ];
         let mut serializer_buffer = vec![];
@@ -343,6 +528,11 @@ mod tests {
                 .expect(&format!("deserialization failed for input '{}'", serialized));
             assert_eq!(reconstructed, message, "an 'External Connector' message couldn't resist serde. It was serialized to '{}'", serialized);
             println!("✓ {}", serialized.trim_end());
+            let binary_message = WireMessage { format: WireFormat::Binary, message };
+            <WireMessage<ExternalConnectorMessages> as SocketServerSerializer<WireMessage<ExternalConnectorMessages>>>::serialize(&binary_message, &mut serializer_buffer);
+            let reconstructed = <ExternalConnectorMessages as SocketServerDeserializer<ExternalConnectorMessages>>::deserialize(&serializer_buffer)
+                .expect(&format!("binary deserialization failed for input {:?}", serializer_buffer));
+            assert_eq!(reconstructed, binary_message.message, "an 'External Connector' message couldn't resist the binary serde. It was serialized to {:?}", serializer_buffer);
         }
     }
 
@@ -351,12 +541,16 @@ mod tests {
     fn serde_for_server_messages() {
         // keep this in sync with all available `OgreExchangeMessagesForExternalConnectors` variants, in the order they are declared there
         let ogre_exchange_messages = vec![
-            OgreExchangeMessagesForExternalConnectors::Welcome,
+            OgreExchangeMessagesForExternalConnectors::Welcome { version: format!("1.2.3"), capabilities: capabilities() },
+            OgreExchangeMessagesForExternalConnectors::BindSession { symbol: format!("PETR3"), account_token: Some(format!("AkD9jH7BcgH68Js7")) },
+            OgreExchangeMessagesForExternalConnectors::BindSession { symbol: format!("PETR3"), account_token: None },
+            OgreExchangeMessagesForExternalConnectors::UseWireFormat(WireFormat::Binary),
             OgreExchangeMessagesForExternalConnectors::ProvideAuthorizationToContinue,
+            OgreExchangeMessagesForExternalConnectors::ServerAuthorization(format!("PaSsD321")),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::UnknownConnectorType),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::DeprecatedConnectorVersion {minimum_accepted_version: format!("v.1.2.3")}),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::UnknownAccount),
-            OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AccountFrozen           {message: format!("too many wrong authorization attempts"), remaining_duration_nanos: 1234567890}),
+            OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AccountFrozen           {message: format!("too many wrong authorization attempts"), remaining_duration_millis: 1234567890}),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AccountDisabled         {message: format!("to enable it back, ask Luiz for a new password")}),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::AuthenticationFailure),
             OgreExchangeMessagesForExternalConnectors::Disconnected(DisconnectionReason::ProtocolOffense         {message: format!("for instance... trying to provide two client identifications...")}),
@@ -386,7 +580,82 @@ mod tests {
                 .expect(&format!("deserialization failed for input '{}'", serialized));
             assert_eq!(reconstructed, message, "an 'OgreExchange for ExternalConnectors' message couldn't resist serde. It was serialized to '{}'", serialized);
             println!("✓ {}", serialized.trim_end());
+            wire_format::serialize(WireFormat::Binary, &message, &mut serializer_buffer).expect("binary serialization");
+            let reconstructed = <OgreExchangeMessagesForExternalConnectors  as SocketServerDeserializer<OgreExchangeMessagesForExternalConnectors>>::deserialize(&serializer_buffer)
+                .expect(&format!("binary deserialization failed for input {:?}", serializer_buffer));
+            assert_eq!(reconstructed, message, "an 'OgreExchange for ExternalConnectors' message couldn't resist the binary serde. It was serialized to {:?}", serializer_buffer);
         }
     }
 
+    /// property test: any valid market data survives the round trip through the internal representation -- and any
+    /// `MarketData` survives the opposite round trip
+    #[cfg_attr(not(doc),test)]
+    fn market_data_round_trips() {
+        let mut random = XorShift::new(0x9E3779B97F4A7C15);
+        for _ in 0..100_000 {
+            let date = from_neat_date(random.next_u32() as u16);
+            let time = random.below(24) * 10000000 + random.below(60) * 100000 + random.below(60) * 1000 + random.below(1000);
+            let side = if random.below(2) == 0 { Parties::Buyer } else { Parties::Seller };
+            let market_data = match random.below(3) {
+                0 => ExternalConnectorMarketData::SymbolState { symbol: format!("PETR4"), in_auction: random.below(2) == 0 },
+                1 => ExternalConnectorMarketData::Book  { date, time, symbol: format!("PETR4"), price_level_mills: random.next_u32(), n_orders: (random.below(2) == 0).then(|| random.next_u32()), available_quantity: random.next_u32(), side },
+                _ => ExternalConnectorMarketData::Trade { date, time, symbol: format!("PETR4"), unitary_mill_value: random.next_u32(), quantity: random.next_u32(), aggressor: side },
+            };
+            let internal = market_data.clone().try_into_market_data_for("PETR4")
+                .unwrap_or_else(|err| panic!("valid market data {market_data:?} was refused: {err}"));
+            assert_eq!(ExternalConnectorMarketData::from(internal.clone()), market_data, "market data was not restored from {internal:?}");
+
+            let neat_date = random.next_u32() as u16;
+            let neat_time = random.next_u32();
+            let internal = MarketData::Trade(SingleTrade { date: neat_date, time: neat_time, symbol: format!("PETR4"), unitary_mill_value: 1, quantity: 1, aggressor: Parties::Buyer });
+            let MarketData::Trade(restored) = MarketData::try_from(ExternalConnectorMarketData::from(internal)).expect("converted market data should be valid") else {
+                panic!("the market data kind was changed")
+            };
+            assert_eq!(restored.date, neat_date, "NeatDate was not preserved");
+            assert_eq!(millis_of_day_from_neat_time(restored.time), millis_of_day_from_neat_time(neat_time), "NeatTime lost more than its sub-millisecond precision");
+        }
+    }
+
+    /// checks invalid market data is refused with the right error -- instead of panicking
+    #[cfg_attr(not(doc),test)]
+    fn market_data_validation() {
+        let trade = |date, time, symbol: &str| ExternalConnectorMarketData::Trade { date, time, symbol: symbol.to_string(), unitary_mill_value: 32120, quantity: 100, aggressor: Parties::Buyer };
+        let cases = [
+            (trade(20230230, 100000000, "PETR4"), MarketDataConversionError::InvalidDate  { date: 20230230 }),
+            (trade(19790121, 100000000, "PETR4"), MarketDataConversionError::DateOverflow { date: 19790121 }),
+            (trade(21600101, 100000000, "PETR4"), MarketDataConversionError::DateOverflow { date: 21600101 }),
+            (trade(20230704, 240000000, "PETR4"), MarketDataConversionError::InvalidTime  { time: 240000000 }),
+            (trade(20230704, 106000000, "PETR4"), MarketDataConversionError::InvalidTime  { time: 106000000 }),
+            (trade(20230704, 100000000, "VALE3"), MarketDataConversionError::SymbolMismatch { expected: format!("PETR4"), found: format!("VALE3") }),
+        ];
+        for (market_data, expected_error) in cases {
+            assert_eq!(market_data.clone().try_into_market_data_for("PETR4"), Err(expected_error), "{market_data:?} should have been refused");
+        }
+        assert!(MarketData::try_from(trade(19790122, 0, "VALE3")).is_ok(), "the epoch & midnight should be accepted -- and the symbol is only checked when asked");
+    }
+
+    /// property test: cancellation reasons survive the round trip through the "External Connector" representation
+    #[cfg_attr(not(doc),test)]
+    fn cancellation_reasons_round_trips() {
+        let mut random = XorShift::new(0x2545F4914F6CDD1D);
+        for _ in 0..10_000 {
+            let order_id = random.next_u32();
+            let message = format!("reason #{}", random.next_u32());
+            let reason = match random.below(6) {
+                0 => OrderCancellationReasons::TimeoutWhileScheduling { order_id, elapsed_nanos: random.next_u32() },
+                1 => OrderCancellationReasons::RiskManagerInitiated   { order_id, reason: RiskManagementOrderCancellationConditions::SymbolChangedNegotiationStatus { message } },
+                2 => OrderCancellationReasons::UserInitiated          { order_id, message },
+                3 => OrderCancellationReasons::BrokerInitiated        { order_id, message },
+                4 => OrderCancellationReasons::ExchangeInitiated      { order_id, message },
+                _ => OrderCancellationReasons::Unspecified            { order_id, message },
+            };
+            let connector_reason = ConnectorIdentificationOrderCancellationReasons::from(reason.clone());
+            assert_eq!(OrderCancellationReasons::from(connector_reason.clone()), reason, "cancellation reason was not restored from {connector_reason:?}");
+        }
+    }
+
+    fn capabilities() -> Capabilities {
+        Capabilities { binary_encoding: true, book_depth: 32, order_types: vec![OrderKinds::MarketOrder, OrderKinds::LimitedOrder] }
+    }
+
 }
\ No newline at end of file
@@ -5,5 +5,17 @@ pub use comms::*;
 
 mod runtime;
 mod messages_model;
+mod wire_format;
+pub use wire_format::WireFormat;
 mod external_connector_processor;
+mod authenticator;
+pub use authenticator::{CredentialStore, AuthenticationLimits};
+#[cfg(test)]
+mod loopback_exchange;
+mod negotiation;
+mod heartbeat;
+pub use heartbeat::HeartbeatLimits;
+mod backoff;
+mod market_data_publisher;
+pub use market_data_publisher::{publish_trade, publish_book_deltas};
 // mod server_logic;
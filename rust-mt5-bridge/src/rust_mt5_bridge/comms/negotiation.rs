@@ -0,0 +1,141 @@
+//! Protocol version & capabilities negotiation for the "External Connector" protocol:
+//!   1) the `OgreExchange` greets with [OgreExchangeMessagesForExternalConnectors::Welcome], stating its [PROTOCOL_VERSION]
+//!      and the [Capabilities] it offers;
+//!   2) the "External Connector" checks the server's version -- saying `GoodBye` if it is not compatible -- then identifies
+//!      itself with its own version and the capabilities both sides support (see [Capabilities::intersection()]);
+//!   3) the `OgreExchange` checks the connector's version -- refusing it with [DisconnectionReason::DeprecatedConnectorVersion]
+//!      if it is not compatible -- and that it didn't claim capabilities that were not offered.
+//!
+//! Versions follow "semantic versioning" -- `MAJOR.MINOR.PATCH` -- and are compatible (see [ProtocolVersion::is_compatible_with()]) if:
+//!   - they share the same `MAJOR` version -- and the same `MINOR`, while in `0.x`;
+//!   - the peer's is not below [MINIMUM_ACCEPTED_PROTOCOL_VERSION].
+//!
+//! The dated versions of the alpha days (like "2023-07-04") are not accepted.
+//!
+//! [OgreExchangeMessagesForExternalConnectors::Welcome]: super::messages_model::OgreExchangeMessagesForExternalConnectors::Welcome
+
+use super::{
+    super::ogre_exchange_models::{Capabilities, DisconnectionReason, OrderKinds},
+    messages_model::PROTOCOL_VERSION,
+};
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+
+/// Peers stating older versions are refused
+pub const MINIMUM_ACCEPTED_PROTOCOL_VERSION: &str = "1.0.0";
+
+/// How many price levels, for each side, our books may have
+pub const SUPPORTED_BOOK_DEPTH: u32 = 64;
+
+
+/// A parsed protocol version -- see the [module](self) docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ProtocolVersion {
+
+    /// Our version -- [PROTOCOL_VERSION]
+    pub fn ours() -> Self {
+        PROTOCOL_VERSION.parse().expect("`PROTOCOL_VERSION` is not a valid version")
+    }
+
+    /// [MINIMUM_ACCEPTED_PROTOCOL_VERSION]
+    pub fn minimum_accepted() -> Self {
+        MINIMUM_ACCEPTED_PROTOCOL_VERSION.parse().expect("`MINIMUM_ACCEPTED_PROTOCOL_VERSION` is not a valid version")
+    }
+
+    /// Tells if a peer stating this version may talk to us, at version `ours` -- see the [module](self) docs
+    pub fn is_compatible_with(&self, ours: &ProtocolVersion) -> bool {
+        self.major == ours.major &&
+        (self.major > 0 || self.minor == ours.minor) &&
+        *self >= Self::minimum_accepted()
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = String;
+
+    /// Parses `MAJOR.MINOR.PATCH` -- optionally prefixed by "v" or "v."
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let numbers = version.trim().trim_start_matches('v').trim_start_matches('.');
+        let mut parts = numbers.split('.').map(|part| part.parse::<u32>().ok());
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => Ok(Self { major, minor, patch }),
+            _ => Err(format!("'{version}' is not a valid protocol version -- `MAJOR.MINOR.PATCH` was expected")),
+        }
+    }
+}
+
+impl Display for ProtocolVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+
+/// Checks a peer stating `peer_version` may talk to us -- returning the reason to refuse it, otherwise
+pub fn check_peer_version(peer_version: &str) -> Result<ProtocolVersion, DisconnectionReason> {
+    let refused = || DisconnectionReason::DeprecatedConnectorVersion { minimum_accepted_version: MINIMUM_ACCEPTED_PROTOCOL_VERSION.to_string() };
+    let version = peer_version.parse::<ProtocolVersion>().map_err(|_| refused())?;
+    if version.is_compatible_with(&ProtocolVersion::ours()) {
+        Ok(version)
+    } else {
+        Err(refused())
+    }
+}
+
+/// Everything this "External Connector" is able to do -- to be narrowed down by what the `OgreExchange` offers
+pub fn supported_capabilities() -> Capabilities {
+    Capabilities {
+        binary_encoding: true,
+        book_depth:      SUPPORTED_BOOK_DEPTH,
+        order_types:     vec![OrderKinds::MarketOrder, OrderKinds::LimitedOrder],
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks versions are parsed & the compatibility rules are followed -- refusing peers deterministically
+    #[test]
+    fn version_compatibility() {
+        assert_eq!("v.1.2.3".parse(), Ok(ProtocolVersion { major: 1, minor: 2, patch: 3 }), "The \"v.\" prefix should be accepted");
+        assert_eq!("1.20.3".parse::<ProtocolVersion>().map(|version| version.to_string()), Ok(String::from("1.20.3")), "Versions should be displayed as parsed");
+        for invalid in ["2023-07-04", "1.2", "1.2.3.4", "1.2.x", ""] {
+            assert!(invalid.parse::<ProtocolVersion>().is_err(), "'{invalid}' should not be parsed");
+        }
+
+        let version = |version: &str| version.parse::<ProtocolVersion>().expect("parsing a valid version");
+        assert!(ProtocolVersion::minimum_accepted() <= ProtocolVersion::ours(), "We should accept our own version");
+        assert!(version("1.9.0").is_compatible_with(&version("1.0.0")), "Newer minors of the same major should be compatible");
+        assert!(!version("2.0.0").is_compatible_with(&version("1.0.0")), "Other majors should not be compatible");
+        assert!(!version("0.2.0").is_compatible_with(&version("0.3.0")), "While in 0.x, other minors should not be compatible");
+
+        assert_eq!(check_peer_version(PROTOCOL_VERSION), Ok(ProtocolVersion::ours()), "Peers in our version should be accepted");
+        for refused in ["0.9.9", "2.0.0", "2023-07-04"] {
+            assert_eq!(check_peer_version(refused),
+                       Err(DisconnectionReason::DeprecatedConnectorVersion { minimum_accepted_version: MINIMUM_ACCEPTED_PROTOCOL_VERSION.to_string() }),
+                       "Peers in version '{refused}' should be refused");
+        }
+    }
+
+    /// checks only the capabilities supported by both sides are agreed upon
+    #[test]
+    fn capabilities_agreement() {
+        let offered = Capabilities { binary_encoding: false, book_depth: 10, order_types: vec![OrderKinds::MarketOrder] };
+        let agreed = supported_capabilities().intersection(&offered);
+        assert_eq!(agreed, offered, "Only the capabilities offered by the server should be agreed upon");
+        assert!(agreed.is_subset_of(&offered), "Agreed capabilities should be a subset of the offered ones");
+        assert!(!supported_capabilities().is_subset_of(&offered), "Claiming capabilities that were not offered should be detected");
+    }
+}
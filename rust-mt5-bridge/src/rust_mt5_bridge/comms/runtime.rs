@@ -2,10 +2,21 @@
 
 use std::pin::Pin;
 use std::sync::Arc;
-use reactive_messaging::SocketClient;
+use super::external_connector_processor::ServerProtocolProcessor;
+use std::thread::JoinHandle;
+use reactive_messaging::{SocketClient, SocketServer};
+use tokio::sync::watch;
 
 
 pub struct Runtime {
-    pub tokio_runtime: Option<Arc<tokio::runtime::Runtime>>,
-    pub socket_server: Option<SocketClient>,
+    /// the thread running the Tokio runtime -- present while the communications are up
+    pub runner:          Option<JoinHandle<()>>,
+    /// signals `runner` to stop
+    pub shutdown_sender: Option<watch::Sender<bool>>,
+    /// present once the socket is started
+    pub processor:       Option<Arc<ServerProtocolProcessor>>,
+    /// present when connected to the `OgreExchange` -- see `CommsMode::Client`
+    pub socket_client:   Option<SocketClient>,
+    /// present when waiting for the `OgreExchange` to connect -- see `CommsMode::Server`
+    pub socket_server:   Option<SocketServer>,
 }
\ No newline at end of file
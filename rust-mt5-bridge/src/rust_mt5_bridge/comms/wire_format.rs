@@ -0,0 +1,223 @@
+//! Wire formats for the "External Connector" protocol: the textual RON -- the default -- and a compact binary one, for
+//! the high frequency market data streams.
+//!
+//! Binary frames are self describing: [BINARY_FRAME_TAG] -- a byte never found in RON, which is UTF-8 text -- then the
+//! [BINARY_FORMAT_VERSION] and the `bincode` encoded message. Receivers, so, accept both formats at any time (see
+//! [deserialize()]), allowing each side to pick the format it sends, per connection, without synchronization -- see
+//! [OgreExchangeMessagesForExternalConnectors::UseWireFormat](super::messages_model::OgreExchangeMessagesForExternalConnectors::UseWireFormat).
+//!
+//! As `reactive-messaging` delimits messages with '\n' -- appending it when sending & stripping it when receiving -- that
+//! byte is escaped in the binary payloads -- see [escape()].
+
+use std::{borrow::Cow, error::Error};
+use reactive_messaging::{ron_deserializer, ron_serializer};
+use bincode::Options;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+
+/// The first byte of binary frames -- invalid in UTF-8, so it never starts a RON message
+pub const BINARY_FRAME_TAG: u8 = 0xFF;
+/// The version of the binary encoding, following [BINARY_FRAME_TAG] -- frames with other versions are refused
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+/// Precedes escaped bytes in binary payloads: the escaped byte follows, XORed with [ESCAPE_XOR]
+const ESCAPE: u8 = 0x1B;
+const ESCAPE_XOR: u8 = 0x20;
+
+
+/// How messages are encoded when sent -- see the [module](self) docs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum WireFormat {
+    /// Textual & human readable
+    #[default]
+    Ron,
+    /// Compact & fast -- see [BINARY_FORMAT_VERSION]
+    Binary,
+}
+
+/// A message to be sent in the given `format` -- for connections where it is chosen per peer
+#[derive(Debug, PartialEq)]
+pub struct WireMessage<T> {
+    pub format:  WireFormat,
+    pub message: T,
+}
+
+
+/// Encodes `message` into `buffer` (replacing its contents), in the given `format` -- without the '\n' terminator, which is
+/// added by `reactive-messaging`
+pub fn serialize<T: Serialize>(format: WireFormat, message: &T, buffer: &mut Vec<u8>) -> Result<(), String> {
+    match format {
+        WireFormat::Ron => ron_serializer(message, buffer)
+            .map_err(|err| format!("RON serialization failed: {err}")),
+        WireFormat::Binary => {
+            buffer.clear();
+            buffer.extend_from_slice(&[BINARY_FRAME_TAG, BINARY_FORMAT_VERSION]);
+            bincode_options().serialize_into(&mut *buffer, message)
+                .map_err(|err| format!("binary serialization failed: {err}"))?;
+            if buffer[2..].iter().any(|&byte| needs_escaping(byte)) {
+                let payload = buffer.split_off(2);
+                escape(&payload, buffer);
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Decodes a message in any of the [WireFormat]s -- detected from its first byte -- as received from `reactive-messaging`:
+/// without the '\n' terminator
+pub fn deserialize<T: DeserializeOwned>(frame: &[u8]) -> Result<T, Box<dyn Error + Sync + Send>> {
+    match frame {
+        [BINARY_FRAME_TAG, BINARY_FORMAT_VERSION, payload @ ..] => Ok(bincode_options().deserialize(&unescape(payload)?)?),
+        [BINARY_FRAME_TAG, version, ..] => Err(format!("unsupported binary wire format version {version} -- only {BINARY_FORMAT_VERSION} is known").into()),
+        _ => ron_deserializer(frame),
+    }
+}
+
+/// Appends `payload` to `buffer`, escaping '\n' (and [ESCAPE] itself) so it may be sent through `reactive-messaging`
+pub fn escape(payload: &[u8], buffer: &mut Vec<u8>) {
+    buffer.reserve(payload.len() + payload.len() / 64);
+    for &byte in payload {
+        if needs_escaping(byte) {
+            buffer.extend_from_slice(&[ESCAPE, byte ^ ESCAPE_XOR]);
+        } else {
+            buffer.push(byte);
+        }
+    }
+}
+
+/// Reverses [escape()] -- borrowing `escaped` if nothing was escaped
+pub fn unescape(escaped: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    if !escaped.contains(&ESCAPE) {
+        return Ok(Cow::Borrowed(escaped));
+    }
+    let mut payload = Vec::with_capacity(escaped.len());
+    let mut bytes = escaped.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == ESCAPE {
+            let &escaped_byte = bytes.next().ok_or("binary frame ends in the middle of an escape sequence")?;
+            payload.push(escaped_byte ^ ESCAPE_XOR);
+        } else {
+            payload.push(byte);
+        }
+    }
+    Ok(Cow::Owned(payload))
+}
+
+
+fn needs_escaping(byte: u8) -> bool {
+    byte == b'\n' || byte == ESCAPE
+}
+
+/// Little endian with variable length integers -- most of our numbers are small -- refusing trailing bytes
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{
+        messages_model::{ExternalConnectorMessages, ExternalConnectorMarketData},
+        super::ogre_exchange_models::Parties,
+        super::xorshift::XorShift,
+    };
+    use std::time::{Duration, Instant};
+
+
+    /// checks binary frames have no '\n' -- whatever the payload -- and that they decode back
+    #[test]
+    fn escaping() {
+        let mut random = XorShift::new(0x9E3779B97F4A7C15);
+        let mut buffer = vec![];
+        for len in 0..2_000 {
+            let payload = (0..len % 257).map(|_| {
+                let random = random.next_u64();
+                // biased towards the bytes needing escaping
+                [b'\n', ESCAPE, ESCAPE ^ ESCAPE_XOR, random as u8][(random >> 32) as usize % 4]
+            }).collect::<Vec<u8>>();
+            buffer.clear();
+            escape(&payload, &mut buffer);
+            assert!(!buffer.contains(&b'\n'), "Escaped payloads shouldn't contain '\\n': {payload:?} => {buffer:?}");
+            assert_eq!(unescape(&buffer).as_deref(), Ok(payload.as_slice()), "Unescaping should give back the payload");
+        }
+        assert!(unescape(&[1, 2, ESCAPE]).is_err(), "Truncated escape sequences should be refused");
+
+        let message = ExternalConnectorMessages::KeepAliveRequest(b'\n' as u32);
+        serialize(WireFormat::Binary, &message, &mut buffer).expect("serializing");
+        assert!(!buffer.contains(&b'\n'), "Binary frames shouldn't contain '\\n' -- `reactive-messaging` uses it as the terminator: {buffer:?}");
+        assert_eq!(deserialize::<ExternalConnectorMessages>(&buffer).ok(), Some(message), "Binary frames with escaped bytes should decode back");
+    }
+
+    /// checks both formats are accepted by the same deserializer -- refusing unknown binary versions
+    #[test]
+    fn format_detection() {
+        let message = trade();
+        let mut buffer = vec![];
+        for format in [WireFormat::Ron, WireFormat::Binary] {
+            serialize(format, &message, &mut buffer).expect("serializing");
+            assert_eq!(buffer.first() == Some(&BINARY_FRAME_TAG), format == WireFormat::Binary, "Only binary frames should start with the tag");
+            assert_eq!(deserialize::<ExternalConnectorMessages>(&buffer).ok(), Some(trade()), "{format:?} frames should decode back");
+        }
+        buffer[1] = BINARY_FORMAT_VERSION + 1;
+        let error = deserialize::<ExternalConnectorMessages>(&buffer).expect_err("unknown versions should be refused");
+        assert!(error.to_string().contains("version"), "The error should tell about the version: '{error}'");
+    }
+
+    /// checks the binary format is, at least, 2x smaller than RON for the high frequency messages
+    #[test]
+    fn message_sizes() {
+        for message in [trade(), book()] {
+            let (ron, binary) = (encoded(WireFormat::Ron, &message), encoded(WireFormat::Binary, &message));
+            assert!(binary.len() * 2 <= ron.len(), "Binary frames should be, at least, 2x smaller than RON: {} bytes against {} for {message:?}", binary.len(), ron.len());
+        }
+    }
+
+    /// compares the encoding & decoding throughputs of both formats -- run with
+    /// `cargo test --release -- --ignored --nocapture wire_format_benchmark`.\
+    /// NOTE: a test rather than a `criterion` bench, as this crate is a `cdylib` -- which benches can't link against
+    /// Results, on a single core Xeon VM (sizes without the '\n' terminator):
+    /// ```nocompile
+    /// Trade Ron   : 116 bytes;   2788480 encodings/s;    558693 decodings/s
+    /// Trade Binary:  25 bytes;  14943420 encodings/s;   6070501 decodings/s
+    /// Book  Ron   : 131 bytes;   2016057 encodings/s;    497575 decodings/s
+    /// Book  Binary:  28 bytes;  13783910 encodings/s;   5979372 decodings/s
+    /// ```
+    #[test]
+    #[ignore]
+    fn wire_format_benchmark() {
+        const ITERATIONS: u32 = 1_000_000;
+        let per_second = |elapsed: Duration| (ITERATIONS as f64 / elapsed.as_secs_f64()) as u64;
+        for (name, message) in [("Trade", trade()), ("Book", book())] {
+            for format in [WireFormat::Ron, WireFormat::Binary] {
+                let mut buffer = vec![];
+                let start = Instant::now();
+                for _ in 0..ITERATIONS {
+                    serialize(format, &message, &mut buffer).expect("serializing");
+                }
+                let encoding = start.elapsed();
+                let start = Instant::now();
+                for _ in 0..ITERATIONS {
+                    std::hint::black_box(deserialize::<ExternalConnectorMessages>(&buffer).expect("deserializing"));
+                }
+                let decoding = start.elapsed();
+                println!("{name:5} {:6}: {:3} bytes; {:9} encodings/s; {:9} decodings/s", format!("{format:?}"), buffer.len(), per_second(encoding), per_second(decoding));
+            }
+        }
+    }
+
+
+    fn trade() -> ExternalConnectorMessages {
+        ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Trade { date: 20230704, time: 100509021, symbol: String::from("PETR4"), unitary_mill_value: 32020, quantity: 100, aggressor: Parties::Buyer })
+    }
+
+    fn book() -> ExternalConnectorMessages {
+        ExternalConnectorMessages::MarketData(ExternalConnectorMarketData::Book { date: 20230704, time: 100509021, symbol: String::from("PETR4"), price_level_mills: 32020, n_orders: None, available_quantity: 300, side: Parties::Seller })
+    }
+
+    fn encoded(format: WireFormat, message: &ExternalConnectorMessages) -> Vec<u8> {
+        let mut buffer = vec![];
+        serialize(format, message, &mut buffer).expect("serializing");
+        buffer
+    }
+}
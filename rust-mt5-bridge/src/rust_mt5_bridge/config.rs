@@ -0,0 +1,206 @@
+//! Configuration for this DLL, loaded once, by `DllMain()` -- see [load()].
+//!
+//! It is read from the RON file `rust_mt5_bridge.ron` in the terminal's working directory (where `rust_mt5_bridge.log`
+//! is also written) -- or from the path given by the `RUST_MT5_BRIDGE_CONFIG` environment variable. All fields are
+//! optional, defaulting to the values in the `Default` implementations. For instance:
+//! ```ron
+//! (
+//!     comms: (
+//!         mode: Server,
+//!         port: 9759,
+//!         retry: (initial_backoff_millis: 500, max_backoff_millis: 30000),
+//!         credentials: {"AkD9jH7BcgH68Js7": (secret: "PaSsD321")},
+//!         wire_format: Binary,
+//!     ),
+//! )
+//! ```
+//! The most commonly changed fields may be overridden through environment variables -- so several terminals may share
+//! the same file while avoiding port clashes: `RUST_MT5_BRIDGE_COMMS_ENABLED` (`true` / `false`), `RUST_MT5_BRIDGE_COMMS_MODE`
+//! (`Client` / `Server`), `RUST_MT5_BRIDGE_COMMS_HOST` & `RUST_MT5_BRIDGE_COMMS_PORT`.
+
+use super::comms::{AuthenticationLimits, CredentialStore, HeartbeatLimits, WireFormat};
+use std::{env, fs, io};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use log::info;
+
+
+/// Where the configuration is read from, if `RUST_MT5_BRIDGE_CONFIG` is not set
+pub const DEFAULT_CONFIG_FILE: &str = "rust_mt5_bridge.ron";
+/// The environment variable telling where to read the configuration from
+pub const CONFIG_FILE_ENV: &str = "RUST_MT5_BRIDGE_CONFIG";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct Config {
+    pub comms: CommsConfig,
+}
+
+/// Communications with the `OgreExchange` -- see `comms/comms.rs`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct CommsConfig {
+    /// If `false`, no communications are started
+    pub enabled:                 bool,
+    pub mode:                    CommsMode,
+    /// The address to connect to -- or to listen at, in [CommsMode::Server]
+    pub host:                    String,
+    pub port:                    u16,
+    /// How long to wait, after the DLL is loaded, before starting the communications
+    pub startup_delay_millis:    u64,
+    /// How long `DLL_PROCESS_DETACH` may take to say goodbye to the peers & stop the communications -- see `comms/comms.rs`
+    pub shutdown_timeout_millis: u64,
+    pub retry:                   RetryPolicy,
+    /// The secrets of the accounts -- see `comms/authenticator.rs`
+    pub credentials:             CredentialStore,
+    /// In [CommsMode::Server], limits the failed attempts of peers proving they are the `OgreExchange` -- see `comms/authenticator.rs`
+    pub authentication:          AuthenticationLimits,
+    pub heartbeat:               HeartbeatLimits,
+    /// The format our messages are sent in, until the `OgreExchange` asks for another one -- see `comms/wire_format.rs`
+    pub wire_format:             WireFormat,
+}
+
+impl Default for CommsConfig {
+    fn default() -> Self {
+        Self {
+            enabled:                 true,
+            mode:                    CommsMode::Client,
+            host:                    String::from("127.0.0.1"),
+            port:                    9758,
+            startup_delay_millis:    5_000,
+            shutdown_timeout_millis: 3_000,
+            retry:                   RetryPolicy::default(),
+            credentials:             CredentialStore::default(),
+            authentication:          AuthenticationLimits::default(),
+            heartbeat:               HeartbeatLimits::default(),
+            wire_format:             WireFormat::default(),
+        }
+    }
+}
+
+/// Which side of the socket we are on
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum CommsMode {
+    /// Connects to the `OgreExchange`
+    Client,
+    /// Waits for the `OgreExchange` to connect -- which must then prove itself with the secret of the account (see `comms/authenticator.rs`)
+    Server,
+}
+
+/// How to retry establishing the communications: waits start at `initial_backoff_millis`, being multiplied by
+/// `backoff_multiplier` after each failed attempt -- up to `max_backoff_millis`. See `comms/backoff.rs`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RetryPolicy {
+    pub initial_backoff_millis: u64,
+    pub max_backoff_millis:     u64,
+    pub backoff_multiplier:     f64,
+    /// Each wait is randomly shortened or lengthened by up to this fraction of it -- so several terminals don't retry in lockstep
+    pub jitter:                 f64,
+    /// After this many failed attempts in a row, no more are done -- `None` to retry forever
+    pub max_attempts:           Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { initial_backoff_millis: 1_000, max_backoff_millis: 60_000, backoff_multiplier: 2.0, jitter: 0.2, max_attempts: None }
+    }
+}
+
+impl Config {
+
+    /// Parses the RON representation of the configuration -- see the [module](self) docs
+    pub fn from_ron(ron: &str) -> Result<Self, String> {
+        ron::from_str(ron)
+            .map_err(|err| format!("Couldn't parse the configuration: {err}"))
+    }
+
+    /// Applies the overrides given by the environment variables -- resolved by `env_var` -- see the [module](self) docs
+    pub fn with_env_overrides(mut self, env_var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        fn parse<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+            value.trim().parse().map_err(|_| format!("Invalid value for the environment variable {name}: '{value}'"))
+        }
+        if let Some(enabled) = env_var("RUST_MT5_BRIDGE_COMMS_ENABLED") {
+            self.comms.enabled = parse("RUST_MT5_BRIDGE_COMMS_ENABLED", enabled)?;
+        }
+        if let Some(mode) = env_var("RUST_MT5_BRIDGE_COMMS_MODE") {
+            self.comms.mode = match mode.trim() {
+                "Client" => CommsMode::Client,
+                "Server" => CommsMode::Server,
+                _ => return Err(format!("Invalid value for the environment variable RUST_MT5_BRIDGE_COMMS_MODE: '{mode}' -- expected 'Client' or 'Server'")),
+            };
+        }
+        if let Some(host) = env_var("RUST_MT5_BRIDGE_COMMS_HOST") {
+            self.comms.host = host.trim().to_string();
+        }
+        if let Some(port) = env_var("RUST_MT5_BRIDGE_COMMS_PORT") {
+            self.comms.port = parse("RUST_MT5_BRIDGE_COMMS_PORT", port)?;
+        }
+        Ok(self)
+    }
+}
+
+
+/// Loads the configuration from the file & environment variables (see the [module](self) docs), making it available
+/// through [get()]. A missing file is not an error: the defaults are used.\
+/// Should be called once, by `DllMain()` -- further calls return the already loaded configuration
+pub fn load() -> Result<&'static Config, String> {
+    CONFIG.get_or_try_init(|| {
+        let path = env::var(CONFIG_FILE_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+        let config = match fs::read_to_string(&path) {
+            Ok(ron) => Config::from_ron(&ron).map_err(|err| format!("'{path}': {err}"))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                info!("config::load(): '{path}' not found -- using the default configuration");
+                Config::default()
+            },
+            Err(err) => return Err(format!("Couldn't read the configuration file '{path}': {err}")),
+        };
+        config.with_env_overrides(|name| env::var(name).ok())
+    })
+}
+
+/// The configuration loaded by [load()] -- or the default one, if it wasn't (yet)
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// checks the RON file is parsed, with absent fields taking their defaults -- and rejecting unknown ones
+    #[test]
+    fn config_from_ron() {
+        let config = Config::from_ron(r#"(comms: (mode: Server, port: 9759, retry: (max_attempts: Some(3)), credentials: {"acnt_tkn": (secret: "s3cr3t")}))"#)
+            .expect("parsing a valid configuration");
+        assert_eq!(config.comms, CommsConfig {
+            mode:        CommsMode::Server,
+            port:        9759,
+            retry:       RetryPolicy { max_attempts: Some(3), ..RetryPolicy::default() },
+            credentials: CredentialStore::default().with_account("acnt_tkn", "s3cr3t"),
+            ..CommsConfig::default()
+        }, "Wrong configuration parsed");
+        assert_eq!(Config::from_ron("()"), Ok(Config::default()), "An empty configuration should yield the defaults");
+        let error = Config::from_ron("(comms: (prot: 9759))").expect_err("unknown fields should be rejected");
+        assert!(error.contains("prot"), "The error should tell the offending field: '{error}'");
+    }
+
+    /// checks environment variables override the configuration -- and that invalid values are reported
+    #[test]
+    fn env_overrides() {
+        let env = |vars: &'static [(&'static str, &'static str)]| move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string());
+        let config = Config::default()
+            .with_env_overrides(env(&[("RUST_MT5_BRIDGE_COMMS_PORT", "9760"), ("RUST_MT5_BRIDGE_COMMS_MODE", "Server"), ("RUST_MT5_BRIDGE_COMMS_ENABLED", "false")]))
+            .expect("valid overrides");
+        assert_eq!((config.comms.port, config.comms.mode, config.comms.enabled, config.comms.host.as_str()), (9760, CommsMode::Server, false, "127.0.0.1"),
+                   "Overrides should replace only their fields");
+        for invalid in [&[("RUST_MT5_BRIDGE_COMMS_PORT", "97600")], &[("RUST_MT5_BRIDGE_COMMS_MODE", "Peer")], &[("RUST_MT5_BRIDGE_COMMS_ENABLED", "yes")]] {
+            assert!(Config::default().with_env_overrides(env(invalid)).is_err(), "{invalid:?} should have been refused");
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Keeps the [Handle]s of the MQL Programs (Expert Advisors, Indicators, Testers, etc.) registered in this DLL.
+//!
+//! Calls from MQL come from many Metatrader threads at once, while the comms server (on its own Tokio threads) also
+//! reads the handles -- so everything here is `Sync` without any `unsafe` code:
+//!   - each slot holds an `Option<Arc<Handle>>` in an [ArcSwapOption], allowing lock-free O(1) reads (the hot-path
+//!     `on_tick()` & `on_book()` calls) while registering and unregistering happen concurrently;
+//!   - anyone holding an `Arc<Handle>` (like the comms sessions) keeps it alive even after it is unregistered;
+//!   - the mutable parts of a [Handle] use their own interior mutability (see the `Mutex`es in [Handle]).
+//!
+//! Slot allocation & `handle_id` generations are delegated to [HandleSlots]. Since the slot validation and the
+//! handle load are two separate atomic operations, the loaded handle has its `handle_id` checked as well -- so a
+//! concurrent unregister + register on the same slot can never make a stale `handle_id` resolve to the new owner.
+
+use super::{
+    handle_slots::HandleSlots,
+    types::Handle,
+};
+use std::sync::Arc;
+use arc_swap::ArcSwapOption;
+
+
+/// Fixed capacity, thread-safe registry of [Handle]s -- see the [module](self) docs
+pub struct HandleRegistry {
+    slots:   HandleSlots,
+    handles: Box<[ArcSwapOption<Handle>]>,
+}
+
+impl HandleRegistry {
+
+    /// Creates a registry able to keep up to `capacity` simultaneously registered handles
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots:   HandleSlots::new(capacity),
+            handles: (0..capacity).map(|_| ArcSwapOption::empty()).collect(),
+        }
+    }
+
+    /// Reserves a slot and places the handle built by `handle_builder(handle_id)` on it, returning its `handle_id`
+    /// -- or `None` if all slots are taken
+    pub fn register(&self, handle_builder: impl FnOnce(i32) -> Handle) -> Option<i32> {
+        let handle_id = self.slots.acquire()?;
+        let slot = self.slots.slot_of(handle_id).expect("BUG! A just acquired `handle_id` is not live");
+        self.handles[slot].store(Some(Arc::new(handle_builder(handle_id))));
+        Some(handle_id)
+    }
+
+    /// Removes the handle for `handle_id` from the registry, making its slot available for the next [Self::register()].\
+    /// Returns the removed handle -- or `None` if `handle_id` was not live
+    pub fn unregister(&self, handle_id: i32) -> Option<Arc<Handle>> {
+        let slot = self.slots.slot_of(handle_id)?;
+        let handle = self.handles[slot].rcu(|handle| match handle {
+            Some(handle) if handle.handle_id == handle_id => None,
+            _ => handle.clone(),
+        });
+        let handle = handle.filter(|handle| handle.handle_id == handle_id)?;
+        self.slots.release(handle_id);
+        Some(handle)
+    }
+
+    /// Lock-free O(1) resolution of `handle_id` into its [Handle] -- or `None` if it isn't live
+    #[inline(always)]
+    pub fn get(&self, handle_id: i32) -> Option<Arc<Handle>> {
+        let slot = self.slots.slot_of(handle_id)?;
+        self.handles[slot].load_full()
+            .filter(|handle| handle.handle_id == handle_id)
+    }
+
+    /// The `handle_id`s currently live, in slot order
+    pub fn live_handle_ids(&self) -> Vec<i32> {
+        self.slots.live_handle_ids()
+    }
+
+    /// The first live handle, in slot order, satisfying `predicate` -- or `None` if there are none
+    pub fn find(&self, predicate: impl Fn(&Handle) -> bool) -> Option<Arc<Handle>> {
+        self.handles.iter()
+            .filter_map(|handle| handle.load_full())
+            .find(|handle| predicate(handle))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mql5_commands::Mql5Command;
+    use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+
+    /// iterations are kept low under Miri, which is orders of magnitude slower
+    const CYCLES: usize = if cfg!(miri) { 16 } else { 10_000 };
+
+
+    /// registered handles must be retrievable -- and unregistered ones mustn't, even if retained elsewhere
+    #[test]
+    fn register_and_unregister() {
+        let registry = HandleRegistry::new(2);
+        let first  = registry.register(|handle_id| Handle::for_testing(handle_id, "FIRST")).expect("registering the 1st handle");
+        let second = registry.register(|handle_id| Handle::for_testing(handle_id, "SECOND")).expect("registering the 2nd handle");
+        assert!(registry.register(|handle_id| Handle::for_testing(handle_id, "THIRD")).is_none(), "Registering past the capacity should fail");
+        let retained = registry.get(first).expect("a registered handle should be retrievable");
+        assert_eq!(retained.symbol, "FIRST", "Wrong handle retrieved");
+        let removed = registry.unregister(first).expect("unregistering a live handle");
+        assert!(Arc::ptr_eq(&removed, &retained), "The unregistered handle should be the one that was registered");
+        assert!(registry.unregister(first).is_none(), "Unregistering twice should fail");
+        assert!(registry.get(first).is_none(), "An unregistered handle should not be retrievable");
+        assert_eq!(retained.symbol, "FIRST", "Handles retained elsewhere should outlive their unregistration");
+        let third = registry.register(|handle_id| Handle::for_testing(handle_id, "THIRD")).expect("the released slot should be reusable");
+        assert!(registry.get(first).is_none(), "A stale `handle_id` must not alias the new owner of its slot");
+        assert_eq!(registry.get(third).map(|handle| handle.symbol.clone()), Some("THIRD".to_string()), "Wrong handle for the reused slot");
+        assert_eq!(registry.get(second).map(|handle| handle.symbol.clone()), Some("SECOND".to_string()), "Unrelated handles should be unaffected");
+        assert_eq!(registry.find(|handle| handle.symbol == "THIRD").map(|handle| handle.handle_id), Some(third), "Live handles should be found by their attributes");
+        assert!(registry.find(|handle| handle.symbol == "FIRST").is_none(), "Unregistered handles should not be found");
+    }
+
+    /// readers (like `on_tick()` & the comms server) race against registering/unregistering threads:
+    /// a resolved handle must always be the one registered for the requested `handle_id`
+    /// -- run it with `cargo +nightly miri test handle_registry` to have the absence of data races verified
+    #[test]
+    fn concurrent_access() {
+        let registry = HandleRegistry::new(4);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for writer in 0..2 {
+                let (registry, done) = (&registry, &done);
+                scope.spawn(move || {
+                    for _ in 0..CYCLES {
+                        let handle_id = registry.register(|handle_id| Handle::for_testing(handle_id, &format!("{handle_id}")))
+                            .expect("writers never take all slots");
+                        let handle = registry.get(handle_id).expect("a just registered handle should be live");
+                        handle.mql5_calls.schedule(Mql5Command::Print(format!("from writer {writer}")));
+                        registry.unregister(handle_id).expect("unregistering a live handle");
+                    }
+                    done.store(true, Relaxed);
+                });
+            }
+            for _reader in 0..2 {
+                let (registry, done) = (&registry, &done);
+                scope.spawn(move || {
+                    while !done.load(Relaxed) {
+                        for handle_id in 0..(4 * CYCLES as i32) {
+                            if let Some(handle) = registry.get(handle_id) {
+                                assert_eq!(handle.handle_id, handle_id, "A `handle_id` resolved to another handle");
+                                assert_eq!(handle.symbol, format!("{handle_id}"), "A `handle_id` resolved to another handle's data");
+                                handle.books.lock().buy_orders.clear();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert!(registry.live_handle_ids().is_empty(), "All handles should have been unregistered");
+    }
+}
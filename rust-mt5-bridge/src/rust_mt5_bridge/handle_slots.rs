@@ -0,0 +1,158 @@
+//! Allocation of the `handle_id`s conceived to MQL Programs (Expert Advisors, Indicators, Testers, etc.).
+//!
+//! Slots are reclaimed when MQL Programs unregister -- so charts may be changed, EAs recompiled and templates reloaded
+//! as many times as needed, without requiring Metatrader to be restarted. To avoid a stale `handle_id` (from an
+//! already unregistered MQL Program) being silently aliased to whichever MQL Program took its slot next, each slot
+//! carries a generation counter -- which is encoded in the `handle_id` itself:
+//! ```nocompile
+//!     handle_id = generation * capacity + slot
+//! ```
+//! ... so the first `handle_id`s given out are still `0, 1, 2, ...`.
+//!
+//! # Implementation notes:
+//!
+//! Validating a `handle_id` (which happens on every call from MQL, including the hot-path `on_tick()` & `on_book()`)
+//! is lock-free: a single atomic load. A mutex is only used to guard the free list -- when registering or unregistering.
+
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::Ordering::{Acquire, Release};
+use parking_lot::Mutex;
+
+
+/// Bit, in the slot state, indicating the slot is taken
+const LIVE_BIT: u32 = 1;
+
+
+/// Fixed capacity allocator of `handle_id`s -- see the [module](self) docs
+pub struct HandleSlots {
+    /// For each slot: `(generation << 1) | LIVE_BIT` -- the generation is incremented whenever the slot is released
+    slot_states: Box<[AtomicU32]>,
+    /// Vacant slots, in the order they should be reused: the longer a slot stays vacant, the less likely
+    /// a stale `handle_id` for it is still around
+    free_slots: Mutex<VecDeque<u32>>,
+    /// Generations wrap around after this one, so that `handle_id`s always fit in a positive `i32`
+    max_generation: u32,
+}
+
+impl HandleSlots {
+
+    /// Creates an allocator able to keep up to `capacity` simultaneously registered handles
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0 && capacity <= i32::MAX as usize / 2, "HandleSlots: invalid capacity {capacity}");
+        Self {
+            slot_states:    (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            free_slots:     Mutex::new((0..capacity as u32).collect()),
+            max_generation: (i32::MAX as u32 / capacity as u32) - 1,
+        }
+    }
+
+    /// Reserves a vacant slot, returning the `handle_id` for it -- or `None` if all slots are taken
+    pub fn acquire(&self) -> Option<i32> {
+        let mut free_slots = self.free_slots.lock();
+        let slot = free_slots.pop_front()?;
+        let generation = self.slot_states[slot as usize].load(Acquire) >> 1;
+        self.slot_states[slot as usize].store((generation << 1) | LIVE_BIT, Release);
+        Some(self.handle_id(slot, generation))
+    }
+
+    /// Vacates the slot taken by `handle_id`, advancing its generation so that `handle_id` is no longer valid.\
+    /// Returns `false` if `handle_id` was not live (never given out, already released or stale)
+    pub fn release(&self, handle_id: i32) -> bool {
+        let mut free_slots = self.free_slots.lock();
+        let Some(slot) = self.slot_of(handle_id) else {
+            return false;
+        };
+        let generation = self.slot_states[slot].load(Acquire) >> 1;
+        let next_generation = if generation >= self.max_generation { 0 } else { generation + 1 };
+        self.slot_states[slot].store(next_generation << 1, Release);
+        free_slots.push_back(slot as u32);
+        true
+    }
+
+    /// Lock-free validation of `handle_id`, returning the slot index it refers to -- or `None` if
+    /// `handle_id` is not live (never given out, already released or stale)
+    #[inline(always)]
+    pub fn slot_of(&self, handle_id: i32) -> Option<usize> {
+        if handle_id < 0 {
+            return None;
+        }
+        let capacity = self.slot_states.len() as u32;
+        let (generation, slot) = (handle_id as u32 / capacity, handle_id as u32 % capacity);
+        (self.slot_states[slot as usize].load(Acquire) == (generation << 1) | LIVE_BIT)
+            .then_some(slot as usize)
+    }
+
+    /// The `handle_id`s currently live, in slot order
+    pub fn live_handle_ids(&self) -> Vec<i32> {
+        self.slot_states.iter()
+            .enumerate()
+            .filter_map(|(slot, state)| {
+                let state = state.load(Acquire);
+                (state & LIVE_BIT == LIVE_BIT).then(|| self.handle_id(slot as u32, state >> 1))
+            })
+            .collect()
+    }
+
+    fn handle_id(&self, slot: u32, generation: u32) -> i32 {
+        (generation * self.slot_states.len() as u32 + slot) as i32
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    /// the first `handle_id`s must be sequential, as MQL Programs rely on `handle_id` 0 being the first one
+    #[test]
+    fn sequential_first_generation() {
+        let slots = HandleSlots::new(4);
+        let handle_ids = (0..4).map(|_| slots.acquire()).collect::<Vec<_>>();
+        assert_eq!(handle_ids, vec![Some(0), Some(1), Some(2), Some(3)], "First generation `handle_id`s should match the slot indexes");
+        assert_eq!(slots.acquire(), None, "No `handle_id`s should be given out when all slots are taken");
+        assert_eq!(slots.live_handle_ids(), vec![0, 1, 2, 3], "Wrong live `handle_id`s");
+    }
+
+    /// released slots must be reusable -- with a new `handle_id` -- and the old `handle_id` must be rejected
+    #[test]
+    fn reclaiming_and_stale_detection() {
+        let slots = HandleSlots::new(2);
+        let first  = slots.acquire().expect("acquiring the 1st slot");
+        let second = slots.acquire().expect("acquiring the 2nd slot");
+        assert!(slots.release(first), "Releasing a live `handle_id` should succeed");
+        assert!(!slots.release(first), "Releasing an already released `handle_id` should fail");
+        assert_eq!(slots.slot_of(first), None, "A released `handle_id` should not be considered live");
+        let reused = slots.acquire().expect("a released slot should be reusable");
+        assert_ne!(reused, first, "A reclaimed slot should be given out with a different `handle_id`");
+        assert_eq!(slots.slot_of(reused), Some(0), "The reused `handle_id` should refer to the released slot");
+        assert_eq!(slots.slot_of(first), None, "A stale `handle_id` must not alias the new owner of its slot");
+        assert_eq!(slots.slot_of(second), Some(1), "Unrelated `handle_id`s should be unaffected");
+        assert_eq!(slots.live_handle_ids(), vec![reused, second], "Wrong live `handle_id`s");
+    }
+
+    /// garbage `handle_id`s -- negative, out of range, future generations -- must all be rejected
+    #[test]
+    fn invalid_handle_ids() {
+        let slots = HandleSlots::new(3);
+        let handle_id = slots.acquire().expect("acquiring a slot");
+        for invalid in [-1, i32::MIN, 1, 2, 3, handle_id + 3, i32::MAX] {
+            assert_eq!(slots.slot_of(invalid), None, "`handle_id` {invalid} should not be considered live");
+        }
+    }
+
+    /// register/unregister cycles must be sustainable indefinitely -- generations wrap around
+    #[test]
+    fn many_cycles() {
+        let slots = HandleSlots::new(128);
+        let mut previous = -1;
+        for _ in 0..100_000 {
+            let handle_id = slots.acquire().expect("cycles should never exhaust the slots");
+            assert!(handle_id >= 0, "`handle_id`s must always be positive");
+            assert_ne!(handle_id, previous, "Consecutive cycles should not repeat `handle_id`s");
+            assert!(slots.release(handle_id), "Releasing a live `handle_id` should succeed");
+            previous = handle_id;
+        }
+    }
+}
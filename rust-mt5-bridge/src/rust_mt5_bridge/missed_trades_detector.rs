@@ -0,0 +1,160 @@
+//! Detects the trades Metatrader didn't report: on some brokers (see [MqlTick::to_event()]), trades may not produce an
+//! `OnTick()` event -- luckily, only when the price doesn't change. So, between `OnBook()` events, the quantity that
+//! disappeared from the levels a trade at the last traded price could have consumed -- asks at or below it; bids at or
+//! above it -- is reconciled with the volume reported by the [Trade]s: whatever is left unexplained is taken as missed
+//! trades, synthesized as a single [Trade] marked as `inferred`.
+//!
+//! Trades may be reported before the books reflect them, so the reported volume left unmatched by a book update is
+//! carried to the next one. Cancellations at the last traded price are indistinguishable from missed trades, hence the
+//! counts in [MissedTradesStats] are estimates -- they feed [RiskLimits::max_missed_trades_per_minute].
+//!
+//! [RiskLimits::max_missed_trades_per_minute]: super::risk_manager::RiskLimits::max_missed_trades_per_minute
+
+use super::types::*;
+use chrono::NaiveDateTime;
+
+
+/// Quantities smaller than this are taken as reconciled
+const VOLUME_EPSILON: f64 = 1e-9;
+
+
+/// Counters for the reconciliation of the reported trades with the book updates
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct MissedTradesStats {
+    /// book updates reconciled with the trades
+    pub reconciliations: u64,
+    /// book updates that consumed more than the trades reported -- how many inferred trades were synthesized
+    pub discrepancies:   u64,
+    /// the quantity of all inferred trades
+    pub inferred_volume: f64,
+}
+
+
+/// Per handle, reconciles the trades with the book updates -- see the [module](self) docs
+#[derive(Debug,Default)]
+pub struct MissedTradesDetector {
+    last_trade_price: Option<f64>,
+    /// volume of the trades reported since the last book update
+    reported_volume:  f64,
+    /// reported volume the last book update didn't account for -- from trades reported ahead of their book update
+    carried_volume:   f64,
+    stats:            MissedTradesStats,
+}
+
+impl MissedTradesDetector {
+
+    /// Registers a trade reported by Metatrader
+    pub fn on_trade(&mut self, trade: &Trade) {
+        self.last_trade_price = Some(trade.price);
+        self.reported_volume += trade.quantity as f64;
+    }
+
+    /// Reconciles the reported trades with `delta_events` -- already applied to `books` -- returning the inferred trade,
+    /// at `time`, if the books were consumed beyond what was reported
+    pub fn on_book_deltas<'a>(&mut self, symbol: &'a String, time: NaiveDateTime, books: &OrderBooks, delta_events: &[BookEvents]) -> Option<Trade<'a>> {
+        let last_trade_price = self.last_trade_price?;
+        let (mut asks_consumed, mut bids_consumed) = (0.0, 0.0);
+        for delta_event in delta_events {
+            let (book, price, consumed) = match delta_event {
+                BookEvents::Del    { book, price, quantity }                                => (book, *price, *quantity),
+                BookEvents::Update { book, price, delta_quantity } if *delta_quantity < 0.0 => (book, *price, -delta_quantity),
+                _ => continue,
+            };
+            match book {
+                BookParties::Sellers if price <= last_trade_price => asks_consumed += consumed,
+                BookParties::Buyers  if price >= last_trade_price => bids_consumed += consumed,
+                _ => (),
+            }
+        }
+
+        let available_volume = self.reported_volume + self.carried_volume;
+        let consumed_volume = asks_consumed + bids_consumed;
+        self.carried_volume = (available_volume - consumed_volume).max(0.0);
+        self.reported_volume = 0.0;
+        self.stats.reconciliations += 1;
+
+        let missed_volume = consumed_volume - available_volume;
+        if missed_volume < VOLUME_EPSILON {
+            return None;
+        }
+        self.stats.discrepancies += 1;
+        self.stats.inferred_volume += missed_volume;
+        let (bid, ask) = (books.best_bid().map_or(0.0, |level| level.price), books.best_ask().map_or(0.0, |level| level.price));
+        Some(Trade {
+            symbol,
+            time,
+            aggressor:  if asks_consumed >= bids_consumed { TradeParty::Buyer {bid, ask} } else { TradeParty::Seller {bid, ask} },
+            confidence: AggressorConfidence::Low,
+            inferred:   true,
+            quantity:   missed_volume.round() as u32,
+            price:      last_trade_price,
+        })
+    }
+
+    /// Forgets the reported volume, as the books were rebuilt -- their delta events can't be reconciled with the trades
+    pub fn on_resync(&mut self) {
+        self.reported_volume = 0.0;
+        self.carried_volume = 0.0;
+    }
+
+    /// How the reconciliations went, so far
+    pub fn stats(&self) -> &MissedTradesStats {
+        &self.stats
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+
+    /// checks reported trades explain the consumed levels -- even if reported ahead of their book updates -- and that
+    /// unexplained consumption is inferred as missed trades, on the right side
+    #[test]
+    fn reconciliation() {
+        let symbol = String::from("PETR4");
+        let time = NaiveDate::from_ymd_opt(2023, 7, 4).unwrap().and_hms_milli_opt(10, 5, 9, 21).unwrap();
+        let books = OrderBooks::default();
+        let trade = |quantity| Trade { symbol: &symbol, time, aggressor: TradeParty::Buyer { bid: 32.01, ask: 32.02 }, confidence: AggressorConfidence::High, inferred: false, quantity, price: 32.02 };
+        let consumed = |book, price, delta_quantity| BookEvents::Update { book, price, delta_quantity };
+        let mut detector = MissedTradesDetector::default();
+
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.02, -300.0)]).is_none(),
+                "Nothing should be inferred before the first trade -- the last traded price is unknown");
+
+        detector.on_trade(&trade(300));
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.02, -300.0)]).is_none(), "Reported trades should explain the consumption");
+
+        let inferred = detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.02, -500.0)])
+            .expect("consumption without reported trades should be inferred as a missed trade");
+        assert!(inferred.inferred && matches!(inferred.aggressor, TradeParty::Buyer {..}) && inferred.quantity == 500 && inferred.price == 32.02,
+                "Wrong inferred trade: {inferred:?}");
+
+        detector.on_trade(&trade(200));
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[BookEvents::Add { book: BookParties::Buyers, price: 31.99, quantity: 100.0 }]).is_none(), "Additions consume nothing");
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[BookEvents::Del { book: BookParties::Sellers, price: 32.02, quantity: 200.0 }]).is_none(),
+                "Trades reported ahead of their book update should explain the consumption");
+
+        detector.on_trade(&trade(500));
+        for (book_update, consumed_quantity) in [(1, 200.0), (2, 100.0), (3, 200.0)] {
+            assert!(detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.02, -consumed_quantity)]).is_none(),
+                    "The reported volume should be carried along as many book updates as needed to be consumed -- failed on update #{book_update}");
+        }
+
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.10, -1000.0), consumed(BookParties::Buyers, 31.90, -1000.0)]).is_none(),
+                "Levels a trade at the last traded price couldn't reach should not be reconciled");
+
+        let inferred = detector.on_book_deltas(&symbol, time, &books, &[BookEvents::Del { book: BookParties::Buyers, price: 32.02, quantity: 100.0 }])
+            .expect("consumed bids should be inferred as a missed trade");
+        assert!(matches!(inferred.aggressor, TradeParty::Seller {..}) && inferred.quantity == 100, "Consumed bids should be inferred as sold: {inferred:?}");
+
+        detector.on_trade(&trade(400));
+        detector.on_resync();
+        assert!(detector.on_book_deltas(&symbol, time, &books, &[consumed(BookParties::Sellers, 32.02, -400.0)]).is_some(),
+                "Reported volume should be forgotten on resyncs");
+
+        assert_eq!(detector.stats(), &MissedTradesStats { reconciliations: 10, discrepancies: 3, inferred_volume: 1000.0 }, "Wrong stats");
+    }
+}
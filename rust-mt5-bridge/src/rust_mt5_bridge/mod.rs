@@ -3,8 +3,19 @@ pub mod types;
 
 mod rust_mt5_bridge;
 pub use rust_mt5_bridge::*;
+mod config;
+mod handle_slots;
+mod handle_registry;
 
 mod mql_rust_enum;
+mod mql5_commands;
+mod order_manager;
+mod risk_manager;
+mod aggressor_classifier;
+mod missed_trades_detector;
+mod portfolio;
+mod algorithms;
+mod xorshift;
 
 mod comms;
 mod ogre_exchange_models;
\ No newline at end of file
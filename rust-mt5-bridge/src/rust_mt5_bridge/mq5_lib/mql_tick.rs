@@ -4,7 +4,7 @@
 use std::fmt::{Debug, Formatter};
 use chrono::NaiveDateTime;
 use super::{
-    super::types::{TickEvent, Trade, Spread, TradeParty},
+    super::types::{TickEvent, Trade, Spread, TradeParty, AggressorConfidence},
     types::*,
 };
 
@@ -83,7 +83,8 @@ impl MqlTick<'_> {
 	/// The provided information is tricky and rather poorly documented -- it is most likely that it is dependent on the broker being used./
 	/// For instance, on the "Clear broker (for B3)", flags are known to come zeroed out for legitimate trades --
 	/// other discrepancies also happen, like a tick event having the information of both Buying and Selling flags as well as, some times, even bids > asks./
-	/// For that particular broker, some trades misses the event completely, but, fortunately, only when there isn't a price change.
+	/// For that particular broker, some trades misses the event completely, but, fortunately, only when there isn't a price change.\
+	/// The aggressor of trades is just a first guess, from this tick alone -- see `aggressor_classifier.rs` for the definitive one.
 	pub fn to_event(&self) -> TickEvent {
 
 		// `NaiveDateTime` with millisecond precision
@@ -94,8 +95,8 @@ impl MqlTick<'_> {
 			let trade = Trade {
 				symbol:    self.symbol,
 				time:      datetime,
-				// NOTE: According to production data (see tests), the aggressor determination is not always accurate. Further analysis
-				//       may improve the classification -- for instance, by keeping track of the last bid and ask price points
+				// NOTE: According to production data, this determination is not always accurate -- the `AggressorClassifier` keeps
+				//       track of the prevailing quotes & trade prices to do better
 				aggressor: if (self.flags & TICK_FLAG_BUY > 0 && self.flags & TICK_FLAG_SELL > 0) ||
 					(self.last == self.ask && self.last == self.bid) {
 					TradeParty::Ambiguous {bid: self.bid, ask: self.ask}
@@ -106,6 +107,8 @@ impl MqlTick<'_> {
 				} else {
 					TradeParty::Unspecified {bid: self.bid, ask: self.ask}
 				},
+				confidence: AggressorConfidence::Unknown,
+				inferred:  false,
 				quantity:  self.volume as u32,
 				price:     self.last,
 			};
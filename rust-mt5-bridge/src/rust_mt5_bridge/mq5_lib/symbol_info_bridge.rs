@@ -361,6 +361,35 @@ impl SymbolInfoBridge {
     }
 }
 
+#[cfg(test)]
+impl SymbolInfoRust {
+    /// A symbol info with everything zeroed (and strings empty) but the given `symbol_trade_tick_size` & `symbol_volume_min`
+    pub fn for_testing(symbol_trade_tick_size: f64, symbol_volume_min: f64) -> Self {
+        static EMPTY_MQL_STRING: [u16; 1] = [0];
+        let empty: MQ5String = (1, EMPTY_MQL_STRING.as_ptr() as u64 as u32, (EMPTY_MQL_STRING.as_ptr() as u64 >> 32) as u32);
+        // all fields are plain numbers -- but the strings, which are pointed to `EMPTY_MQL_STRING` below
+        let mut symbol_info_bridge: SymbolInfoBridge = unsafe { std::mem::zeroed() };
+        symbol_info_bridge.symbol_basis = empty;
+        symbol_info_bridge.symbol_category = empty;
+        symbol_info_bridge.symbol_country = empty;
+        symbol_info_bridge.symbol_sector_name = empty;
+        symbol_info_bridge.symbol_industry_name = empty;
+        symbol_info_bridge.symbol_currency_base = empty;
+        symbol_info_bridge.symbol_currency_profit = empty;
+        symbol_info_bridge.symbol_currency_margin = empty;
+        symbol_info_bridge.symbol_bank = empty;
+        symbol_info_bridge.symbol_description = empty;
+        symbol_info_bridge.symbol_exchange = empty;
+        symbol_info_bridge.symbol_formula = empty;
+        symbol_info_bridge.symbol_isin = empty;
+        symbol_info_bridge.symbol_page = empty;
+        symbol_info_bridge.symbol_path = empty;
+        symbol_info_bridge.symbol_trade_tick_size = symbol_trade_tick_size;
+        symbol_info_bridge.symbol_volume_min = symbol_volume_min;
+        SymbolInfoBridge::from_ptr_to_internal(&symbol_info_bridge)
+    }
+}
+
 /// Rust version of the Metatrader 5 [SymbolInfoBridge], with with correct alignment, redundant fields removed, dates, colors, strings & enums resolved and copied to Rust -- so the MQL reference may be freed as soon as possible in MT5
 #[derive(Debug)]
 pub struct SymbolInfoRust {
@@ -0,0 +1,582 @@
+//! Typed API for Rust to have MQL5 functions called -- as MQL5 doesn't allow DLLs to call its functions
+//! (see https://www.mql5.com/en/forum/127282), MQL Programs ask Rust for the next functions to call, which are kept
+//! in a per-handle [Mql5Calls] queue.
+//!
+//! The protocol, implemented by `RustToMQLMethodCall.mqh`, is as follows:
+//!   1) Rust enqueues an [Mql5Command], which is given a `call_id`;
+//!   2) `next_mql5_function_to_call()` returns the `call_id` & places the JSON call descriptor in MQL's buffer, in the form
+//!      `{"fn_to_call": "MqlFunction", "params": ...}` -- see [Mql5Command::to_json()];
+//!   3) MQL calls the function and reports back, through `report_mql5_function_called()`, a JSON in the form
+//!      `{"fn_called": "MqlFunction", "call_id": 12, "returns": ...}` -- which is parsed into an [Mql5CommandResult] by
+//!      [Mql5Calls::complete()], resolving the future or callback given when the command was scheduled.
+//!
+//! Calls not completed within [MQL5_CALL_TIMEOUT] are given up on, resolving with [Mql5CallError::Timeout].
+//!
+//! NOTE: awaiting on the results from within the Metatrader threads would deadlock, as they are the ones executing the commands
+//!       -- so only callbacks (or fire-and-forget commands) should be used there. Futures are for the comms (Tokio) threads.
+
+use super::types::*;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use futures::channel::oneshot;
+use log::{debug, info, warn};
+
+
+/// Magic number identifying, in Metatrader, the orders issued by this DLL
+pub const ORDER_MAGIC: u64 = 9758;
+
+/// How long, since being scheduled, a command may take for MQL to pick, execute & report it back -- after which
+/// it is resolved with [Mql5CallError::Timeout]
+pub const MQL5_CALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+
+/// The MQL5 functions Rust may have called -- see `RustToMQLMethodCall.mqh` for how each one is executed
+#[derive(Debug,Clone,PartialEq)]
+pub enum Mql5Command {
+    /// Shows a dialog box with the given message -- https://www.mql5.com/en/docs/common/alert
+    Alert(String),
+    /// Prints the message on the Experts log -- https://www.mql5.com/en/docs/common/print
+    Print(String),
+    /// Shows the message on the top left corner of the chart -- https://www.mql5.com/en/docs/common/comment
+    Comment(String),
+    /// Calculates the margin required for an order -- https://www.mql5.com/en/docs/trading/ordercalcmargin
+    OrderCalcMargin { order_type: EnumOrderType, symbol: String, volume: f64, price: f64 },
+    /// Checks if there are enough funds to execute the order -- https://www.mql5.com/en/docs/trading/ordercheck
+    OrderCheck(OrderRequest),
+    /// Sends the order to the trade server -- https://www.mql5.com/en/docs/trading/ordersend
+    OrderSend(OrderRequest),
+    /// Closes the position with the given ticket with an opposite market order, accepting the given price `deviation` (in points)
+    PositionClose { position: u64, deviation: u64 },
+    /// Removes the pending order with the given ticket -- through `OrderSend()` with `TRADE_ACTION_REMOVE`
+    OrderDelete { order: u64 },
+    /// Has MQL call `report_account_info()`
+    CollectAccountInfo,
+    /// Has MQL call `report_symbol_info()`
+    CollectSymbolInfo,
+    /// Has MQL call `report_deal_properties()` for every deal in the history
+    CollectAllDealsProperties,
+}
+
+impl Mql5Command {
+
+    /// The function name, as known by `RustToMQLMethodCall.mqh`
+    pub fn fn_name(&self) -> &'static str {
+        match self {
+            Self::Alert(_)                  => "Alert",
+            Self::Print(_)                  => "Print",
+            Self::Comment(_)                => "Comment",
+            Self::OrderCalcMargin { .. }    => "OrderCalcMargin",
+            Self::OrderCheck(_)             => "OrderCheck",
+            Self::OrderSend(_)              => "OrderSend",
+            Self::PositionClose { .. }      => "PositionClose",
+            Self::OrderDelete { .. }        => "OrderDelete",
+            Self::CollectAccountInfo        => "collect_and_report_account_info",
+            Self::CollectSymbolInfo         => "collect_and_report_symbol_info",
+            Self::CollectAllDealsProperties => "collect_and_report_all_deals_properties",
+        }
+    }
+
+    /// The JSON call descriptor for MQL to execute this command.\
+    /// Enums are given in their MQL values, as informed by `set_enum_variant_value()`
+    pub fn to_json(&self) -> String {
+        let params = match self {
+            Self::Alert(message) | Self::Print(message) | Self::Comment(message) => json!([message]),
+            Self::OrderCalcMargin { order_type, symbol, volume, price } => json!({
+                "enum_order_type_action": ENUM_ORDER_TYPE.resolve_mql_variant(*order_type),
+                "symbol":                 symbol,
+                "volume":                 volume,
+                "price":                  price,
+            }),
+            Self::OrderCheck(request) | Self::OrderSend(request) => json!({"request": request.to_json()}),
+            Self::PositionClose { position, deviation } => json!({"position": position, "deviation": deviation}),
+            Self::OrderDelete { order } => json!({"order": order}),
+            Self::CollectAccountInfo | Self::CollectSymbolInfo | Self::CollectAllDealsProperties => json!([]),
+        };
+        json!({"fn_to_call": self.fn_name(), "params": params}).to_string()
+    }
+
+    /// Parses the `returns` JSON reported by MQL after executing this command
+    fn parse_returns(&self, returns: Value) -> Result<Mql5CommandResult, serde_json::Error> {
+        Ok(match self {
+            Self::Alert(_) | Self::Print(_) | Self::Comment(_) |
+            Self::CollectAccountInfo | Self::CollectSymbolInfo | Self::CollectAllDealsProperties => Mql5CommandResult::Done,
+            Self::OrderCalcMargin { .. } => {
+                let MarginReturns { mt5_error_code, margin } = serde_json::from_value(returns)?;
+                Mql5CommandResult::Margin { mt5_error_code, margin }
+            },
+            Self::OrderCheck(_) => {
+                let TradeReturns { mt5_error_code, result } = serde_json::from_value::<TradeReturns<TradeCheckResultReturns>>(returns)?;
+                Mql5CommandResult::TradeCheck { mt5_error_code, result: result.into() }
+            },
+            Self::OrderSend(_) | Self::PositionClose { .. } | Self::OrderDelete { .. } => {
+                let TradeReturns { mt5_error_code, result } = serde_json::from_value::<TradeReturns<TradeResultReturns>>(returns)?;
+                Mql5CommandResult::Trade { mt5_error_code, result: result.into() }
+            },
+        })
+    }
+}
+
+
+/// An order to be checked or sent to the trade server -- the Rust side of MQL's `MqlTradeRequest` for `OrderCheck()` & `OrderSend()`
+#[derive(Debug,Clone,PartialEq)]
+pub struct OrderRequest {
+    pub action:             EnumTradeRequestActions,
+    pub symbol:             String,
+    /// in lots
+    pub volume:             f64,
+    /// 0.0 for market orders
+    pub price:              f64,
+    /// Stop Loss level -- 0.0 for none
+    pub sl:                 f64,
+    /// Take Profit level -- 0.0 for none
+    pub tp:                 f64,
+    pub order_type:         EnumOrderType,
+    pub order_type_filling: EnumOrderTypeFilling,
+    pub order_type_time:    EnumOrderTypeTime,
+    pub comment:            String,
+}
+
+impl OrderRequest {
+
+    /// The `request` param, as read by `mql_trade_request_from_json()` in `RustToMQLMethodCall.mqh`
+    fn to_json(&self) -> Value {
+        json!({
+            "action":       ENUM_TRADE_REQUEST_ACTIONS.resolve_mql_variant(self.action),
+            "magic":        ORDER_MAGIC,
+            "order":        0,
+            "symbol":       self.symbol,
+            "volume":       self.volume,
+            "price":        self.price,
+            "stoplimit":    0.0,
+            "sl":           self.sl,
+            "tp":           self.tp,
+            "deviation":    5,
+            "type":         ENUM_ORDER_TYPE.resolve_mql_variant(self.order_type),
+            "type_filling": ENUM_ORDER_TYPE_FILLING.resolve_mql_variant(self.order_type_filling),
+            "type_time":    ENUM_ORDER_TYPE_TIME.resolve_mql_variant(self.order_type_time),
+            "expiration":   0,
+            "comment":      self.comment,
+            "position":     0,
+            "position_by":  0,
+        })
+    }
+}
+
+
+/// The typed outcome of an [Mql5Command] -- `mt5_error_code` is MQL's `GetLastError()`, `0` meaning success
+#[derive(Debug)]
+pub enum Mql5CommandResult {
+    /// For commands that don't return anything
+    Done,
+    /// For [Mql5Command::OrderCalcMargin]
+    Margin     { mt5_error_code: i32, margin: f64 },
+    /// For [Mql5Command::OrderCheck]
+    TradeCheck { mt5_error_code: i32, result: MqlTradeCheckResult },
+    /// For [Mql5Command::OrderSend], [Mql5Command::PositionClose] & [Mql5Command::OrderDelete]
+    Trade      { mt5_error_code: i32, result: MqlTradeResult },
+}
+
+/// Rust version of MQL's `MqlTradeCheckResult` -- https://www.mql5.com/en/docs/constants/structures/mqltradecheckresult
+#[derive(Debug)]
+pub struct MqlTradeCheckResult {
+    /// Reply code
+    pub retcode:      Mt5TradeServerReturnCodes,
+    /// Balance after the execution of the deal
+    pub balance:      f64,
+    /// Equity after the execution of the deal
+    pub equity:       f64,
+    /// Floating profit
+    pub profit:       f64,
+    /// Margin requirements
+    pub margin:       f64,
+    /// Free margin
+    pub margin_free:  f64,
+    /// Margin level
+    pub margin_level: f64,
+    /// Comment to the reply code (description of the error)
+    pub comment:      String,
+}
+
+/// Reasons for a scheduled [Mql5Command] not to yield an [Mql5CommandResult]
+#[derive(Debug,Clone,PartialEq)]
+pub enum Mql5CallError {
+    /// The MQL Program went away (unregistered) before reporting the results back -- or the call couldn't be given to it
+    /// (see [Mql5Calls::abandon()])
+    Abandoned,
+    /// MQL didn't report the results back within the deadline -- see [MQL5_CALL_TIMEOUT]. If it was picked, the command
+    /// might still have been executed
+    Timeout,
+    /// MQL reported results that couldn't be parsed -- likely, `RustToMQLMethodCall.mqh` is out of sync with this DLL
+    InvalidReturns(String),
+}
+
+
+/// Receives the outcome of a command, when MQL reports it back
+type Responder = Box<dyn FnOnce(Result<Mql5CommandResult, Mql5CallError>) + Send>;
+
+/// A command executing in MQL, awaiting its results to be reported
+struct PendingCall {
+    command:   Mql5Command,
+    responder: Option<Responder>,
+    /// when to give up waiting for the results
+    deadline:  Instant,
+}
+
+/// Per-handle queue of MQL5 function calls & their pending results -- see the [module](self) docs
+pub struct Mql5Calls {
+    next_call_id:     AtomicU32,
+    /// JSON call descriptors waiting to be picked by MQL, by `call_id`
+    to_call:          Mutex<VecDeque<(u32, String)>>,
+    /// Typed commands already scheduled, by `call_id` -- kept until MQL reports their results back
+    pending_results:  Mutex<HashMap<u32, PendingCall>>,
+    /// see [MQL5_CALL_TIMEOUT]
+    timeout:          Duration,
+}
+
+impl Mql5Calls {
+
+    pub fn new() -> Self {
+        Self::with_timeout(MQL5_CALL_TIMEOUT)
+    }
+
+    /// Like [Self::new()], but with a custom `timeout` for the calls -- instead of [MQL5_CALL_TIMEOUT]
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            next_call_id:    AtomicU32::new(0),
+            to_call:         Mutex::new(VecDeque::with_capacity(16)),
+            pending_results: Mutex::new(HashMap::with_capacity(16)),
+            timeout,
+        }
+    }
+
+    /// Schedules `command`, whose results will only be logged.\
+    /// Returns the `call_id`
+    pub fn schedule(&self, command: Mql5Command) -> u32 {
+        self.enqueue(command, None)
+    }
+
+    /// Schedules `command`, having `callback` called (from the Metatrader thread reporting it) with its results.\
+    /// Returns the `call_id`
+    pub fn schedule_with_callback(&self, command: Mql5Command, callback: impl FnOnce(Result<Mql5CommandResult, Mql5CallError>) + Send + 'static) -> u32 {
+        self.enqueue(command, Some(Box::new(callback)))
+    }
+
+    /// Schedules `command`, returning its `call_id` and a future to be resolved with its results -- see the [module](self) docs
+    pub fn call(&self, command: Mql5Command) -> (u32, impl Future<Output=Result<Mql5CommandResult, Mql5CallError>>) {
+        let (sender, receiver) = oneshot::channel();
+        let call_id = self.schedule_with_callback(command, move |result| { let _ = sender.send(result); });
+        (call_id, async move { receiver.await.unwrap_or(Err(Mql5CallError::Abandoned)) })
+    }
+
+    /// Schedules a hand-written JSON call descriptor -- sent verbatim to MQL, with the results being only logged.\
+    /// For testing the MQL side of the protocol -- typed commands should use [Self::schedule()] instead.\
+    /// Returns the `call_id`
+    pub fn schedule_raw(&self, function_call_descriptor: String) -> u32 {
+        let call_id = self.new_call_id();
+        self.to_call.lock().push_back((call_id, function_call_descriptor));
+        call_id
+    }
+
+    /// Consumes the next call for MQL to execute, returning its `call_id` & JSON call descriptor
+    pub fn next_call(&self) -> Option<(u32, String)> {
+        self.expire_calls();
+        self.to_call.lock().pop_front()
+    }
+
+    /// Gives up on `call_id` -- already consumed by [Self::next_call()] but, for some reason, not given to MQL -- resolving
+    /// it with [Mql5CallError::Abandoned]
+    pub fn abandon(&self, call_id: u32) {
+        let Some(PendingCall { command, responder, .. }) = self.pending_results.lock().remove(&call_id) else {
+            return;
+        };
+        match responder {
+            Some(responder) => responder(Err(Mql5CallError::Abandoned)),
+            None => warn!("Mql5Calls: {command:?} (call #{call_id}) was abandoned"),
+        }
+    }
+
+    /// Number of calls waiting to be picked by MQL
+    pub fn len(&self) -> usize {
+        self.to_call.lock().len()
+    }
+
+    /// Processes the JSON MQL reports after executing a call -- see the [module](self) docs -- resolving the pending command.\
+    /// `Err` is returned, with a descriptive message, if the JSON is not valid or doesn't match any pending call
+    pub fn complete(&self, function_called_json: &str) -> Result<(), String> {
+        self.expire_calls();
+        let FunctionCalled { fn_called, call_id, returns } = serde_json::from_str(function_called_json)
+            .map_err(|err| format!("Couldn't parse the function called JSON '{function_called_json}': {err}"))?;
+        let Some(call_id) = call_id else {
+            return Err(format!("The function called JSON '{function_called_json}' lacks the `call_id` -- is `RustToMQLMethodCall.mqh` out of sync with this DLL?"));
+        };
+        let Some(PendingCall { command, responder, .. }) = self.pending_results.lock().remove(&call_id) else {
+            // hand-written calls (see `schedule_raw()`) are not tracked -- nor are the expired ones
+            debug!("Mql5Calls: results for the untracked call #{call_id} to '{fn_called}': {returns}");
+            return Ok(());
+        };
+        let result = if fn_called != command.fn_name() {
+            Err(Mql5CallError::InvalidReturns(format!("call #{call_id} was for '{}', but results for '{fn_called}' were reported", command.fn_name())))
+        } else {
+            command.parse_returns(returns)
+                .map_err(|err| Mql5CallError::InvalidReturns(format!("couldn't parse the results for call #{call_id} to '{fn_called}': {err}")))
+        };
+        match responder {
+            Some(responder) => responder(result),
+            None => match result {
+                Ok(result) => info!("Mql5Calls: {command:?} (call #{call_id}) yielded {result:?}"),
+                Err(err)   => warn!("Mql5Calls: {command:?} (call #{call_id}) failed: {err:?}"),
+            },
+        }
+        Ok(())
+    }
+
+    fn enqueue(&self, command: Mql5Command, responder: Option<Responder>) -> u32 {
+        let call_id = self.new_call_id();
+        let function_call_descriptor = command.to_json();
+        // tracked before being enqueued, so MQL can never report results for an unknown call
+        self.pending_results.lock().insert(call_id, PendingCall { command, responder, deadline: Instant::now() + self.timeout });
+        self.to_call.lock().push_back((call_id, function_call_descriptor));
+        call_id
+    }
+
+    /// Resolves, with [Mql5CallError::Timeout], the pending calls past their deadlines -- also withdrawing them from MQL, if not picked yet
+    fn expire_calls(&self) {
+        let now = Instant::now();
+        let expired = {
+            let mut pending_results = self.pending_results.lock();
+            let expired_call_ids: Vec<u32> = pending_results.iter()
+                .filter(|(_call_id, pending_call)| pending_call.deadline <= now)
+                .map(|(call_id, _pending_call)| *call_id)
+                .collect();
+            if expired_call_ids.is_empty() {
+                return;
+            }
+            self.to_call.lock().retain(|(call_id, _)| !expired_call_ids.contains(call_id));
+            expired_call_ids.into_iter()
+                .filter_map(|call_id| pending_results.remove(&call_id).map(|pending_call| (call_id, pending_call)))
+                .collect::<Vec<_>>()
+        };
+        // responders are called outside the locks, as they may schedule new commands
+        for (call_id, PendingCall { command, responder, .. }) in expired {
+            match responder {
+                Some(responder) => responder(Err(Mql5CallError::Timeout)),
+                None => warn!("Mql5Calls: {command:?} (call #{call_id}) timed out"),
+            }
+        }
+    }
+
+    /// `call_id`s wrap around within the positive `i32` range -- as negative values tell MQL there is nothing to call
+    fn new_call_id(&self) -> u32 {
+        self.next_call_id.fetch_add(1, Relaxed) & i32::MAX as u32
+    }
+}
+
+impl Default for Mql5Calls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Mql5Calls {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Mql5Calls {{ to_call: {}, pending_results: {} }}", self.to_call.lock().len(), self.pending_results.lock().len())
+    }
+}
+
+impl Drop for Mql5Calls {
+    /// Informs whoever is still waiting that no results will come
+    fn drop(&mut self) {
+        for (_call_id, PendingCall { responder, .. }) in self.pending_results.get_mut().drain() {
+            if let Some(responder) = responder {
+                responder(Err(Mql5CallError::Abandoned));
+            }
+        }
+    }
+}
+
+
+// JSON models for what `RustToMQLMethodCall.mqh` reports back
+///////////////////////////////////////////////////////////////
+
+#[derive(Deserialize)]
+struct FunctionCalled {
+    fn_called: String,
+    call_id:   Option<u32>,
+    /// absent for functions that don't return anything
+    #[serde(default)]
+    returns:   Value,
+}
+
+#[derive(Deserialize)]
+struct MarginReturns {
+    mt5_error_code: i32,
+    margin:         f64,
+}
+
+#[derive(Deserialize)]
+struct TradeReturns<ResultType> {
+    mt5_error_code: i32,
+    result:         ResultType,
+}
+
+#[derive(Deserialize)]
+struct TradeCheckResultReturns {
+    retcode:      u32,
+    balance:      f64,
+    equity:       f64,
+    profit:       f64,
+    margin:       f64,
+    margin_free:  f64,
+    margin_level: f64,
+    comment:      String,
+}
+impl From<TradeCheckResultReturns> for MqlTradeCheckResult {
+    fn from(returns: TradeCheckResultReturns) -> Self {
+        Self {
+            retcode:      Mt5TradeServerReturnCodes::from(returns.retcode),
+            balance:      returns.balance,
+            equity:       returns.equity,
+            profit:       returns.profit,
+            margin:       returns.margin,
+            margin_free:  returns.margin_free,
+            margin_level: returns.margin_level,
+            comment:      returns.comment,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TradeResultReturns {
+    retcode:          u32,
+    deal:             u64,
+    order:            u64,
+    volume:           f64,
+    price:            f64,
+    bid:              f64,
+    ask:              f64,
+    comment:          String,
+    request_id:       u32,
+    retcode_external: u32,
+}
+impl From<TradeResultReturns> for MqlTradeResult {
+    fn from(returns: TradeResultReturns) -> Self {
+        Self {
+            retcode:          Mt5TradeServerReturnCodes::from(returns.retcode),
+            deal:             returns.deal,
+            order:            returns.order,
+            volume:           returns.volume,
+            price:            returns.price,
+            bid:              returns.bid,
+            ask:              returns.ask,
+            comment:          returns.comment,
+            request_id:       returns.request_id,
+            retcode_external: returns.retcode_external,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+
+    /// checks the commands are serialized as `RustToMQLMethodCall.mqh` expects them
+    #[test]
+    fn commands_to_json() {
+        assert_eq!(Mql5Command::Alert(String::from("Hi!")).to_json(), r#"{"fn_to_call":"Alert","params":["Hi!"]}"#, "Wrong JSON for `Alert`");
+        assert_eq!(Mql5Command::OrderDelete { order: 1234 }.to_json(), r#"{"fn_to_call":"OrderDelete","params":{"order":1234}}"#, "Wrong JSON for `OrderDelete`");
+        assert_eq!(Mql5Command::CollectSymbolInfo.to_json(), r#"{"fn_to_call":"collect_and_report_symbol_info","params":[]}"#, "Wrong JSON for `CollectSymbolInfo`");
+        let order_send: Value = serde_json::from_str(&Mql5Command::OrderSend(OrderRequest {
+            action:             EnumTradeRequestActions::TradeActionDeal,
+            symbol:             String::from("PETR4"),
+            volume:             100.0,
+            price:              32.32,
+            sl:                 0.0,
+            tp:                 33.0,
+            order_type:         EnumOrderType::OrderTypeBuy,
+            order_type_filling: EnumOrderTypeFilling::OrderFillingFok,
+            order_type_time:    EnumOrderTypeTime::OrderTimeDay,
+            comment:            String::from("test"),
+        }).to_json()).expect("the command JSON should be valid");
+        assert_eq!(order_send["fn_to_call"], "OrderSend", "Wrong function to call");
+        let request = &order_send["params"]["request"];
+        assert_eq!((&request["symbol"], &request["volume"], &request["tp"], &request["magic"]), (&json!("PETR4"), &json!(100.0), &json!(33.0), &json!(ORDER_MAGIC)), "Wrong request: {request}");
+    }
+
+    /// checks results reported by MQL resolve the futures & callbacks of their commands -- and that mismatches are reported
+    #[test]
+    fn results_matching() {
+        let calls = Mql5Calls::new();
+        let (margin_call_id, margin_future) = calls.call(Mql5Command::OrderCalcMargin { order_type: EnumOrderType::OrderTypeBuy, symbol: String::from("PETR4"), volume: 100.0, price: 32.02 });
+        let callback_result = Arc::new(Mutex::new(None));
+        let callback_result_setter = Arc::clone(&callback_result);
+        let send_call_id = calls.schedule_with_callback(Mql5Command::OrderSend(OrderRequest {
+            action:             EnumTradeRequestActions::TradeActionDeal,
+            symbol:             String::from("PETR4"),
+            volume:             100.0,
+            price:              0.0,
+            sl:                 0.0,
+            tp:                 0.0,
+            order_type:         EnumOrderType::OrderTypeBuy,
+            order_type_filling: EnumOrderTypeFilling::OrderFillingFok,
+            order_type_time:    EnumOrderTypeTime::OrderTimeDay,
+            comment:            String::new(),
+        }), move |result| { callback_result_setter.lock().replace(result); });
+        let print_call_id = calls.schedule(Mql5Command::Print(String::from("not awaited")));
+        assert_eq!(calls.len(), 3, "All scheduled commands should be waiting for MQL");
+        assert_eq!(calls.next_call().map(|(call_id, _)| call_id), Some(margin_call_id), "Calls should be consumed in the scheduling order");
+
+        calls.complete(&format!(r#"{{"fn_called": "OrderCalcMargin", "call_id": {margin_call_id}, "returns": {{"mt5_error_code": 0, "margin": 3202.0}}}}"#))
+            .expect("completing a pending call");
+        match futures::executor::block_on(margin_future) {
+            Ok(Mql5CommandResult::Margin { mt5_error_code: 0, margin }) => assert_eq!(margin, 3202.0, "Wrong margin"),
+            unexpected => panic!("Unexpected result for `OrderCalcMargin`: {unexpected:?}"),
+        }
+
+        calls.complete(&format!(r#"{{"fn_called": "OrderSend", "call_id": {send_call_id}, "returns": {{"mt5_error_code": 0, "result": {{"retcode": 10009, "deal": 7, "order": 8, "volume": 100.0, "price": 32.03, "bid": 32.02, "ask": 32.03, "comment": "done", "request_id": 1, "retcode_external": 0}}}}}}"#))
+            .expect("completing a pending call");
+        match callback_result.lock().take() {
+            Some(Ok(Mql5CommandResult::Trade { mt5_error_code: 0, result })) => assert_eq!((result.retcode, result.deal), (Mt5TradeServerReturnCodes::TradeRetcodeDone, 7), "Wrong trade result"),
+            unexpected => panic!("Unexpected result for `OrderSend`: {unexpected:?}"),
+        }
+
+        assert!(calls.complete(r#"{"fn_called": "Print"}"#).is_err(), "Results without a `call_id` should be rejected");
+        assert!(calls.complete("{garbage").is_err(), "Invalid JSONs should be rejected");
+        calls.complete(&format!(r#"{{"fn_called": "Print", "call_id": {print_call_id}}}"#)).expect("commands without returns should be completed");
+    }
+
+    /// checks those waiting for results are informed when the MQL Program goes away
+    #[test]
+    fn abandoned_calls() {
+        let calls = Mql5Calls::new();
+        let (_call_id, future) = calls.call(Mql5Command::CollectAccountInfo);
+        drop(calls);
+        assert_eq!(futures::executor::block_on(future).err(), Some(Mql5CallError::Abandoned), "Pending calls should be abandoned when their queue is dropped");
+    }
+
+    /// checks calls MQL takes too long to report back are resolved with [Mql5CallError::Timeout] -- and withdrawn, if not picked yet
+    #[test]
+    fn expired_calls() {
+        let calls = Mql5Calls::with_timeout(Duration::from_millis(50));
+        let (picked_call_id, picked) = calls.call(Mql5Command::CollectAccountInfo);
+        let (_unpicked_call_id, unpicked) = calls.call(Mql5Command::CollectSymbolInfo);
+        assert_eq!(calls.next_call().map(|(call_id, _)| call_id), Some(picked_call_id), "Calls should be available before their deadlines");
+        std::thread::sleep(Duration::from_millis(60));
+        let (live_call_id, live) = calls.call(Mql5Command::CollectAllDealsProperties);
+
+        assert_eq!(calls.next_call().map(|(call_id, _)| call_id), Some(live_call_id), "Expired calls should no longer be given to MQL");
+        assert_eq!(futures::executor::block_on(picked).err(),   Some(Mql5CallError::Timeout), "Picked calls not reported in time should time out");
+        assert_eq!(futures::executor::block_on(unpicked).err(), Some(Mql5CallError::Timeout), "Calls not picked in time should time out");
+        calls.complete(&format!(r#"{{"fn_called": "collect_and_report_account_info", "call_id": {picked_call_id}}}"#)).expect("late results should be ignored");
+        calls.complete(&format!(r#"{{"fn_called": "collect_and_report_all_deals_properties", "call_id": {live_call_id}}}"#)).expect("completing a live call");
+        assert!(matches!(futures::executor::block_on(live), Ok(Mql5CommandResult::Done)), "Calls reported in time should not be affected");
+    }
+}
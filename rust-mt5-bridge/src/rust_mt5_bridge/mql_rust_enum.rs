@@ -101,6 +101,15 @@ impl MqlRustEnumDescriptor {
         }
     }
 
+    /// Given the `rust_variant`, will return the MQL variant value (previously registered with [set_enum_variant_value()]) -- or `-1`
+    /// if MQL didn't inform it
+    pub fn resolve_mql_variant<RustEnumType: Into<i32>>(&self, rust_variant: RustEnumType) -> i32 {
+        let rust_variant_value: i32 = rust_variant.into();
+        self.rust_to_mql_variants.get(rust_variant_value as usize)
+            .copied()
+            .unwrap_or(-1)
+    }
+
     pub fn name(&self) -> &str {
         &self.rust_enum_name
     }
@@ -234,6 +243,9 @@ mod tests {
         let resolved_hard: MqlEnumMappedToRust = mql_rust_enum_descriptor.resolve_rust_variant(mql_hard);
         assert_eq!(resolved_soft, MqlEnumMappedToRust::Soft, "Resolving a MQL variant value as Rust enum variant didn't work!");
         assert_eq!(resolved_hard, MqlEnumMappedToRust::Hard, "Resolving a MQL variant value as Rust enum variant didn't work!");
+        // resolving from Rust variants to MQL enum values
+        assert_eq!(mql_rust_enum_descriptor.resolve_mql_variant(MqlEnumMappedToRust::Soft), mql_soft, "Resolving a Rust enum variant as MQL variant value didn't work!");
+        assert_eq!(mql_rust_enum_descriptor.resolve_mql_variant(MqlEnumMappedToRust::Hard), mql_hard, "Resolving a Rust enum variant as MQL variant value didn't work!");
     }
 
     /// some foreseen erroneous usage patterns and the meaningful feedback they should yield
@@ -3,6 +3,7 @@
 //! Note: some types defined here will derive both `Serialize` & `Deserialize` to ease their usage in network communications or even IPC.
 
 use serde::{Serialize, Deserialize};
+use chrono::{Datelike, NaiveDate};
 
 
 /// Info that will uniquely identify a client's software
@@ -18,6 +19,37 @@ pub type NeatTime = u32;
 /// In currency unit, multiplied by 1000 -- or in cent, multiplied by 10
 pub type MonetaryMillValue = u32;
 
+/// Milliseconds in a day -- the range spanned by a [NeatTime]
+const MILLIS_PER_DAY: u64 = 86_400_000;
+
+/// Encodes the given date as a [NeatDate] -- `None` if it is not a valid date or if it is out of the representable range
+/// (from the epoch up to ~179 years later)
+pub fn neat_date(year: i32, month: u32, day: u32) -> Option<NeatDate> {
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    NeatDate::try_from(date.signed_duration_since(neat_date_epoch()).num_days()).ok()
+}
+
+/// Decodes a [NeatDate] into its (year, month, day)
+pub fn ymd_from_neat_date(neat_date: NeatDate) -> (i32, u32, u32) {
+    let date = neat_date_epoch() + chrono::Duration::days(neat_date as i64);
+    (date.year(), date.month(), date.day())
+}
+
+/// Encodes the milliseconds since midnight as a [NeatTime] -- `None` if past the end of the day
+pub fn neat_time(millis_of_day: u32) -> Option<NeatTime> {
+    let millis_of_day = millis_of_day as u64;
+    (millis_of_day < MILLIS_PER_DAY).then(|| (((millis_of_day << 32) + MILLIS_PER_DAY / 2) / MILLIS_PER_DAY) as NeatTime)
+}
+
+/// Decodes a [NeatTime] into the milliseconds since midnight -- rounded, so encoding & decoding is lossless
+pub fn millis_of_day_from_neat_time(neat_time: NeatTime) -> u32 {
+    ((((neat_time as u64) * MILLIS_PER_DAY) + (1 << 31)) >> 32).min(MILLIS_PER_DAY - 1) as u32
+}
+
+fn neat_date_epoch() -> NaiveDate {
+    NaiveDate::from_ymd_opt(1979, 1, 22).expect("the NeatDate epoch is a valid date")
+}
+
 
 /// Auto-declarations from the "External Connectors", affecting how the `OgreExchange` server takes their messages into account
 /// and what types of commands they will have permission to use
@@ -26,14 +58,43 @@ pub enum ConnectorIdentification {
 
     /// Means the "External Connector" is a Market Data Bridge: it will only report trades & other market data
     /// -- the `OgreExchange` server will take these messages with a higher weight
-    MarketDataBridge { version: Version, symbol: Symbol, account_token: AccountToken },
+    MarketDataBridge { version: Version, symbol: Symbol, account_token: AccountToken, capabilities: Capabilities },
     /// Means the "External Connector" is "OMS-able" (may execute and track orders) -- it may also
     /// provide Market Data
-    FullAdvisor      { version: Version, symbol: Symbol, account_token: AccountToken },
+    FullAdvisor      { version: Version, symbol: Symbol, account_token: AccountToken, capabilities: Capabilities },
     /// Means the "External Connector" declares itself as just a "Monitor": no orders nor any Market Data
     /// may be sent by it, but it will receive important events, for accountability and monitoring purposes
     /// -- this "External Connector" doesn't undergo any "minimum ping time enforcement" as the other members of this enum do
-    WatcherAdvisor   { version: Version, symbol: Symbol, account_token: AccountToken },
+    WatcherAdvisor   { version: Version, symbol: Symbol, account_token: AccountToken, capabilities: Capabilities },
+}
+
+/// The features a peer supports -- offered by the `OgreExchange` server when greeting and narrowed down by the "External Connector"
+/// in its [ConnectorIdentification], so both sides only use what both support. See `comms/negotiation.rs`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// If messages may be sent in the binary wire format -- otherwise, only RON is used
+    pub binary_encoding: bool,
+    /// How many price levels, for each side, the books may have
+    pub book_depth:      u32,
+    /// The order types that may be scheduled
+    pub order_types:     Vec<OrderKinds>,
+}
+
+impl Capabilities {
+
+    /// The capabilities supported by both `self` and `other`
+    pub fn intersection(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            binary_encoding: self.binary_encoding && other.binary_encoding,
+            book_depth:      self.book_depth.min(other.book_depth),
+            order_types:     self.order_types.iter().filter(|order_type| other.order_types.contains(order_type)).copied().collect(),
+        }
+    }
+
+    /// Tells if all of our capabilities are also supported by `other`
+    pub fn is_subset_of(&self, other: &Capabilities) -> bool {
+        self.intersection(other) == *self
+    }
 }
 
 /// Details for the disconnection -- that usually is initiated by the `OgreExchange`
@@ -42,8 +103,8 @@ pub enum DisconnectionReason {
     UnknownConnectorType,
     DeprecatedConnectorVersion { minimum_accepted_version: Version },
     UnknownAccount,
-    /// Happens if the `OgreExchange` determined it has been abused
-    AccountFrozen           { message: String, remaining_duration_nanos: u32 },
+    /// Happens if the `OgreExchange` determined it has been abused -- in milliseconds, as freezes last for minutes
+    AccountFrozen           { message: String, remaining_duration_millis: u32 },
     /// Happens when the the account is known to perpetrate repetitive abuses
     AccountDisabled         { message: String },
     AuthenticationFailure,
@@ -99,6 +160,14 @@ pub enum RiskManagementOrderRefusalConditions {
     DailyCountTooHigh           { daily_count_limit: u32 },
     /// A buying operation was blocked due to the minute limit of buy/sell pair of operations being reached
     ThroughputTooHigh           { round_transactions_per_minute_limit: u32 },
+    /// An operation was blocked due to the margin it requires being above the account's free margin
+    MarginTooHigh               { free_margin_millis_limit: u32 },
+    /// An operation was blocked due to its volume being below the minimum allowed for the symbol -- in lots, multiplied by 1000
+    VolumeTooLow                { volume_min_millis_limit: u32 },
+    /// An operation was blocked due to its volume being above the maximum allowed for the symbol -- in lots, multiplied by 1000
+    VolumeTooHigh               { volume_max_millis_limit: u32 },
+    /// An operation was blocked due to its volume not being a multiple of the symbol's volume step -- in lots, multiplied by 1000
+    VolumeNotInStep             { volume_step_millis: u32 },
     // from RiskManagementOrderCancellationConditions... to be polished
     BadSymbolNegotiationStatus,
     BadSymbolRiskStatus,
@@ -117,7 +186,7 @@ pub enum RiskManagementConnectionDroppingConditions {
 }
 
 /// All possible `RiskManager` detectable conditions
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum RiskManagementConditions {
     /// Informs, whoever it may concern, that an intention to place an order, from `DecisionMaker`, won't be made into an Order Event endorsed by the `RiskManager`
     OrderRefused(RiskManagementOrderRefusalConditions),
@@ -170,6 +239,22 @@ pub enum OrderTypes {
     LimitedOrder { price_limit_mill: u32 },
 }
 
+impl OrderTypes {
+    pub fn kind(&self) -> OrderKinds {
+        match self {
+            OrderTypes::MarketOrder       => OrderKinds::MarketOrder,
+            OrderTypes::LimitedOrder {..} => OrderKinds::LimitedOrder,
+        }
+    }
+}
+
+/// The [OrderTypes], without their data -- as in [Capabilities]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderKinds {
+    MarketOrder,
+    LimitedOrder,
+}
+
 /// Payload for [Events.market_data] event
 #[derive(Clone, Debug, PartialEq)]
 pub enum MarketData {
@@ -192,8 +277,8 @@ pub struct SymbolState {
 pub struct GroupedBook {
     /// the unitary paper currency value -- see [MonetaryMillValue]
     price_level_mills: MonetaryMillValue,
-    /// the number of orders waiting
-    n_orders: u32,
+    /// the number of orders waiting -- `None` if unknown, as for the books coming from Metatrader
+    n_orders: Option<u32>,
     /// the total quantity of booked orders
     available_quantity: u32,
 }
@@ -201,17 +286,17 @@ pub struct GroupedBook {
 /// Represents the full info of a book event -- to be yet grouped and stored (see [GroupedBook])
 #[derive(Clone, Debug, PartialEq)]
 pub struct SingleBook {
-    date: NeatDate,
-    time: NeatTime,
-    symbol: Symbol,
+    pub date: NeatDate,
+    pub time: NeatTime,
+    pub symbol: Symbol,
     /// the unitary paper currency value -- see [MonetaryMillValue]
-    price_level_mills: MonetaryMillValue,
-    /// the number of orders waiting
-    n_orders: u32,
+    pub price_level_mills: MonetaryMillValue,
+    /// the number of orders waiting -- `None` if unknown, as for the books coming from Metatrader
+    pub n_orders: Option<u32>,
     /// the total quantity of booked orders
-    available_quantity: u32,
+    pub available_quantity: u32,
     /// the operation those orders want to make
-    side: Parties,
+    pub side: Parties,
 }
 
 /// Represents a trade made to be kept in containers that groups them by `symbol` and `date`,
@@ -231,13 +316,13 @@ pub struct GroupedTrade {
 /// Represents the full info of a trade -- to be yet grouped and stored (see [GroupedTrade])
 #[derive(Clone, Debug, PartialEq)]
 pub struct SingleTrade {
-    date: NeatDate,
-    time: NeatTime,
-    symbol: Symbol,
+    pub date: NeatDate,
+    pub time: NeatTime,
+    pub symbol: Symbol,
     /// the unitary paper currency value -- see [MonetaryMillValue]
-    unitary_mill_value: MonetaryMillValue,
+    pub unitary_mill_value: MonetaryMillValue,
     /// how many papers of that symbol were traded
-    quantity: u32,
+    pub quantity: u32,
     /// who emitted the Market Order?
-    aggressor: Parties,
+    pub aggressor: Parties,
 }
\ No newline at end of file
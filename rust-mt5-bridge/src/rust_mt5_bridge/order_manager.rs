@@ -0,0 +1,472 @@
+//! Order Management System: tracks the lifecycle of the orders & deals of an MQL Program, as informed by `OnTradeTransaction()`.
+//!
+//! Metatrader informs each step of an order through several trade transactions -- for a market order, typically:
+//!   1) `TRADE_TRANSACTION_ORDER_ADD`: the order was accepted (`ORDER_STATE_STARTED` or `ORDER_STATE_PLACED`);
+//!   2) `TRADE_TRANSACTION_REQUEST`: the trade server processed the request -- `MqlTradeResult::retcode` tells how;
+//!   3) `TRADE_TRANSACTION_DEAL_ADD`: one for each (partial) execution;
+//!   4) `TRADE_TRANSACTION_ORDER_DELETE` & `TRADE_TRANSACTION_HISTORY_ADD`: the order left the open ones, with its final state.
+//!
+//! ... which may arrive in different orders, as documented in https://www.mql5.com/en/docs/event_handlers/ontradetransaction --
+//! hence, final states ([OrderStatus::is_final()]) are never reverted by late transactions.\
+//! Finished orders & their deals are kept until the day (UTC) rolls over -- see [OrderManager::roll_over()].\
+//! See [OrderManager] for the queries available to the algorithms & the comms layer.
+
+use super::types::*;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+use log::{debug, warn};
+
+
+/// How many requests refused before an order ticket was given are remembered -- see [OrderManager::rejected_requests()]
+const MAX_REJECTED_REQUESTS: usize = 128;
+
+
+/// The states an order goes through -- simplified from MQL's `EnumOrderState`
+#[derive(Debug,Clone,PartialEq)]
+pub enum OrderStatus {
+    /// Sent, but not yet accepted by the trade server
+    Submitted,
+    /// Accepted & awaiting execution
+    Placed,
+    /// Some, but not all, of the volume was executed
+    PartiallyFilled,
+    /// The whole volume was executed
+    Filled,
+    /// Canceled by the client (or by the broker, with the remaining volume, for `IOC` orders)
+    Canceled,
+    /// Refused by the trade server
+    Rejected(OrderRejection),
+    /// The validity period ended before the order was (completely) filled
+    Expired,
+}
+
+impl OrderStatus {
+
+    /// Maps MQL's order state -- `None` is returned for unknown states, which should be ignored
+    pub fn from_order_state(order_state: EnumOrderState) -> Option<Self> {
+        match order_state {
+            EnumOrderState::OrderStateStarted       |
+            EnumOrderState::OrderStateRequestAdd    => Some(Self::Submitted),
+            EnumOrderState::OrderStatePlaced        |
+            EnumOrderState::OrderStateRequestModify |
+            EnumOrderState::OrderStateRequestCancel => Some(Self::Placed),
+            EnumOrderState::OrderStatePartial       => Some(Self::PartiallyFilled),
+            EnumOrderState::OrderStateFilled        => Some(Self::Filled),
+            EnumOrderState::OrderStateCanceled      => Some(Self::Canceled),
+            EnumOrderState::OrderStateRejected      => Some(Self::Rejected(OrderRejection::Refused { retcode: Mt5TradeServerReturnCodes::TradeRetcodeReject })),
+            EnumOrderState::OrderStateExpired       => Some(Self::Expired),
+            EnumOrderState::UnknownMqlVariantValue  => None,
+        }
+    }
+
+    /// Tells if no further changes are expected for the order
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Filled | Self::Canceled | Self::Rejected(_) | Self::Expired)
+    }
+}
+
+
+/// Typed reasons for the trade server to refuse a request -- grouped from [Mt5TradeServerReturnCodes], which is kept for reference
+#[derive(Debug,Clone,PartialEq)]
+pub enum OrderRejection {
+    /// The price moved before the request could be fulfilled (requotes, price changes, no quotes) -- retrying with fresh prices may succeed
+    PriceMoved        { retcode: Mt5TradeServerReturnCodes },
+    /// The request has invalid parameters (volume, price, stops, expiration, filling or order type) -- retrying won't help
+    InvalidRequest    { retcode: Mt5TradeServerReturnCodes },
+    /// There is not enough money to complete the request
+    InsufficientFunds { retcode: Mt5TradeServerReturnCodes },
+    /// Trading is not possible at the moment: market closed, trade or autotrading disabled or restricted by symbol or account rules
+    TradingDisabled   { retcode: Mt5TradeServerReturnCodes },
+    /// Limits for orders, volume, positions or the request rate were reached
+    LimitReached      { retcode: Mt5TradeServerReturnCodes },
+    /// Temporary issues: timeouts, lost connection, locked or frozen orders and processing errors -- retrying may succeed
+    Transient         { retcode: Mt5TradeServerReturnCodes },
+    /// Refused or canceled by the broker, the exchange or the trader
+    Refused           { retcode: Mt5TradeServerReturnCodes },
+    /// A `retcode` unknown to this DLL
+    Unknown           { retcode: Mt5TradeServerReturnCodes },
+}
+
+impl OrderRejection {
+
+    /// Maps `retcode` into its rejection -- or `None` if it tells the request was successfully processed
+    pub fn from_retcode(retcode: Mt5TradeServerReturnCodes) -> Option<Self> {
+        use Mt5TradeServerReturnCodes::*;
+        match retcode {
+            TradeRetcodePlaced | TradeRetcodeDone | TradeRetcodeDonePartial => None,
+            TradeRetcodeRequote | TradeRetcodePriceChanged | TradeRetcodePriceOff => Some(Self::PriceMoved { retcode }),
+            TradeRetcodeInvalid | TradeRetcodeInvalidVolume | TradeRetcodeInvalidPrice | TradeRetcodeInvalidStops |
+            TradeRetcodeInvalidExpiration | TradeRetcodeInvalidFill | TradeRetcodeInvalidOrder | TradeRetcodeInvalidCloseVolume |
+            TradeRetcodeNoChanges | TradeRetcodePositionClosed | TradeRetcodeCloseOrderExist => Some(Self::InvalidRequest { retcode }),
+            TradeRetcodeNoMoney => Some(Self::InsufficientFunds { retcode }),
+            TradeRetcodeTradeDisabled | TradeRetcodeMarketClosed | TradeRetcodeServerDisablesAt | TradeRetcodeClientDisablesAt |
+            TradeRetcodeOnlyReal | TradeRetcodeLongOnly | TradeRetcodeShortOnly | TradeRetcodeCloseOnly | TradeRetcodeFifoClose |
+            TradeRetcodeHedgeProhibited => Some(Self::TradingDisabled { retcode }),
+            TradeRetcodeLimitOrders | TradeRetcodeLimitVolume | TradeRetcodeLimitPositions | TradeRetcodeTooManyRequests => Some(Self::LimitReached { retcode }),
+            TradeRetcodeError | TradeRetcodeTimeout | TradeRetcodeConnection | TradeRetcodeLocked | TradeRetcodeFrozen |
+            TradeRetcodeOrderChanged => Some(Self::Transient { retcode }),
+            TradeRetcodeReject | TradeRetcodeCancel | TradeRetcodeRejectCancel => Some(Self::Refused { retcode }),
+            UnknownRetcode => Some(Self::Unknown { retcode }),
+        }
+    }
+
+    /// Tells if the same request may succeed if sent again (with fresh prices)
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::PriceMoved { .. } | Self::Transient { .. })
+    }
+}
+
+
+/// An order tracked by the [OrderManager]
+#[derive(Debug,Clone,PartialEq)]
+pub struct ManagedOrder {
+    /// Order ticket
+    pub ticket:         u64,
+    pub symbol:         String,
+    pub order_type:     EnumOrderType,
+    pub status:         OrderStatus,
+    /// The highest volume seen for the order, in lots
+    pub volume_initial: f64,
+    /// Executed volume, in lots, according to the deals seen so far
+    pub volume_filled:  f64,
+    /// 0.0 for market orders
+    pub price:          f64,
+    pub sl:             f64,
+    pub tp:             f64,
+    pub time_type:      EnumOrderTypeTime,
+    /// Tickets of the deals executing this order
+    pub deals:          Vec<u64>,
+}
+
+/// An execution of one of our orders -- or balance operations, whose `order` is 0
+#[derive(Debug,Clone,PartialEq)]
+pub struct Deal {
+    /// Deal ticket
+    pub ticket:    u64,
+    /// The ticket of the order executed by this deal
+    pub order:     u64,
+    pub symbol:    String,
+    pub deal_type: mql_trade_transaction::EnumDealType,
+    /// in lots
+    pub volume:    f64,
+    pub price:     f64,
+    /// The ticket of the position opened, changed or closed by this deal
+    pub position:  u64,
+}
+
+/// A request refused by the trade server before an order ticket was given to it
+#[derive(Debug,Clone,PartialEq)]
+pub struct RejectedRequest {
+    pub request_id: u32,
+    pub symbol:     String,
+    pub order_type: EnumOrderType,
+    pub volume:     f64,
+    pub price:      f64,
+    pub rejection:  OrderRejection,
+    /// The broker's comment
+    pub comment:    String,
+}
+
+
+/// Per-handle Order Management System -- see the [module](self) docs
+#[derive(Debug,Default)]
+pub struct OrderManager {
+    orders:            BTreeMap<u64, ManagedOrder>,
+    deals:             BTreeMap<u64, Deal>,
+    rejected_requests: VecDeque<RejectedRequest>,
+    /// The UTC day (since the epoch) the tracked orders refer to -- see [Self::roll_over()]
+    day:               u64,
+}
+
+impl OrderManager {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Updates the orders & deals with the event informed by `OnTradeTransaction()`
+    pub fn on_trade_transaction(&mut self, transaction: &MqlTradeTransaction, request: &MqlTradeRequest, result: &MqlTradeResult) {
+        match transaction.transaction_type {
+            EnumTradeTransactionType::TradeTransactionOrderAdd      |
+            EnumTradeTransactionType::TradeTransactionOrderUpdate   |
+            EnumTradeTransactionType::TradeTransactionOrderDelete   |
+            EnumTradeTransactionType::TradeTransactionHistoryAdd    |
+            EnumTradeTransactionType::TradeTransactionHistoryUpdate => self.on_order_transaction(transaction),
+            EnumTradeTransactionType::TradeTransactionDealAdd       |
+            EnumTradeTransactionType::TradeTransactionDealUpdate    => self.on_deal(transaction),
+            EnumTradeTransactionType::TradeTransactionDealDelete    => self.on_deal_deletion(transaction.deal),
+            EnumTradeTransactionType::TradeTransactionRequest       => self.on_request_result(request, result),
+            EnumTradeTransactionType::TradeTransactionHistoryDelete |
+            EnumTradeTransactionType::TradeTransactionPosition      |
+            EnumTradeTransactionType::UnknownMqlVariantValue        => (),
+        }
+    }
+
+    /// To be called before processing events: once the UTC day of `now` differs from the one seen before, evicts the finished
+    /// orders, the deals not executing any open order & the rejected requests -- keeping memory bounded on long-running sessions
+    pub fn roll_over(&mut self, now: SystemTime) {
+        let day = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400;
+        if day == self.day {
+            return;
+        }
+        self.day = day;
+        self.orders.retain(|_ticket, order| !order.status.is_final());
+        let orders = &self.orders;
+        self.deals.retain(|_ticket, deal| orders.contains_key(&deal.order));
+        self.rejected_requests.clear();
+        debug!("OrderManager: day rolled over -- {} open orders & {} of their deals kept", self.orders.len(), self.deals.len());
+    }
+
+    /// What happened to the order with the given `ticket`
+    pub fn order(&self, ticket: u64) -> Option<&ManagedOrder> {
+        self.orders.get(&ticket)
+    }
+
+    /// The orders still awaiting (complete) execution
+    pub fn open_orders(&self) -> impl Iterator<Item=&ManagedOrder> {
+        self.orders.values().filter(|order| !order.status.is_final())
+    }
+
+    /// All orders seen, in ticket order
+    pub fn orders(&self) -> impl Iterator<Item=&ManagedOrder> {
+        self.orders.values()
+    }
+
+    /// All deals seen, in ticket order
+    pub fn deals(&self) -> impl Iterator<Item=&Deal> {
+        self.deals.values()
+    }
+
+    /// The deals executing the order with the given `ticket`
+    pub fn deals_of(&self, ticket: u64) -> impl Iterator<Item=&Deal> {
+        self.orders.get(&ticket)
+            .into_iter()
+            .flat_map(|order| order.deals.iter())
+            .filter_map(|deal_ticket| self.deals.get(deal_ticket))
+    }
+
+    /// The most recent requests refused before being given an order ticket -- oldest first
+    pub fn rejected_requests(&self) -> impl Iterator<Item=&RejectedRequest> {
+        self.rejected_requests.iter()
+    }
+
+    fn on_order_transaction(&mut self, transaction: &MqlTradeTransaction) {
+        let Some(status) = OrderStatus::from_order_state(transaction.order_state) else {
+            warn!("OrderManager: ignoring order #{} transaction with an unknown state: {transaction:?}", transaction.order);
+            return;
+        };
+        let order = self.order_entry(transaction);
+        order.order_type = transaction.order_type;
+        order.time_type = transaction.time_type;
+        order.price = transaction.price;
+        order.sl = transaction.price_sl;
+        order.tp = transaction.price_tp;
+        order.volume_initial = order.volume_initial.max(transaction.volume);
+        Self::set_status(order, status);
+    }
+
+    fn on_deal(&mut self, transaction: &MqlTradeTransaction) {
+        let deal = Deal {
+            ticket:    transaction.deal,
+            order:     transaction.order,
+            symbol:    transaction.symbol.clone(),
+            deal_type: transaction.deal_type,
+            volume:    transaction.volume,
+            price:     transaction.price,
+            position:  transaction.position,
+        };
+        let previous_volume = self.deals.insert(deal.ticket, deal.clone()).map_or(0.0, |previous| previous.volume);
+        if deal.order == 0 {
+            // balance operations
+            return;
+        }
+        let order = self.order_entry(transaction);
+        if !order.deals.contains(&deal.ticket) {
+            order.deals.push(deal.ticket);
+        }
+        order.volume_filled += deal.volume - previous_volume;
+        // deals may come before the order's volume is known -- in which case, the order's final transaction will tell if it was filled
+        let status = if order.volume_initial > 0.0 && order.volume_filled >= order.volume_initial { OrderStatus::Filled } else { OrderStatus::PartiallyFilled };
+        Self::set_status(order, status);
+    }
+
+    fn on_deal_deletion(&mut self, deal_ticket: u64) {
+        let Some(deal) = self.deals.remove(&deal_ticket) else {
+            return;
+        };
+        if let Some(order) = self.orders.get_mut(&deal.order) {
+            order.deals.retain(|ticket| *ticket != deal_ticket);
+            order.volume_filled -= deal.volume;
+        }
+    }
+
+    fn on_request_result(&mut self, request: &MqlTradeRequest, result: &MqlTradeResult) {
+        let Some(rejection) = OrderRejection::from_retcode(result.retcode) else {
+            return;
+        };
+        match self.orders.get_mut(&result.order).filter(|_| result.order != 0) {
+            Some(order) => match &order.status {
+                // the typed rejection is more informative than the one given by `OrderStateRejected`
+                OrderStatus::Rejected(_) => order.status = OrderStatus::Rejected(rejection),
+                // rejections may come after the order reached its final state -- which is kept
+                _ => Self::set_status(order, OrderStatus::Rejected(rejection)),
+            },
+            None => {
+                if self.rejected_requests.len() >= MAX_REJECTED_REQUESTS {
+                    self.rejected_requests.pop_front();
+                }
+                self.rejected_requests.push_back(RejectedRequest {
+                    request_id: result.request_id,
+                    symbol:     request.symbol.clone(),
+                    order_type: request.order_type,
+                    volume:     request.volume,
+                    price:      request.price,
+                    rejection,
+                    comment:    result.comment.clone(),
+                });
+            },
+        }
+    }
+
+    fn order_entry(&mut self, transaction: &MqlTradeTransaction) -> &mut ManagedOrder {
+        self.orders.entry(transaction.order)
+            .or_insert_with(|| ManagedOrder {
+                ticket:         transaction.order,
+                symbol:         transaction.symbol.clone(),
+                order_type:     transaction.order_type,
+                status:         OrderStatus::Submitted,
+                volume_initial: 0.0,
+                volume_filled:  0.0,
+                price:          transaction.price,
+                sl:             transaction.price_sl,
+                tp:             transaction.price_tp,
+                time_type:      transaction.time_type,
+                deals:          vec![],
+            })
+    }
+
+    /// Advances `order` to `status` -- final states are never left and fills are never undone by late transactions
+    fn set_status(order: &mut ManagedOrder, status: OrderStatus) {
+        if order.status.is_final() {
+            return;
+        }
+        if order.status == OrderStatus::PartiallyFilled && matches!(status, OrderStatus::Submitted | OrderStatus::Placed) {
+            return;
+        }
+        debug!("OrderManager: order #{}: {:?} => {status:?}", order.ticket, order.status);
+        order.status = status;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use EnumTradeTransactionType::*;
+    use EnumOrderState::*;
+
+
+    /// follows a market order through acceptance, a partial fill, the complete fill and late transactions
+    #[test]
+    fn market_order_lifecycle() {
+        let mut oms = OrderManager::new();
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 7, OrderStateStarted, 200.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        assert_eq!(oms.order(7).map(|order| &order.status), Some(&OrderStatus::Submitted), "A just added order should be `Submitted`");
+        oms.on_trade_transaction(&order_transaction(TradeTransactionRequest, 0, OrderStateStarted, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 7));
+        oms.on_trade_transaction(&deal_transaction(TradeTransactionDealAdd, 70, 7, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        assert_eq!(oms.order(7).map(|order| (&order.status, order.volume_filled)), Some((&OrderStatus::PartiallyFilled, 100.0)), "Wrong state after a partial execution");
+        assert_eq!(oms.open_orders().count(), 1, "A partially filled order is still open");
+        oms.on_trade_transaction(&deal_transaction(TradeTransactionDealAdd, 71, 7, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderUpdate, 7, OrderStatePlaced, 200.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        let order = oms.order(7).expect("the order should be tracked");
+        assert_eq!((&order.status, order.volume_filled), (&OrderStatus::Filled, 200.0), "Late transactions should not revert a filled order: {order:?}");
+        assert_eq!(oms.deals_of(7).map(|deal| deal.ticket).collect::<Vec<_>>(), vec![70, 71], "Wrong deals for the order");
+        assert_eq!(oms.open_orders().count(), 0, "Filled orders are not open");
+    }
+
+    /// checks cancellations, expirations & rejections -- both for orders with & without tickets
+    #[test]
+    fn unsuccessful_orders() {
+        let mut oms = OrderManager::new();
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 8, OrderStatePlaced, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionHistoryAdd, 8, OrderStateCanceled, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        assert_eq!(oms.order(8).map(|order| &order.status), Some(&OrderStatus::Canceled), "The order should have been canceled");
+
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 9, OrderStatePlaced, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionHistoryAdd, 9, OrderStateExpired, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        assert_eq!(oms.order(9).map(|order| &order.status), Some(&OrderStatus::Expired), "The order should have expired");
+
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 10, OrderStateStarted, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionRequest, 0, OrderStateStarted, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeInvalidStops, 10));
+        assert_eq!(oms.order(10).map(|order| &order.status), Some(&OrderStatus::Rejected(OrderRejection::InvalidRequest { retcode: Mt5TradeServerReturnCodes::TradeRetcodeInvalidStops })), "The order should have been rejected");
+
+        oms.on_trade_transaction(&order_transaction(TradeTransactionRequest, 0, OrderStateStarted, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeNoMoney, 0));
+        let rejected = oms.rejected_requests().collect::<Vec<_>>();
+        assert_eq!(rejected.len(), 1, "Requests without tickets should be remembered when rejected");
+        assert_eq!(rejected[0].rejection, OrderRejection::InsufficientFunds { retcode: Mt5TradeServerReturnCodes::TradeRetcodeNoMoney }, "Wrong rejection");
+        assert!(!rejected[0].rejection.is_retryable(), "Lack of funds is not retryable");
+        assert_eq!(oms.open_orders().count(), 0, "No orders should be open");
+
+        oms.on_trade_transaction(&order_transaction(TradeTransactionRequest, 0, OrderStateStarted, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeReject, 8));
+        assert_eq!(oms.order(8).map(|order| &order.status), Some(&OrderStatus::Canceled), "Late rejections should not overwrite final states");
+    }
+
+    /// checks finished orders & their deals are evicted when the day rolls over -- but open ones are kept
+    #[test]
+    fn day_roll_over() {
+        let day = |days: u64| UNIX_EPOCH + std::time::Duration::from_secs(days * 86400 + 3600);
+        let mut oms = OrderManager::new();
+        oms.roll_over(day(1));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 7, OrderStatePlaced, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&deal_transaction(TradeTransactionDealAdd, 70, 7, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionOrderAdd, 8, OrderStatePlaced, 200.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&deal_transaction(TradeTransactionDealAdd, 80, 8, 100.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeDone, 0));
+        oms.on_trade_transaction(&order_transaction(TradeTransactionRequest, 0, OrderStateStarted, 0.0), &request(), &result(Mt5TradeServerReturnCodes::TradeRetcodeNoMoney, 0));
+
+        oms.roll_over(day(1) + std::time::Duration::from_secs(3600));
+        assert_eq!((oms.orders().count(), oms.deals().count(), oms.rejected_requests().count()), (2, 2, 1), "Nothing should be evicted within the same day");
+
+        oms.roll_over(day(2));
+        assert_eq!(oms.orders().map(|order| order.ticket).collect::<Vec<_>>(), vec![8], "Only the open orders should be kept");
+        assert_eq!(oms.deals().map(|deal| deal.ticket).collect::<Vec<_>>(), vec![80], "Only the deals of open orders should be kept");
+        assert_eq!(oms.rejected_requests().count(), 0, "Rejected requests should be evicted");
+    }
+
+    /// all known retcodes should map to either success or a typed rejection
+    #[test]
+    fn retcode_mapping() {
+        assert_eq!(OrderRejection::from_retcode(Mt5TradeServerReturnCodes::TradeRetcodeDonePartial), None, "Partial executions are not rejections");
+        assert_eq!(OrderRejection::from_retcode(Mt5TradeServerReturnCodes::TradeRetcodeRequote), Some(OrderRejection::PriceMoved { retcode: Mt5TradeServerReturnCodes::TradeRetcodeRequote }), "Wrong mapping for requotes");
+        assert!(OrderRejection::from_retcode(Mt5TradeServerReturnCodes::from(10031)).is_some_and(|rejection| rejection.is_retryable()), "Connection losses should be retryable");
+        assert_eq!(OrderRejection::from_retcode(Mt5TradeServerReturnCodes::from(999)), Some(OrderRejection::Unknown { retcode: Mt5TradeServerReturnCodes::UnknownRetcode }), "Unknown retcodes should be kept as unknown rejections");
+    }
+
+
+    fn order_transaction(transaction_type: EnumTradeTransactionType, order: u64, order_state: EnumOrderState, volume: f64) -> MqlTradeTransaction {
+        MqlTradeTransaction {
+            deal: 0, order, symbol: String::from("PETR4"), transaction_type,
+            order_type: EnumOrderType::OrderTypeBuyLimit, order_state, deal_type: mql_trade_transaction::EnumDealType::DealTypeBuy,
+            time_type: EnumOrderTypeTime::OrderTimeDay, time_expiration: NaiveDateTime::from_timestamp(0, 0),
+            price: 25.00, price_trigger: 0.0, price_sl: 0.0, price_tp: 0.0, volume, position: 0, position_by: 0,
+        }
+    }
+
+    fn deal_transaction(transaction_type: EnumTradeTransactionType, deal: u64, order: u64, volume: f64) -> MqlTradeTransaction {
+        MqlTradeTransaction { deal, position: 1, ..order_transaction(transaction_type, order, OrderStateFilled, volume) }
+    }
+
+    fn request() -> MqlTradeRequest {
+        MqlTradeRequest {
+            action: EnumTradeRequestActions::TradeActionPending, magic: 0, order: 0, symbol: String::from("PETR4"),
+            volume: 100.0, price: 25.00, stoplimit: 0.0, sl: 0.0, tp: 0.0, deviation: 0,
+            order_type: EnumOrderType::OrderTypeBuyLimit, order_type_filling: EnumOrderTypeFilling::OrderFillingReturn,
+            order_type_time: EnumOrderTypeTime::OrderTimeDay, expiration: NaiveDateTime::from_timestamp(0, 0),
+            comment: String::new(), position: 0, position_by: 0,
+        }
+    }
+
+    fn result(retcode: Mt5TradeServerReturnCodes, order: u64) -> MqlTradeResult {
+        MqlTradeResult { retcode, deal: 0, order, volume: 0.0, price: 0.0, bid: 0.0, ask: 0.0, comment: String::new(), request_id: 1, retcode_external: 0 }
+    }
+}
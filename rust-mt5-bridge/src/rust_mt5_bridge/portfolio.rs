@@ -0,0 +1,341 @@
+//! Positions & P&L of a trading account, built from the deals reported by MQL (see `report_deal_properties()`) and
+//! marked to market with the latest [Spread]s from `on_tick()`.
+//!
+//! Deals are aggregated into [Position]s by their `deal_position_id`, following their [EnumDealEntry]:
+//!   - `DealEntryIn` opens or increases the position -- averaging its price;
+//!   - `DealEntryOut` & `DealEntryOutBy` decrease or close it -- realizing the profit informed by the trade server;
+//!   - `DealEntryInout` reverses it: the old volume is closed & the remaining one is opened in the opposite direction.
+//!
+//! Since all deals -- including deposits, withdrawals and charges -- move the account balance, [Portfolio::reconcile()]
+//! is able to check our numbers against the ones reported by `report_account_info()`, flagging any [Discrepancy]
+//! (which also happens if the history wasn't completely reported -- see `collect_and_report_all_deals_properties()`).
+
+use super::types::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use log::{debug, warn};
+
+
+/// Volumes below this are considered zero -- closed positions
+const VOLUME_EPSILON: f64 = 1e-9;
+/// Differences, in the account currency, tolerated by [Portfolio::reconcile()]
+const DEFAULT_RECONCILIATION_TOLERANCE: f64 = 0.01;
+
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum PositionDirection {
+    Long,
+    Short,
+}
+
+/// Deals sharing the same `deal_position_id`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Position {
+    pub position_id:   i64,
+    pub symbol:        String,
+    pub direction:     PositionDirection,
+    /// Currently held, in lots -- 0.0 for closed positions
+    pub volume:        f64,
+    /// Volume weighted entry price of the currently held volume
+    pub average_price: f64,
+    /// Profit informed by the trade server for the closing deals -- costs not included
+    pub realized_profit: f64,
+    /// Commission, swap & fees charged for this position's deals -- usually negative
+    pub costs:         f64,
+    /// Tickets of the deals, in the order they were applied
+    pub deals:         Vec<i64>,
+}
+
+impl Position {
+
+    pub fn is_open(&self) -> bool {
+        self.volume > VOLUME_EPSILON
+    }
+
+    /// Realized profit, net of costs
+    pub fn realized_pnl(&self) -> f64 {
+        self.realized_profit + self.costs
+    }
+
+    /// Profit that would be realized by closing the held volume at the given `mark` -- longs sell on the bid, shorts buy on the ask
+    pub fn unrealized_pnl(&self, mark: &Mark, contract_size: f64) -> f64 {
+        if !self.is_open() {
+            return 0.0;
+        }
+        match self.direction {
+            PositionDirection::Long  => (mark.bid - self.average_price) * self.volume * contract_size,
+            PositionDirection::Short => (self.average_price - mark.ask) * self.volume * contract_size,
+        }
+    }
+}
+
+/// The latest prices for a symbol -- taken from the [Spread] events
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Mark {
+    pub bid: f64,
+    pub ask: f64,
+}
+
+/// Differences between our numbers and the ones reported by the trade server -- see [Portfolio::reconcile()]
+#[derive(Debug,Clone,PartialEq)]
+pub enum Discrepancy {
+    /// The sum of all deals doesn't match the account balance
+    Balance { expected: f64, reported: f64 },
+    /// Our unrealized P&L doesn't match the account's floating profit -- might be due to outdated [Mark]s
+    FloatingProfit { expected: f64, reported: f64 },
+    /// Balance + floating profit doesn't match the account equity
+    Equity { expected: f64, reported: f64 },
+}
+
+
+/// Positions & P&L for a trading account -- see the [module](self) docs
+#[derive(Debug)]
+pub struct Portfolio {
+    positions:       BTreeMap<i64, Position>,
+    seen_deals:      HashSet<i64>,
+    /// Sum of the cash flows of all deals -- what the account balance should be, if all the history was reported
+    balance:         f64,
+    marks:           HashMap<String, Mark>,
+    contract_sizes:  HashMap<String, f64>,
+    /// Results of the last [Self::reconcile()]
+    discrepancies:   Vec<Discrepancy>,
+    tolerance:       f64,
+}
+
+impl Portfolio {
+
+    pub fn new() -> Self {
+        Self {
+            positions:      BTreeMap::new(),
+            seen_deals:     HashSet::new(),
+            balance:        0.0,
+            marks:          HashMap::new(),
+            contract_sizes: HashMap::new(),
+            discrepancies:  vec![],
+            tolerance:      DEFAULT_RECONCILIATION_TOLERANCE,
+        }
+    }
+
+    /// Incorporates a deal -- deals already seen are ignored, so the history may be reported again
+    pub fn on_deal(&mut self, deal: &DealPropertiesRust) {
+        if !self.seen_deals.insert(deal.deal_ticket) {
+            debug!("Portfolio: deal #{} was already accounted for", deal.deal_ticket);
+            return;
+        }
+        let costs = deal.deal_commission + deal.deal_swap + deal.deal_fee;
+        match deal.deal_type {
+            // credit is kept apart from the balance
+            deal_properties_bridge::EnumDealType::DealTypeCredit => return,
+            deal_properties_bridge::EnumDealType::DealTypeBuy | deal_properties_bridge::EnumDealType::DealTypeSell => (),
+            _ => {
+                // balance operations: deposits, withdrawals, charges, dividends, ...
+                self.balance += deal.deal_profit + costs;
+                return;
+            },
+        }
+        self.balance += deal.deal_profit + costs;
+        let deal_direction = if deal.deal_type == deal_properties_bridge::EnumDealType::DealTypeBuy { PositionDirection::Long } else { PositionDirection::Short };
+        let position = self.positions.entry(deal.deal_position_id)
+            .or_insert_with(|| Position {
+                position_id:     deal.deal_position_id,
+                symbol:          deal.deal_symbol.clone(),
+                direction:       deal_direction,
+                volume:          0.0,
+                average_price:   0.0,
+                realized_profit: 0.0,
+                costs:           0.0,
+                deals:           vec![],
+            });
+        position.deals.push(deal.deal_ticket);
+        position.costs += costs;
+        position.realized_profit += deal.deal_profit;
+        match deal.deal_entry {
+            EnumDealEntry::DealEntryIn => {
+                if !position.is_open() {
+                    position.direction = deal_direction;
+                }
+                let volume = position.volume + deal.deal_volume;
+                position.average_price = (position.average_price * position.volume + deal.deal_price * deal.deal_volume) / volume;
+                position.volume = volume;
+            },
+            EnumDealEntry::DealEntryOut | EnumDealEntry::DealEntryOutBy => {
+                position.volume = (position.volume - deal.deal_volume).max(0.0);
+                if !position.is_open() {
+                    position.volume = 0.0;
+                }
+            },
+            EnumDealEntry::DealEntryInout => {
+                let remaining = deal.deal_volume - position.volume;
+                position.direction = deal_direction;
+                position.volume = remaining.max(0.0);
+                position.average_price = deal.deal_price;
+            },
+            EnumDealEntry::UnknownMqlVariantValue => warn!("Portfolio: deal #{} has an unknown entry -- the position #{} may be wrong: {deal:?}", deal.deal_ticket, deal.deal_position_id),
+        }
+    }
+
+    /// Updates the prices used to compute the unrealized P&L
+    pub fn on_spread(&mut self, spread: &Spread) {
+        let mark = Mark { bid: spread.best_bid, ask: spread.best_ask };
+        match self.marks.get_mut(spread.symbol) {
+            Some(existing) => *existing = mark,
+            None => { self.marks.insert(spread.symbol.clone(), mark); },
+        }
+    }
+
+    /// Informs the contract size for `symbol`, used to compute the unrealized P&L -- 1.0 is assumed for unknown symbols
+    pub fn on_symbol_info(&mut self, symbol: &str, symbol_info: &SymbolInfoRust) {
+        self.contract_sizes.insert(symbol.to_string(), symbol_info.symbol_trade_contract_size);
+    }
+
+    /// Compares our numbers against the ones reported by the trade server, returning (and keeping) the differences found
+    pub fn reconcile(&mut self, account_info: &AccountInfoRust) -> &[Discrepancy] {
+        let floating_profit = self.unrealized_pnl();
+        let candidates = [
+            Discrepancy::Balance        { expected: self.balance,                   reported: account_info.account_balance },
+            Discrepancy::FloatingProfit { expected: floating_profit,                reported: account_info.account_profit },
+            Discrepancy::Equity         { expected: self.balance + floating_profit, reported: account_info.account_equity - account_info.account_credit },
+        ];
+        self.discrepancies = candidates.into_iter()
+            .filter(|discrepancy| match discrepancy {
+                Discrepancy::Balance { expected, reported } |
+                Discrepancy::FloatingProfit { expected, reported } |
+                Discrepancy::Equity { expected, reported } => (expected - reported).abs() > self.tolerance,
+            })
+            .collect();
+        for discrepancy in &self.discrepancies {
+            warn!("Portfolio: reconciliation against the account info reported by the trade server found a discrepancy: {discrepancy:?}");
+        }
+        &self.discrepancies
+    }
+
+    /// The position with the given id -- open or closed
+    pub fn position(&self, position_id: i64) -> Option<&Position> {
+        self.positions.get(&position_id)
+    }
+
+    pub fn open_positions(&self) -> impl Iterator<Item=&Position> {
+        self.positions.values().filter(|position| position.is_open())
+    }
+
+    /// Net realized P&L for all positions
+    pub fn realized_pnl(&self) -> f64 {
+        self.positions.values().map(Position::realized_pnl).sum()
+    }
+
+    /// Unrealized P&L for all open positions with known [Mark]s
+    pub fn unrealized_pnl(&self) -> f64 {
+        self.open_positions()
+            .filter_map(|position| self.marks.get(&position.symbol)
+                .map(|mark| position.unrealized_pnl(mark, self.contract_sizes.get(&position.symbol).copied().unwrap_or(1.0))))
+            .sum()
+    }
+
+    /// What the account balance should be, according to the deals
+    pub fn balance(&self) -> f64 {
+        self.balance
+    }
+
+    /// The differences found by the last [Self::reconcile()]
+    pub fn discrepancies(&self) -> &[Discrepancy] {
+        &self.discrepancies
+    }
+}
+
+impl Default for Portfolio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use deal_properties_bridge::EnumDealType::*;
+    use EnumDealEntry::*;
+
+
+    /// follows a position through increases, a partial close & a reversal -- checking the realized & unrealized P&L
+    #[test]
+    fn position_lifecycle() {
+        let mut portfolio = Portfolio::new();
+        portfolio.on_deal(&deal(1, DealTypeBalance, DealEntryIn, 0, 0.0, 0.0, 10_000.0));
+        portfolio.on_deal(&deal(2, DealTypeBuy, DealEntryIn, 5, 100.0, 10.0, 0.0));
+        portfolio.on_deal(&deal(3, DealTypeBuy, DealEntryIn, 5, 100.0, 12.0, 0.0));
+        portfolio.on_deal(&deal(3, DealTypeBuy, DealEntryIn, 5, 100.0, 12.0, 0.0));
+        let position = portfolio.position(5).expect("the position should be tracked");
+        assert_eq!((position.direction, position.volume, position.average_price), (PositionDirection::Long, 200.0, 11.0), "Wrong position after increasing it (repeated deals should be ignored)");
+
+        let symbol = String::from("PETR4");
+        portfolio.on_spread(&Spread { symbol: &symbol, time: NaiveDateTime::from_timestamp(0, 0), best_bid: 11.5, best_ask: 11.6 });
+        assert_eq!(portfolio.unrealized_pnl(), 100.0, "Longs should be marked at the bid");
+
+        portfolio.on_deal(&deal(4, DealTypeSell, DealEntryOut, 5, 50.0, 13.0, 100.0));
+        assert_eq!(portfolio.position(5).map(|position| position.volume), Some(150.0), "Partially closing should decrease the volume");
+        assert_eq!(portfolio.realized_pnl(), 100.0 - 3.0 * 0.5, "Wrong realized P&L -- costs should be discounted");
+
+        portfolio.on_deal(&deal(5, DealTypeSell, DealEntryInout, 5, 250.0, 12.0, 150.0));
+        let position = portfolio.position(5).expect("the position should be tracked");
+        assert_eq!((position.direction, position.volume, position.average_price), (PositionDirection::Short, 100.0, 12.0), "Wrong position after reversing it");
+        assert!((portfolio.unrealized_pnl() - 40.0).abs() < 1e-9, "Shorts should be marked at the ask -- unrealized P&L is {}", portfolio.unrealized_pnl());
+
+        portfolio.on_deal(&deal(6, DealTypeBuy, DealEntryOut, 5, 100.0, 11.0, 100.0));
+        assert_eq!(portfolio.open_positions().count(), 0, "The position should be closed");
+        assert_eq!(portfolio.balance(), 10_000.0 + 350.0 - 5.0 * 0.5, "The balance should include all cash flows");
+    }
+
+    /// checks the numbers are reconciled against the account info, flagging discrepancies
+    #[test]
+    fn reconciliation() {
+        let mut portfolio = Portfolio::new();
+        portfolio.on_deal(&deal(1, DealTypeBalance, DealEntryIn, 0, 0.0, 0.0, 1_000.0));
+        portfolio.on_deal(&deal(2, DealTypeBuy, DealEntryIn, 7, 10.0, 10.0, 0.0));
+        let symbol = String::from("PETR4");
+        portfolio.on_spread(&Spread { symbol: &symbol, time: NaiveDateTime::from_timestamp(0, 0), best_bid: 11.0, best_ask: 11.1 });
+        let balance = 1_000.0 - 0.5;
+        assert_eq!(portfolio.reconcile(&account_info(balance, 10.0, balance + 10.0)), &[], "Matching numbers should yield no discrepancies");
+        assert_eq!(portfolio.reconcile(&account_info(balance + 20.0, 10.0, balance + 30.0)),
+                   &[Discrepancy::Balance { expected: balance, reported: balance + 20.0 }, Discrepancy::Equity { expected: balance + 10.0, reported: balance + 30.0 }],
+                   "Balance differences should be flagged");
+        assert_eq!(portfolio.discrepancies().len(), 2, "Discrepancies should be kept until the next reconciliation");
+    }
+
+
+    fn deal(ticket: i64, deal_type: deal_properties_bridge::EnumDealType, deal_entry: EnumDealEntry, position_id: i64, volume: f64, price: f64, profit: f64) -> DealPropertiesRust {
+        DealPropertiesRust {
+            deal_volume:      volume,
+            deal_price:       price,
+            deal_commission:  if position_id == 0 { 0.0 } else { -0.5 },
+            deal_swap:        0.0,
+            deal_profit:      profit,
+            deal_fee:         0.0,
+            deal_sl:          0.0,
+            deal_tp:          0.0,
+            deal_ticket:      ticket,
+            deal_order:       ticket,
+            deal_magic:       0,
+            deal_position_id: position_id,
+            deal_time:        NaiveDateTime::from_timestamp(0, 0),
+            deal_symbol:      String::from(if position_id == 0 { "" } else { "PETR4" }),
+            deal_comment:     String::new(),
+            deal_external_id: String::new(),
+            deal_type,
+            deal_entry,
+            deal_reason:      EnumDealReason::DealReasonExpert,
+        }
+    }
+
+    fn account_info(balance: f64, profit: f64, equity: f64) -> AccountInfoRust {
+        AccountInfoRust {
+            account_balance: balance, account_credit: 0.0, account_profit: profit, account_equity: equity,
+            account_margin: 0.0, account_margin_free: 0.0, account_margin_level: 0.0, account_margin_so_call: 0.0,
+            account_margin_so_so: 0.0, account_margin_initial: 0.0, account_margin_maintenance: 0.0, account_assets: 0.0,
+            account_liabilities: 0.0, account_commission_blocked: 0.0, account_login: 0, account_leverage: 1,
+            account_name: String::new(), account_server: String::new(), account_currency: String::from("BRL"), account_company: String::new(),
+            account_trade_mode: EnumAccountTradeMode::AccountTradeModeDemo, account_limit_orders: 0,
+            account_margin_so_mode: EnumAccountStopoutMode::AccountStopoutModePercent, account_margin_mode: EnumAccountMarginMode::AccountMarginModeRetailNetting,
+            account_currency_digits: 2, account_trade_allowed: true, account_trade_expert: true, account_fifo_close: false, account_hedge_allowed: false,
+        }
+    }
+}
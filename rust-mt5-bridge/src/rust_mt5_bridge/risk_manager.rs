@@ -0,0 +1,472 @@
+//! Pre-trade risk checks: every order a [TradingAlgorithm](super::algorithms::TradingAlgorithm) decides to issue passes
+//! through [RiskManager::check()] before being scheduled for MQL to send -- orders breaking any of the configured
+//! [RiskLimits] (or the limits informed by the trade server for the symbol & account) are refused with a
+//! [RiskManagementConditions::OrderRefused] event, telling the exact limit that was hit.
+//!
+//! Limits are configured through the optional `risk_limits` field of the `algorithm` JSON parameter given when registering
+//! -- for instance: `{"algorithm": "NaiveTrader", "stop_win": 0.02, "risk_limits": {"max_quantity": 1000, "max_daily_orders": 50}}`.
+//! See [RiskLimits::from_algorithm_json()].
+
+use super::{
+    types::*,
+    mql5_commands::OrderRequest,
+    ogre_exchange_models::{RiskManagementConditions, RiskManagementOrderRefusalConditions},
+};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+
+
+/// How many refusals are kept for inspection -- see [RiskManager::refusals()]
+const MAX_REFUSALS: usize = 128;
+/// The window for [RiskLimits::max_round_trips_per_minute]
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+
+
+/// The configurable limits enforced by the [RiskManager] -- absent ones are not enforced
+#[derive(Debug,Clone,Default,PartialEq,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RiskLimits {
+    /// Maximum financial amount (price x volume x contract size) of a single order, in the account currency
+    pub max_amount:                   Option<f64>,
+    /// Maximum volume of a single order, in lots
+    pub max_quantity:                 Option<u32>,
+    /// Maximum number of orders issued in a day (UTC)
+    pub max_daily_orders:             Option<u32>,
+    /// Maximum number of buy/sell pairs of operations started (by a buy order) within any minute
+    pub max_round_trips_per_minute:   Option<u32>,
+    /// Orders are refused if no market data arrived for longer than this
+    pub max_market_data_gap_millis:   Option<u32>,
+    /// Orders are refused if more trades than this were missed by Metatrader within the last minute -- see `missed_trades_detector.rs`
+    pub max_missed_trades_per_minute: Option<u32>,
+}
+
+impl RiskLimits {
+
+    /// Parses the optional `risk_limits` field from the `algorithm` JSON parameter -- returning the default (unlimited)
+    /// [RiskLimits] if `algorithm_json` is not a JSON object or if the field is absent
+    pub fn from_algorithm_json(algorithm_json: &str) -> Result<Self, String> {
+        if !algorithm_json.trim_start().starts_with('{') {
+            return Ok(Self::default());
+        }
+        let mut parameters: serde_json::Map<String, serde_json::Value> = serde_json::from_str(algorithm_json)
+            .map_err(|err| format!("Couldn't parse the algorithm JSON '{algorithm_json}': {err}"))?;
+        match parameters.remove("risk_limits") {
+            Some(risk_limits) => serde_json::from_value(risk_limits)
+                .map_err(|err| format!("Invalid \"risk_limits\" in '{algorithm_json}': {err}")),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+
+/// Symbol limits informed by the trade server -- see [RiskManager::on_symbol_info()]
+#[derive(Debug,Clone,PartialEq)]
+struct SymbolLimits {
+    trade_mode:     EnumSymbolTradeMode,
+    volume_min:     f64,
+    volume_max:     f64,
+    volume_step:    f64,
+    contract_size:  f64,
+    margin_initial: f64,
+}
+
+/// Account limits informed by the trade server -- see [RiskManager::on_account_info()]
+#[derive(Debug,Clone,PartialEq)]
+struct AccountLimits {
+    margin_free: f64,
+    leverage:    i64,
+}
+
+
+/// See the [module](self) docs
+#[derive(Debug)]
+pub struct RiskManager {
+    symbol:           String,
+    limits:           RiskLimits,
+    symbol_limits:    Option<SymbolLimits>,
+    account_limits:   Option<AccountLimits>,
+    /// The latest (bid, ask) -- used to price market orders
+    last_quote:       Option<(f64, f64)>,
+    last_market_data: Option<SystemTime>,
+    /// The UTC day (since the epoch) [Self::daily_count] refers to
+    day:              u64,
+    daily_count:      u32,
+    /// When the buy orders within the last [THROUGHPUT_WINDOW] were accepted -- see [forget_older_than_the_window()]
+    recent_buys:      VecDeque<SystemTime>,
+    /// When the trades missed within the last [THROUGHPUT_WINDOW] were inferred -- see [forget_older_than_the_window()]
+    missed_trades:    VecDeque<SystemTime>,
+    refusals:         VecDeque<RiskManagementOrderRefusalConditions>,
+}
+
+impl RiskManager {
+
+    pub fn new(symbol: &str, limits: RiskLimits) -> Self {
+        Self {
+            symbol:           symbol.to_string(),
+            limits,
+            symbol_limits:    None,
+            account_limits:   None,
+            last_quote:       None,
+            last_market_data: None,
+            day:              0,
+            daily_count:      0,
+            recent_buys:      VecDeque::new(),
+            missed_trades:    VecDeque::new(),
+            refusals:         VecDeque::new(),
+        }
+    }
+
+    pub fn on_symbol_info(&mut self, symbol_info: &SymbolInfoRust) {
+        self.symbol_limits = Some(SymbolLimits {
+            trade_mode:     symbol_info.symbol_trade_mode,
+            volume_min:     symbol_info.symbol_volume_min,
+            volume_max:     symbol_info.symbol_volume_max,
+            volume_step:    symbol_info.symbol_volume_step,
+            contract_size:  symbol_info.symbol_trade_contract_size,
+            margin_initial: symbol_info.symbol_margin_initial,
+        });
+    }
+
+    pub fn on_account_info(&mut self, account_info: &AccountInfoRust) {
+        self.account_limits = Some(AccountLimits {
+            margin_free: account_info.account_margin_free,
+            leverage:    account_info.account_leverage,
+        });
+    }
+
+    /// Registers the arrival of a quote, at `now` -- see [Self::on_market_data()]
+    pub fn on_tick(&mut self, tick: &MqlTick, now: SystemTime) {
+        if tick.bid > 0.0 && tick.ask > 0.0 {
+            self.last_quote = Some((tick.bid, tick.ask));
+        }
+        self.on_market_data(now);
+    }
+
+    /// Registers the arrival of any market data, at `now` -- for [RiskLimits::max_market_data_gap_millis]
+    pub fn on_market_data(&mut self, now: SystemTime) {
+        self.last_market_data = Some(now);
+    }
+
+    /// Registers a trade Metatrader didn't report, inferred at `now` -- for [RiskLimits::max_missed_trades_per_minute]
+    pub fn on_missed_trade(&mut self, now: SystemTime) {
+        self.missed_trades.push_back(now);
+        forget_older_than_the_window(&mut self.missed_trades, now);
+    }
+
+    /// Decides if `order` may be sent at `now`, returning the [RiskManagementConditions::OrderRefused] event otherwise
+    /// -- accepted orders count towards [RiskLimits::max_daily_orders] & [RiskLimits::max_round_trips_per_minute]
+    pub fn check(&mut self, order: &OrderRequest, now: SystemTime) -> Result<(), RiskManagementConditions> {
+        match self.refusal_condition(order, now) {
+            None => {
+                self.daily_count += 1;
+                if is_buy(order) {
+                    self.recent_buys.push_back(now);
+                    forget_older_than_the_window(&mut self.recent_buys, now);
+                }
+                Ok(())
+            },
+            Some(condition) => {
+                if self.refusals.len() >= MAX_REFUSALS {
+                    self.refusals.pop_front();
+                }
+                self.refusals.push_back(condition.clone());
+                Err(RiskManagementConditions::OrderRefused(condition))
+            },
+        }
+    }
+
+    /// The most recent refusals, oldest first
+    pub fn refusals(&self) -> impl Iterator<Item=&RiskManagementOrderRefusalConditions> {
+        self.refusals.iter()
+    }
+
+    fn refusal_condition(&mut self, order: &OrderRequest, now: SystemTime) -> Option<RiskManagementOrderRefusalConditions> {
+        use RiskManagementOrderRefusalConditions::*;
+
+        if let Some(max_gap_millis) = self.limits.max_market_data_gap_millis {
+            let gap_start = self.last_market_data.unwrap_or(UNIX_EPOCH);
+            let gap = now.duration_since(gap_start).unwrap_or_default();
+            if gap > Duration::from_millis(max_gap_millis as u64) {
+                return Some(MarketDataGap {
+                    symbol:         self.symbol.clone(),
+                    start_time:     gap_start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().min(u32::MAX as u64) as u32,
+                    duration_nanos: gap.as_nanos().min(u32::MAX as u128) as u32,
+                });
+            }
+        }
+
+        // the data is incomplete if trades are being missed -- the gap goes from the first missed trade until now
+        forget_older_than_the_window(&mut self.missed_trades, now);
+        if let (Some(missed_trades_limit), Some(&gap_start)) = (self.limits.max_missed_trades_per_minute, self.missed_trades.front()) {
+            if self.missed_trades.len() > missed_trades_limit as usize {
+                return Some(MarketDataGap {
+                    symbol:         self.symbol.clone(),
+                    start_time:     gap_start.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().min(u32::MAX as u64) as u32,
+                    duration_nanos: now.duration_since(gap_start).unwrap_or_default().as_nanos().min(u32::MAX as u128) as u32,
+                });
+            }
+        }
+
+        let contract_size = match &self.symbol_limits {
+            Some(symbol_limits) => {
+                let allowed = match symbol_limits.trade_mode {
+                    EnumSymbolTradeMode::SymbolTradeModeFull      => true,
+                    EnumSymbolTradeMode::SymbolTradeModeLongonly  => is_buy(order),
+                    EnumSymbolTradeMode::SymbolTradeModeShortonly => !is_buy(order),
+                    _ => false,
+                };
+                if !allowed {
+                    return Some(BadSymbolNegotiationStatus);
+                }
+                if order.volume < symbol_limits.volume_min {
+                    return Some(VolumeTooLow { volume_min_millis_limit: to_millis(symbol_limits.volume_min) });
+                }
+                if symbol_limits.volume_max > 0.0 && order.volume > symbol_limits.volume_max {
+                    return Some(VolumeTooHigh { volume_max_millis_limit: to_millis(symbol_limits.volume_max) });
+                }
+                if symbol_limits.volume_step > 0.0 {
+                    let steps = order.volume / symbol_limits.volume_step;
+                    if (steps - steps.round()).abs() > 1e-6 {
+                        return Some(VolumeNotInStep { volume_step_millis: to_millis(symbol_limits.volume_step) });
+                    }
+                }
+                if symbol_limits.contract_size > 0.0 { symbol_limits.contract_size } else { 1.0 }
+            },
+            None => 1.0,
+        };
+
+        if let Some(quantity_limit) = self.limits.max_quantity {
+            if order.volume > quantity_limit as f64 {
+                return Some(QuantityTooHigh { quantity_limit });
+            }
+        }
+
+        let price = match (order.price, self.last_quote) {
+            (price, _) if price > 0.0 => price,
+            (_, Some((bid, ask))) => if is_buy(order) { ask } else { bid },
+            _ => 0.0,
+        };
+        let amount = price * order.volume * contract_size;
+        if let Some(max_amount) = self.limits.max_amount {
+            if amount > max_amount {
+                return Some(AmountTooHigh { amount_millis_limit: to_millis(max_amount) });
+            }
+        }
+
+        if let Some(account_limits) = &self.account_limits {
+            let required_margin = match &self.symbol_limits {
+                Some(symbol_limits) if symbol_limits.margin_initial > 0.0 => symbol_limits.margin_initial * order.volume,
+                _ => amount / account_limits.leverage.max(1) as f64,
+            };
+            if required_margin > account_limits.margin_free {
+                return Some(MarginTooHigh { free_margin_millis_limit: to_millis(account_limits.margin_free) });
+            }
+        }
+
+        if let Some(daily_count_limit) = self.limits.max_daily_orders {
+            let day = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400;
+            if day != self.day {
+                self.day = day;
+                self.daily_count = 0;
+            }
+            if self.daily_count >= daily_count_limit {
+                return Some(DailyCountTooHigh { daily_count_limit });
+            }
+        }
+
+        if let Some(round_transactions_per_minute_limit) = self.limits.max_round_trips_per_minute {
+            forget_older_than_the_window(&mut self.recent_buys, now);
+            if is_buy(order) && self.recent_buys.len() >= round_transactions_per_minute_limit as usize {
+                return Some(ThroughputTooHigh { round_transactions_per_minute_limit });
+            }
+        }
+
+        None
+    }
+}
+
+
+/// Drops, from the front of `instants`, the ones older than [THROUGHPUT_WINDOW] at `now` -- to be called whenever they are
+/// pushed to, so they stay bounded regardless of which limits are configured
+fn forget_older_than_the_window(instants: &mut VecDeque<SystemTime>, now: SystemTime) {
+    while matches!(instants.front(), Some(&instant) if now.duration_since(instant).unwrap_or_default() >= THROUGHPUT_WINDOW) {
+        instants.pop_front();
+    }
+}
+
+fn is_buy(order: &OrderRequest) -> bool {
+    matches!(order.order_type, EnumOrderType::OrderTypeBuy | EnumOrderType::OrderTypeBuyLimit | EnumOrderType::OrderTypeBuyStop | EnumOrderType::OrderTypeBuyStopLimit)
+}
+
+/// Converts `value` to the `*_millis` representation used in [RiskManagementOrderRefusalConditions] -- saturating
+fn to_millis(value: f64) -> u32 {
+    (value * 1000.0).round().clamp(0.0, u32::MAX as f64) as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use RiskManagementOrderRefusalConditions::*;
+
+
+    /// checks the `risk_limits` field is parsed from the algorithm JSON
+    #[test]
+    fn limits_from_json() {
+        assert_eq!(RiskLimits::from_algorithm_json("Market Data Provider"), Ok(RiskLimits::default()), "Plain descriptions should yield no limits");
+        assert_eq!(RiskLimits::from_algorithm_json(r#"{"algorithm": "NaiveTrader", "stop_win": 0.02}"#), Ok(RiskLimits::default()), "Absent limits should not be enforced");
+        assert_eq!(RiskLimits::from_algorithm_json(r#"{"algorithm": "NaiveTrader", "risk_limits": {"max_quantity": 100, "max_amount": 1e4}}"#),
+                   Ok(RiskLimits { max_amount: Some(1e4), max_quantity: Some(100), ..RiskLimits::default() }),
+                   "Wrong limits parsed");
+        let error = RiskLimits::from_algorithm_json(r#"{"algorithm": "NaiveTrader", "risk_limits": {"max_quantiti": 100}}"#).expect_err("unknown limits should be refused");
+        assert!(error.contains("unknown field `max_quantiti`"), "Wrong error message: '{error}'");
+    }
+
+    /// [RiskLimits::max_market_data_gap_millis]
+    #[test]
+    fn market_data_gap() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_market_data_gap_millis: Some(1000), ..RiskLimits::default() });
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(10_000)),
+                       MarketDataGap { symbol: "PETR4".to_string(), start_time: 0, duration_nanos: u32::MAX },
+                       "Orders should be refused before any market data arrives");
+        risk_manager.on_market_data(at(10_000));
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(10_500)), Ok(()), "Recent market data should allow orders");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(12_000)),
+                       MarketDataGap { symbol: "PETR4".to_string(), start_time: 10, duration_nanos: 2_000_000_000 },
+                       "Old market data should refuse orders");
+    }
+
+    /// [RiskLimits::max_missed_trades_per_minute]
+    #[test]
+    fn missed_trades() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_missed_trades_per_minute: Some(1), ..RiskLimits::default() });
+        risk_manager.on_missed_trade(at(10_000));
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(10_500)), Ok(()), "Missed trades within the limit should allow orders");
+        risk_manager.on_missed_trade(at(11_000));
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(11_500)),
+                       MarketDataGap { symbol: "PETR4".to_string(), start_time: 10, duration_nanos: 1_500_000_000 },
+                       "Too many missed trades should refuse orders");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(70_000)), Ok(()), "Trades missed over a minute ago should be forgotten");
+    }
+
+    /// symbol trade modes & volume limits
+    #[test]
+    fn symbol_trade_mode_and_volumes() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits::default());
+        risk_manager.symbol_limits = Some(symbol_limits(EnumSymbolTradeMode::SymbolTradeModeFull));
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 50.0, 25.0), at(0)), VolumeTooLow { volume_min_millis_limit: 100_000 }, "Volume below the symbol's minimum");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 20_000.0, 25.0), at(0)), VolumeTooHigh { volume_max_millis_limit: 10_000_000 }, "Volume above the symbol's maximum");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 150.0, 25.0), at(0)), VolumeNotInStep { volume_step_millis: 100_000 }, "Volume not in the symbol's step");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 200.0, 25.0), at(0)), Ok(()), "Valid volumes should be accepted");
+
+        risk_manager.symbol_limits = Some(symbol_limits(EnumSymbolTradeMode::SymbolTradeModeLongonly));
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(0)), Ok(()), "Buying should be allowed for long only symbols");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeSell, 100.0, 25.0), at(0)), BadSymbolNegotiationStatus, "Selling should be refused for long only symbols");
+        risk_manager.symbol_limits = Some(symbol_limits(EnumSymbolTradeMode::SymbolTradeModeDisabled));
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(0)), BadSymbolNegotiationStatus, "Nothing should be allowed for disabled symbols");
+    }
+
+    /// [RiskLimits::max_quantity]
+    #[test]
+    fn quantity_limit() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_quantity: Some(500), ..RiskLimits::default() });
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 500.0, 25.0), at(0)), Ok(()), "Quantities up to the limit should be accepted");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 600.0, 25.0), at(0)), QuantityTooHigh { quantity_limit: 500 }, "Quantities above the limit");
+    }
+
+    /// [RiskLimits::max_amount] -- market orders are priced with the latest quote
+    #[test]
+    fn amount_limit() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_amount: Some(2_500.0), ..RiskLimits::default() });
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(0)), Ok(()), "Amounts up to the limit should be accepted");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.01), at(0)), AmountTooHigh { amount_millis_limit: 2_500_000 }, "Amounts above the limit");
+        let symbol = String::from("PETR4");
+        risk_manager.on_tick(&MqlTick { symbol: &symbol, time_msc: 0, bid: 24.99, ask: 25.02, last: 25.00, volume: 100.0, flags: 0 }, at(0));
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 0.0), at(0)), AmountTooHigh { amount_millis_limit: 2_500_000 }, "Market buys should be priced at the ask");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeSell, 100.0, 0.0), at(0)), Ok(()), "Market sells should be priced at the bid");
+    }
+
+    /// margin requirements against the account's free margin
+    #[test]
+    fn margin_limit() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits::default());
+        risk_manager.account_limits = Some(AccountLimits { margin_free: 1_000.0, leverage: 2 });
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 80.0, 25.0), at(0)), Ok(()), "Orders within the free margin should be accepted");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(0)), MarginTooHigh { free_margin_millis_limit: 1_000_000 }, "Orders above the free margin -- given the leverage");
+        risk_manager.symbol_limits = Some(SymbolLimits { margin_initial: 20.0, ..symbol_limits(EnumSymbolTradeMode::SymbolTradeModeFull) });
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 1.0), at(0)), MarginTooHigh { free_margin_millis_limit: 1_000_000 }, "The symbol's initial margin should be used, if informed");
+    }
+
+    /// [RiskLimits::max_daily_orders]
+    #[test]
+    fn daily_count_limit() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_daily_orders: Some(2), ..RiskLimits::default() });
+        let day = 86_400_000;
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(day)), Ok(()), "1st order of the day");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeSell, 100.0, 25.0), at(day + 1)), Ok(()), "2nd order of the day");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(day + 2)), DailyCountTooHigh { daily_count_limit: 2 }, "3rd order of the day");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(2 * day)), Ok(()), "The count should be reset on the next day");
+    }
+
+    /// [RiskLimits::max_round_trips_per_minute] -- only buys are throttled
+    #[test]
+    fn throughput_limit() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_round_trips_per_minute: Some(2), ..RiskLimits::default() });
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(0)), Ok(()), "1st round trip");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuyLimit, 100.0, 25.0), at(10_000)), Ok(()), "2nd round trip");
+        assert_refused(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(59_999)), ThroughputTooHigh { round_transactions_per_minute_limit: 2 }, "3rd round trip within the minute");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeSell, 100.0, 25.0), at(59_999)), Ok(()), "Sells should not be throttled");
+        assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(60_000)), Ok(()), "The 1st round trip should be out of the window after a minute");
+        assert_eq!(risk_manager.refusals().count(), 1, "Refusals should be kept for inspection");
+    }
+
+    /// checks the throughput windows stay bounded -- even when their limits are not configured or checks return early
+    #[test]
+    fn bounded_windows() {
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits::default());
+        for second in 0..600 {
+            risk_manager.on_missed_trade(at(second * 1000));
+            assert_eq!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(second * 1000)), Ok(()), "No limits are configured");
+        }
+        assert_eq!((risk_manager.recent_buys.len(), risk_manager.missed_trades.len()), (60, 60), "Only the instants within the last minute should be kept");
+
+        let mut risk_manager = RiskManager::new("PETR4", RiskLimits { max_market_data_gap_millis: Some(1000), ..RiskLimits::default() });
+        for second in 10..610 {
+            risk_manager.on_missed_trade(at(second * 1000));
+            assert!(risk_manager.check(&order(EnumOrderType::OrderTypeBuy, 100.0, 25.0), at(second * 1000)).is_err(), "Orders should be refused without market data");
+        }
+        assert_eq!(risk_manager.missed_trades.len(), 60, "Missed trades should be forgotten even when the checks return early");
+    }
+
+
+    fn assert_refused(result: Result<(), RiskManagementConditions>, expected: RiskManagementOrderRefusalConditions, message: &str) {
+        match result {
+            Err(RiskManagementConditions::OrderRefused(condition)) => assert_eq!(condition, expected, "{message}: wrong refusal condition"),
+            other => panic!("{message}: the order should have been refused with {expected:?}, but {other:?} was returned"),
+        }
+    }
+
+    fn at(epoch_millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(epoch_millis)
+    }
+
+    fn order(order_type: EnumOrderType, volume: f64, price: f64) -> OrderRequest {
+        OrderRequest {
+            action:             EnumTradeRequestActions::TradeActionDeal,
+            symbol:             String::from("PETR4"),
+            volume,
+            price,
+            sl:                 0.0,
+            tp:                 0.0,
+            order_type,
+            order_type_filling: EnumOrderTypeFilling::OrderFillingFok,
+            order_type_time:    EnumOrderTypeTime::OrderTimeDay,
+            comment:            String::new(),
+        }
+    }
+
+    fn symbol_limits(trade_mode: EnumSymbolTradeMode) -> SymbolLimits {
+        SymbolLimits { trade_mode, volume_min: 100.0, volume_max: 10_000.0, volume_step: 100.0, contract_size: 1.0, margin_initial: 0.0 }
+    }
+}
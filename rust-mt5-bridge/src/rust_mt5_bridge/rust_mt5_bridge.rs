@@ -3,52 +3,73 @@ use super::{
     mql_rust_enum,
     mq5_lib::types::MQ5StringRef,
     comms,
+    config,
+    handle_registry::HandleRegistry,
+    algorithms::{self, TradingAlgorithm},
+    mql5_commands::{Mql5Calls, Mql5Command, OrderRequest},
+    order_manager::OrderManager,
+    portfolio::Portfolio,
+    risk_manager::{RiskManager, RiskLimits},
+    aggressor_classifier::AggressorClassifier,
+    missed_trades_detector::MissedTradesDetector,
 };
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::fs;
 use std::io::Write;
 use std::iter::Iterator;
-use std::sync::atomic::AtomicI32;
-use std::sync::atomic::Ordering::Relaxed;
-use widestring::{U16CString};
-use parking_lot::RawMutex;
-use parking_lot::lock_api::RawMutex as _RawMutex;
+use std::sync::{Arc, Once};
+use std::panic::AssertUnwindSafe;
+use std::time::SystemTime;
+use widestring::{U16CString, U16String};
+use chrono::Local;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
 use log::{debug, info, warn, error};
 
 
 const MAX_HANDLES: i32 = 128;
+/// Book levels to pre-allocate for each side -- the deepest books B3 provides through Metatrader. Deeper ones are also handled
+const BOOK_DEPTH_HINT: usize = 64;
 
 // Runtime (static) data
 ////////////////////////
 
-pub static HANDLE_COUNT: AtomicI32 = AtomicI32::new(0);
-/// Keeps track of `handle_id`s conceived to clients (MT5 scripts)
-pub static mut HANDLES: Vec<Handle> = Vec::new();
-/// Guard for writes to HANDLES (reads are unguarded)
-static HANDLES_GUARD: RawMutex = RawMutex::INIT;
-/// If present, indicates any fatal errors that should cause MQL Programs to quit in order to avoid undefined behavior
-static mut FATAL_ERROR: Option<String> = None;
+/// Keeps track of the `handle_id`s conceived to clients (MT5 scripts) and their data -- see [live_handle()]
+pub static HANDLES: Lazy<HandleRegistry> = Lazy::new(|| HandleRegistry::new(MAX_HANDLES as usize));
+/// If present, indicates a fatal error that should cause all MQL Programs to quit in order to avoid undefined behavior
+/// -- errors scoped to a single MQL Program are kept in its [Handle]. See [FatalErrorSeverity]
+static GLOBAL_FATAL_ERROR: Mutex<Option<FatalError>> = Mutex::new(None);
+/// Positions & P&L for each trading account, by `account_token` -- shared by all MQL Programs operating on the same account.\
+/// Only consulted on [register()]: afterwards, each [Handle::portfolio] is used directly
+static PORTFOLIOS: Lazy<Mutex<HashMap<String, Arc<Mutex<Portfolio>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
 
 /// See the docs docs for this function in https://learn.microsoft.com/en-us/windows/win32/dlls/dllmain
 #[no_mangle]
 pub extern "system" fn DllMain(_: *const (), fdw_reason: u32, _: *const ()) -> u32 {
-    match fdw_reason {
-        0 => {
-            warn!("DllMain() called for reason 0: DLL_PROCESS_DETACH -- the DLL is being completely unloaded for the process is about to cleanly exit");
-            comms::shutdown_external_connector_server();
-        },
-        1 => {
-            init(Some("rust_mt5_bridge.log"));
-            warn!("'rust_mt5_bridge.dll' was loaded and started -- allowing up to {MAX_HANDLES} handles (Expert Advisors, Indicators, Testers, etc.) to be created -- removing them won't free resources (restarting Metatrader will)");
-            warn!("DllMain() called for reason 1: DLL_PROCESS_ATTACH -- DLL was loaded!");
-            comms::start_external_connector_server();
-        },
-        2 => debug!("DllMain() called for reason 2: DLL_THREAD_ATTACH -- host process just created another thread"),
-        3 => debug!("DllMain() called for reason 3: DLL_THREAD_DETACH -- host process just ended one of its threads"),
-        n => debug!("DllMain() called for unknown reason {n}"),
-    }
-    1   // = TRUE: the DLL is good with the reported event
+    // a panic here (like failing to create the log file) makes the DLL loading fail -- instead of crashing Metatrader
+    guarded("DllMain", -1, 0, || {
+        match fdw_reason {
+            0 => {
+                warn!("DllMain() called for reason 0: DLL_PROCESS_DETACH -- the DLL is being completely unloaded for the process is about to cleanly exit");
+                comms::shutdown_external_connector_server();
+            },
+            1 => {
+                init(Some("rust_mt5_bridge.log"));
+                warn!("'rust_mt5_bridge.dll' was loaded and started -- allowing up to {MAX_HANDLES} simultaneous handles (Expert Advisors, Indicators, Testers, etc.) to be created -- slots are reclaimed when they are removed");
+                warn!("DllMain() called for reason 1: DLL_PROCESS_ATTACH -- DLL was loaded!");
+                match config::load() {
+                    Ok(config) => comms::start_external_connector_server(&config.comms),
+                    Err(err) => set_fatal_error(-1, FatalErrorKind::InvalidConfiguration, FatalErrorKind::InvalidConfiguration.default_severity(),
+                                                format!("DllMain(): the configuration couldn't be loaded -- communications were not started: {err}")),
+                }
+            },
+            2 => debug!("DllMain() called for reason 2: DLL_THREAD_ATTACH -- host process just created another thread"),
+            3 => debug!("DllMain() called for reason 3: DLL_THREAD_DETACH -- host process just ended one of its threads"),
+            n => debug!("DllMain() called for unknown reason {n}"),
+        }
+        1   // = TRUE: the DLL is good with the reported event
+    })
 }
 
 /// Called by the `OnInit()` to expose, to Rust, the MQL values for the mapped enums.\
@@ -58,125 +79,180 @@ pub extern "system" fn DllMain(_: *const (), fdw_reason: u32, _: *const ()) -> u
 /// to happen (See [has_fatal_error()])
 #[no_mangle]
 pub extern fn set_enum_variant_value(rust_enum_name: MQ5StringRef, rust_variant_name: MQ5StringRef, mql_variant_value: i32) {
-    let rust_enum_name     = unsafe { U16CString::from_ptr_str(    rust_enum_name) }.to_string().unwrap_or(String::from("ERROR CONVERTING `rust_enum_name` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let rust_variant_name  = unsafe { U16CString::from_ptr_str( rust_variant_name) }.to_string().unwrap_or(String::from("ERROR CONVERTING `rust_variant_name` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    match mql_rust_enum::set_enum_variant_value(&rust_enum_name, &rust_variant_name, mql_variant_value) {
-        Ok(()) => {
-            info!("set_enum_variant_value: rust_enum_name: '{rust_enum_name}'; rust_variant_name: '{rust_variant_name}'; mql_variant_value: {mql_variant_value}");
-        },
-        Err(error_message) => {
-            error!("set_enum_variant_value: {} -- MQL Program should quit, otherwise UNDEFINED BEHAVIOR will happen", error_message);
-            unsafe { FATAL_ERROR = Some(error_message.clone()); }
-        },
-    }
+    guarded("set_enum_variant_value", -1, (), || {
+        let rust_enum_name     = unsafe { U16CString::from_ptr_str(    rust_enum_name) }.to_string().unwrap_or(String::from("ERROR CONVERTING `rust_enum_name` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let rust_variant_name  = unsafe { U16CString::from_ptr_str( rust_variant_name) }.to_string().unwrap_or(String::from("ERROR CONVERTING `rust_variant_name` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        match mql_rust_enum::set_enum_variant_value(&rust_enum_name, &rust_variant_name, mql_variant_value) {
+            Ok(()) => {
+                info!("set_enum_variant_value: rust_enum_name: '{rust_enum_name}'; rust_variant_name: '{rust_variant_name}'; mql_variant_value: {mql_variant_value}");
+            },
+            Err(error_message) => {
+                error!("set_enum_variant_value: {} -- MQL Program should quit, otherwise UNDEFINED BEHAVIOR will happen", error_message);
+                set_fatal_error(-1, FatalErrorKind::EnumMappingFailure, FatalErrorKind::EnumMappingFailure.default_severity(), error_message);
+            },
+        }
+    })
 }
 
-/// "fatal errors" are DLL errors that should cause any MQL programs to quit, as attempting to continue
+/// "fatal errors" are DLL errors that should cause MQL programs to quit, as attempting to continue
 /// is likely to cause undefined behavior -- which is sure to be disastrous.\
 /// MQL Programs should check on this function before returning from `OnInit()`, and quit in case `true` is returned -- in this case,
-/// `pre_allocated_error_message_buffer` will contain a brief explanation of the problem -- which should be shown to the
-/// Metatrader Terminal User.\
-/// NOTE: `pre_allocated_error_message_buffer` should be allocated on the MQL side and
-///       have enough space to hold the error messages -- 256 bytes should be enough.\
+/// `pre_allocated_error_message_buffer` will contain a brief explanation of the problem, prefixed by its [FatalErrorKind] -- which
+/// should be shown to the Metatrader Terminal User.\
+/// Both "global" errors (affecting all MQL Programs) and the ones scoped to `handle_id` are considered -- `-1` may be used
+/// to consider only the global ones.\
+/// NOTE: `pre_allocated_error_message_buffer` should be allocated on the MQL side, with its size (as given by MQL's
+///       `StringBufferLen()`) passed in `buffer_capacity` -- longer messages are truncated. 256 chars should be enough.\
 /// NOTE 2: the MQL Program must be asked to quit through the "Rust=>MQL calling interface" as well, if errors
 ///         were detected past `OnInit()`
 #[no_mangle]
-pub extern fn has_fatal_error(handle_id: i32, pre_allocated_error_message_buffer: *mut u16) -> bool {
-    if let Some(fatal_error) = unsafe { &FATAL_ERROR } {
-        warn!("Informing handle_id {handle_id} that it must quit due to the fatal error '{fatal_error}'");
-        unchecked_convert_rust_to_mql5_string(fatal_error.clone(), pre_allocated_error_message_buffer);
-        true
-    } else {
-        false
-    }
+pub extern fn has_fatal_error(handle_id: i32, pre_allocated_error_message_buffer: *mut u16, buffer_capacity: i32) -> bool {
+    guarded("has_fatal_error", handle_id, true, || {
+        // the refusal is, itself, a fatal error
+        non_null("has_fatal_error", handle_id, "pre_allocated_error_message_buffer", pre_allocated_error_message_buffer);
+        if let Some(fatal_error) = fatal_error_for(handle_id) {
+            warn!("Informing handle_id {handle_id} that it must quit due to the fatal error '{fatal_error}'");
+            convert_rust_to_mql5_string("has_fatal_error", handle_id, "pre_allocated_error_message_buffer", &fatal_error.to_string(), pre_allocated_error_message_buffer, buffer_capacity);
+            true
+        } else {
+            false
+        }
+    })
 }
 
-/// Causes this DLL to, as soon as possible, cease its operations and quit all MQL5 programs using it due to an
-/// unrecoverable error prone to cause undefined behavior -- stopping the operations, then, is imposed to avoid disaster
+/// Causes this DLL to, as soon as possible, cease its operations for `handle_id` and quit the MQL5 program using it due to an
+/// unrecoverable error prone to cause undefined behavior -- stopping the operations, then, is imposed to avoid disaster.\
+/// If `handle_id` is `-1`, the error is "global" and all MQL5 programs using this DLL will be asked to quit
 #[no_mangle]
 pub extern fn report_fatal_error(handle_id: i32, error_message: MQ5StringRef) {
-    let error_message = unsafe { U16CString::from_ptr_str(error_message) }.to_string().unwrap_or(String::from("ERROR CONVERTING `error_message` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol = &handle.symbol;
-    error!("report_fatal_error({handle_id}): {symbol}: a FATAL error was reported: '{error_message}' -- all MQL5 programs using this DLL should quit as soon as possible!");
-    unsafe {
-        FATAL_ERROR = Some(error_message);
-    }
+    guarded("report_fatal_error", handle_id, (), || {
+        // a null message still reports the error
+        let error_message = if error_message.is_null() {
+            String::from("<<null `error_message`>>")
+        } else {
+            unsafe { U16CString::from_ptr_str(error_message) }.to_string().unwrap_or(String::from("ERROR CONVERTING `error_message` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"))
+        };
+        set_fatal_error(handle_id, FatalErrorKind::MqlReported, FatalErrorKind::MqlReported.default_severity(), error_message);
+    })
+}
+
+/// Clears the fatal error reported by [has_fatal_error()] for `handle_id` -- to be called by MQL Programs after they recovered from it.\
+/// Global errors must be acknowledged by passing `-1` as `handle_id`.\
+/// Returns `true` if there was an error to be cleared
+#[no_mangle]
+pub extern fn acknowledge_fatal_error(handle_id: i32) -> bool {
+    guarded("acknowledge_fatal_error", handle_id, false, || {
+        let cleared = if handle_id == -1 {
+            GLOBAL_FATAL_ERROR.lock().take()
+        } else {
+            live_handle("acknowledge_fatal_error", handle_id)
+                .and_then(|handle| handle.fatal_error.lock().take())
+        };
+        if let Some(fatal_error) = &cleared {
+            warn!("acknowledge_fatal_error({handle_id}): the fatal error '{fatal_error}' was acknowledged & cleared -- operations may resume");
+        }
+        cleared.is_some()
+    })
 }
 
 /// Called by the `OnInit()` to inform the market data for the symbol being considered
 /// (as well the session information for operation) and to get the `handle` to be passed
 /// to all the other functions here -- if negative, it indicates an error code and the
-/// loading of the MT5 script must be cancelled
+/// loading of the MT5 script must be cancelled (see [register()] for the codes).\
+/// `algorithm` is either a JSON describing the trading algorithm to run -- see `algorithms/mod.rs` -- or a plain
+/// description, for MQL Programs that don't trade
 #[no_mangle]
 pub extern fn register_trading_expert_advisor_for_production(account_token: MQ5StringRef, algorithm: MQ5StringRef, symbol: MQ5StringRef) -> i32 {
-    let account_token = unsafe { U16CString::from_ptr_str(account_token) }.to_string().unwrap_or(String::from("ERROR CONVERTING `account_token` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let algorithm = unsafe { U16CString::from_ptr_str(algorithm) }.to_string().unwrap_or(String::from("ERROR CONVERTING `algorithm` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let symbol = unsafe { U16CString::from_ptr_str(symbol) }.to_string().unwrap_or(String::from("ERROR CONVERTING `symbol` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-
-    let handle_id = register(account_token, algorithm, symbol);
+    guarded("register_trading_expert_advisor_for_production", -1, -1, || {
+        let account_token = unsafe { U16CString::from_ptr_str(account_token) }.to_string().unwrap_or(String::from("ERROR CONVERTING `account_token` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let algorithm = unsafe { U16CString::from_ptr_str(algorithm) }.to_string().unwrap_or(String::from("ERROR CONVERTING `algorithm` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let symbol = unsafe { U16CString::from_ptr_str(symbol) }.to_string().unwrap_or(String::from("ERROR CONVERTING `symbol` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
 
-    // sleep a little -- relative to the conceived `handle_id` -- to allow the UI to be responsive when we approach the limit of 100 EA's in the Metatrader 5 Terminal
-    std::thread::sleep(std::time::Duration::from_millis(100 * handle_id as u64));
+        let handle_id = register(account_token.clone(), algorithm.clone(), symbol.clone());
 
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    if handle_id == -1 {
-        error!("OnInit: FAILED registering trading expert advisor for PRODUCTION: {:?} -- exhausted handles {handle_id} where the max is {MAX_HANDLES}", handle);
-    } else {
-        info!("OnInit: registering trading expert advisor for PRODUCTION: {:?} -- attributed handle_id: {handle_id}", handle);
-    }
-    handle_id
+        if let Some(handle) = live_handle("register_trading_expert_advisor_for_production", handle_id) {
+            // sleep a little -- relative to the conceived slot -- to allow the UI to be responsive when we approach the limit of 100 EA's in the Metatrader 5 Terminal
+            std::thread::sleep(std::time::Duration::from_millis(100 * (handle_id % MAX_HANDLES) as u64));
+            info!("OnInit: registering trading expert advisor for PRODUCTION: {:?} -- attributed handle_id: {handle_id}", handle);
+        } else {
+            error!("OnInit: FAILED registering trading expert advisor for PRODUCTION: account_token: {account_token:?}, algorithm: {algorithm:?}, symbol: {symbol:?} -- error code {handle_id}");
+        }
+        handle_id
+    })
 }
 
 /// Called by `OnDeinit()` or `OnTesterDeinit()` when the MT5 script is ending.\
-/// IMPORTANT: tradeoff decision: this function doesn't clean resources -- this way we don't lose speed requiring a Mutex for `HANDLES`.
-///            As a consequence, MT5 must be restarted every day.\
-///            (In the future, we may mark the slot as vacant and search for vacant ones when registering)
+/// The slot taken by `handle_id` is reclaimed, to be reused by the next MQL Program to register -- from now on,
+/// `handle_id` is stale and will be rejected by all functions in this DLL
 #[no_mangle]
 pub extern fn unregister_trading_expert_advisor(handle_id: i32, _reason_id: i32) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    info!("OnDeinit/OnTesterDeinit: unregistering trading expert advisor for `handle_id` #{handle_id}: {:?}", handle);
+    with_handle("unregister_trading_expert_advisor", handle_id, (), |handle| {
+        info!("OnDeinit/OnTesterDeinit: unregistering trading expert advisor for `handle_id` #{handle_id}: {:?}", handle);
+        unregister(handle_id);
+    })
 }
 
 /// Called by the `OnInit()` to inform the market data for the symbol being considered
 /// (as well the session information for operation) and to get the `handle` to be passed
 /// to all the other functions here -- if negative, it indicates an error code and the
-/// loading of the MT5 script must be cancelled
+/// loading of the MT5 script must be cancelled (see [register()] for the codes).\
+/// `algorithm` is either a JSON describing the trading algorithm to run -- see `algorithms/mod.rs` -- or a plain
+/// description, for MQL Programs that don't trade
 #[no_mangle]
 pub extern fn register_trading_expert_advisor_for_testing(account_token: MQ5StringRef, algorithm: MQ5StringRef, symbol: MQ5StringRef) -> i32 {
-    let account_token = unsafe { U16CString::from_ptr_str(account_token) }.to_string().unwrap_or(String::from("ERROR CONVERTING `account_token` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let algorithm = unsafe { U16CString::from_ptr_str(algorithm) }.to_string().unwrap_or(String::from("ERROR CONVERTING `algorithm` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let symbol = unsafe { U16CString::from_ptr_str(symbol) }.to_string().unwrap_or(String::from("ERROR CONVERTING `symbol` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let handle_id = register(account_token, algorithm, symbol);
-
-    // sleep a little -- relative to the conceived `handle_id` -- to allow the UI to be responsive when we approach the limit of 100 EA's in the Metatrader 5 Terminal
-    std::thread::sleep(std::time::Duration::from_millis(100 * handle_id as u64));
-
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    if handle_id == -1 {
-        error!("OnTesterInit: FAILED registering trading expert advisor for TESTING: {:?} -- exhausted handles {handle_id} where the max is {MAX_HANDLES}", handle);
-    } else {
-        info!("OnInit: registering trading expert advisor for TESTING: {:?} -- attributed handle_id: {handle_id}", handle);
-    }
-    handle_id
+    guarded("register_trading_expert_advisor_for_testing", -1, -1, || {
+        let account_token = unsafe { U16CString::from_ptr_str(account_token) }.to_string().unwrap_or(String::from("ERROR CONVERTING `account_token` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let algorithm = unsafe { U16CString::from_ptr_str(algorithm) }.to_string().unwrap_or(String::from("ERROR CONVERTING `algorithm` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let symbol = unsafe { U16CString::from_ptr_str(symbol) }.to_string().unwrap_or(String::from("ERROR CONVERTING `symbol` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let handle_id = register(account_token.clone(), algorithm.clone(), symbol.clone());
+
+        if let Some(handle) = live_handle("register_trading_expert_advisor_for_testing", handle_id) {
+            // sleep a little -- relative to the conceived slot -- to allow the UI to be responsive when we approach the limit of 100 EA's in the Metatrader 5 Terminal
+            std::thread::sleep(std::time::Duration::from_millis(100 * (handle_id % MAX_HANDLES) as u64));
+            info!("OnInit: registering trading expert advisor for TESTING: {:?} -- attributed handle_id: {handle_id}", handle);
+        } else {
+            error!("OnTesterInit: FAILED registering trading expert advisor for TESTING: account_token: {account_token:?}, algorithm: {algorithm:?}, symbol: {symbol:?} -- error code {handle_id}");
+        }
+        handle_id
+    })
 }
 
 /// Called to inform details over the symbol under negotiation./
 /// Typically consulted once per session per symbol, at the start.
 #[no_mangle]
 pub extern fn report_symbol_info(handle_id: i32, symbol_info: *const SymbolInfoBridge) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol_info = SymbolInfoBridge::from_ptr_to_internal(symbol_info);
-    info!("report_symbol_info({handle_id}): {}: {:?}", handle.symbol, symbol_info);
+    with_handle("report_symbol_info", handle_id, (), |handle| {
+        if !non_null("report_symbol_info", handle_id, "symbol_info", symbol_info) {
+            return;
+        }
+        let symbol_info = SymbolInfoBridge::from_ptr_to_internal(symbol_info);
+        info!("report_symbol_info({handle_id}): {}: {:?}", handle.symbol, symbol_info);
+        handle.portfolio.lock().on_symbol_info(&handle.symbol, &symbol_info);
+        handle.risk_manager.lock().on_symbol_info(&symbol_info);
+        feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_symbol_info(&symbol_info));
+    })
 }
 
 /// Called to inform details for the account used to make the negotiations./
 /// Typically consulted after every order issued / executed / edited / cancelled.
 #[no_mangle]
 pub extern fn report_account_info(handle_id: i32, account_info: *const AccountInfoBridge) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let account_info = AccountInfoBridge::from_ptr_to_internal(account_info);
-    info!("report_account_info({handle_id}): {}: {:?}", handle.symbol, account_info);
+    with_handle("report_account_info", handle_id, (), |handle| {
+        if !non_null("report_account_info", handle_id, "account_info", account_info) {
+            return;
+        }
+        let account_info = AccountInfoBridge::from_ptr_to_internal(account_info);
+        info!("report_account_info({handle_id}): {}: {:?}", handle.symbol, account_info);
+        handle.risk_manager.lock().on_account_info(&account_info);
+        {
+            let mut portfolio = handle.portfolio.lock();
+            let discrepancies_count = portfolio.reconcile(&account_info).len();
+            if discrepancies_count > 0 {
+                warn!("report_account_info({handle_id}): {}: {discrepancies_count} discrepancies found between the portfolio and the account info -- realized P&L: {}; unrealized P&L: {}", handle.symbol, portfolio.realized_pnl(), portfolio.unrealized_pnl());
+            }
+        }
+        feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_account_info(&account_info));
+    })
 }
 
 /// Called to inform details for a "deal" (an executed order)./
@@ -184,9 +260,14 @@ pub extern fn report_account_info(handle_id: i32, account_info: *const AccountIn
 /// at the start of the session.
 #[no_mangle]
 pub extern fn report_deal_properties(handle_id: i32, deal_properties: *const DealPropertiesBridge) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let deal_properties = DealPropertiesBridge::from_ptr_to_internal(deal_properties);
-    info!("report_deal_properties({handle_id}): {}: {:?}", handle.symbol, deal_properties);
+    with_handle("report_deal_properties", handle_id, (), |handle| {
+        if !non_null("report_deal_properties", handle_id, "deal_properties", deal_properties) {
+            return;
+        }
+        let deal_properties = DealPropertiesBridge::from_ptr_to_internal(deal_properties);
+        info!("report_deal_properties({handle_id}): {}: {:?}", handle.symbol, deal_properties);
+        handle.portfolio.lock().on_deal(&deal_properties);
+    })
 }
 
 
@@ -195,16 +276,35 @@ pub extern fn report_deal_properties(handle_id: i32, deal_properties: *const Dea
 /// See the docs https://www.mql5.com/en/docs/event_handlers/ontick
 #[no_mangle]
 pub extern fn on_tick(handle_id: i32, mt5_tick: *const Mq5MqlTick) {
-    // this will be logged
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let mt5_tick = unsafe { &*mt5_tick };
-    info!("OnTick({handle_id}): {}: {:?}", handle.symbol, mt5_tick);
-    // this will be enqueued
-    let rust_tick = mt5_tick.to_internal(&handle.symbol);
-    match rust_tick.to_event() {
-        TickEvent::Trade(trade_event)    => info!("OnTick({handle_id}): {}:   {:?}", handle.symbol, trade_event),
-        TickEvent::Spread(spread_event) => info!("OnTick({handle_id}): {}:  {:?}", handle.symbol, spread_event),
-    }
+    with_handle("on_tick", handle_id, (), |handle| {
+        if !non_null("on_tick", handle_id, "mt5_tick", mt5_tick) {
+            return;
+        }
+        // this will be logged
+        let mt5_tick = unsafe { &*mt5_tick };
+        info!("OnTick({handle_id}): {}: {:?}", handle.symbol, mt5_tick);
+        // this will be enqueued
+        let rust_tick = mt5_tick.to_internal(&handle.symbol);
+        handle.risk_manager.lock().on_tick(&rust_tick, SystemTime::now());
+        match rust_tick.to_event() {
+            TickEvent::Trade(mut trade_event)    => {
+                (trade_event.aggressor, trade_event.confidence) = {
+                    let books = handle.books.lock();
+                    handle.aggressor_classifier.lock().classify(&rust_tick, &books)
+                };
+                info!("OnTick({handle_id}): {}:   {:?}", handle.symbol, trade_event);
+                handle.missed_trades.lock().on_trade(&trade_event);
+                comms::publish_trade(&trade_event);
+                feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_trade(&trade_event));
+            },
+            TickEvent::Spread(spread_event) => {
+                info!("OnTick({handle_id}): {}:  {:?}", handle.symbol, spread_event);
+                handle.aggressor_classifier.lock().on_spread(&spread_event);
+                handle.portfolio.lock().on_spread(&spread_event);
+            },
+        }
+        feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_tick(&rust_tick));
+    })
 }
 
 /// Called when:
@@ -222,27 +322,61 @@ pub extern fn on_tick(handle_id: i32, mt5_tick: *const Mq5MqlTick) {
 pub extern fn on_trade(handle_id:            i32,
                        pending_orders_count: u32,
                        open_positions_count: u32) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol = &handle.symbol;
-    info!("OnTrade: handle_id: {handle_id}, symbol: '{symbol}', pending_orders_count: {pending_orders_count}, open_positions_count: {open_positions_count}");
+    with_handle("on_trade", handle_id, (), |handle| {
+        let symbol = &handle.symbol;
+        info!("OnTrade: handle_id: {handle_id}, symbol: '{symbol}', pending_orders_count: {pending_orders_count}, open_positions_count: {open_positions_count}");
+    })
 }
 
 /// Called on book updates -- notice, however, that many book events may be skipped: this function is only
-/// useful for reporting the new book state, which is shared in the `book_info_array_ptr` with a limit of
-/// 5 entries the buying book and 5 entries for the selling book.
+/// useful for reporting the new book state, which is shared in the `book_info_array_ptr` with as many levels
+/// as the broker provides for each side -- see [OrderBooks].
 #[no_mangle]
 pub extern fn on_book(handle_id:           i32,
                       book_info_array_ptr: *const Mq5MqlBookInfo,
                       array_len:           i32) {
-    let handle = unsafe { &mut HANDLES[handle_id as usize] };
-    let book_info_array = unsafe { std::slice::from_raw_parts(book_info_array_ptr, array_len as usize) };
-    // this should be logged
-    info!("OnBook({handle_id}): {}: {:?}", handle.symbol, book_info_array);
-    let delta_events = compute_book_delta_events(&handle.books, book_info_array);
-    // these will be enqueued for later processing
-    debug!("OnBook({handle_id}): {}: {:?}", handle.symbol, delta_events);
-    apply_book_delta_events(&mut handle.books, &delta_events);
-    debug!("OnBook({handle_id}): {}: {:?}", handle.symbol, handle.books);
+    with_handle("on_book", handle_id, (), |handle| {
+        let Some(book_info_array) = mql_array("on_book", handle_id, "book_info_array_ptr", book_info_array_ptr, array_len) else {
+            return;
+        };
+        // this should be logged
+        info!("OnBook({handle_id}): {}: {:?}", handle.symbol, book_info_array);
+        handle.risk_manager.lock().on_market_data(SystemTime::now());
+        let mut books = handle.books.lock();
+        let mut resynced = false;
+        let delta_events = compute_book_delta_events(&books, book_info_array)
+            .and_then(|delta_events| apply_book_delta_events(&mut books, &delta_events).map(|_| delta_events))
+            .unwrap_or_else(|inconsistency| {
+                warn!("OnBook({handle_id}): {}: books are out of sync ({inconsistency:?}) -- rebuilding them from Metatrader's", handle.symbol);
+                let mut book_stats = handle.book_stats.lock();
+                book_stats.resyncs += 1;
+                book_stats.last_inconsistency = Some(inconsistency);
+                resynced = true;
+                resync_books(&mut books, book_info_array)
+            });
+        handle.book_stats.lock().updates += 1;
+        let time = Local::now().naive_local();
+        let inferred_trade = {
+            let mut missed_trades = handle.missed_trades.lock();
+            if resynced {
+                missed_trades.on_resync();
+                None
+            } else {
+                missed_trades.on_book_deltas(&handle.symbol, time, &books, &delta_events)
+            }
+        };
+        if let Some(inferred_trade) = inferred_trade {
+            // not published, as the protocol has no marker for inferred trades, yet
+            warn!("OnBook({handle_id}): {}: the books were consumed by trades Metatrader didn't report -- inferred {:?}", handle.symbol, inferred_trade);
+            handle.risk_manager.lock().on_missed_trade(SystemTime::now());
+            feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_trade(&inferred_trade));
+        }
+        // these will be enqueued for later processing
+        debug!("OnBook({handle_id}): {}: {:?}", handle.symbol, delta_events);
+        debug!("OnBook({handle_id}): {}: {:?}", handle.symbol, books);
+        comms::publish_book_deltas(&handle.symbol, &time, &books, &delta_events);
+        feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_book_deltas(&books, &delta_events));
+    })
 }
 
 #[no_mangle]
@@ -250,12 +384,21 @@ pub extern fn on_trade_transaction(handle_id:   i32,
                                    transaction: *const Mq5MqlTradeTransaction,
                                    request:     *const Mq5MqlTradeRequest,
                                    result:      *const Mq5MqlTradeResult) {
-
-    let handle = unsafe { &mut HANDLES[handle_id as usize] };
-    let transaction = unsafe { &*transaction };
-    let request       = unsafe { &*request };
-    let result          = unsafe { &*result };
-    info!("OnTradeTransaction({handle_id}): {}: {:?}; {:?}; {:?}", handle.symbol, transaction, request, result);
+    with_handle("on_trade_transaction", handle_id, (), |handle| {
+        if !(non_null("on_trade_transaction", handle_id, "transaction", transaction) &&
+             non_null("on_trade_transaction", handle_id, "request",     request) &&
+             non_null("on_trade_transaction", handle_id, "result",      result)) {
+            return;
+        }
+        info!("OnTradeTransaction({handle_id}): {}: {:?}; {:?}; {:?}", handle.symbol, unsafe { &*transaction }, unsafe { &*request }, unsafe { &*result });
+        let transaction = Mq5MqlTradeTransaction::from_ptr_to_internal(transaction);
+        let request = Mq5MqlTradeRequest::from_ptr_to_internal(request);
+        let result = Mq5MqlTradeResult::from_ptr_to_internal(result);
+        let mut orders = handle.orders.lock();
+        orders.roll_over(SystemTime::now());
+        orders.on_trade_transaction(&transaction, &request, &result);
+        feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_trade_transaction(&orders, &transaction, &request, &result));
+    })
 }
 
 /// Called when a testing session ends -- returns the genetic evaluation function result, for which the genetic engine (built into Metatrader)
@@ -263,45 +406,83 @@ pub extern fn on_trade_transaction(handle_id:   i32,
 /// See the docs https://www.mql5.com/en/docs/event_handlers/ontester
 #[no_mangle]
 pub extern fn on_tester(handle_id: u32) -> f64 {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol = &handle.symbol;
-    info!("OnTester: handle_id: {handle_id}, symbol: '{symbol}'");
-    -1.0
+    with_handle("on_tester", handle_id as i32, -1.0, |handle| {
+        let symbol = &handle.symbol;
+        info!("OnTester: handle_id: {handle_id}, symbol: '{symbol}'");
+        -1.0
+    })
 }
 
 /// Usage and utility of this function is not yet clear --
 /// more study and experiments are needed https://www.mql5.com/en/docs/event_handlers/ontesterpass
 #[no_mangle]
 pub extern fn on_tester_pass(handle_id: u32) {
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol = &handle.symbol;
-    info!("OnTester: handle_id: {handle_id}, symbol: '{symbol}'");
+    with_handle("on_tester_pass", handle_id as i32, (), |handle| {
+        let symbol = &handle.symbol;
+        info!("OnTester: handle_id: {handle_id}, symbol: '{symbol}'");
+    })
 }
 
-/// If the returned value >= 0, places (in the pre-allocated `buffer`) the JSON function call descriptor that Rust wants MQL5 to do.\
-/// The JSON in the form `{"fn_to_call": "MqlFunction", "params": [10, "yes!", 9]}` -- see `RustToMQLMethodCall.mqh`
+/// If the returned value >= 0, it is the `call_id` of the next MQL5 function Rust wants to be called -- whose JSON call descriptor
+/// is placed in the pre-allocated `buffer`, in the form `{"fn_to_call": "MqlFunction", "params": [10, "yes!", 9]}`.\
+/// `buffer_capacity` is the size of `buffer`, as given by MQL's `StringBufferLen()` -- calls whose descriptors don't fit
+/// are not given to MQL, resolving as failed instead (see [Mql5Calls::abandon()]).\
+/// See `mql5_commands.rs` & `RustToMQLMethodCall.mqh`
 #[no_mangle]
-pub extern fn next_mql5_function_to_call(handle_id: i32, buffer: *mut u16) -> i32 {
-    let next_function_call = consume_next_mql5_function_call(handle_id);
-    if let Some(next_function_call) = next_function_call {
-        let handle = unsafe { &HANDLES[handle_id as usize] };
-        let symbol = &handle.symbol;
-        debug!("ExecuteMQL5Function({handle_id}): {symbol}: {next_function_call}");
-        unchecked_convert_rust_to_mql5_string(next_function_call, buffer);
-        1
-    } else {
-        -1
-    }
+pub extern fn next_mql5_function_to_call(handle_id: i32, buffer: *mut u16, buffer_capacity: i32) -> i32 {
+    with_handle("next_mql5_function_to_call", handle_id, -1, |handle| {
+        if !non_null("next_mql5_function_to_call", handle_id, "buffer", buffer) {
+            return -1;
+        }
+        let next_function_call = consume_next_mql5_function_call(handle_id);
+        if let Some((call_id, next_function_call)) = next_function_call {
+            let symbol = &handle.symbol;
+            debug!("ExecuteMQL5Function({handle_id}): {symbol}: call #{call_id}: {next_function_call}");
+            if !convert_rust_to_mql5_string("next_mql5_function_to_call", handle_id, "buffer", &next_function_call, buffer, buffer_capacity) {
+                // a truncated JSON would be refused by MQL -- so the call is never made
+                handle.mql5_calls.abandon(call_id);
+                return -1;
+            }
+            call_id as i32
+        } else {
+            -1
+        }
+    })
+}
+
+/// Places, in `pre_allocated_stats_buffer`, the latency statistics of the `OgreExchange` sessions bound to the symbol of
+/// `handle_id` -- one line per session -- so the MQL Program may show them (with `Comment()`, for instance).\
+/// Returns `false` if no session is bound to the symbol.\
+/// NOTE: `pre_allocated_stats_buffer` should be allocated on the MQL side, with its size (as given by MQL's
+///       `StringBufferLen()`) passed in `buffer_capacity` -- longer texts are truncated
+#[no_mangle]
+pub extern fn latency_stats(handle_id: i32, pre_allocated_stats_buffer: *mut u16, buffer_capacity: i32) -> bool {
+    with_handle("latency_stats", handle_id, false, |handle| {
+        let stats = comms::latency_stats(&handle.symbol);
+        let report = stats.iter()
+            .map(|(peer_id, stats)| format!("peer #{peer_id}: {stats:?}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        convert_rust_to_mql5_string("latency_stats", handle_id, "pre_allocated_stats_buffer", &report, pre_allocated_stats_buffer, buffer_capacity);
+        !stats.is_empty()
+    })
 }
 
 /// Called after a Rust triggered MQL5 function call was completed -- `function_called_json_descriptor` is a JSON with calling results in the form:
-/// `{"fn_called": "MqlFunction", "returns": [1, "done!", 2]}`
+/// `{"fn_called": "MqlFunction", "call_id": 12, "returns": {"mt5_error_code": 0, ...}}` -- which resolves the pending call (see `mql5_commands.rs`)
 #[no_mangle]
 pub extern fn report_mql5_function_called(handle_id: i32, function_called_json_descriptor: *mut u16) {
-    let function_called_json_descriptor = unsafe { U16CString::from_ptr_str(    function_called_json_descriptor) }.to_string().unwrap_or(String::from("ERROR CONVERTING `function_called_json_descriptor` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    let handle = unsafe { &HANDLES[handle_id as usize] };
-    let symbol = &handle.symbol;
-    debug!("ExecutedMQL5Function({handle_id}): {symbol}: {function_called_json_descriptor}");
+    with_handle("report_mql5_function_called", handle_id, (), |handle| {
+        if !non_null("report_mql5_function_called", handle_id, "function_called_json_descriptor", function_called_json_descriptor) {
+            return;
+        }
+        let function_called_json_descriptor = unsafe { U16CString::from_ptr_str(    function_called_json_descriptor) }.to_string().unwrap_or(String::from("ERROR CONVERTING `function_called_json_descriptor` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        let symbol = &handle.symbol;
+        debug!("ExecutedMQL5Function({handle_id}): {symbol}: {function_called_json_descriptor}");
+        if let Err(error_message) = handle.mql5_calls.complete(&function_called_json_descriptor) {
+            error!("ExecutedMQL5Function({handle_id}): {symbol}: {error_message}");
+        }
+    })
 }
 
 
@@ -314,352 +495,585 @@ pub extern fn report_mql5_function_called(handle_id: i32, function_called_json_d
 // triggered by Rust logic)
 
 /// Dumps the Rust internal values of the constants used in `MqlTick::flags` -- both to the log and
-/// back to the MQL program, via the pre-allocated MQL String `buffer`, so that the MQL Tester
+/// back to the MQL program, via the pre-allocated MQL String `buffer` (of `buffer_capacity` chars), so that the MQL Tester
 /// program may validate them
 #[no_mangle]
-pub extern fn dump_mql_tick_flag_constants(buffer: *mut u16) {
-    let constants = serialize_mql_tick_flag_constants();
-    info!("dump_mql_tick_flag_constants(): {:?}", constants);
-    unchecked_convert_rust_to_mql5_string(constants, buffer);
+pub extern fn dump_mql_tick_flag_constants(buffer: *mut u16, buffer_capacity: i32) {
+    guarded("dump_mql_tick_flag_constants", -1, (), || {
+        let constants = serialize_mql_tick_flag_constants();
+        info!("dump_mql_tick_flag_constants(): {:?}", constants);
+        convert_rust_to_mql5_string("dump_mql_tick_flag_constants", -1, "buffer", &constants, buffer, buffer_capacity);
+    })
 }
 
 #[no_mangle]
-pub extern fn dump_on_deinit_reasons(buffer: *mut u16) {
-    let constants = serialize_on_deinit_reasons();
-    info!("dump_on_deinit_reasons(): {:?}", constants);
-    unchecked_convert_rust_to_mql5_string(constants, buffer);
+pub extern fn dump_on_deinit_reasons(buffer: *mut u16, buffer_capacity: i32) {
+    guarded("dump_on_deinit_reasons", -1, (), || {
+        let constants = serialize_on_deinit_reasons();
+        info!("dump_on_deinit_reasons(): {:?}", constants);
+        convert_rust_to_mql5_string("dump_on_deinit_reasons", -1, "buffer", &constants, buffer, buffer_capacity);
+    })
 }
 
 /// Dumps how Rust reads the [Mq5MqlTick] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_mql_tick(buffer: *mut u16, tick: *const Mq5MqlTick) {
-    info!("dump_mql_tick(): {:?}", unsafe { &*tick });
-    serialize_mql5_struct(buffer, tick);
+pub extern fn dump_mql_tick(buffer: *mut u16, buffer_capacity: i32, tick: *const Mq5MqlTick) {
+    guarded("dump_mql_tick", -1, (), || {
+        if !non_null("dump_mql_tick", -1, "tick", tick) {
+            return;
+        }
+        info!("dump_mql_tick(): {:?}", unsafe { &*tick });
+        serialize_mql5_struct("dump_mql_tick", buffer, buffer_capacity, tick);
+    })
 }
 
 /// Dumps how Rust reads the [SymbolInfoBridge] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_symbol_info_bridge(buffer: *mut u16, symbol_info: *const SymbolInfoBridge) {
-    let symbol_info = SymbolInfoBridge::from_ptr_to_internal(symbol_info);
-    info!("dump_symbol_info_bridge(): {:?}", symbol_info);
-    serialize_mql5_struct(buffer, std::ptr::addr_of!(symbol_info));
+pub extern fn dump_symbol_info_bridge(buffer: *mut u16, buffer_capacity: i32, symbol_info: *const SymbolInfoBridge) {
+    guarded("dump_symbol_info_bridge", -1, (), || {
+        if !non_null("dump_symbol_info_bridge", -1, "symbol_info", symbol_info) {
+            return;
+        }
+        let symbol_info = SymbolInfoBridge::from_ptr_to_internal(symbol_info);
+        info!("dump_symbol_info_bridge(): {:?}", symbol_info);
+        serialize_mql5_struct("dump_symbol_info_bridge", buffer, buffer_capacity, std::ptr::addr_of!(symbol_info));
+    })
 }
 
 /// Dumps how Rust reads the [AccountInfoBridge] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_account_info_bridge(buffer: *mut u16, account_info: *const AccountInfoBridge) {
-    let account_info = AccountInfoBridge::from_ptr_to_internal(account_info);
-    info!("dump_account_info_bridge(): {:?}", account_info);
-    serialize_mql5_struct(buffer, std::ptr::addr_of!(account_info));
+pub extern fn dump_account_info_bridge(buffer: *mut u16, buffer_capacity: i32, account_info: *const AccountInfoBridge) {
+    guarded("dump_account_info_bridge", -1, (), || {
+        if !non_null("dump_account_info_bridge", -1, "account_info", account_info) {
+            return;
+        }
+        let account_info = AccountInfoBridge::from_ptr_to_internal(account_info);
+        info!("dump_account_info_bridge(): {:?}", account_info);
+        serialize_mql5_struct("dump_account_info_bridge", buffer, buffer_capacity, std::ptr::addr_of!(account_info));
+    })
 }
 
 /// Dumps how Rust reads the [DealPropertiesBridge] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_deal_properties_bridge(buffer: *mut u16, deal_properties: *const DealPropertiesBridge) {
-    let deal_properties = DealPropertiesBridge::from_ptr_to_internal(deal_properties);
-    info!("dump_deal_properties_bridge(): {:?}", deal_properties);
-    serialize_mql5_struct(buffer, std::ptr::addr_of!(deal_properties));
+pub extern fn dump_deal_properties_bridge(buffer: *mut u16, buffer_capacity: i32, deal_properties: *const DealPropertiesBridge) {
+    guarded("dump_deal_properties_bridge", -1, (), || {
+        if !non_null("dump_deal_properties_bridge", -1, "deal_properties", deal_properties) {
+            return;
+        }
+        let deal_properties = DealPropertiesBridge::from_ptr_to_internal(deal_properties);
+        info!("dump_deal_properties_bridge(): {:?}", deal_properties);
+        serialize_mql5_struct("dump_deal_properties_bridge", buffer, buffer_capacity, std::ptr::addr_of!(deal_properties));
+    })
 }
 
 /// Dumps how Rust reads the [Mq5MqlBookInfo] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_mql_book_info(buffer: *mut u16, book_info_array: *const Mq5MqlBookInfo, array_len: i32) {
-    let mut owned_book_info = Vec::with_capacity(array_len as usize);
-    let mut book_info_cursor = book_info_array;
-    for _ in 0..array_len as usize {
-        let mt5_book_info = unsafe { &*book_info_cursor };
-        let rust_book_info = mt5_book_info.to_internal();
-        owned_book_info.push(rust_book_info);
-        book_info_cursor = (book_info_cursor as usize + std::mem::size_of::<Mq5MqlBookInfo>()) as *mut Mq5MqlBookInfo;
-    }
-    info!("dump_mql_book_info(): {:?}", owned_book_info);
-    serialize_mql5_array(buffer, owned_book_info);
+pub extern fn dump_mql_book_info(buffer: *mut u16, buffer_capacity: i32, book_info_array: *const Mq5MqlBookInfo, array_len: i32) {
+    guarded("dump_mql_book_info", -1, (), || {
+        let Some(book_info_array) = mql_array("dump_mql_book_info", -1, "book_info_array", book_info_array, array_len) else {
+            return;
+        };
+        let owned_book_info = book_info_array.iter()
+            .map(Mq5MqlBookInfo::to_internal)
+            .collect::<Vec<_>>();
+        info!("dump_mql_book_info(): {:?}", owned_book_info);
+        serialize_mql5_array("dump_mql_book_info", buffer, buffer_capacity, owned_book_info);
+    })
 }
 
 /// Dumps how Rust reads the [Mq5MqlTradeTransaction] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_mql_trade_transaction(buffer:      *mut u16,
-                                         transaction: *const Mq5MqlTradeTransaction) {
-    let transaction = Mq5MqlTradeTransaction::from_ptr_to_internal(transaction);
-    info!("dump_mql_trade_transaction(): {:?}", transaction);
-    serialize_mql5_struct(buffer, &transaction);
+pub extern fn dump_mql_trade_transaction(buffer:          *mut u16,
+                                         buffer_capacity: i32,
+                                         transaction:     *const Mq5MqlTradeTransaction) {
+    guarded("dump_mql_trade_transaction", -1, (), || {
+        if !non_null("dump_mql_trade_transaction", -1, "transaction", transaction) {
+            return;
+        }
+        let transaction = Mq5MqlTradeTransaction::from_ptr_to_internal(transaction);
+        info!("dump_mql_trade_transaction(): {:?}", transaction);
+        serialize_mql5_struct("dump_mql_trade_transaction", buffer, buffer_capacity, &transaction);
+    })
 }
 
 /// Dumps how Rust reads the [Mq5MqlTradeRequest] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_mql_trade_request(buffer:  *mut u16,
-                                     request: *const Mq5MqlTradeRequest) {
-    let request = Mq5MqlTradeRequest::from_ptr_to_internal(request);
-    info!("dump_mql_trade_request(): {:?}", request);
-    serialize_mql5_struct(buffer, &request);
+pub extern fn dump_mql_trade_request(buffer:          *mut u16,
+                                     buffer_capacity: i32,
+                                     request:         *const Mq5MqlTradeRequest) {
+    guarded("dump_mql_trade_request", -1, (), || {
+        if !non_null("dump_mql_trade_request", -1, "request", request) {
+            return;
+        }
+        let request = Mq5MqlTradeRequest::from_ptr_to_internal(request);
+        info!("dump_mql_trade_request(): {:?}", request);
+        serialize_mql5_struct("dump_mql_trade_request", buffer, buffer_capacity, &request);
+    })
 }
 
 /// Dumps how Rust reads the [Mq5MqlTradeResult] structure -- both to the log and
 /// back to the MQL program, via the pre-allocated MQL String `buffer`
 #[no_mangle]
-pub extern fn dump_mql_trade_result(buffer: *mut u16,
-                                    result: *const Mq5MqlTradeResult) {
-    let result = Mq5MqlTradeResult::from_ptr_to_internal(result);
-    info!("dump_mql_trade_result(): {:?}", result);
-    serialize_mql5_struct(buffer, &result);
+pub extern fn dump_mql_trade_result(buffer:          *mut u16,
+                                    buffer_capacity: i32,
+                                    result:          *const Mq5MqlTradeResult) {
+    guarded("dump_mql_trade_result", -1, (), || {
+        if !non_null("dump_mql_trade_result", -1, "result", result) {
+            return;
+        }
+        let result = Mq5MqlTradeResult::from_ptr_to_internal(result);
+        info!("dump_mql_trade_result(): {:?}", result);
+        serialize_mql5_struct("dump_mql_trade_result", buffer, buffer_capacity, &result);
+    })
 }
 
-/// calls [schedule_mql5_function_call()] for testing purposes
+/// Schedules the hand-written `function_call_descriptor` for testing purposes -- see [Mql5Calls::schedule_raw()].\
+/// Returns the number of pending functions to call after the scheduling is done -- or 0 if `executing_handle_id` is not live
 #[no_mangle]
 pub extern fn test_schedule_mql5_function_call(executing_handle_id: i32, function_call_descriptor: MQ5StringRef) -> u32 {
-    let function_call_descriptor = unsafe { U16CString::from_ptr_str(    function_call_descriptor) }.to_string().unwrap_or(String::from("ERROR CONVERTING `function_call_descriptor` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
-    schedule_mql5_function_call(executing_handle_id, function_call_descriptor)
+    with_handle("test_schedule_mql5_function_call", executing_handle_id, 0, |handle| {
+        let function_call_descriptor = unsafe { U16CString::from_ptr_str(    function_call_descriptor) }.to_string().unwrap_or(String::from("ERROR CONVERTING `function_call_descriptor` -- a supposedly UTF-16 Metatrader 5 String reference to a UTF-8 Rust String"));
+        handle.mql5_calls.schedule_raw(function_call_descriptor);
+        handle.mql5_calls.len() as u32
+    })
 }
 
-/// Converts & copies `rust_string` into `pre_allocated_mql5_string`./
-/// `pre_allocated_mql5_string` should have enough space to hold the data + \0, or else
-/// Undefined Behavior will happen due to the corruption to the Metatrader heap
-fn unchecked_convert_rust_to_mql5_string(rust_string: String, pre_allocated_mql5_string: *mut u16) {
-    let u16_string = U16CString::from_str(rust_string).unwrap();
-    let u16_string_ptr = u16_string.as_ptr();
+/// Converts & copies `rust_string` into `pre_allocated_mql5_string` -- the `arg_name` argument `fn_name()` received from the MQL
+/// Program, with room for `buffer_capacity` UTF-16 chars (as given by MQL's `StringBufferLen()`), the terminating \0 included.\
+/// As Rust can't grow MQL's strings, what doesn't fit is truncated -- and logged. A null `pre_allocated_mql5_string` is
+/// refused (see [non_null()]).\
+/// Returns `true` if `rust_string` was copied whole
+fn convert_rust_to_mql5_string(fn_name: &str, handle_id: i32, arg_name: &str, rust_string: &str, pre_allocated_mql5_string: *mut u16, buffer_capacity: i32) -> bool {
+    if !non_null(fn_name, handle_id, arg_name, pre_allocated_mql5_string) {
+        return false;
+    }
+    let Some(max_len) = usize::try_from(buffer_capacity).ok().and_then(|capacity| capacity.checked_sub(1)) else {
+        error!("{fn_name}({handle_id}): the MQL String `{arg_name}` has no room (`buffer_capacity` is {buffer_capacity}) -- not even for an empty string");
+        return false;
+    };
+    let u16_string = U16String::from_str(rust_string);
+    let len = if u16_string.len() > max_len {
+        error!("{fn_name}({handle_id}): the MQL String `{arg_name}` has room for {max_len} chars only -- TRUNCATING the {} chars of '{rust_string}'", u16_string.len());
+        max_len
+    } else {
+        u16_string.len()
+    };
     unsafe {
-        std::ptr::copy_nonoverlapping(u16_string_ptr, pre_allocated_mql5_string, (u16_string.len()) * 2);
+        // `len` elements, not bytes: both sides are `u16`s
+        std::ptr::copy_nonoverlapping(u16_string.as_ptr(), pre_allocated_mql5_string, len);
         // write the end of the string char \0
-        std::ptr::write((pre_allocated_mql5_string as usize + u16_string.len()*2) as *mut u16, 0);
+        pre_allocated_mql5_string.add(len).write(0);
     }
+    len == u16_string.len()
 }
 
-/// Puts the Debug output of `struct_ptr` into `pre_allocated_mql5_string` -- which should have enough size or else
-/// memory corruption will occur
-fn serialize_mql5_struct<StructType: Debug>(pre_allocated_mql5_string: *mut u16, struct_ptr: *const StructType) {
+/// Puts the Debug output of `struct_ptr` into `pre_allocated_mql5_string` -- of `buffer_capacity` chars, truncating
+/// what doesn't fit. See [convert_rust_to_mql5_string()]
+fn serialize_mql5_struct<StructType: Debug>(fn_name: &str, pre_allocated_mql5_string: *mut u16, buffer_capacity: i32, struct_ptr: *const StructType) {
     let strct = unsafe { &*struct_ptr };
-    unchecked_convert_rust_to_mql5_string(format!("{:?}", strct), pre_allocated_mql5_string);
+    convert_rust_to_mql5_string(fn_name, -1, "buffer", &format!("{:?}", strct), pre_allocated_mql5_string, buffer_capacity);
 }
 
-/// Dumps the Debug output of `array` into `pre_allocated_mql5_string` -- which should have enough size or else
-/// memory corruption will occur
-fn serialize_mql5_array<StructType: Debug>(pre_allocated_mql5_string: *mut u16, array: Vec<StructType>) {
-    unchecked_convert_rust_to_mql5_string(format!("{:?}", array), pre_allocated_mql5_string);
+/// Dumps the Debug output of `array` into `pre_allocated_mql5_string` -- of `buffer_capacity` chars, truncating
+/// what doesn't fit. See [convert_rust_to_mql5_string()]
+fn serialize_mql5_array<StructType: Debug>(fn_name: &str, pre_allocated_mql5_string: *mut u16, buffer_capacity: i32, array: Vec<StructType>) {
+    convert_rust_to_mql5_string(fn_name, -1, "buffer", &format!("{:?}", array), pre_allocated_mql5_string, buffer_capacity);
 }
 
-/// Prepares the environment for this library's functions to work
+/// Prepares the environment for this library's functions to work.\
+/// Only the first call has effect -- so the logger set up by the tests is kept when they drive `DllMain()`
 fn init(log_file_path: Option<&str>) {
-    let mut config = simple_log::LogConfigBuilder::builder();
-    if let Some(log_file_path) = log_file_path {
-        config = config
-            .path(log_file_path)
-            .output_file();
-    } else {
-        config = config
-            .output_console();
-    }
-    config = config
-        .size(MAX_LOG_FILE_SIZE_MB)
-        .roll_count(MAX_LOG_FILES)
-        .time_format("%H:%M:%S.%f")
-        .level(LOG_LEVEL);
-
-    // TODO 2023-02-28: this causes MT5 to crash (without any output) if the file cannot be opened
-    simple_log::new(config.build())
-        .expect("instantiating simplelog file writer");
-    unsafe {
-        for _i in 0..MAX_HANDLES {
-            // place holders -- see `register()` for the real data to be put in `HANDLES`
-            HANDLES.push(Handle {
-                client_type:           ClientType::ProductionExpertAdvisor,
-                account_token:         "".to_string(),
-                algorithm:             "".to_string(),
-                symbol:                "".to_string(),
-                books:                 OrderBooks {
-                                           sell_orders: VecDeque::with_capacity(0),
-                                           buy_orders: VecDeque::with_capacity(0),
-                                       },
-                mql_functions_to_call: VecDeque::with_capacity(0)
-            });
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let mut config = simple_log::LogConfigBuilder::builder();
+        if let Some(log_file_path) = log_file_path {
+            config = config
+                .path(log_file_path)
+                .output_file();
+        } else {
+            config = config
+                .output_console();
         }
-    }
-    symbol_info_bridge::init();
-    account_info_bridge::init();
-    deal_properties_bridge::init();
-    mql_book_info::init();
-    mql_trade_request::init();
-    mql_trade_transaction::init();
+        config = config
+            .size(MAX_LOG_FILE_SIZE_MB)
+            .roll_count(MAX_LOG_FILES)
+            .time_format("%H:%M:%S.%f")
+            .level(LOG_LEVEL);
+
+        // TODO 2023-02-28: this panics (without any output) if the file cannot be opened -- causing `DllMain()` to refuse loading the DLL
+        simple_log::new(config.build())
+            .expect("instantiating simplelog file writer");
+        symbol_info_bridge::init();
+        account_info_bridge::init();
+        deal_properties_bridge::init();
+        mql_book_info::init();
+        mql_trade_request::init();
+        mql_trade_transaction::init();
+    });
 }
 
 /// Reserves a slot, inits it & returns the `handle_id` that is required by, almost, every function in this DLL./
-/// A returned value of `-1` means a slot could not be obtained (all possible slots were already used)./
-/// `handle_id` may be used to access the handle as in `let handle = unsafe { &HANDLES[handle_id as usize] };`
+/// Negative values are error codes:
+///   - `-1`: a slot could not be obtained (all possible slots are taken);
+///   - `-2`: `algorithm` describes an unknown trading algorithm or has invalid parameters or risk limits -- see [algorithms::instantiate()] & [RiskLimits::from_algorithm_json()].
+///
+/// `handle_id` may be used to access the handle as in `let Some(handle) = live_handle("fn_name", handle_id) else { return };`
 fn register(account_token: String, algorithm: String, symbol: String) -> i32 {
-    let books = OrderBooks {
-        sell_orders: VecDeque::with_capacity(5),
-        buy_orders:  VecDeque::with_capacity(5),
+    let trading_algorithm = match algorithms::instantiate(&algorithm, &symbol) {
+        Ok(trading_algorithm) => trading_algorithm,
+        Err(error_message) => {
+            error!("register(): {symbol}: refusing to register an MQL Program with an invalid `algorithm`: {error_message}");
+            return -2;
+        },
+    };
+    let risk_limits = match RiskLimits::from_algorithm_json(&algorithm) {
+        Ok(risk_limits) => risk_limits,
+        Err(error_message) => {
+            error!("register(): {symbol}: refusing to register an MQL Program with invalid risk limits: {error_message}");
+            return -2;
+        },
     };
-    let mql_functions_to_call = VecDeque::with_capacity(16);
-    let handle = Handle {
-        client_type: ClientType::ProductionExpertAdvisor,
+    let risk_manager = RiskManager::new(&symbol, risk_limits);
+    let portfolio = Arc::clone(PORTFOLIOS.lock().entry(account_token.clone()).or_default());
+    let handle_id = HANDLES.register(|handle_id| Handle {
+        handle_id,
+        client_type:           ClientType::ProductionExpertAdvisor,
         account_token,
         algorithm,
         symbol,
-        books,
-        mql_functions_to_call,
-    };
-    let handle_id = HANDLE_COUNT.fetch_add(1, Relaxed);
-    if handle_id >= MAX_HANDLES {
+        books:                 Mutex::new(OrderBooks {
+                                   sell_orders: VecDeque::with_capacity(BOOK_DEPTH_HINT),
+                                   buy_orders:  VecDeque::with_capacity(BOOK_DEPTH_HINT),
+                                   ..OrderBooks::default()
+                               }),
+        book_stats:            Mutex::new(BookStats::default()),
+        mql5_calls:            Mql5Calls::new(),
+        fatal_error:           Mutex::new(None),
+        orders:                Mutex::new(OrderManager::new()),
+        trading_algorithm:     Mutex::new(trading_algorithm),
+        risk_manager:          Mutex::new(risk_manager),
+        aggressor_classifier:  Mutex::new(AggressorClassifier::default()),
+        missed_trades:         Mutex::new(MissedTradesDetector::default()),
+        portfolio,
+    });
+    handle_id.unwrap_or_else(|| {
+        error!("register(): all {MAX_HANDLES} handle slots are taken");
         -1
-    } else {
-        unsafe {
-            // although most hardware is capable of working OK without these guards, we use a "better safe than sorry" approach here
-            // -- and this is out of the hot-path anyway
-            HANDLES_GUARD.lock();
-            HANDLES[handle_id as usize] = handle;
-            HANDLES_GUARD.unlock();
-        }
-        handle_id
+    })
+}
+
+/// Releases the slot taken by `handle_id`, making it available for the next [register()] call -- the handle's resources are
+/// freed as soon as no one else (like the comms server) holds it.\
+/// From here on, `handle_id` is stale and will be rejected by [live_handle()]
+fn unregister(handle_id: i32) {
+    if HANDLES.unregister(handle_id).is_none() {
+        warn!("unregister({handle_id}): attempted to unregister a `handle_id` that is not live -- ignoring");
     }
 }
 
-/// applies `delta_events` to `rolling_books` in order to update the order books
-/// -- or, in other words, "reconstruct the book"./
-/// [compute_book_delta_events()] is the opposite operation
-fn apply_book_delta_events(rolling_books: &mut OrderBooks, delta_events: &[BookEvents]) {
-
-    // if >= 0, the return value indicates that the price point was found at that index
-    fn search(book: &VecDeque<MqlBookInfo>, price: f64) -> i32 {
-        // the books are sorted, so `book.binary_search()` could be used if the books were allowed to grow past 5 elements
-        // -- for only 5, a full search is likely to be more efficient
-        for i in 0..book.len() {
-            if book[i].price == price {
-                return i as i32;
-            }
+/// Feeds the [TradingAlgorithm] of `handle` (if any) through `callback`, scheduling the orders it decides to issue
+/// for MQL to send -- see [issue_orders()]
+fn feed_trading_algorithm(handle: &Arc<Handle>, callback: impl FnOnce(&mut dyn TradingAlgorithm) -> Vec<OrderRequest>) {
+    let (algorithm_name, orders) = match handle.trading_algorithm.lock().as_mut() {
+        Some(trading_algorithm) => (trading_algorithm.name(), callback(trading_algorithm.as_mut())),
+        None => return,
+    };
+    issue_orders(handle, algorithm_name, orders);
+}
+
+/// Schedules `orders`, issued by the [TradingAlgorithm] of `handle`, for MQL to send -- see [Mql5Command::OrderSend] -- provided
+/// they pass the pre-trade checks of [Handle::risk_manager]. Their outcomes are fed back to the algorithm through
+/// [TradingAlgorithm::on_order_result()] -- or [TradingAlgorithm::on_order_refused()], if they didn't pass
+fn issue_orders(handle: &Arc<Handle>, algorithm_name: &str, orders: Vec<OrderRequest>) {
+    for order in orders {
+        let check = handle.risk_manager.lock().check(&order, SystemTime::now());
+        if let Err(risk_management_condition) = check {
+            warn!("feed_trading_algorithm({}): {}: the Risk Manager refused {algorithm_name}'s {order:?}: {risk_management_condition:?}", handle.handle_id, handle.symbol);
+            feed_trading_algorithm(handle, |trading_algorithm| trading_algorithm.on_order_refused(&order, &risk_management_condition));
+            continue;
         }
-        -1
+        info!("feed_trading_algorithm({}): {}: {algorithm_name} is issuing {order:?}", handle.handle_id, handle.symbol);
+        // weak, as the handle owns the pending calls
+        let weak_handle = Arc::downgrade(handle);
+        handle.mql5_calls.schedule_with_callback(Mql5Command::OrderSend(order.clone()), move |result| {
+            if let Some(handle) = weak_handle.upgrade() {
+                feed_trading_algorithm(&handle, |trading_algorithm| trading_algorithm.on_order_result(&order, &result));
+            }
+        });
+    }
+}
+
+/// Uniform guard for the FFI functions operating on a handle: validates `handle_id` and calls `f()` with its [Handle]
+/// -- see [guarded()] for the panic handling.\
+/// If `handle_id` isn't live, `sentinel` is returned without calling `f()` -- and [has_fatal_error()] will report it
+/// as a [FatalErrorKind::InvalidHandle] for the offending MQL Program, without affecting the others
+#[inline(always)]
+fn with_handle<R>(fn_name: &str, handle_id: i32, sentinel: R, f: impl FnOnce(Arc<Handle>) -> R) -> R {
+    let Some(handle) = live_handle(fn_name, handle_id) else {
+        return sentinel;
+    };
+    guarded(fn_name, handle_id, sentinel, || f(handle))
+}
+
+/// Uniform guard for all FFI functions: calls `f()`, catching any panics -- which must never cross the FFI boundary,
+/// as that would take the whole Metatrader Terminal down.\
+/// On panics, a [FatalErrorKind::Panic] is set for `handle_id` (see [has_fatal_error()]) and `sentinel` is returned.
+/// `handle_id` is used for logging purposes only -- `-1` should be used for functions not tied to a handle
+#[inline(always)]
+fn guarded<R>(fn_name: &str, handle_id: i32, sentinel: R, f: impl FnOnce() -> R) -> R {
+    match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result,
+        Err(panic_payload) => {
+            let panic_message = panic_payload.downcast_ref::<&str>().map(|message| message.to_string())
+                .or_else(|| panic_payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| String::from("<<non textual panic payload>>"));
+            let error_message = format!("{fn_name}({handle_id}): PANICKED: '{panic_message}'");
+            set_fatal_error(handle_id, FatalErrorKind::Panic, FatalErrorKind::Panic.default_severity(), error_message);
+            sentinel
+        },
     }
-    let del = |book: &mut VecDeque<MqlBookInfo>, price: f64| {
-        let i = search(book, price);
-        book.remove(i as usize);
+}
+
+/// Marks `handle_id` -- or the whole DLL, depending on `severity` -- as having experienced a fatal error. See [has_fatal_error()].\
+/// Errors for `-1` (no handle) are always "global"; errors scoped to other handles that aren't live are dropped (and logged), as
+/// their MQL Programs are already told about the [FatalErrorKind::InvalidHandle] -- escalating them would make all others quit
+pub(crate) fn set_fatal_error(handle_id: i32, kind: FatalErrorKind, severity: FatalErrorSeverity, message: String) {
+    let handle = match severity {
+        FatalErrorSeverity::Handle if handle_id == -1 => None,
+        FatalErrorSeverity::Handle => match HANDLES.get(handle_id) {
+            Some(handle) => Some(handle),
+            None => {
+                warn!("set_fatal_error({handle_id}): dropping a fatal error of kind {kind:?} for a `handle_id` that is not live (never registered, unregistered or stale): '{message}'");
+                return;
+            },
+        },
+        FatalErrorSeverity::Global => None,
     };
-    let update = |book: &mut VecDeque<MqlBookInfo>, price: f64, delta_quantity: f64| {
-        let i = search(book, price);
-        book[i as usize].volume += delta_quantity;
+    let (severity, symbol) = handle.as_ref()
+        .map_or((FatalErrorSeverity::Global, "<<all symbols>>"), |handle| (severity, handle.symbol.as_str()));
+    error!("set_fatal_error({handle_id}): {symbol}: a {severity:?} FATAL error of kind {kind:?} happened: '{message}' -- MQL Program(s) should quit, otherwise UNDEFINED BEHAVIOR will happen");
+    let fatal_error = FatalError { kind, severity, message };
+    match handle {
+        Some(handle) => handle.fatal_error.lock().replace(fatal_error),
+        None => GLOBAL_FATAL_ERROR.lock().replace(fatal_error),
     };
-    let add = |book: &mut VecDeque<MqlBookInfo>, new_book_order: MqlBookInfo| {
-        // whatever the book, they are always sorted descendingly by price
-        let i = book.partition_point(|order| order.price > new_book_order.price);
-        book.insert(i, new_book_order);
+}
+
+/// Clears the "global" fatal error of the given `kind`, if there is one -- for when the condition that caused it is gone
+pub(crate) fn clear_global_fatal_error(kind: FatalErrorKind) {
+    let mut global_fatal_error = GLOBAL_FATAL_ERROR.lock();
+    if global_fatal_error.as_ref().is_some_and(|fatal_error| fatal_error.kind == kind) {
+        let fatal_error = global_fatal_error.take();
+        warn!("clear_global_fatal_error({kind:?}): the fatal error {fatal_error:?} is gone -- operations may resume");
+    }
+}
+
+/// Returns the fatal error `handle_id` should be informed of -- "global" ones taking precedence
+fn fatal_error_for(handle_id: i32) -> Option<FatalError> {
+    if let Some(global_fatal_error) = GLOBAL_FATAL_ERROR.lock().clone() {
+        return Some(global_fatal_error);
+    }
+    if handle_id == -1 {
+        return None;
+    }
+    match HANDLES.get(handle_id) {
+        Some(handle) => handle.fatal_error.lock().clone(),
+        None => Some(FatalError {
+            kind:     FatalErrorKind::InvalidHandle,
+            severity: FatalErrorSeverity::Handle,
+            message:  format!("`handle_id` {handle_id} is not live (never registered, unregistered or stale)"),
+        }),
+    }
+}
+
+/// Lock-free resolution of `handle_id` into its [Handle] -- returning `None` (and logging the offense) if it isn't live:
+/// either it was never given out, it was already unregistered or it is stale (from an older generation of its slot)
+#[inline(always)]
+pub(crate) fn live_handle(fn_name: &str, handle_id: i32) -> Option<Arc<Handle>> {
+    let handle = HANDLES.get(handle_id);
+    if handle.is_none() {
+        error!("{fn_name}({handle_id}): rejected call for a `handle_id` that is not live (never registered, unregistered or stale)");
+    }
+    handle
+}
+
+/// Tells if `ptr` -- the `arg_name` argument `fn_name()` received from the MQL Program -- may be dereferenced: if it is
+/// null, a [FatalErrorKind::InvalidArguments] is set for `handle_id` (see [has_fatal_error()])
+#[inline(always)]
+fn non_null<T>(fn_name: &str, handle_id: i32, arg_name: &str, ptr: *const T) -> bool {
+    if ptr.is_null() {
+        set_fatal_error(handle_id, FatalErrorKind::InvalidArguments, FatalErrorKind::InvalidArguments.default_severity(),
+                        format!("{fn_name}({handle_id}): `{arg_name}` is a null pointer"));
+    }
+    !ptr.is_null()
+}
+
+/// Borrows the MQL array of `len` elements at `ptr` -- the `arg_name` argument `fn_name()` received from the MQL Program.
+/// A null `ptr` with `len` 0 is taken as an empty array; other null pointers & negative lengths set a
+/// [FatalErrorKind::InvalidArguments] for `handle_id` (see [has_fatal_error()]), returning `None`
+#[inline(always)]
+fn mql_array<'a, T>(fn_name: &str, handle_id: i32, arg_name: &str, ptr: *const T, len: i32) -> Option<&'a [T]> {
+    let invalidity = match (ptr.is_null(), len) {
+        (_, len) if len < 0 => format!("has a negative length of {len}"),
+        (true, 0)           => return Some(&[]),
+        (true, len)         => format!("is a null pointer, yet {len} elements were told to be there"),
+        (false, len)        => return Some(unsafe { std::slice::from_raw_parts(ptr, len as usize) }),
     };
+    set_fatal_error(handle_id, FatalErrorKind::InvalidArguments, FatalErrorKind::InvalidArguments.default_severity(),
+                    format!("{fn_name}({handle_id}): the array `{arg_name}` {invalidity}"));
+    None
+}
 
-    for delta_event in delta_events {
-        match delta_event {
-            BookEvents::Add    { book, price, quantity } => {
-                let book_deque = match book {
-                    BookParties::Sellers => &mut rolling_books.sell_orders,
-                    BookParties::Buyers => &mut rolling_books.buy_orders,
-                };
-                add(book_deque, MqlBookInfo { book_type: book.to_mt5_enum_book(), price: *price, volume: *quantity })
-            },
-            BookEvents::Del    { book, price, quantity: _quantity } => {
-                let book_deque = match book {
-                    BookParties::Sellers => &mut rolling_books.sell_orders,
-                    BookParties::Buyers => &mut rolling_books.buy_orders,
-                };
-                del(book_deque, *price)
-            },
-            BookEvents::Update { book, price, delta_quantity } => {
-                let book_deque = match book {
-                    BookParties::Sellers => &mut rolling_books.sell_orders,
-                    BookParties::Buyers => &mut rolling_books.buy_orders,
+/// Resolves the live [Handle] of the MQL Program trading `symbol` -- for `account_token`, if given. Should there be
+/// several, the one registered on the lowest slot is returned
+pub(crate) fn find_handle(symbol: &str, account_token: Option<&str>) -> Option<Arc<Handle>> {
+    HANDLES.find(|handle| handle.symbol == symbol && account_token.is_none_or(|account_token| handle.account_token == account_token))
+}
+
+/// applies `delta_events` to `rolling_books` in order to update the order books
+/// -- or, in other words, "reconstruct the book".\
+/// Either all events are applied or, on the first [BookInconsistency], none are: `rolling_books` are left untouched, so they
+/// may be rebuilt with [resync_books()].\
+/// [compute_book_delta_events()] is the opposite operation
+fn apply_book_delta_events(rolling_books: &mut OrderBooks, delta_events: &[BookEvents]) -> Result<(), BookInconsistency> {
+    for (applied, delta_event) in delta_events.iter().enumerate() {
+        if let Err(inconsistency) = apply_book_delta_event(rolling_books, delta_event) {
+            // undoes what was applied -- which can't fail, as it was just done
+            for delta_event in delta_events[..applied].iter().rev() {
+                let undo_event = match *delta_event {
+                    BookEvents::Add    { book, price, quantity }       => BookEvents::Del    { book, price, quantity },
+                    BookEvents::Del    { book, price, quantity }       => BookEvents::Add    { book, price, quantity },
+                    BookEvents::Update { book, price, delta_quantity } => BookEvents::Update { book, price, delta_quantity: -delta_quantity },
                 };
-                update(book_deque, *price, *delta_quantity)
+                _ = apply_book_delta_event(rolling_books, &undo_event);
             }
+            return Err(inconsistency)
         }
     }
+    Ok(())
+}
+
+/// applies a single `delta_event` to `rolling_books` -- which are left untouched if it is inconsistent with them
+fn apply_book_delta_event(rolling_books: &mut OrderBooks, delta_event: &BookEvents) -> Result<(), BookInconsistency> {
+    // whatever the book, they are always sorted descendingly by price -- so levels are binary searched
+    match *delta_event {
+        BookEvents::Add    { book, price, quantity } => {
+            let i = rolling_books.search(&book, price).err()
+                .ok_or(BookInconsistency::DuplicateLevel { book, price })?;
+            rolling_books.side_mut(&book).insert(i, MqlBookInfo { book_type: book.to_mt5_enum_book(), price, volume: quantity });
+        },
+        BookEvents::Del    { book, price, quantity } => {
+            let i = rolling_books.search(&book, price)
+                .map_err(|_| BookInconsistency::MissingLevel { book, price })?;
+            let booked = rolling_books.side(&book)[i].volume;
+            if booked != quantity {
+                return Err(BookInconsistency::VolumeMismatch { book, price, booked, deleted: quantity })
+            }
+            rolling_books.side_mut(&book).remove(i);
+        },
+        BookEvents::Update { book, price, delta_quantity } => {
+            let i = rolling_books.search(&book, price)
+                .map_err(|_| BookInconsistency::MissingLevel { book, price })?;
+            let level = &mut rolling_books.side_mut(&book)[i];
+            let volume = level.volume + delta_quantity;
+            if volume < 0.0 {
+                return Err(BookInconsistency::NegativeVolume { book, price, volume })
+            }
+            level.volume = volume;
+        },
+    }
+    Ok(())
+}
+
+/// rebuilds `rolling_books` from Metatrader's full `new_books` -- for when they got out of sync (see [BookInconsistency]).
+/// Entries of unknown types are left out, as well as repeated prices.\
+/// Returns the events that describe the rebuild: a `Del` for each level that was booked, then an `Add` for each new one
+fn resync_books(rolling_books: &mut OrderBooks, new_books: &[Mq5MqlBookInfo]) -> Vec<BookEvents> {
+    let book_events = |books: &OrderBooks, make_event: fn(BookParties, f64, f64) -> BookEvents| books.iter()
+        .filter_map(|level| BookParties::from_mt5_enum_book(level.book_type).ok()
+            .map(|book| make_event(book, level.price, level.volume)))
+        .collect::<Vec<_>>();
+    let mut resync_events = book_events(rolling_books, |book, price, quantity| BookEvents::Del { book, price, quantity });
+    *rolling_books = OrderBooks { sell_orders: VecDeque::with_capacity(BOOK_DEPTH_HINT), buy_orders: VecDeque::with_capacity(BOOK_DEPTH_HINT), ..OrderBooks::default() };
+    for new in new_books {
+        let level = new.to_internal();
+        let Ok(book) = BookParties::from_mt5_enum_book(level.book_type) else {
+            warn!("resync_books(): leaving out a book entry of an unknown type: {new:?}");
+            continue
+        };
+        rolling_books.side_mut(&book).push_back(level);
+    }
+    // Metatrader's order is kept, as binary searches depend on it -- but it is not trusted
+    for side in [BookParties::Sellers, BookParties::Buyers, BookParties::MarketSellers, BookParties::MarketBuyers] {
+        let levels = rolling_books.side_mut(&side);
+        levels.make_contiguous().sort_by(|a, b| b.price.total_cmp(&a.price));
+        let mut previous_price = None;
+        levels.retain(|level| previous_price.replace(level.price) != Some(level.price));
+    }
+    resync_events.extend(book_events(rolling_books, |book, price, quantity| BookEvents::Add { book, price, quantity }));
+    resync_events
 }
 
 /// returns the delta events that would turn `old_books` into `new_books`, where:
 ///   - `new_books` is the Metatrader array received by the `OnBook()` event
 ///   - `old_books` is our internally kept structure to allow us to compute the event deltas.\
-/// [apply_book_delta_events()] should be used to advance the book.
-fn compute_book_delta_events(old_books: &OrderBooks, new_books: &[Mq5MqlBookInfo]) -> Vec<BookEvents> {
+/// [apply_book_delta_events()] should be used to advance the book -- failing if `new_books` has entries of types unknown to Rust.
+fn compute_book_delta_events(old_books: &OrderBooks, new_books: &[Mq5MqlBookInfo]) -> Result<Vec<BookEvents>, BookInconsistency> {
+    let book_type = |new: &Mq5MqlBookInfo| ENUM_BOOK_TYPE.resolve_rust_variant::<EnumBookType>(new.book_type);
+    if new_books.iter().any(|new| book_type(new) == EnumBookType::UnknownMqlVariantValue) {
+        return Err(BookInconsistency::UnknownBookType)
+    }
     let mut delta_events = Vec::<BookEvents>::with_capacity(new_books.len());
-    let mut old_books_iter = old_books.iter().peekable();
-    let mut new_books_iter = new_books.iter().peekable();
-    // The two iterators want to walk together, building the 'delta_events' whatever one lags behind the other
-    'next: loop {
-        let mut peeked_old = old_books_iter.peek();
-        let mut peeked_new = new_books_iter.peek();
-        're_evaluate: loop {
-            match (peeked_old, peeked_new) {
+    // each side is diffed on its own -- so market orders are found wherever Metatrader places them
+    for book in [BookParties::Sellers, BookParties::MarketSellers, BookParties::MarketBuyers, BookParties::Buyers] {
+        let mut old_books_iter = old_books.side(&book).iter().peekable();
+        let mut new_books_iter = new_books.iter().filter(|new| book_type(new) == book.to_mt5_enum_book()).peekable();
+        // both are descending by price: the two iterators walk together, building the 'delta_events' whatever one lags behind the other
+        loop {
+            match (old_books_iter.peek(), new_books_iter.peek()) {
+                (Some(old), Some(new)) if old.price < new.price => {
+                    delta_events.push(BookEvents::Add { book, price: new.price, quantity: new.volume_real });
+                    new_books_iter.next();
+                },
+                (Some(old), Some(new)) if old.price > new.price => {
+                    delta_events.push(BookEvents::Del { book, price: old.price, quantity: old.volume });
+                    old_books_iter.next();
+                },
                 (Some(old), Some(new)) => {
-                    if old.book_type != ENUM_BOOK_TYPE.resolve_rust_variant(new.book_type) {
-                        if old.book_type.is_sell() {
-                            peeked_new = None;
-                            continue 're_evaluate
-                        } else {
-                            peeked_old = None;
-                            continue 're_evaluate
-                        }
-                    }
-                    if old.price < new.price {
-                        delta_events.push(BookEvents::Add { book: BookParties::from_mt5_enum_book(ENUM_BOOK_TYPE.resolve_rust_variant(new.book_type)), price: new.price, quantity: new.volume_real });
-                        peeked_old = None;
-                    } else if old.price > new.price {
-                        delta_events.push(BookEvents::Del { book: BookParties::from_mt5_enum_book(old.book_type), price: old.price, quantity: old.volume });
-                        peeked_new = None;
-                    } else if old.volume != new.volume_real {
-                        delta_events.push(BookEvents::Update { book: BookParties::from_mt5_enum_book(old.book_type), price: new.price, delta_quantity: new.volume_real-old.volume });
+                    if old.volume != new.volume_real {
+                        delta_events.push(BookEvents::Update { book, price: new.price, delta_quantity: new.volume_real-old.volume });
                     }
+                    old_books_iter.next();
+                    new_books_iter.next();
                 },
                 (Some(old), None) => {
-                    delta_events.push(BookEvents::Del { book: BookParties::from_mt5_enum_book(old.book_type), price: old.price, quantity: old.volume });
+                    delta_events.push(BookEvents::Del { book, price: old.price, quantity: old.volume });
+                    old_books_iter.next();
                 },
                 (None, Some(new)) => {
-                    delta_events.push(BookEvents::Add { book: BookParties::from_mt5_enum_book(ENUM_BOOK_TYPE.resolve_rust_variant(new.book_type)), price: new.price, quantity: new.volume_real });
+                    delta_events.push(BookEvents::Add { book, price: new.price, quantity: new.volume_real });
+                    new_books_iter.next();
                 },
-                (None, None) => break 'next,
+                (None, None) => break,
             }
-            break 're_evaluate
-        }
-        // advance the cursors
-        if let Some(_old) = peeked_old {
-            old_books_iter.next();
-        }
-        if let Some(_new) = peeked_new {
-            new_books_iter.next();
         }
     }
-    delta_events
+    Ok(delta_events)
 }
 
 
-/// Schedules a function to be executed by one of the MQL5 programs.\
-/// `function_call` is a JSON in the form `{"fn_to_call": "MqlFunction", "params": [10, "yes!", 9]}` -- see `RustToMQLMethodCall.mqh`.\
-/// Returns the number of pending functions to call after the scheduling is done
-fn schedule_mql5_function_call(executing_handle_id: i32, function_call: String) -> u32 {
-    unsafe {
-        let mql_functions_to_call = &mut HANDLES[executing_handle_id as usize].mql_functions_to_call;
-        HANDLES_GUARD.lock();
-        mql_functions_to_call.push_back(function_call);
-        HANDLES_GUARD.unlock();
-        mql_functions_to_call.len() as u32
-    }
-}
-
 /// Consumes any next MQL5 function to be called for the given `handle_id`.\
-/// Returns the JSON call descriptor -- see [schedule_mql5_function_execution()]\
-/// Implementation note: occasional false negatives are acceptable for the sake of execution speed (.len() check is out of the mutex section)
-fn consume_next_mql5_function_call(handle_id: i32) -> Option<String> {
-    unsafe {
-        if HANDLES[handle_id as usize].mql_functions_to_call.len() > 0 {
-            HANDLES_GUARD.lock();
-            let function_call = HANDLES[handle_id as usize].mql_functions_to_call.pop_front();
-            HANDLES_GUARD.unlock();
-            function_call
-        } else {
-            None
-        }
-    }
+/// Returns the `call_id` & JSON call descriptor -- see [Mql5Command::to_json()]
+fn consume_next_mql5_function_call(handle_id: i32) -> Option<(u32, String)> {
+    let handle = live_handle("consume_next_mql5_function_call", handle_id)?;
+    handle.mql5_calls.next_call()
 }
 
 /// are we compiled in DEBUG or RELEASE mode?
@@ -686,28 +1100,183 @@ fn _internal_logger(path: &str, contents: &str) {
 mod tests {
     use super::*;
     use super::super::mq5_lib::EnumBookType::*;
+    use super::super::mql5_commands::Mql5CallError;
+    use super::super::xorshift::XorShift;
+    use std::str::FromStr;
 
     #[ctor::ctor]
     fn suite_setup() {
         init(None);
     }
 
+    /// checks that invalid handles & panics are contained by [with_handle()] & [guarded()]
+    /// -- returning the sentinel values & setting the fatal error instead of unwinding through the FFI boundary
+    #[test]
+    fn ffi_guard() {
+        let invalid_handle_id = MAX_HANDLES * 7919;
+        assert_eq!(on_tester(u32::MAX), -1.0, "`on_tester()` should return its sentinel for an invalid `handle_id`");
+        assert_eq!(next_mql5_function_to_call(invalid_handle_id, std::ptr::null_mut(), 0), -1, "`next_mql5_function_to_call()` should return its sentinel for a never given `handle_id`");
+        let fatal_error = fatal_error_for(invalid_handle_id).expect("invalid handles should be reported as fatal errors");
+        assert_eq!(fatal_error.kind, FatalErrorKind::InvalidHandle, "Wrong fatal error kind for an invalid handle: {fatal_error:?}");
+
+        let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("PANIC"));
+        let observed = with_handle("panicking_fn", handle_id, -1, |_handle| -> i32 { panic!("a deliberate test panic") });
+        assert_eq!(observed, -1, "A panicking function should return its sentinel");
+        let fatal_error = fatal_error_for(handle_id).expect("panics should set the fatal error");
+        assert_eq!(fatal_error.kind, FatalErrorKind::Panic, "Wrong fatal error kind for a panic: {fatal_error:?}");
+        assert!(fatal_error.message.contains("a deliberate test panic"), "The panic message should be in the fatal error: '{fatal_error}'");
+        unregister(handle_id);
+    }
+
+    /// errors scoped to a handle must not affect the others -- and must go away when acknowledged
+    #[test]
+    fn per_handle_fatal_errors() {
+        let failing_handle_id = register(format!("acnt_tkn"), format!("algo"), format!("FAILING"));
+        let healthy_handle_id = register(format!("acnt_tkn"), format!("algo"), format!("HEALTHY"));
+        set_fatal_error(failing_handle_id, FatalErrorKind::RiskBreach, FatalErrorSeverity::Handle, format!("daily loss limit reached"));
+        let fatal_error = fatal_error_for(failing_handle_id).expect("the error should have been set for the failing handle");
+        assert_eq!((fatal_error.kind, fatal_error.severity), (FatalErrorKind::RiskBreach, FatalErrorSeverity::Handle), "Wrong fatal error: {fatal_error:?}");
+        assert_eq!(fatal_error.to_string(), "RiskBreach: daily loss limit reached", "Wrong fatal error message for MQL");
+        assert_eq!(fatal_error_for(healthy_handle_id), None, "Errors scoped to a handle should not affect others");
+        assert!(acknowledge_fatal_error(failing_handle_id), "Acknowledging an existing error should report it was cleared");
+        assert_eq!(fatal_error_for(failing_handle_id), None, "Acknowledged errors should be cleared");
+        assert!(!acknowledge_fatal_error(failing_handle_id), "Acknowledging twice should report nothing was cleared");
+        unregister(failing_handle_id);
+
+        // a stale `handle_id` only affects its MQL Program
+        let message = String::from("reported through a stale handle").encode_utf16().chain([0]).collect::<Vec<u16>>();
+        report_fatal_error(failing_handle_id, message.as_ptr());
+        assert_eq!(fatal_error_for(healthy_handle_id), None, "Errors reported for stale handles should not be escalated to the others");
+        assert_eq!(fatal_error_for(failing_handle_id).map(|fatal_error| fatal_error.kind), Some(FatalErrorKind::InvalidHandle), "Stale handles should be told they are invalid");
+        unregister(healthy_handle_id);
+    }
+
+    /// checks null pointers & negative lengths from MQL are refused -- setting the handle's fatal error instead of
+    /// being dereferenced -- while a null array with no elements is taken as empty
+    #[test]
+    fn invalid_ffi_arguments() {
+        let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("NULLPTRS"));
+        let assert_refused = |call: &str, arg_name: &str| {
+            let fatal_error = fatal_error_for(handle_id).unwrap_or_else(|| panic!("`{call}` should have set the fatal error"));
+            assert_eq!(fatal_error.kind, FatalErrorKind::InvalidArguments, "Wrong fatal error kind for `{call}`: {fatal_error:?}");
+            assert!(fatal_error.message.contains(arg_name), "The fatal error for `{call}` should tell about `{arg_name}`: '{fatal_error}'");
+            assert!(acknowledge_fatal_error(handle_id), "The fatal error for `{call}` should be acknowledgeable");
+        };
+
+        super::on_book(handle_id, std::ptr::null(), 0);
+        assert_eq!(fatal_error_for(handle_id), None, "A null array with no elements should be taken as empty");
+        assert_eq!(HANDLES.get(handle_id).expect("live handle").book_stats.lock().updates, 1, "The empty book should have been processed");
+
+        super::on_book(handle_id, std::ptr::null(), 3);
+        assert_refused("on_book(null, 3)", "book_info_array_ptr");
+        let book_info_array = mt5_dom(2, 3202, |_| 100.0);
+        super::on_book(handle_id, book_info_array.as_ptr(), -1);
+        assert_refused("on_book(array, -1)", "negative length");
+        on_tick(handle_id, std::ptr::null());
+        assert_refused("on_tick(null)", "mt5_tick");
+        on_trade_transaction(handle_id, std::ptr::null(), std::ptr::null(), std::ptr::null());
+        assert_refused("on_trade_transaction(null, null, null)", "transaction");
+        report_symbol_info(handle_id, std::ptr::null());
+        assert_refused("report_symbol_info(null)", "symbol_info");
+        report_account_info(handle_id, std::ptr::null());
+        assert_refused("report_account_info(null)", "account_info");
+        report_deal_properties(handle_id, std::ptr::null());
+        assert_refused("report_deal_properties(null)", "deal_properties");
+        report_mql5_function_called(handle_id, std::ptr::null_mut());
+        assert_refused("report_mql5_function_called(null)", "function_called_json_descriptor");
+        assert_eq!(next_mql5_function_to_call(handle_id, std::ptr::null_mut(), 4096), -1, "A null buffer should not be given any call");
+        assert_refused("next_mql5_function_to_call(null)", "buffer");
+        assert!(has_fatal_error(handle_id, std::ptr::null_mut(), 256), "A null buffer should, itself, be reported as a fatal error");
+        assert_refused("has_fatal_error(null)", "pre_allocated_error_message_buffer");
+        unregister(handle_id);
+    }
+
+    /// checks strings given to MQL never go past the buffer capacity it informs -- truncating what doesn't fit -- and that
+    /// calls whose descriptors don't fit are not given to MQL
+    #[test]
+    fn mql5_string_buffers() {
+        const CANARY: u16 = 0xCAFE;
+        let mut buffer = [CANARY; 16];
+        let read = |buffer: &[u16]| String::from_utf16_lossy(&buffer[..buffer.iter().position(|&c| c == 0).expect("the string should be \0 terminated")]);
+
+        assert!(convert_rust_to_mql5_string("test", -1, "buffer", "fits", buffer.as_mut_ptr(), 8), "A string fitting the buffer should be copied whole");
+        assert_eq!(read(&buffer), "fits", "Wrong string copied");
+        assert!(buffer[5..].iter().all(|&c| c == CANARY), "Nothing past the string's \\0 should have been written: {buffer:?}");
+
+        assert!(!convert_rust_to_mql5_string("test", -1, "buffer", "doesn't fit", buffer.as_mut_ptr(), 8), "Truncations should be told");
+        assert_eq!(read(&buffer), "doesn't", "The string should have been truncated to the capacity -- \\0 included");
+        assert!(buffer[8..].iter().all(|&c| c == CANARY), "Nothing past the capacity should have been written: {buffer:?}");
+        assert!(!convert_rust_to_mql5_string("test", -1, "buffer", "", buffer.as_mut_ptr(), 0), "No capacity means not even the \\0 fits");
+
+        let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("BUFFERS"));
+        let handle = live_handle("mql5_string_buffers", handle_id).expect("a just registered `handle_id` should be live");
+        let (_call_id, abandoned) = handle.mql5_calls.call(Mql5Command::Print(String::from("a message longer than the buffer")));
+        assert_eq!(next_mql5_function_to_call(handle_id, buffer.as_mut_ptr(), buffer.len() as i32), -1, "Calls not fitting the buffer should not be given to MQL");
+        assert_eq!(futures::executor::block_on(abandoned).err(), Some(Mql5CallError::Abandoned), "Calls not fitting the buffer should be abandoned");
+        let call_id = handle.mql5_calls.schedule(Mql5Command::CollectSymbolInfo);
+        let mut buffer = [CANARY; 128];
+        assert_eq!(next_mql5_function_to_call(handle_id, buffer.as_mut_ptr(), buffer.len() as i32), call_id as i32, "Calls fitting the buffer should be given to MQL");
+        assert_eq!(read(&buffer), Mql5Command::CollectSymbolInfo.to_json(), "Wrong call descriptor");
+        unregister(handle_id);
+    }
+
+    /// checks handles of the same account share their [Portfolio] -- resolved once, when registering
+    #[test]
+    fn shared_portfolios() {
+        let handle_ids = [
+            register(format!("pf_acnt_1"), format!("algo"), format!("PF1")),
+            register(format!("pf_acnt_1"), format!("algo"), format!("PF2")),
+            register(format!("pf_acnt_2"), format!("algo"), format!("PF1")),
+        ];
+        let handles = handle_ids.map(|handle_id| HANDLES.get(handle_id).expect("the handle should be live"));
+        assert!(Arc::ptr_eq(&handles[0].portfolio, &handles[1].portfolio), "Handles of the same account should share the portfolio");
+        assert!(!Arc::ptr_eq(&handles[0].portfolio, &handles[2].portfolio), "Handles of different accounts should not share portfolios");
+        for handle_id in handle_ids {
+            unregister(handle_id);
+        }
+    }
+
+    /// checks the communications are started by `DLL_PROCESS_ATTACH` and stopped by `DLL_PROCESS_DETACH`
+    /// -- within the configured timeout & releasing the port
+    #[test]
+    fn dll_lifecycle() {
+        let _serial = comms::COMMS_TESTS.lock();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").and_then(|listener| listener.local_addr()).expect("finding a free port").port();
+        let config_file = std::env::temp_dir().join(format!("rust_mt5_bridge_test_{port}.ron"));
+        fs::write(&config_file, format!("(comms: (mode: Server, port: {port}, startup_delay_millis: 0, shutdown_timeout_millis: 1000))")).expect("writing the configuration");
+        // no other test loads the configuration, so this one is used
+        std::env::set_var(config::CONFIG_FILE_ENV, &config_file);
+
+        assert_eq!(DllMain(std::ptr::null(), 1, std::ptr::null()), 1, "DLL_PROCESS_ATTACH should succeed");
+        let start = std::time::Instant::now();
+        while !comms::is_running() && start.elapsed() < std::time::Duration::from_secs(5) {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(comms::is_running(), "The communications should have been started");
+        let mut peer = std::net::TcpStream::connect(("127.0.0.1", port)).expect("connecting to the communications");
+        peer.set_read_timeout(Some(std::time::Duration::from_secs(2))).expect("setting the peer's read timeout");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        assert_eq!(DllMain(std::ptr::null(), 0, std::ptr::null()), 1, "DLL_PROCESS_DETACH should succeed");
+        assert!(start.elapsed() < std::time::Duration::from_millis(1500), "The shutdown should respect its timeout -- it took {:?}", start.elapsed());
+        assert!(!comms::is_running(), "The communications should have been stopped");
+        let mut received = String::new();
+        _ = std::io::Read::read_to_string(&mut peer, &mut received);
+        assert!(received.contains("GoodBye"), "Connected peers should have been told `GoodBye` -- they received '{received}'");
+        assert!(std::net::TcpListener::bind(("127.0.0.1", port)).is_ok(), "The port should have been released");
+        assert_eq!(DllMain(std::ptr::null(), 0, std::ptr::null()), 1, "A repeated DLL_PROCESS_DETACH should be harmless");
+        _ = fs::remove_file(config_file);
+    }
+
     /// tests both [apply_book_delta_events()] & [compute_book_delta_events()]
     /// -- sharing the same test since they are complementary
     #[test]
     fn on_book() {
 
-        ENUM_BOOK_TYPE.debug();
-        mql_rust_enum::set_enum_variant_value("EnumBookType", "BookTypeBuy", BookTypeBuy as i32)
-            .expect("Setting the MQL variant value of a Rust-known variant for a previously registered enum");
-        mql_rust_enum::set_enum_variant_value("EnumBookType", "BookTypeSell", BookTypeSell as i32)
-            .expect("Setting the MQL variant value of a Rust-known variant for a previously registered enum");
-        mql_rust_enum::set_enum_variant_value("EnumBookType", "BookTypeBuyMarket", BookTypeBuyMarket as i32)
-            .expect("Setting the MQL variant value of a Rust-known variant for a previously registered enum");
-        mql_rust_enum::set_enum_variant_value("EnumBookType", "BookTypeSellMarket", BookTypeSellMarket as i32)
-            .expect("Setting the MQL variant value of a Rust-known variant for a previously registered enum");
+        map_book_types();
         let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("SYMBL"));
-        let _handle = unsafe { &HANDLES[handle_id as usize] };
+        let _handle = live_handle("on_book", handle_id).expect("a just registered `handle_id` should be live");
 
         // "original" book used for delta computation --
         // Metatrader, as production data shows as of 2022-11-24, will always yield books like this:
@@ -727,7 +1296,8 @@ mod tests {
                 MqlBookInfo { book_type: BookTypeBuy, price: 23.41, volume: 42300.00 },
                 MqlBookInfo { book_type: BookTypeBuy, price: 23.40, volume: 51700.00 },
                 MqlBookInfo { book_type: BookTypeBuy, price: 23.39, volume: 61300.00 },
-                MqlBookInfo { book_type: BookTypeBuy, price: 23.38, volume: 55900.00 }])
+                MqlBookInfo { book_type: BookTypeBuy, price: 23.38, volume: 55900.00 }]),
+            ..OrderBooks::default()
         };
 
         // scenario containing the new books (to be received by Metatrader) and the expected generated book event deltas
@@ -814,17 +1384,19 @@ mod tests {
         // asserts the behavior of the functions under test both individually and as the opposite operation of one another
         // "delta events" will be generated (and asserted) to transform the `old_books` into `new_books` (which is also asserted)
         let assert = |prefix_message, old_books, new_books: Vec<Mq5MqlBookInfo>, expected_book_events| {
-            let observed_book_events = compute_book_delta_events(&old_books, &new_books);
+            let observed_book_events = compute_book_delta_events(&old_books, &new_books).expect("computing the delta events");
 //println!("observed_book_events: {:#?}", observed_book_events);
             assert_eq!(&observed_book_events, &expected_book_events, "{prefix_message} at 'compute_book_delta_events(...)': wrong 'delta events' were generated");
             // assert `old_books` really turns into `new_books` when the returned `observed_book_events` are applied to it.
             // `work_books`, therefore, is to be considered the "rolling books", constantly being updated with new book events
             let mut work_books = OrderBooks {
                 sell_orders: VecDeque::from_iter(old_books.sell_orders.iter().map(|e_ref| MqlBookInfo { book_type: e_ref.book_type, price: e_ref.price, volume: e_ref.volume })),
-                buy_orders: VecDeque::from_iter(old_books.buy_orders.iter().map(|e_ref| MqlBookInfo { book_type: e_ref.book_type, price: e_ref.price, volume: e_ref.volume }))
+                buy_orders: VecDeque::from_iter(old_books.buy_orders.iter().map(|e_ref| MqlBookInfo { book_type: e_ref.book_type, price: e_ref.price, volume: e_ref.volume })),
+                sell_market_orders: VecDeque::from_iter(old_books.sell_market_orders.iter().map(|e_ref| MqlBookInfo { book_type: e_ref.book_type, price: e_ref.price, volume: e_ref.volume })),
+                buy_market_orders: VecDeque::from_iter(old_books.buy_market_orders.iter().map(|e_ref| MqlBookInfo { book_type: e_ref.book_type, price: e_ref.price, volume: e_ref.volume })),
             };
             let converted_new_books = new_books.iter().map(|mq5_mql_book_event| mq5_mql_book_event.to_internal()).collect::<Vec<_>>();
-            apply_book_delta_events(&mut work_books, &observed_book_events);
+            apply_book_delta_events(&mut work_books, &observed_book_events).expect("applying the delta events");
 //println!("work_books: {:#?}", work_books);
             assert_eq!(work_books.iter().collect::<Vec<_>>(),
                        converted_new_books.iter().collect::<Vec<_>>(),
@@ -837,7 +1409,7 @@ mod tests {
 
         // exercise 'Add' events
         assert("Check 1 (empty `old_books` / full `new_books`)",
-               OrderBooks { sell_orders: Default::default(), buy_orders: Default::default() },
+               OrderBooks::default(),
                base_books_generator().iter().map(|e| Mq5MqlBookInfo { book_type: e.book_type as i32, price: e.price, volume: 0, volume_real: e.volume }).collect::<Vec<_>>(),
                base_books_generator().iter()
                    .map(|mql_book_info| match mql_book_info.book_type {
@@ -912,7 +1484,8 @@ mod tests {
                        MqlBookInfo { book_type: BookTypeBuy, price: 23.69, volume: 29300.0 },
                        MqlBookInfo { book_type: BookTypeBuy, price: 23.68, volume: 50700.0 },
                        MqlBookInfo { book_type: BookTypeBuy, price: 23.67, volume: 31600.0 }
-                   ])
+                   ]),
+                   ..OrderBooks::default()
                },
                vec![
                    Mq5MqlBookInfo { book_type: BookTypeSell as i32, price: 23.75, volume: 34600, volume_real: 34600.0 },
@@ -956,7 +1529,8 @@ mod tests {
                        MqlBookInfo { book_type: BookTypeBuy, price: 45.82, volume: 800.0 },
                        MqlBookInfo { book_type: BookTypeBuy, price: 45.81, volume: 1200.0 },
                        MqlBookInfo { book_type: BookTypeBuy, price: 45.8, volume: 1600.0 }
-                   ])
+                   ]),
+                   ..OrderBooks::default()
                },
                vec![
                    Mq5MqlBookInfo { book_type: BookTypeSell as i32, price: 45.91, volume: 800, volume_real: 800.0 },
@@ -984,4 +1558,316 @@ mod tests {
                ]);
 
     }
+
+    /// checks books deeper than the 5 levels of the early days are reconstructed -- and that the book queries work
+    #[test]
+    fn deep_books() {
+        map_book_types();
+        let mut books = OrderBooks::default();
+        for (mid_cents, volume_offset) in [(2340, 0.0), (2343, 100.0), (2338, 200.0)] {
+            let dom = mt5_dom(64, mid_cents, |level| 100.0 * (level + 1) as f64 + volume_offset);
+            let delta_events = compute_book_delta_events(&books, &dom).expect("computing the delta events");
+            apply_book_delta_events(&mut books, &delta_events).expect("applying the delta events");
+            assert_eq!(books.iter().collect::<Vec<_>>(), dom.iter().map(Mq5MqlBookInfo::to_internal).collect::<Vec<_>>().iter().collect::<Vec<_>>(),
+                       "A 64 levels book around {mid_cents} cents should have been reconstructed");
+        }
+
+        let books = OrderBooks {
+            sell_orders: VecDeque::from([
+                MqlBookInfo { book_type: BookTypeSell, price: 23.45, volume: 300.0 },
+                MqlBookInfo { book_type: BookTypeSell, price: 23.44, volume: 200.0 },
+                MqlBookInfo { book_type: BookTypeSell, price: 23.43, volume: 100.0 }]),
+            buy_orders:  VecDeque::from([
+                MqlBookInfo { book_type: BookTypeBuy, price: 23.42, volume: 900.0 },
+                MqlBookInfo { book_type: BookTypeBuy, price: 23.41, volume: 500.0 }]),
+            ..OrderBooks::default()
+        };
+        assert_eq!((books.best_ask().map(|level| level.price), books.best_bid().map(|level| level.price)), (Some(23.43), Some(23.42)), "Wrong book tops");
+        assert_eq!((books.depth_at(&BookParties::Sellers, 23.44), books.depth_at(&BookParties::Buyers, 23.44)), (200.0, 0.0), "Wrong depths at price");
+        assert_eq!(books.imbalance(1), Some(0.8), "Wrong imbalance for the book tops");
+        assert_eq!(books.imbalance(10), Some(0.4), "Wrong imbalance for the whole books");
+        assert_eq!(books.vwap_to_fill(&BookParties::Sellers, 300.0).map(|vwap| (vwap * 1e6).round() / 1e6), Some(23.436667), "Wrong VWAP for buying 300");
+        assert_eq!(books.vwap_to_fill(&BookParties::Buyers, 900.0), Some(23.42), "Selling what the top holds should pay its price");
+        assert_eq!(books.vwap_to_fill(&BookParties::Buyers, 1401.0), None, "There is not enough quantity to fill the order");
+    }
+
+    /// checks market orders -- queued during auctions -- are kept apart from the priced levels, using hand-written books in the
+    /// shape B3's pre-opening auction is expected to show on Metatrader: crossed priced levels & the market orders at the indicative
+    /// opening price.\
+    /// TODO: these books are NOT real snapshots -- no captured B3 pre-opening `OnBook()` dump is available yet. Validating against
+    ///       such dumps is still pending: they should replace the hand-written books below as soon as they are captured
+    #[test]
+    fn hand_written_auction_books() {
+        map_book_types();
+        let entry = |book_type: EnumBookType, price: f64, volume: f64| Mq5MqlBookInfo { book_type: book_type as i32, price, volume: volume as i64, volume_real: volume };
+        // made up PETR4, 09:45 -- pre-opening
+        let pre_opening = vec![
+            entry(BookTypeSell,       32.10, 1500.0),
+            entry(BookTypeSell,       32.05,  800.0),
+            entry(BookTypeSell,       31.98, 2000.0),
+            entry(BookTypeSellMarket, 32.02, 3400.0),
+            entry(BookTypeBuyMarket,  32.02, 5100.0),
+            entry(BookTypeBuy,        32.06, 1200.0),
+            entry(BookTypeBuy,        32.00, 2600.0),
+            entry(BookTypeBuy,        31.95,  900.0),
+        ];
+        // made up PETR4, 09:55 -- more buyers at market: the indicative price goes up
+        let closer_to_opening = vec![
+            entry(BookTypeSell,       32.10, 1500.0),
+            entry(BookTypeSell,       32.05,  800.0),
+            entry(BookTypeSell,       31.98, 2500.0),
+            entry(BookTypeSellMarket, 32.04, 3400.0),
+            entry(BookTypeBuyMarket,  32.04, 7300.0),
+            entry(BookTypeBuy,        32.06, 1200.0),
+            entry(BookTypeBuy,        32.00, 2600.0),
+            entry(BookTypeBuy,        31.95,  900.0),
+        ];
+        // made up PETR4, 10:00:05 -- opened: market orders were matched & the book is no longer crossed
+        let opened = vec![
+            entry(BookTypeSell,       32.10, 1500.0),
+            entry(BookTypeSell,       32.08,  400.0),
+            entry(BookTypeBuy,        32.03,  700.0),
+            entry(BookTypeBuy,        32.00, 2600.0),
+        ];
+
+        let mut books = OrderBooks::default();
+        let mut advance = |new_books: &[Mq5MqlBookInfo]| {
+            let delta_events = compute_book_delta_events(&books, new_books).expect("computing the delta events");
+            apply_book_delta_events(&mut books, &delta_events).expect("applying the delta events");
+            assert_eq!(books.iter().collect::<Vec<_>>(), new_books.iter().map(Mq5MqlBookInfo::to_internal).collect::<Vec<_>>().iter().collect::<Vec<_>>(),
+                       "Books -- market orders included -- should have been reconstructed");
+            (delta_events, books.market_orders_imbalance(), books.best_ask().map(|level| level.price), books.best_bid().map(|level| level.price))
+        };
+        let (_, imbalance, best_ask, best_bid) = advance(&pre_opening);
+        assert_eq!((imbalance, best_ask, best_bid), (1700.0, Some(31.98), Some(32.06)), "Market orders should count for the imbalance, but not for the (crossed) book tops");
+
+        let (delta_events, imbalance, ..) = advance(&closer_to_opening);
+        assert_eq!(delta_events, vec![
+            BookEvents::Update { book: BookParties::Sellers,       price: 31.98, delta_quantity: 500.0 },
+            BookEvents::Add    { book: BookParties::MarketSellers, price: 32.04, quantity: 3400.0 },
+            BookEvents::Del    { book: BookParties::MarketSellers, price: 32.02, quantity: 3400.0 },
+            BookEvents::Add    { book: BookParties::MarketBuyers,  price: 32.04, quantity: 7300.0 },
+            BookEvents::Del    { book: BookParties::MarketBuyers,  price: 32.02, quantity: 5100.0 },
+        ], "Market orders with a new indicative price should be replaced");
+        assert_eq!(imbalance, 3900.0, "Wrong auction imbalance");
+
+        let (_, imbalance, ..) = advance(&opened);
+        assert_eq!(imbalance, 0.0, "After the opening, no market orders should be left");
+
+        // wherever Metatrader lists the market orders, they are recognized
+        let mut books = OrderBooks::default();
+        let market_orders_first = pre_opening[3..5].iter().chain(&pre_opening[..3]).chain(&pre_opening[5..]).copied().collect::<Vec<_>>();
+        let delta_events = compute_book_delta_events(&books, &market_orders_first).expect("computing the delta events");
+        apply_book_delta_events(&mut books, &delta_events).expect("applying the delta events");
+        assert_eq!(books.iter().collect::<Vec<_>>(), pre_opening.iter().map(Mq5MqlBookInfo::to_internal).collect::<Vec<_>>().iter().collect::<Vec<_>>(),
+                   "Market orders should have been recognized, even if listed first");
+        assert_eq!(books.buy_market_orders.front().map(|entry| entry.book_type), Some(BookTypeBuyMarket), "The market order types should be kept");
+    }
+
+    /// property test: for random books -- of any depth, with price gaps, market orders & levels coming and going -- applying the delta events
+    /// computed from the old & new books should always give back the new books
+    #[test]
+    fn book_deltas_round_trip() {
+        map_book_types();
+        let mut random = XorShift::new(0x2545F4914F6CDD1D);
+        let mut books = OrderBooks::default();
+        for round in 0..5_000 {
+            let mid_cents = 2300 + (random.next_u64() % 20) as u32;
+            let (sell_depth, buy_depth) = (random.next_u64() % 65, random.next_u64() % 65);
+            // as in auctions, market orders come & go
+            let (sell_market_depth, buy_market_depth) = (random.next_u64() % 2, random.next_u64() % 2);
+            // up to 3 cents between levels -- so some prices are skipped
+            let mut levels = |book_type: EnumBookType, depth: u64, direction: i32| (0..depth)
+                .scan(mid_cents as i32, |cents, _| {
+                    *cents += direction * (1 + (random.next_u64() % 3) as i32);
+                    Some(Mq5MqlBookInfo { book_type: book_type as i32, price: *cents as f64 / 100.0, volume: 0, volume_real: 100.0 * (1 + random.next_u64() % 50) as f64 })
+                })
+                .collect::<Vec<_>>();
+            let sell_levels = levels(BookTypeSell, sell_depth, 1);
+            let buy_levels = levels(BookTypeBuy, buy_depth, -1);
+            let sell_market_orders = levels(BookTypeSellMarket, sell_market_depth, 1);
+            let buy_market_orders = levels(BookTypeBuyMarket, buy_market_depth, -1);
+            let dom = sell_levels.into_iter().rev().chain(sell_market_orders).chain(buy_market_orders).chain(buy_levels).collect::<Vec<_>>();
+            let delta_events = compute_book_delta_events(&books, &dom).expect("computing the delta events");
+            apply_book_delta_events(&mut books, &delta_events).unwrap_or_else(|inconsistency| panic!("Round #{round}: applying the delta events failed: {inconsistency:?}"));
+            assert_eq!(books.iter().collect::<Vec<_>>(), dom.iter().map(Mq5MqlBookInfo::to_internal).collect::<Vec<_>>().iter().collect::<Vec<_>>(),
+                       "Round #{round}: the new books should have been reconstructed from the old ones + delta events");
+        }
+    }
+
+    /// fuzz test: random -- mostly inconsistent -- delta events never panic and, when refused, leave the books untouched
+    #[test]
+    fn book_deltas_fuzzing() {
+        map_book_types();
+        let mut random = XorShift::new(0x9E3779B97F4A7C15);
+        let mut books = OrderBooks::default();
+        let contents = |books: &OrderBooks| books.iter().map(|level| (level.book_type, level.price, level.volume)).collect::<Vec<_>>();
+        let (mut applied, mut refused) = (0, 0);
+        for _ in 0..20_000 {
+            let delta_events = (0..1 + random.next_u64() % 4).map(|_| {
+                let book = [BookParties::Sellers, BookParties::Buyers][(random.next_u64() % 2) as usize];
+                // few prices & quantities, so the events often hit the booked levels
+                let (price, quantity) = ((2300 + random.next_u64() % 8) as f64 / 100.0, (100 * (random.next_u64() % 4)) as f64);
+                match random.next_u64() % 3 {
+                    0 => BookEvents::Add    { book, price, quantity },
+                    1 => BookEvents::Del    { book, price, quantity },
+                    _ => BookEvents::Update { book, price, delta_quantity: quantity - 150.0 },
+                }
+            }).collect::<Vec<_>>();
+            let before = contents(&books);
+            match apply_book_delta_events(&mut books, &delta_events) {
+                Ok(()) => applied += 1,
+                Err(_) => {
+                    refused += 1;
+                    assert_eq!(contents(&books), before, "Refused delta events {delta_events:?} should leave the books untouched");
+                },
+            }
+            for side in [BookParties::Sellers, BookParties::Buyers] {
+                assert!(books.side(&side).iter().zip(books.side(&side).iter().skip(1)).all(|(a, b)| a.price > b.price),
+                        "{side:?} levels should always be unique & sorted descendingly: {books:?}");
+            }
+        }
+        assert!(applied > 1_000 && refused > 1_000, "Both consistent & inconsistent delta events should have been exercised: {applied} applied; {refused} refused");
+    }
+
+    /// checks inconsistent delta events are refused with the appropriate errors -- and that `on_book()` recovers from them,
+    /// rebuilding the books from Metatrader's & counting the resyncs
+    #[test]
+    fn book_inconsistencies() {
+        map_book_types();
+        let new_books = || OrderBooks {
+            sell_orders: VecDeque::from([MqlBookInfo { book_type: BookTypeSell, price: 23.43, volume: 100.0 }]),
+            buy_orders:  VecDeque::from([MqlBookInfo { book_type: BookTypeBuy,  price: 23.42, volume: 200.0 }]),
+            ..OrderBooks::default()
+        };
+        let (sellers, buyers) = (BookParties::Sellers, BookParties::Buyers);
+        for (delta_events, expected_inconsistency) in [
+            (vec![BookEvents::Add    { book: sellers, price: 23.44, quantity: 100.0 },
+                  BookEvents::Add    { book: buyers,  price: 23.42, quantity: 100.0 }],       BookInconsistency::DuplicateLevel { book: buyers,  price: 23.42 }),
+            (vec![BookEvents::Del    { book: sellers, price: 23.42, quantity: 100.0 }],       BookInconsistency::MissingLevel   { book: sellers, price: 23.42 }),
+            (vec![BookEvents::Update { book: buyers,  price: 23.41, delta_quantity: 100.0 }], BookInconsistency::MissingLevel   { book: buyers,  price: 23.41 }),
+            (vec![BookEvents::Del    { book: buyers,  price: 23.42, quantity: 100.0 }],       BookInconsistency::VolumeMismatch { book: buyers,  price: 23.42, booked: 200.0, deleted: 100.0 }),
+            (vec![BookEvents::Update { book: sellers, price: 23.43, delta_quantity: -50.0 },
+                  BookEvents::Update { book: sellers, price: 23.43, delta_quantity: -51.0 }], BookInconsistency::NegativeVolume { book: sellers, price: 23.43, volume: -1.0 }),
+        ] {
+            let mut books = new_books();
+            assert_eq!(apply_book_delta_events(&mut books, &delta_events), Err(expected_inconsistency), "Wrong inconsistency for {delta_events:?}");
+            assert_eq!(books.iter().collect::<Vec<_>>(), new_books().iter().collect::<Vec<_>>(), "Refused delta events {delta_events:?} should leave the books untouched");
+        }
+        let unknown_entry = Mq5MqlBookInfo { book_type: 99, price: 23.44, volume: 0, volume_real: 100.0 };
+        assert_eq!(compute_book_delta_events(&new_books(), &[unknown_entry]), Err(BookInconsistency::UnknownBookType), "Entries of unknown types should be refused");
+
+        // `on_book()` recovers -- Metatrader repeats a price level, so the delta events are not consistent with our books
+        let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("RSYNC"));
+        let handle = live_handle("book_inconsistencies", handle_id).expect("a just registered `handle_id` should be live");
+        let internal = |dom: &[Mq5MqlBookInfo]| dom.iter().map(Mq5MqlBookInfo::to_internal).collect::<Vec<_>>();
+        let dom = mt5_dom(5, 2340, |level| 100.0 * (level + 1) as f64);
+        super::on_book(handle_id, dom.as_ptr(), dom.len() as i32);
+        let with_repeated_level = dom[..6].iter().chain(&dom[5..]).copied().collect::<Vec<_>>();
+        super::on_book(handle_id, with_repeated_level.as_ptr(), with_repeated_level.len() as i32);
+        assert_eq!(handle.books.lock().iter().collect::<Vec<_>>(), internal(&dom).iter().collect::<Vec<_>>(), "Books should have been rebuilt from Metatrader's -- without the repetition");
+        let next_dom = mt5_dom(5, 2340, |level| 200.0 * (level + 1) as f64);
+        super::on_book(handle_id, next_dom.as_ptr(), next_dom.len() as i32);
+        assert_eq!(handle.books.lock().iter().collect::<Vec<_>>(), internal(&next_dom).iter().collect::<Vec<_>>(), "Rebuilt books should be updated as usual");
+        let with_unknown_entry = next_dom.iter().copied().chain([unknown_entry]).collect::<Vec<_>>();
+        super::on_book(handle_id, with_unknown_entry.as_ptr(), with_unknown_entry.len() as i32);
+        assert_eq!(handle.books.lock().iter().count(), next_dom.len(), "Entries of unknown types should have been left out");
+        assert_eq!(*handle.book_stats.lock(), BookStats { updates: 4, resyncs: 2, last_inconsistency: Some(BookInconsistency::UnknownBookType) }, "Wrong book stats");
+        unregister(handle_id);
+    }
+
+    /// Records the trades it is fed with, as `(quantity, price, inferred)`
+    #[derive(Debug)]
+    struct TradesRecorder(Arc<Mutex<Vec<(u32, f64, bool)>>>);
+    impl TradingAlgorithm for TradesRecorder {
+        fn name(&self) -> &'static str {
+            "TradesRecorder"
+        }
+        fn on_trade(&mut self, trade: &Trade) -> Vec<OrderRequest> {
+            self.0.lock().push((trade.quantity, trade.price, trade.inferred));
+            vec![]
+        }
+    }
+
+    /// checks the trading algorithm is fed with the trades reported through `on_tick()` -- as well as with the ones `on_book()`
+    /// infers, when the books are consumed beyond what Metatrader reported
+    #[test]
+    fn trades_routing() {
+        map_book_types();
+        let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("TRDS"));
+        let handle = live_handle("trades_routing", handle_id).expect("a just registered `handle_id` should be live");
+        let trades = Arc::new(Mutex::new(Vec::new()));
+        *handle.trading_algorithm.lock() = Some(Box::new(TradesRecorder(Arc::clone(&trades))));
+        let dom = mt5_dom(5, 2340, |_| 500.0);
+        super::on_book(handle_id, dom.as_ptr(), dom.len() as i32);
+        // a buyer takes 100 from the best ask...
+        let mt5_tick = Mq5MqlTick { time: 1690290000, bid: 23.39, ask: 23.41, last: 23.41, volume: 100, time_msc: 1690290000480, flags: 56, volume_real: 100.0_f64.to_ne_bytes() };
+        super::on_tick(handle_id, &mt5_tick);
+        // ... but 300 are gone from it
+        let consumed_dom = mt5_dom(5, 2340, |level| if level == 0 { 200.0 } else { 500.0 });
+        super::on_book(handle_id, consumed_dom.as_ptr(), consumed_dom.len() as i32);
+        assert_eq!(*trades.lock(), vec![(100, 23.41, false), (200, 23.41, true)], "Both the reported & the inferred trades should have been fed to the algorithm");
+        unregister(handle_id);
+    }
+
+    /// measures the cost of each `on_book()` call -- on a registered handle, with no trading algorithm & no market data
+    /// subscribers -- for books of 10, 32 & 64 levels, timing every call. Logging is switched off while measuring, as its
+    /// cost depends on where the logs go. Run with `cargo test --release --lib -- --ignored --nocapture on_book_benchmark`.\
+    /// NOTE: a test rather than a `criterion` bench, as this crate is a `cdylib` -- which benches can't link against.\
+    /// Results of the command above, on a KVM guest with a single vCPU (reported only as "Intel(R) Xeon(R) Processor") &
+    /// rustc 1.95.0 -- the max being the guest's scheduling hiccups:
+    /// ```nocompile
+    /// 10 levels:  1985ns average;  1520ns median;  1995ns p99; 12238095ns max -- per `on_book()`
+    /// 32 levels:  3794ns average;  3107ns median;  4250ns p99; 13211179ns max -- per `on_book()`
+    /// 64 levels:  5988ns average;  5577ns median;  7873ns p99;  7315568ns max -- per `on_book()`
+    /// ```
+    #[test]
+    #[ignore]
+    fn on_book_benchmark() {
+        const ITERATIONS: usize = 200_000;
+        map_book_types();
+        let log_level = log::max_level();
+        log::set_max_level(log::LevelFilter::Off);
+        for depth in [10, 32, 64] {
+            let handle_id = register(format!("acnt_tkn"), format!("algo"), format!("BENCH{depth}"));
+            let handle = live_handle("on_book_benchmark", handle_id).expect("a just registered `handle_id` should be live");
+            // the book moves a tick up & down, with most quantities changing -- as when the market is busy
+            let doms = [mt5_dom(depth, 2340, |level| 100.0 * (level + 1) as f64), mt5_dom(depth, 2341, |level| 100.0 * (level + 2) as f64)];
+            let mut elapsed_nanos = Vec::with_capacity(ITERATIONS);
+            for i in 0..ITERATIONS {
+                let dom = &doms[i % 2];
+                let start = std::time::Instant::now();
+                super::on_book(handle_id, dom.as_ptr(), dom.len() as i32);
+                elapsed_nanos.push(start.elapsed().as_nanos());
+            }
+            let last_dom = &doms[(ITERATIONS - 1) % 2];
+            assert!(handle.books.lock().iter().map(|level| (level.price, level.volume)).eq(last_dom.iter().map(|level| (level.price, level.volume_real))),
+                    "The books of {depth} levels were not reconstructed");
+            assert_eq!(handle.book_stats.lock().resyncs, 0, "The books of {depth} levels should have been kept by the delta events alone");
+            unregister(handle_id);
+            elapsed_nanos.sort_unstable();
+            let percentile = |p: usize| elapsed_nanos[(elapsed_nanos.len() - 1) * p / 100];
+            println!("{depth:2} levels: {:5}ns average; {:5}ns median; {:5}ns p99; {:7}ns max -- per `on_book()`",
+                     elapsed_nanos.iter().sum::<u128>() / ITERATIONS as u128, percentile(50), percentile(99), percentile(100));
+        }
+        log::set_max_level(log_level);
+    }
+
+
+    /// Registers `ENUM_BOOK_TYPE`, mapping its MQL values to the same ones Rust uses
+    fn map_book_types() {
+        ENUM_BOOK_TYPE.debug();
+        for variant in ["BookTypeBuy", "BookTypeSell", "BookTypeBuyMarket", "BookTypeSellMarket"] {
+            mql_rust_enum::set_enum_variant_value("EnumBookType", variant, EnumBookType::from_str(variant).expect("a Rust-known variant") as i32)
+                .expect("Setting the MQL variant value of a Rust-known variant for a previously registered enum");
+        }
+    }
+
+    /// A Metatrader Depth of Market with `depth` levels, one cent apart, on each side of `mid_cents` -- in Metatrader's order
+    fn mt5_dom(depth: usize, mid_cents: u32, volume: impl Fn(usize) -> f64) -> Vec<Mq5MqlBookInfo> {
+        let level = |book_type: EnumBookType, cents: u32, level: usize| Mq5MqlBookInfo { book_type: book_type as i32, price: cents as f64 / 100.0, volume: 0, volume_real: volume(level) };
+        (0..depth).rev().map(|i| level(BookTypeSell, mid_cents + 1 + i as u32, i))
+            .chain((0..depth).map(|i| level(BookTypeBuy, mid_cents - 1 - i as u32, i)))
+            .collect()
+    }
 }
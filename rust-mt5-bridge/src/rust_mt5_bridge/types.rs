@@ -1,35 +1,196 @@
 use std::collections::VecDeque;
 pub use super::mq5_lib::*;
+use super::algorithms::TradingAlgorithm;
+use super::mql5_commands::Mql5Calls;
+use super::order_manager::OrderManager;
+use super::risk_manager::RiskManager;
+use super::aggressor_classifier::AggressorClassifier;
+use super::missed_trades_detector::MissedTradesDetector;
+use super::portfolio::Portfolio;
 
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
 use chrono::NaiveDateTime;
+use parking_lot::Mutex;
 
 
+/// Data kept for each registered MQL Program -- shared between Metatrader & the comms threads (see `handle_registry.rs`),
+/// hence the interior mutability for the fields that change after registration
 #[derive(Debug)]
 pub struct Handle {
+    pub handle_id:             i32,
     pub client_type:           ClientType,
     pub account_token:         String,
-    pub algorithm:             String,  // this should be parsed
+    /// the `algorithm` parameter given when registering -- see [Self::trading_algorithm]
+    pub algorithm:             String,
     pub symbol:                String,
-    pub books:                 OrderBooks,
-    pub mql_functions_to_call: VecDeque<String>,
+    pub books:                 Mutex<OrderBooks>,
+    /// how the reconstruction of [Self::books] is going -- see [BookStats]
+    pub book_stats:            Mutex<BookStats>,
+    /// MQL5 functions Rust wants this MQL Program to call -- see `mql5_commands.rs`
+    pub mql5_calls:            Mql5Calls,
+    /// errors scoped to this handle -- see [FatalErrorSeverity]
+    pub fatal_error:           Mutex<Option<FatalError>>,
+    /// the lifecycle of this MQL Program's orders & deals -- see `order_manager.rs`
+    pub orders:                Mutex<OrderManager>,
+    /// the algorithm parsed from [Self::algorithm] -- `None` for MQL Programs that don't trade (like market data providers)
+    pub trading_algorithm:     Mutex<Option<Box<dyn TradingAlgorithm>>>,
+    /// pre-trade checks for the orders issued by [Self::trading_algorithm] -- see `risk_manager.rs`
+    pub risk_manager:          Mutex<RiskManager>,
+    /// tells who started each trade -- see `aggressor_classifier.rs`
+    pub aggressor_classifier:  Mutex<AggressorClassifier>,
+    /// infers the trades Metatrader didn't report -- see `missed_trades_detector.rs`
+    pub missed_trades:         Mutex<MissedTradesDetector>,
+    /// positions & P&L of the account this MQL Program operates on -- shared with the other handles of [Self::account_token]
+    pub portfolio:             Arc<Mutex<Portfolio>>,
     // what else should I keep here or just on the server? open positions, symbol information, book, trades, etc...
 }
+#[cfg(test)]
+impl Handle {
+    /// A testing handle for `symbol`, with no trading algorithm nor risk limits -- tests needing otherwise may override
+    /// the fields, as in `Handle { risk_manager: ..., ..Handle::for_testing(handle_id, "PETR4") }`
+    pub fn for_testing(handle_id: i32, symbol: &str) -> Self {
+        use super::risk_manager::RiskLimits;
+        Self {
+            handle_id,
+            client_type:           ClientType::TestingExpertAdvisor,
+            account_token:         "acnt_tkn".to_string(),
+            algorithm:             "algo".to_string(),
+            symbol:                symbol.to_string(),
+            books:                 Mutex::new(OrderBooks::default()),
+            book_stats:            Mutex::new(BookStats::default()),
+            mql5_calls:            Mql5Calls::new(),
+            fatal_error:           Mutex::new(None),
+            orders:                Mutex::new(OrderManager::new()),
+            trading_algorithm:     Mutex::new(None),
+            risk_manager:          Mutex::new(RiskManager::new(symbol, RiskLimits::default())),
+            aggressor_classifier:  Mutex::new(AggressorClassifier::default()),
+            missed_trades:         Mutex::new(MissedTradesDetector::default()),
+            portfolio:             Arc::new(Mutex::new(Portfolio::new())),
+        }
+    }
+}
 
-#[derive(Debug)]
+/// The Depth of Market of a symbol, with as many price levels as Metatrader shares -- 20 to 64, for each side, on B3.\
+/// Both sides are kept sorted as Metatrader presents them -- descending by price -- so the best ask is the last of
+/// [Self::sell_orders] and the best bid, the first of [Self::buy_orders]: levels are found with binary searches.\
+/// Market orders -- queued, during auctions, to be matched at whatever the opening price is -- are kept apart (see
+/// [BookParties::MarketSellers]), as they are not priced levels: the price Metatrader shows for them is only indicative.\
+/// NOTE: Metatrader's `MqlBookInfo` has no order counts -- so the number of orders at each level is not known, being
+/// published as `None` (see `market_data_publisher.rs`)
+#[derive(Debug,Default)]
 pub struct OrderBooks {
-    /// keeps the selling intentions in ascending order (by price), with one entry for each price level
-    pub sell_orders: VecDeque<MqlBookInfo>,
+    /// keeps the selling intentions in descending order (by price), with one entry for each price level
+    pub sell_orders:        VecDeque<MqlBookInfo>,
     /// keeps the buying intentions in descending order (by price), with one entry for each price level
-    pub buy_orders:  VecDeque<MqlBookInfo>,
+    pub buy_orders:         VecDeque<MqlBookInfo>,
+    /// the `BOOK_TYPE_SELL_MARKET` entries, in descending order (by their indicative price) -- usually, one or none
+    pub sell_market_orders: VecDeque<MqlBookInfo>,
+    /// the `BOOK_TYPE_BUY_MARKET` entries, in descending order (by their indicative price) -- usually, one or none
+    pub buy_market_orders:  VecDeque<MqlBookInfo>,
 }
 impl OrderBooks {
-    /// Iterates over the book entries in the same order as MetaTrader presents theirs: Sell orders (descending by price), spread, Buy orders (ascending by price)
+    /// Iterates over the book entries in the same order as MetaTrader presents theirs: Sell orders (descending by price),
+    /// then the market orders -- sellers, then buyers -- at the spread, where they are the first to be matched, then Buy orders (descending by price)
     pub fn iter(&self) -> impl Iterator<Item=&MqlBookInfo> {
-        self.sell_orders.iter().chain(self.buy_orders.iter())
+        self.sell_orders.iter()
+            .chain(self.sell_market_orders.iter())
+            .chain(self.buy_market_orders.iter())
+            .chain(self.buy_orders.iter())
+    }
+
+    /// The entries of `side`, sorted as Metatrader presents them -- descending by price
+    pub fn side(&self, side: &BookParties) -> &VecDeque<MqlBookInfo> {
+        match side {
+            BookParties::Sellers       => &self.sell_orders,
+            BookParties::Buyers        => &self.buy_orders,
+            BookParties::MarketSellers => &self.sell_market_orders,
+            BookParties::MarketBuyers  => &self.buy_market_orders,
+        }
+    }
+
+    /// Mutable version of [Self::side()]
+    pub fn side_mut(&mut self, side: &BookParties) -> &mut VecDeque<MqlBookInfo> {
+        match side {
+            BookParties::Sellers       => &mut self.sell_orders,
+            BookParties::Buyers        => &mut self.buy_orders,
+            BookParties::MarketSellers => &mut self.sell_market_orders,
+            BookParties::MarketBuyers  => &mut self.buy_market_orders,
+        }
+    }
+
+    /// Iterates over the levels of `side`, from the best price to the worst -- from the spread outwards
+    pub fn levels(&self, side: &BookParties) -> Box<dyn Iterator<Item=&MqlBookInfo> + '_> {
+        match side {
+            BookParties::Sellers       => Box::new(self.sell_orders.iter().rev()),
+            BookParties::Buyers        => Box::new(self.buy_orders.iter()),
+            BookParties::MarketSellers => Box::new(self.sell_market_orders.iter().rev()),
+            BookParties::MarketBuyers  => Box::new(self.buy_market_orders.iter()),
+        }
+    }
+
+    /// Binary searches the level of `side` at `price` -- see [slice::binary_search()] for the meaning of the result
+    pub fn search(&self, side: &BookParties, price: f64) -> Result<usize, usize> {
+        // descending order: the comparison is reversed
+        self.side(side).binary_search_by(|level| price.total_cmp(&level.price))
+    }
+
+    /// The lowest selling price
+    pub fn best_ask(&self) -> Option<&MqlBookInfo> {
+        self.sell_orders.back()
+    }
+
+    /// The highest buying price
+    pub fn best_bid(&self) -> Option<&MqlBookInfo> {
+        self.buy_orders.front()
+    }
+
+    /// The quantity booked at `price`, on `side` -- 0 if there is no such level
+    pub fn depth_at(&self, side: &BookParties, price: f64) -> f64 {
+        self.search(side, price).map_or(0.0, |i| self.side(side)[i].volume)
+    }
+
+    /// The quantity of market orders queued on `side` -- [BookParties::MarketSellers] or [BookParties::MarketBuyers]
+    pub fn market_orders(&self, side: &BookParties) -> f64 {
+        self.side(side).iter().map(|entry| entry.volume).sum()
+    }
+
+    /// The auction imbalance: how many more papers the market orders want to buy than to sell -- negative if sellers
+    /// prevail. This is what the priced levels have to absorb for the auction to open
+    pub fn market_orders_imbalance(&self) -> f64 {
+        self.market_orders(&BookParties::MarketBuyers) - self.market_orders(&BookParties::MarketSellers)
+    }
+
+    /// How much the `levels` best prices of each side lean towards buying: from -1.0 (only sellers) to 1.0 (only buyers)
+    /// -- `None` if both sides are empty
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        let volume = |side| self.levels(&side).take(levels).map(|level| level.volume).sum::<f64>();
+        let (bid_volume, ask_volume) = (volume(BookParties::Buyers), volume(BookParties::Sellers));
+        let total_volume = bid_volume + ask_volume;
+        (total_volume > 0.0).then(|| (bid_volume - ask_volume) / total_volume)
+    }
+
+    /// The average price a market order of `quantity` would pay, consuming the levels of `side` from the best price on
+    /// (the `Sellers` side for buying orders) -- `None` if there is not enough quantity booked
+    pub fn vwap_to_fill(&self, side: &BookParties, quantity: f64) -> Option<f64> {
+        if quantity <= 0.0 {
+            return None;
+        }
+        let (mut remaining, mut cost) = (quantity, 0.0);
+        for level in self.levels(side) {
+            let filled = remaining.min(level.volume);
+            cost += filled * level.price;
+            remaining -= filled;
+            if remaining <= 0.0 {
+                return Some(cost / quantity);
+            }
+        }
+        None
     }
 }
 
+/// Changes to a book entry, identified by its `book` & `price`. For market orders -- which are not priced levels -- `price`
+/// is the indicative one shown by Metatrader: a new indicative price is a `Del` followed by an `Add`
 #[derive(Debug,PartialEq)]
 pub enum BookEvents {
     Add    { book: BookParties, price: f64, quantity: f64 },
@@ -38,48 +199,166 @@ pub enum BookEvents {
     Update { book: BookParties, price: f64, delta_quantity: f64 },
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,Clone,Copy,PartialEq)]
 pub enum BookParties {
+    /// Priced selling levels -- the asks
     Sellers,
+    /// Priced buying levels -- the bids
     Buyers,
+    /// Selling market orders, queued for an auction -- see [OrderBooks::market_orders_imbalance()]
+    MarketSellers,
+    /// Buying market orders, queued for an auction -- see [OrderBooks::market_orders_imbalance()]
+    MarketBuyers,
 }
 impl BookParties {
-    pub fn from_mt5_enum_book(book_type: EnumBookType) -> Self {
+    /// The side of the book entries of `book_type` -- failing for the ones MQL & Rust don't agree upon (see `mql_rust_enum.rs`)
+    pub fn from_mt5_enum_book(book_type: EnumBookType) -> Result<Self, BookInconsistency> {
         match book_type {
-            EnumBookType::BookTypeSell | EnumBookType::BookTypeSellMarket=> Self::Sellers,
-            EnumBookType::BookTypeBuy  | EnumBookType::BookTypeBuyMarket => Self::Buyers,
-            _ => panic!("Unknown `EnumBookType`: {:?}", book_type)
+            EnumBookType::BookTypeSell           => Ok(Self::Sellers),
+            EnumBookType::BookTypeBuy            => Ok(Self::Buyers),
+            EnumBookType::BookTypeSellMarket     => Ok(Self::MarketSellers),
+            EnumBookType::BookTypeBuyMarket      => Ok(Self::MarketBuyers),
+            EnumBookType::UnknownMqlVariantValue => Err(BookInconsistency::UnknownBookType),
         }
     }
-    pub fn to_mt5_enum_book(&self) -> EnumBookType {
+    pub fn to_mt5_enum_book(self) -> EnumBookType {
+        match self {
+            BookParties::Sellers       => EnumBookType::BookTypeSell,
+            BookParties::Buyers        => EnumBookType::BookTypeBuy,
+            BookParties::MarketSellers => EnumBookType::BookTypeSellMarket,
+            BookParties::MarketBuyers  => EnumBookType::BookTypeBuyMarket,
+        }
+    }
+}
+
+/// Why the book deltas couldn't be computed or applied -- meaning our [OrderBooks] are out of sync with Metatrader's and
+/// should be rebuilt from its full snapshot
+#[derive(Debug,Clone,PartialEq)]
+pub enum BookInconsistency {
+    /// A Metatrader book entry has an `ENUM_BOOK_TYPE` value Rust doesn't know about
+    UnknownBookType,
+    /// An `Add` for a price level that is already booked
+    DuplicateLevel { book: BookParties, price: f64 },
+    /// A `Del` or `Update` for a price level that isn't booked
+    MissingLevel   { book: BookParties, price: f64 },
+    /// A `Del` whose quantity is not the one booked at its price level
+    VolumeMismatch { book: BookParties, price: f64, booked: f64, deleted: f64 },
+    /// An `Update` that would leave its price level with a negative quantity
+    NegativeVolume { book: BookParties, price: f64, volume: f64 },
+}
+
+/// Counters for the reconstruction of a symbol's [OrderBooks] -- resyncs are expected to be rare: frequent ones suggest
+/// a bug or an MQL Program out of sync with the DLL version
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct BookStats {
+    /// `OnBook()` events processed
+    pub updates:            u64,
+    /// how many times the books had to be rebuilt from the snapshot -- see [BookInconsistency]
+    pub resyncs:            u64,
+    /// what caused the last resync
+    pub last_inconsistency: Option<BookInconsistency>,
+}
+
+/// Errors that should cause MQL Programs to quit, as continuing is likely to cause undefined behavior -- see `has_fatal_error()`
+#[derive(Debug,Clone,PartialEq)]
+pub struct FatalError {
+    pub kind:     FatalErrorKind,
+    pub severity: FatalErrorSeverity,
+    pub message:  String,
+}
+impl Display for FatalError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FatalErrorKind {
+    /// An MQL enum variant couldn't be mapped to Rust -- see `mql_rust_enum.rs`
+    EnumMappingFailure,
+    /// A struct shared between MQL & Rust doesn't have the same fields, alignment or sizes on both sides
+    StructLayoutMismatch,
+    /// The communications with the Ogre Exchange were lost
+    ConnectivityLost,
+    /// A risk limit was breached -- no further orders should be issued
+    RiskBreach,
+    /// The MQL Program used a `handle_id` that is not live (never registered, unregistered or stale)
+    InvalidHandle,
+    /// The Rust code panicked while serving the MQL Program
+    Panic,
+    /// Reported by the MQL Program itself -- see `report_fatal_error()`
+    MqlReported,
+    /// The configuration couldn't be loaded -- see `config.rs`
+    InvalidConfiguration,
+    /// The MQL Program passed invalid arguments to the DLL -- like null pointers or negative array lengths
+    InvalidArguments,
+}
+impl FatalErrorKind {
+    /// The severity errors of this kind have, unless told otherwise
+    pub fn default_severity(&self) -> FatalErrorSeverity {
         match self {
-            BookParties::Sellers => EnumBookType::BookTypeSell,
-            BookParties::Buyers => EnumBookType::BookTypeBuy,
+            FatalErrorKind::EnumMappingFailure |
+            FatalErrorKind::StructLayoutMismatch |
+            FatalErrorKind::ConnectivityLost |
+            FatalErrorKind::InvalidConfiguration => FatalErrorSeverity::Global,
+            FatalErrorKind::RiskBreach |
+            FatalErrorKind::InvalidHandle |
+            FatalErrorKind::Panic |
+            FatalErrorKind::MqlReported |
+            FatalErrorKind::InvalidArguments     => FatalErrorSeverity::Handle,
         }
     }
 }
 
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FatalErrorSeverity {
+    /// Only the MQL Program owning the handle should quit
+    Handle,
+    /// All MQL Programs using this DLL should quit
+    Global,
+}
+
 #[derive(Debug)]
 pub enum ClientType {
     ProductionExpertAdvisor,
     TestingExpertAdvisor,
 }
 
-#[derive(Debug)]
+/// The aggressor of a [Trade] -- along with the `bid` & `ask` informed on its tick
+#[derive(Debug,Clone,PartialEq)]
 pub enum TradeParty {
+    /// Both parties were aggressive -- as in auctions
     Ambiguous   {bid: f64, ask: f64},
     Buyer       {bid: f64, ask: f64},
     Seller      {bid: f64, ask: f64},
+    /// Nothing points to either party
     Unspecified {bid: f64, ask: f64},
 }
 
+/// How much the [TradeParty] of a [Trade] may be trusted -- see `aggressor_classifier.rs`
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum AggressorConfidence {
+    /// Not classified, yet -- or there is no evidence at all
+    Unknown,
+    /// Only the tick rule points to the party -- or the tick flags, contradicted by the quotes
+    Low,
+    /// The trade happened inside the spread -- or only the tick flags point to the party
+    Medium,
+    /// The trade hit the prevailing quotes or both the quotes & tick flags agree -- or it happened in an auction
+    High,
+}
+
 #[derive(Debug)]
 pub struct Trade<'a> {
-    pub symbol:    &'a String,
-    pub time:      NaiveDateTime,
-    pub aggressor: TradeParty,
-    pub quantity:  u32,
-    pub price:     f64
+    pub symbol:     &'a String,
+    pub time:       NaiveDateTime,
+    pub aggressor:  TradeParty,
+    /// see `aggressor_classifier.rs`
+    pub confidence: AggressorConfidence,
+    /// the marker for trades that were never reported by Metatrader, but inferred from the books -- see `missed_trades_detector.rs`
+    pub inferred:   bool,
+    pub quantity:   u32,
+    pub price:      f64
 }
 
 #[derive(Debug)]
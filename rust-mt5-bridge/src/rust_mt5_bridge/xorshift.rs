@@ -0,0 +1,34 @@
+//! Minimal deterministic pseudo-random number generator -- Marsaglia's xorshift64 -- shared by the jitter of
+//! `comms/backoff.rs` and by the property & fuzz tests, which need reproducible sequences rather than quality randomness.
+
+/// See the [module](self) docs
+#[derive(Debug,Clone)]
+pub struct XorShift(u64);
+
+impl XorShift {
+
+    /// The lowest bit of `seed` is always set -- as xorshift would only yield zeroes for a 0 seed
+    pub fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+#[cfg(test)]
+impl XorShift {
+
+    /// The high bits of [Self::next_u64()] -- the better distributed ones
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    /// A number in `0..n`
+    pub fn below(&mut self, n: u32) -> u32 {
+        self.next_u32() % n
+    }
+}